@@ -1,10 +1,14 @@
 use std::{
+    borrow::Cow,
+    collections::HashSet,
     convert::TryInto,
     ops::{Deref, DerefMut},
+    sync::Arc,
 };
 
-use parking_lot::RawRwLock;
+use parking_lot::{RawRwLock, RwLock};
 use thiserror::Error;
+use zerocopy::{AsBytes, ByteSlice, ByteSliceMut};
 
 use crate::{buffer::Buffer, latch::OwnedRwLockExt};
 use crate::{
@@ -13,7 +17,7 @@ use crate::{
     latch::OwnedRwLockWriteGuard,
 };
 
-use super::disk::PageId;
+use super::disk::{PageId, PAGE_SIZE};
 
 mod branch;
 mod leaf;
@@ -23,8 +27,50 @@ mod node;
 pub enum Error {
     #[error(transparent)]
     Buffer(#[from] buffer::Error),
+    /// Never returned: a put that splits a leaf waits for the next one
+    /// instead of failing.
+    #[deprecated(note = "never returned, as splits wait for the next leaf")]
     #[error("dead lock")]
     Deadlock,
+    #[error("value of {len} bytes exceeds the maximum of {max}")]
+    ValueTooLarge { len: usize, max: usize },
+    /// `pool_size` is the size of the smallest shard.
+    #[error("buffer pool of {pool_size} frames is below the minimum of {min} for B-trees")]
+    PoolTooSmall { pool_size: usize, min: usize },
+    #[error("key is not greater than every key in the tree")]
+    KeyNotIncreasing,
+    #[error("leaf {page_id:?} does not match its checksum")]
+    ChecksumMismatch { page_id: PageId },
+    #[error("tree {page_id:?} has {found}-byte keys, not {expected}")]
+    KeyWidthMismatch {
+        page_id: PageId,
+        expected: usize,
+        found: usize,
+    },
+}
+
+/// A flaw in the structure of a tree, as found by `Access::verify`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Problem {
+    /// A page that should be a node of the tree isn't one, or can't be read
+    /// as one.
+    Malformed { page_id: PageId },
+    /// A node whose keys are out of order, or outside the range its parent
+    /// sends to it.
+    Unsorted { page_id: PageId },
+    /// A page linking to one outside the file or already in the tree, or a
+    /// leaf whose sibling links don't lead to its neighbors.
+    BrokenLink { page_id: PageId },
+    /// A leaf whose stamped checksum disagrees with its contents.
+    ChecksumMismatch { page_id: PageId },
+}
+
+/// The outcome of `Access::verify`.
+#[derive(Debug, Default)]
+pub struct Verification {
+    pub problems: Vec<Problem>,
+    /// The meta page and every node reached.
+    pub pages: HashSet<PageId>,
 }
 
 struct BTreePage<T> {
@@ -39,6 +85,46 @@ where
         let bytes = self.data[0..8].try_into().unwrap();
         PageId(u64::from_be_bytes(bytes))
     }
+
+    fn record_count(&self) -> u64 {
+        let bytes = self.data[8..16].try_into().unwrap();
+        u64::from_be_bytes(bytes)
+    }
+
+    /// Trees written before this was tracked read as page 0, which is never a
+    /// leaf since the first page of a file is always a meta page.
+    fn rightmost_leaf_page_id(&self) -> Option<PageId> {
+        let bytes = self.data[16..24].try_into().unwrap();
+        match PageId(u64::from_be_bytes(bytes)) {
+            PageId(0) => None,
+            page_id => Some(page_id),
+        }
+    }
+
+    fn extra_page_id(&self) -> Option<PageId> {
+        let bytes = self.data[24..32].try_into().unwrap();
+        match PageId(u64::from_be_bytes(bytes)) {
+            PageId(0) => None,
+            page_id => Some(page_id),
+        }
+    }
+
+    /// Trees written before this was recorded read as 0, and have 8-byte
+    /// keys.
+    fn key_width(&self) -> usize {
+        match self.data[KEY_WIDTH_OFFSET] {
+            0 => 8,
+            width => width as usize,
+        }
+    }
+
+    fn note(&self) -> Option<&[u8]> {
+        let len_bytes = self.data[NOTE_OFFSET..NOTE_OFFSET + 2].try_into().unwrap();
+        match u16::from_be_bytes(len_bytes) as usize {
+            0 => None,
+            len => Some(&self.data[NOTE_OFFSET + 2..][..len.min(MAX_NOTE_LEN)]),
+        }
+    }
 }
 
 impl<T> BTreePage<T>
@@ -48,16 +134,283 @@ where
     fn set_root_page_id(&mut self, PageId(prev_page_id): PageId) {
         self.data[0..8].copy_from_slice(&prev_page_id.to_be_bytes());
     }
+
+    fn set_record_count(&mut self, record_count: u64) {
+        self.data[8..16].copy_from_slice(&record_count.to_be_bytes());
+    }
+
+    fn set_rightmost_leaf_page_id(&mut self, PageId(leaf_page_id): PageId) {
+        self.data[16..24].copy_from_slice(&leaf_page_id.to_be_bytes());
+    }
+
+    fn set_extra_page_id(&mut self, PageId(page_id): PageId) {
+        self.data[24..32].copy_from_slice(&page_id.to_be_bytes());
+    }
+
+    fn set_key_width(&mut self, width: usize) {
+        self.data[KEY_WIDTH_OFFSET] = width.try_into().expect("keys are at most 255 bytes");
+    }
+
+    /// Empty notes are cleared.
+    fn set_note(&mut self, note: &[u8]) {
+        assert!(note.len() <= MAX_NOTE_LEN);
+        let area = &mut self.data[NOTE_OFFSET..NOTE_OFFSET + 2 + MAX_NOTE_LEN];
+        area.fill(0);
+        area[..2].copy_from_slice(&(note.len() as u16).to_be_bytes());
+        area[2..2 + note.len()].copy_from_slice(note);
+    }
+}
+
+/// The byte of the meta page holding the length of the tree's keys.
+const KEY_WIDTH_OFFSET: usize = 32;
+
+/// Where `Access::note` starts in the meta page: its length as a big-endian
+/// u16, then its bytes.
+const NOTE_OFFSET: usize = 64;
+/// The longest note a meta page holds.
+pub const MAX_NOTE_LEN: usize = 256;
+
+pub type Key<const N: usize = 8> = [u8; N];
+
+/// A key and its value, copied out of a leaf.
+pub type Record<const N: usize = 8> = (Key<N>, Vec<u8>);
+
+/// Leaves split evenly unless `Access::with_fill_factor` says otherwise.
+const DEFAULT_FILL_FACTOR: u8 = 50;
+
+/// The largest value a record with an `N`-byte key can hold, as determined
+/// by the leaf layout. Such a record takes up half of a leaf, so any two
+/// records fit in one and a split can always make room for the record being
+/// put.
+pub fn max_value_size<const N: usize>() -> usize {
+    let mut page = [0u64; PAGE_SIZE / 8];
+    let mut node_page = node::NodePage::<_, N>::new(page.as_bytes_mut()).unwrap();
+    node_page.initialize_as_leaf().max_value_size()
+}
+
+fn check_value_size<const N: usize>(value: &[u8]) -> Result<(), Error> {
+    let max = max_value_size::<N>();
+    if value.len() > max {
+        return Err(Error::ValueTooLarge {
+            len: value.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// Physical state of the leaf holding a key, as reported by
+/// `Access::leaf_stats` and `Access::start_leaf_stats`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LeafStats {
+    pub page_id: PageId,
+    pub num_slots: usize,
+    pub free_space: usize,
+    /// Bytes available to records and their slots when the leaf is empty.
+    pub usable_bytes: usize,
+    /// The slot of the key asked for, or where it would be inserted, as
+    /// returned by a binary search. `None` when no key was given.
+    pub slot: Option<Result<usize, usize>>,
+}
+
+/// The most buffers a `put` into a tree with `N`-byte keys may pin at once:
+/// the meta page, the path from the root, the next leaf and a new page for
+/// each node that splits plus a new root. Page ids are 64-bit and non-root
+/// branches keep at least half their pairs, which bounds the depth of any
+/// tree.
+pub fn min_pool_size<const N: usize>() -> usize {
+    let mut page = [0u64; PAGE_SIZE / 8];
+    let mut node_page = node::NodePage::<_, N>::new(page.as_bytes_mut()).unwrap();
+    let min_fan_out = node_page.initialize_as_branch().max_pairs() as u128 / 2;
+    // A tree of depth 2 has at least two leaves, and each further level
+    // multiplies them by at least `min_fan_out`.
+    let mut depth = 1;
+    let mut min_leaves = 1u128;
+    while min_leaves <= u64::MAX as u128 {
+        depth += 1;
+        min_leaves = if depth == 2 {
+            2
+        } else {
+            min_leaves * min_fan_out
+        };
+    }
+    let max_depth = depth - 1;
+    1 + max_depth + 1 + (max_depth + 1)
+}
+
+/// Fails with `Error::PoolTooSmall` if a shard of `bufmgr` may run out of
+/// free buffers in the middle of a `put` into a tree with `N`-byte keys,
+/// however large the tree. Each shard is checked on its own, as every page
+/// of a `put` may belong to the same one. This only guarantees that a `put`
+/// running alone finishes: concurrent requests pin pages of their own, and
+/// may make it fail with `buffer::Error::NoFreeBuffer`, which leaves the
+/// tree as it was.
+pub fn check_pool_size<const N: usize>(bufmgr: &BufferPoolManager) -> Result<(), Error> {
+    let min = min_pool_size::<N>();
+    match bufmgr.shard_sizes().min() {
+        Some(pool_size) if pool_size < min => Err(Error::PoolTooSmall { pool_size, min }),
+        _ => Ok(()),
+    }
+}
+
+/// Fails if `node_page` is a leaf whose stamped checksum disagrees with its
+/// contents. Only checked if `bufmgr` verifies leaves and the leaf has been
+/// stamped.
+fn verify_leaf<B: ByteSlice, const N: usize>(
+    bufmgr: &BufferPoolManager,
+    page_id: PageId,
+    node_page: &node::NodePage<B, N>,
+) -> Result<(), Error> {
+    if !bufmgr.verifies_leaves() || !node_page.is_leaf() || node_page.checksum() == 0 {
+        return Ok(());
+    }
+    if node_page.checksum() != node_page.compute_checksum() {
+        return Err(Error::ChecksumMismatch { page_id });
+    }
+    Ok(())
+}
+
+/// Restamps a leaf that was just written. Without verification the stamp is
+/// cleared instead, so that no stale one is checked once it is turned on.
+fn stamp_leaf<B: ByteSliceMut, const N: usize>(
+    bufmgr: &BufferPoolManager,
+    node_page: &mut node::NodePage<B, N>,
+) {
+    let checksum = if bufmgr.verifies_leaves() {
+        node_page.compute_checksum()
+    } else {
+        0
+    };
+    node_page.set_checksum(checksum);
+}
+
+/// Finds the meta pages among pages `1..num_pages` by structure alone: a
+/// root page id pointing at a node, a rightmost leaf that is one if set, and
+/// nothing else on the page but the key width and a note. Lets trees be
+/// found when nothing points at them any more.
+pub fn find_meta_pages(bufmgr: &BufferPoolManager, num_pages: u64) -> Result<Vec<PageId>, Error> {
+    let is_node = |page_id: PageId, leaf_only: bool| -> Result<bool, Error> {
+        if page_id.0 == 0 || page_id.0 >= num_pages {
+            return Ok(false);
+        }
+        let buffer = bufmgr.fetch_page(page_id)?;
+        let ro_buffer = buffer.read();
+        let node_page = node::NodePage::<_>::new(ro_buffer.page.as_ref()).unwrap();
+        Ok(node_page.is_node() && !(leaf_only && node_page.node().try_into_leaf().is_err()))
+    };
+    let mut meta_pages = vec![];
+    for page_id in (1..num_pages).map(PageId) {
+        let (root_page_id, rightmost_leaf_page_id, rest_is_zero) = {
+            let buffer = bufmgr.fetch_page(page_id)?;
+            let ro_buffer = buffer.read();
+            let btree = BTreePage {
+                data: &ro_buffer.page[..],
+            };
+            let note_end = NOTE_OFFSET + 2 + MAX_NOTE_LEN;
+            let rest_is_zero = btree.data[KEY_WIDTH_OFFSET + 1..NOTE_OFFSET]
+                .iter()
+                .chain(&btree.data[note_end..])
+                .all(|&byte| byte == 0);
+            (
+                btree.root_page_id(),
+                btree.rightmost_leaf_page_id(),
+                rest_is_zero,
+            )
+        };
+        if rest_is_zero
+            && is_node(root_page_id, false)?
+            && rightmost_leaf_page_id
+                .map_or(Ok(true), |leaf_page_id| is_node(leaf_page_id, true))?
+        {
+            meta_pages.push(page_id);
+        }
+    }
+    Ok(meta_pages)
+}
+
+/// The records of `page_id` if it is a leaf of a tree with `N`-byte keys.
+pub fn read_leaf<const N: usize>(
+    bufmgr: &BufferPoolManager,
+    page_id: PageId,
+) -> Result<Option<Vec<Record<N>>>, Error> {
+    let buffer = bufmgr.fetch_page(page_id)?;
+    let ro_buffer = buffer.read();
+    let node_page = node::NodePage::<_, N>::new(ro_buffer.page.as_ref()).unwrap();
+    if !node_page.is_node() {
+        return Ok(None);
+    }
+    let leaf = match node_page.node().try_into_leaf() {
+        Ok(leaf) => leaf,
+        Err(_) => return Ok(None),
+    };
+    let records = leaf
+        .iter_records()
+        .map(|record| (record.key(), record.value.to_vec()))
+        .collect();
+    Ok(Some(records))
+}
+
+/// Which neighbor `Access::seek` settles for when the key itself is absent.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Direction {
+    /// The largest key less than or equal to the sought one.
+    Floor,
+    /// The smallest key greater than or equal to the sought one.
+    Ceiling,
+}
+
+/// A change to a tree, reported to its `MutationObserver`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mutation<'v, const N: usize = 8> {
+    Put {
+        key: Key<N>,
+        value: &'v [u8],
+    },
+    Delete {
+        key: Key<N>,
+    },
+    /// Every key in `[start, end)` was deleted.
+    DeleteRange {
+        start: Key<N>,
+        end: Key<N>,
+    },
+    /// Part of the node at `page_id` moved to its new right sibling.
+    Split {
+        page_id: PageId,
+        new_page_id: PageId,
+    },
+    NewRoot {
+        page_id: PageId,
+    },
+}
+
+/// Receives the mutations applied through an `Access`, e.g. to feed change
+/// data capture. Leaves are never merged, so there are no merge events.
+pub trait MutationObserver<const N: usize = 8>: Sync {
+    /// Called while the pages touched by `mutation` are still write-latched,
+    /// so mutations are seen in the order they were applied.
+    fn on_mutation(&self, mutation: Mutation<'_, N>);
 }
 
-pub type Key = [u8; 8];
+/// State threaded through the descent of a `put`.
+#[derive(Default)]
+struct PutState {
+    created: bool,
+    rightmost_leaf: Option<PageId>,
+    /// Pages allocated before a split touches anything, so that running out
+    /// of buffers can't leave the split half done.
+    new_pages: Vec<(PageId, Arc<RwLock<Buffer>>)>,
+}
 
-pub struct Access<'a> {
+/// Access to a tree whose keys are `N` bytes long.
+pub struct Access<'a, const N: usize = 8> {
     bufmgr: &'a BufferPoolManager,
     pub btree_page_id: PageId,
+    observer: Option<&'a dyn MutationObserver<N>>,
+    fill_factor: u8,
 }
 
-impl<'a> Access<'a> {
+impl<'a, const N: usize> Access<'a, N> {
     pub fn create(bufmgr: &'a BufferPoolManager) -> Result<Self, Error> {
         let (btree_page_id, meta_buffer) = bufmgr.create_page()?;
         let mut rw_meta_buffer = meta_buffer.write_owned();
@@ -66,30 +419,153 @@ impl<'a> Access<'a> {
         };
         let (root_page_id, root_buffer) = bufmgr.create_page()?;
         let mut rw_root_buffer = root_buffer.write_owned();
-        let mut root = node::NodePage::new(rw_root_buffer.page.as_mut()).unwrap();
+        let mut root = node::NodePage::<_, N>::new(rw_root_buffer.page.as_mut()).unwrap();
         let mut leaf = root.initialize_as_leaf();
         leaf.initialize();
+        stamp_leaf(bufmgr, &mut root);
         btree.set_root_page_id(root_page_id);
+        btree.set_rightmost_leaf_page_id(root_page_id);
+        btree.set_key_width(N);
+        rw_root_buffer.mark_dirty();
+        rw_meta_buffer.mark_dirty();
         Ok(Self {
             bufmgr,
             btree_page_id,
+            observer: None,
+            fill_factor: DEFAULT_FILL_FACTOR,
         })
     }
 
+    /// Makes the tree empty by pointing its meta page at a new root leaf.
+    /// The old nodes are abandoned, so this works however damaged they or
+    /// the meta page are. The owner's `extra_page_id` is kept.
+    pub fn reset(&self) -> Result<(), Error> {
+        self.check_writable()?;
+        let mut rw_meta_buffer = self.bufmgr.fetch_page(self.btree_page_id)?.write_owned();
+        let (root_page_id, root_buffer) = self.bufmgr.create_page()?;
+        let mut rw_root_buffer = root_buffer.write_owned();
+        let mut root = node::NodePage::<_, N>::new(rw_root_buffer.page.as_mut()).unwrap();
+        root.initialize_as_leaf().initialize();
+        stamp_leaf(self.bufmgr, &mut root);
+        let extra_page_id = BTreePage {
+            data: &rw_meta_buffer.page[..],
+        }
+        .extra_page_id();
+        rw_meta_buffer.page.fill(0);
+        let mut btree = BTreePage {
+            data: &mut rw_meta_buffer.page[..],
+        };
+        if let Some(extra_page_id) = extra_page_id {
+            btree.set_extra_page_id(extra_page_id);
+        }
+        btree.set_root_page_id(root_page_id);
+        btree.set_rightmost_leaf_page_id(root_page_id);
+        btree.set_key_width(N);
+        rw_root_buffer.mark_dirty();
+        rw_meta_buffer.mark_dirty();
+        Ok(())
+    }
+
+    /// Points the meta page at the tree rooted at `root_page_id`, which must
+    /// be well-formed and used by no other tree from then on. The record
+    /// count is taken from a scan of the new tree, and the old nodes are
+    /// abandoned.
+    #[cfg(any(test, feature = "admin"))]
+    pub fn set_root_page_id(&self, root_page_id: PageId) -> Result<(), Error> {
+        self.check_writable()?;
+        let mut rw_meta_buffer = self.write_meta()?;
+        // The meta page is held, so the scan must not latch it, and no
+        // writer can get in its way.
+        let root_page = self.bufmgr.fetch_page(root_page_id)?.read_owned();
+        let mut iter = self.iter_internal(root_page, None)?;
+        let mut record_count = 0;
+        while iter.next_key()?.is_some() {
+            record_count += 1;
+        }
+        let mut btree = BTreePage {
+            data: &mut rw_meta_buffer.page[..],
+        };
+        btree.set_root_page_id(root_page_id);
+        btree.set_record_count(record_count);
+        // Found again by the next put that reaches it.
+        btree.set_rightmost_leaf_page_id(PageId(0));
+        rw_meta_buffer.mark_dirty();
+        Ok(())
+    }
+
     pub fn open(bufmgr: &'a BufferPoolManager, btree_page_id: PageId) -> Self {
         Self {
             bufmgr,
             btree_page_id,
+            observer: None,
+            fill_factor: DEFAULT_FILL_FACTOR,
+        }
+    }
+
+    /// Reports every mutation made through this `Access` to `observer`.
+    pub fn with_observer(mut self, observer: &'a dyn MutationObserver<N>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Sets the percentage of a full leaf's bytes that stay in it when it
+    /// splits, from 50 to 100. High values pack trees built from increasing
+    /// keys tighter, since the left half of a split never gets new keys.
+    pub fn with_fill_factor(mut self, fill_factor: u8) -> Self {
+        assert!((50..=100).contains(&fill_factor));
+        self.fill_factor = fill_factor;
+        self
+    }
+
+    /// Latches the meta page for reading, failing if the tree was created
+    /// with keys of another width.
+    fn read_meta(&self) -> Result<OwnedRwLockReadGuard<RawRwLock, Buffer>, Error> {
+        let ro_meta_buffer = self.bufmgr.fetch_page(self.btree_page_id)?.read_owned();
+        self.check_key_width(&ro_meta_buffer.page[..])?;
+        Ok(ro_meta_buffer)
+    }
+
+    /// Like `read_meta`, latching the meta page for writing.
+    fn write_meta(&self) -> Result<OwnedRwLockWriteGuard<RawRwLock, Buffer>, Error> {
+        let rw_meta_buffer = self.bufmgr.fetch_page(self.btree_page_id)?.write_owned();
+        self.check_key_width(&rw_meta_buffer.page[..])?;
+        Ok(rw_meta_buffer)
+    }
+
+    fn check_key_width(&self, data: &[u8]) -> Result<(), Error> {
+        match (BTreePage { data }).key_width() {
+            width if width == N => Ok(()),
+            found => Err(Error::KeyWidthMismatch {
+                page_id: self.btree_page_id,
+                expected: N,
+                found,
+            }),
+        }
+    }
+
+    /// Fails before anything is latched, so that no page is modified in a
+    /// pool that can't write it back.
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.bufmgr.is_read_only() {
+            return Err(buffer::Error::ReadOnly.into());
+        }
+        Ok(())
+    }
+
+    fn notify(&self, mutation: Mutation<'_, N>) {
+        if let Some(observer) = self.observer {
+            observer.on_mutation(mutation);
         }
     }
 
     fn get_internal(
         &self,
         ro_node_buffer: OwnedRwLockReadGuard<RawRwLock, Buffer>,
-        key: Key,
+        key: Key<N>,
         buf: &mut Vec<u8>,
     ) -> Result<bool, Error> {
-        let node = node::NodePage::new(ro_node_buffer.page.as_ref()).unwrap();
+        let node = node::NodePage::<_, N>::new(ro_node_buffer.page.as_ref()).unwrap();
+        verify_leaf(self.bufmgr, ro_node_buffer.page_id(), &node)?;
         match node.node() {
             node::Node::Leaf(leaf) => Ok(leaf.get(key).map(|value| buf.extend(value)).is_some()),
             node::Node::Branch(branch) => {
@@ -102,8 +578,8 @@ impl<'a> Access<'a> {
         }
     }
 
-    pub fn get(&self, key: Key, buf: &mut Vec<u8>) -> Result<bool, Error> {
-        let ro_meta_buffer = self.bufmgr.fetch_page(self.btree_page_id)?.read_owned();
+    pub fn get(&self, key: Key<N>, buf: &mut Vec<u8>) -> Result<bool, Error> {
+        let ro_meta_buffer = self.read_meta()?;
         let btree = BTreePage {
             data: &ro_meta_buffer.page[..],
         };
@@ -113,21 +589,141 @@ impl<'a> Access<'a> {
         self.get_internal(ro_root_buffer, key, buf)
     }
 
+    fn leaf_stats_internal(
+        &self,
+        node_page_id: PageId,
+        ro_node_buffer: OwnedRwLockReadGuard<RawRwLock, Buffer>,
+        key: Option<Key<N>>,
+        backward: bool,
+    ) -> Result<LeafStats, Error> {
+        let node = node::NodePage::<_, N>::new(ro_node_buffer.page.as_ref()).unwrap();
+        match node.node() {
+            node::Node::Leaf(leaf) => Ok(LeafStats {
+                page_id: node_page_id,
+                num_slots: leaf.num_records(),
+                free_space: leaf.free_space(),
+                usable_bytes: leaf.usable_bytes(),
+                slot: key.map(|key| leaf.find(key)),
+            }),
+            node::Node::Branch(branch) => {
+                let index = match key {
+                    Some(key) => branch.find(key),
+                    None if backward => branch.num_pairs() - 1,
+                    None => 0,
+                };
+                let child_page_id = branch.pair(index).child();
+                let child_node_page = self.bufmgr.fetch_page(child_page_id)?.read_owned();
+                drop(ro_node_buffer);
+                self.leaf_stats_internal(child_page_id, child_node_page, key, backward)
+            }
+        }
+    }
+
+    /// Describes the leaf that holds `key`, or would if it were put.
+    pub fn leaf_stats(&self, key: Key<N>) -> Result<LeafStats, Error> {
+        self.start_leaf_stats(Some(key), false)
+    }
+
+    /// Describes the leaf that `iter(key)`, or `iter_rev(key)` if `backward`
+    /// is set, starts in.
+    pub fn start_leaf_stats(
+        &self,
+        key: Option<Key<N>>,
+        backward: bool,
+    ) -> Result<LeafStats, Error> {
+        let ro_meta_buffer = self.read_meta()?;
+        let btree = BTreePage {
+            data: &ro_meta_buffer.page[..],
+        };
+        let root_page_id = btree.root_page_id();
+        let ro_root_buffer = self.bufmgr.fetch_page(root_page_id)?.read_owned();
+        drop(ro_meta_buffer);
+        self.leaf_stats_internal(root_page_id, ro_root_buffer, key, backward)
+    }
+
+    fn edge_key_internal(
+        &self,
+        ro_node_buffer: OwnedRwLockReadGuard<RawRwLock, Buffer>,
+        last: bool,
+    ) -> Result<Option<Key<N>>, Error> {
+        let node = node::NodePage::<_, N>::new(ro_node_buffer.page.as_ref()).unwrap();
+        verify_leaf(self.bufmgr, ro_node_buffer.page_id(), &node)?;
+        match node.node() {
+            node::Node::Leaf(leaf) => match leaf.num_records() {
+                0 => Ok(None),
+                n if last => Ok(Some(leaf.record(n - 1).key())),
+                _ => Ok(Some(leaf.record(0).key())),
+            },
+            node::Node::Branch(branch) => {
+                let index = if last { branch.num_pairs() - 1 } else { 0 };
+                let child_page_id = branch.pair(index).child();
+                let child_node_page = self.bufmgr.fetch_page(child_page_id)?.read_owned();
+                drop(ro_node_buffer);
+                self.edge_key_internal(child_node_page, last)
+            }
+        }
+    }
+
+    /// The first key of the leftmost leaf, or of the rightmost if `last`.
+    fn edge_key(&self, last: bool) -> Result<Option<Key<N>>, Error> {
+        let ro_meta_buffer = self.read_meta()?;
+        let btree = BTreePage {
+            data: &ro_meta_buffer.page[..],
+        };
+        let root_page_id = btree.root_page_id();
+        let ro_root_buffer = self.bufmgr.fetch_page(root_page_id)?.read_owned();
+        drop(ro_meta_buffer);
+        self.edge_key_internal(ro_root_buffer, last)
+    }
+
+    /// The smallest key in O(log n), or `None` if the tree is empty.
+    pub fn first_key(&self) -> Result<Option<Key<N>>, Error> {
+        match self.edge_key(false)? {
+            Some(key) => Ok(Some(key)),
+            // Deletes may have emptied the leftmost leaf.
+            None => self.iter(None)?.next(&mut vec![]),
+        }
+    }
+
+    /// The largest key in O(log n), or `None` if the tree is empty.
+    pub fn last_key(&self) -> Result<Option<Key<N>>, Error> {
+        match self.edge_key(true)? {
+            Some(key) => Ok(Some(key)),
+            None => self.iter_rev(None)?.next(&mut vec![]),
+        }
+    }
+
+    /// `last_key` for callers already holding the meta page latch.
+    fn last_key_from(&self, root_page_id: PageId) -> Result<Option<Key<N>>, Error> {
+        let ro_root_buffer = self.bufmgr.fetch_page(root_page_id)?.read_owned();
+        if let Some(key) = self.edge_key_internal(ro_root_buffer, true)? {
+            return Ok(Some(key));
+        }
+        let ro_root_buffer = self.bufmgr.fetch_page(root_page_id)?.read_owned();
+        self.iter_rev_internal(ro_root_buffer, None)?
+            .next(&mut vec![])
+    }
+
     fn iter_internal(
         &self,
         ro_node_buffer: OwnedRwLockReadGuard<RawRwLock, Buffer>,
-        key: Option<Key>,
-    ) -> Result<Iter<'a>, Error> {
-        let node = node::NodePage::new(ro_node_buffer.page.as_ref()).unwrap();
+        key: Option<Key<N>>,
+    ) -> Result<Iter<'a, N>, Error> {
+        let node = node::NodePage::<_, N>::new(ro_node_buffer.page.as_ref()).unwrap();
+        verify_leaf(self.bufmgr, ro_node_buffer.page_id(), &node)?;
         match node.node() {
             node::Node::Leaf(leaf) => {
                 let start = key
                     .map(|key| leaf.find(key).unwrap_or_else(|index| index))
                     .unwrap_or(0);
                 Ok(Iter {
-                    bufmgr: &self.bufmgr,
+                    bufmgr: self.bufmgr,
+                    btree_page_id: self.btree_page_id,
+                    buffer: Some(OwnedRwLockReadGuard::rwlock(&ro_node_buffer).clone()),
                     index: start,
-                    buffer: Some(ro_node_buffer),
+                    last_key: None,
+                    meta_buffer: None,
+                    pages_visited: 1,
                 })
             }
             node::Node::Branch(branch) => {
@@ -140,8 +736,8 @@ impl<'a> Access<'a> {
         }
     }
 
-    pub fn iter(&self, key: Option<Key>) -> Result<Iter<'a>, Error> {
-        let btree_page = self.bufmgr.fetch_page(self.btree_page_id)?.read_owned();
+    pub fn iter(&self, key: Option<Key<N>>) -> Result<Iter<'a, N>, Error> {
+        let btree_page = self.read_meta()?;
         let btree = BTreePage {
             data: &btree_page.page[..],
         };
@@ -151,12 +747,29 @@ impl<'a> Access<'a> {
         self.iter_internal(root_page, key)
     }
 
+    /// Like `iter`, but keeps the meta page read-latched until the iterator is
+    /// dropped. Every `put` holds the meta page write latch for its whole
+    /// duration, so writers to this tree wait for the scan to finish and the
+    /// scan observes a single point in time.
+    pub fn iter_stable(&self, key: Option<Key<N>>) -> Result<Iter<'a, N>, Error> {
+        let ro_meta_buffer = self.read_meta()?;
+        let btree = BTreePage {
+            data: &ro_meta_buffer.page[..],
+        };
+        let root_page_id = btree.root_page_id();
+        let root_page = self.bufmgr.fetch_page(root_page_id)?.read_owned();
+        let mut iter = self.iter_internal(root_page, key)?;
+        iter.meta_buffer = Some(ro_meta_buffer);
+        Ok(iter)
+    }
+
     fn iter_rev_internal(
         &self,
         ro_node_buffer: OwnedRwLockReadGuard<RawRwLock, Buffer>,
-        key: Option<Key>,
-    ) -> Result<IterRev<'a>, Error> {
-        let node = node::NodePage::new(ro_node_buffer.page.as_ref()).unwrap();
+        key: Option<Key<N>>,
+    ) -> Result<IterRev<'a, N>, Error> {
+        let node = node::NodePage::<_, N>::new(ro_node_buffer.page.as_ref()).unwrap();
+        verify_leaf(self.bufmgr, ro_node_buffer.page_id(), &node)?;
         match node.node() {
             node::Node::Leaf(leaf) => {
                 let start = key
@@ -167,9 +780,15 @@ impl<'a> Access<'a> {
                     })
                     .unwrap_or_else(|| leaf.num_records() as isize - 1);
                 Ok(IterRev {
-                    bufmgr: &self.bufmgr,
+                    bufmgr: self.bufmgr,
+                    btree_page_id: self.btree_page_id,
+                    buffer: Some(OwnedRwLockReadGuard::rwlock(&ro_node_buffer).clone()),
+                    next_page_id: leaf.next_page_id(),
                     index: start,
-                    buffer: Some(ro_node_buffer),
+                    start: key,
+                    last_key: None,
+                    meta_buffer: None,
+                    pages_visited: 1,
                 })
             }
             node::Node::Branch(branch) => {
@@ -184,8 +803,8 @@ impl<'a> Access<'a> {
         }
     }
 
-    pub fn iter_rev(&self, key: Option<Key>) -> Result<IterRev<'a>, Error> {
-        let ro_meta_buffer = self.bufmgr.fetch_page(self.btree_page_id)?.read_owned();
+    pub fn iter_rev(&self, key: Option<Key<N>>) -> Result<IterRev<'a, N>, Error> {
+        let ro_meta_buffer = self.read_meta()?;
         let btree = BTreePage {
             data: &ro_meta_buffer.page[..],
         };
@@ -195,72 +814,163 @@ impl<'a> Access<'a> {
         self.iter_rev_internal(root_page, key)
     }
 
-    fn put_internal(
+    /// Reverse counterpart of `iter_stable`.
+    pub fn iter_rev_stable(&self, key: Option<Key<N>>) -> Result<IterRev<'a, N>, Error> {
+        let ro_meta_buffer = self.read_meta()?;
+        let btree = BTreePage {
+            data: &ro_meta_buffer.page[..],
+        };
+        let root_page_id = btree.root_page_id();
+        let root_page = self.bufmgr.fetch_page(root_page_id)?.read_owned();
+        let mut iter = self.iter_rev_internal(root_page, key)?;
+        iter.meta_buffer = Some(ro_meta_buffer);
+        Ok(iter)
+    }
+
+    /// Returns the record bounding `key` in `direction` in O(log n), or `None`
+    /// if there is no such record.
+    pub fn seek(
+        &self,
+        key: Key<N>,
+        direction: Direction,
+    ) -> Result<Option<(Key<N>, Vec<u8>)>, Error> {
+        let mut buf = vec![];
+        let found = match direction {
+            Direction::Floor => self.iter_rev(Some(key))?.next(&mut buf)?,
+            Direction::Ceiling => self.iter(Some(key))?.next(&mut buf)?,
+        };
+        Ok(found.map(|key| (key, buf)))
+    }
+
+    /// Puts the value `new_value` makes of the current one, if any, into the
+    /// leaf `key` belongs to. Nothing changes if it returns `None`.
+    fn put_internal<'v>(
         &self,
         node_page_id: PageId,
         mut rw_node_buffer: OwnedRwLockWriteGuard<RawRwLock, Buffer>,
-        key: Key,
-        value: &[u8],
-    ) -> Result<Option<(Key, PageId)>, Error> {
-        let mut node = node::NodePage::new(rw_node_buffer.page.as_mut()).unwrap();
+        key: Key<N>,
+        new_value: impl FnOnce(Option<&[u8]>) -> Option<Cow<'v, [u8]>>,
+        ancestor_split_pages: usize,
+        state: &mut PutState,
+    ) -> Result<Option<(Key<N>, PageId)>, Error> {
+        let mut node = node::NodePage::<_, N>::new(rw_node_buffer.page.as_mut()).unwrap();
+        verify_leaf(self.bufmgr, node_page_id, &node)?;
         match node.node_mut() {
             node::Node::Leaf(mut leaf) => {
+                let current = leaf.get(key);
+                state.created = current.is_none();
+                let value = match new_value(current) {
+                    Some(value) => value,
+                    None => {
+                        state.created = false;
+                        return Ok(None);
+                    }
+                };
+                check_value_size::<N>(&value)?;
+                let value = &value[..];
                 if leaf.put(key, value) {
-                    rw_node_buffer.is_dirty = true;
+                    if leaf.next_page_id().is_none() {
+                        state.rightmost_leaf = Some(node_page_id);
+                    }
+                    stamp_leaf(self.bufmgr, &mut node);
+                    self.notify(Mutation::Put { key, value });
+                    rw_node_buffer.mark_dirty();
                     Ok(None)
                 } else {
                     let next_leaf_page_id = leaf.next_page_id();
+                    // Waiting for the next leaf while holding this one can't
+                    // deadlock, as no one latches a leaf while holding one to
+                    // its right: latches are taken down the tree and, among
+                    // leaves, left to right only.
                     let next_leaf_page = next_leaf_page_id
-                        .map(|next_leaf_page_id| {
-                            self.bufmgr
-                                .fetch_page(next_leaf_page_id)?
-                                .try_write_owned()
-                                .map(Ok)
-                                .unwrap_or(Err(Error::Deadlock))
+                        .map(|next_leaf_page_id| -> Result<_, Error> {
+                            let rw_next_leaf_buffer =
+                                self.bufmgr.fetch_page(next_leaf_page_id)?.write_owned();
+                            let node_page =
+                                node::NodePage::<_, N>::new(rw_next_leaf_buffer.page.as_ref())
+                                    .unwrap();
+                            verify_leaf(self.bufmgr, next_leaf_page_id, &node_page)?;
+                            Ok(rw_next_leaf_buffer)
                         })
                         .transpose()?;
 
-                    let (new_leaf_page_id, new_leaf_page) = self.bufmgr.create_page()?;
-
-                    if let Some(mut rw_next_leaf_buffer) = next_leaf_page {
-                        let mut node_page =
-                            node::NodePage::new(rw_next_leaf_buffer.page.as_mut()).unwrap();
-                        let mut next_leaf = node_page.node_mut().try_into_leaf().ok().unwrap();
-                        next_leaf.set_prev_page_id(Some(new_leaf_page_id));
+                    // If this fails, the pages allocated so far are leaked but
+                    // the tree is left untouched.
+                    for _ in 0..=ancestor_split_pages {
+                        state.new_pages.push(self.bufmgr.create_page()?);
                     }
-                    leaf.set_next_page_id(Some(new_leaf_page_id));
+                    let (new_leaf_page_id, new_leaf_page) = state.new_pages.pop().unwrap();
 
+                    // The neighbors are only linked to the new leaf once it
+                    // holds its records and links of its own.
                     let mut rw_new_leaf_buffer = new_leaf_page.write_owned();
                     let mut new_leaf_node_page =
-                        node::NodePage::new(rw_new_leaf_buffer.page.as_mut()).unwrap();
+                        node::NodePage::<_, N>::new(rw_new_leaf_buffer.page.as_mut()).unwrap();
                     let mut new_leaf = new_leaf_node_page.initialize_as_leaf();
                     new_leaf.initialize();
-                    let new_leaf_first_key = leaf.split_put(&mut new_leaf, key, value);
+                    let new_leaf_first_key =
+                        leaf.split_put(&mut new_leaf, key, value, self.fill_factor);
                     new_leaf.set_prev_page_id(Some(node_page_id));
                     new_leaf.set_next_page_id(next_leaf_page_id);
-                    rw_node_buffer.is_dirty = true;
+
+                    if let Some(mut rw_next_leaf_buffer) = next_leaf_page {
+                        let mut node_page =
+                            node::NodePage::<_, N>::new(rw_next_leaf_buffer.page.as_mut()).unwrap();
+                        let mut next_leaf = node_page.node_mut().try_into_leaf().ok().unwrap();
+                        next_leaf.set_prev_page_id(Some(new_leaf_page_id));
+                        stamp_leaf(self.bufmgr, &mut node_page);
+                        rw_next_leaf_buffer.mark_dirty();
+                    }
+                    leaf.set_next_page_id(Some(new_leaf_page_id));
+                    stamp_leaf(self.bufmgr, &mut new_leaf_node_page);
+                    stamp_leaf(self.bufmgr, &mut node);
+                    rw_new_leaf_buffer.mark_dirty();
+                    if next_leaf_page_id.is_none() {
+                        state.rightmost_leaf = Some(new_leaf_page_id);
+                    }
+                    self.notify(Mutation::Split {
+                        page_id: node_page_id,
+                        new_page_id: new_leaf_page_id,
+                    });
+                    self.notify(Mutation::Put { key, value });
+                    rw_node_buffer.mark_dirty();
                     Ok(Some((new_leaf_first_key, new_leaf_page_id)))
                 }
             }
             node::Node::Branch(mut branch) => {
                 let index = branch.find(key);
                 let child_page_id = branch.pair(index).child();
+                let split_pages = if branch.max_pairs() <= branch.num_pairs() + 1 {
+                    ancestor_split_pages + 1
+                } else {
+                    0
+                };
                 let child_node_page = self.bufmgr.fetch_page(child_page_id)?.write_owned();
-                if let Some((key, child)) =
-                    self.put_internal(child_page_id, child_node_page, key, value)?
-                {
+                if let Some((key, child)) = self.put_internal(
+                    child_page_id,
+                    child_node_page,
+                    key,
+                    new_value,
+                    split_pages,
+                    state,
+                )? {
                     branch.insert(index + 1, key, child);
                     if branch.max_pairs() <= branch.num_pairs() {
-                        let (new_branch_page_id, new_branch_page) = self.bufmgr.create_page()?;
+                        let (new_branch_page_id, new_branch_page) = state.new_pages.pop().unwrap();
                         let mut rw_new_branch_buffer = new_branch_page.write_owned();
                         let mut new_branch_node_page =
                             node::NodePage::new(rw_new_branch_buffer.page.as_mut()).unwrap();
                         let mut new_branch = new_branch_node_page.initialize_as_branch();
                         let overflow_key = branch.split(&mut new_branch);
-                        rw_node_buffer.is_dirty = true;
+                        rw_new_branch_buffer.mark_dirty();
+                        self.notify(Mutation::Split {
+                            page_id: node_page_id,
+                            new_page_id: new_branch_page_id,
+                        });
+                        rw_node_buffer.mark_dirty();
                         Ok(Some((overflow_key, new_branch_page_id)))
                     } else {
-                        rw_node_buffer.is_dirty = true;
+                        rw_node_buffer.mark_dirty();
                         Ok(None)
                     }
                 } else {
@@ -270,94 +980,859 @@ impl<'a> Access<'a> {
         }
     }
 
-    pub fn put(&self, key: Key, value: &[u8]) -> Result<(), Error> {
-        let mut rw_meta_buffer = self.bufmgr.fetch_page(self.btree_page_id)?.write_owned();
-        let mut btree = BTreePage {
-            data: &mut rw_meta_buffer.page[..],
+    /// Appends `key` to the rightmost leaf without descending from the root.
+    /// Returns `false` if `key` is not greater than every key in the leaf or
+    /// the leaf would have to split.
+    fn try_append(&self, leaf_page_id: PageId, key: Key<N>, value: &[u8]) -> Result<bool, Error> {
+        let mut rw_leaf_buffer = self.bufmgr.fetch_page(leaf_page_id)?.write_owned();
+        let mut node = node::NodePage::<_, N>::new(rw_leaf_buffer.page.as_mut()).unwrap();
+        verify_leaf(self.bufmgr, leaf_page_id, &node)?;
+        let mut leaf = match node.node_mut().try_into_leaf() {
+            Ok(leaf) => leaf,
+            Err(_) => return Ok(false),
         };
-        let root_page_id = btree.root_page_id();
-        let root_page = self.bufmgr.fetch_page(root_page_id)?.write_owned();
-        if let Some((key, child)) = self.put_internal(root_page_id, root_page, key, value)? {
-            let (new_root_page_id, new_root_page) = self.bufmgr.create_page()?;
-            let mut new_root_page = new_root_page.write_owned();
-            let mut node_page = node::NodePage::new(new_root_page.page.as_mut()).unwrap();
-            let mut branch = node_page.initialize_as_branch();
-            branch.initialize(key, root_page_id, child);
-            btree.set_root_page_id(new_root_page_id);
-            rw_meta_buffer.is_dirty = true;
+        let num_records = leaf.num_records();
+        if leaf.next_page_id().is_some()
+            || num_records == 0
+            || leaf.record(num_records - 1).key() >= key
+            || !leaf.put(key, value)
+        {
+            return Ok(false);
         }
+        stamp_leaf(self.bufmgr, &mut node);
+        self.notify(Mutation::Put { key, value });
+        rw_leaf_buffer.mark_dirty();
+        Ok(true)
+    }
+
+    /// Inserts or replaces the value of `key`. Returns `true` if the key was
+    /// newly created.
+    ///
+    /// Keys greater than every key in the tree are appended straight to the
+    /// rightmost leaf, whose page id is cached in the meta page, so
+    /// append-only workloads skip the descent from the root.
+    ///
+    /// A put keeps up to `2h + 3` pages pinned at once, `h` being the height
+    /// of the tree (1 for a lone root leaf). If the pool runs out of buffers,
+    /// `buffer::Error::NoFreeBuffer` is returned before the tree is modified.
+    ///
+    /// Values longer than `max_value_size::<N>()` are rejected with
+    /// `Error::ValueTooLarge`.
+    pub fn put(&self, key: Key<N>, value: &[u8]) -> Result<bool, Error> {
+        self.put_checked(key, value, false)
+    }
+
+    /// Like `put`, but fails with `Error::KeyNotIncreasing` unless `key` is
+    /// greater than every key in the tree. The check costs nothing extra
+    /// when the key fits in the rightmost leaf.
+    pub fn append(&self, key: Key<N>, value: &[u8]) -> Result<(), Error> {
+        self.put_checked(key, value, true)?;
         Ok(())
     }
-}
 
-pub struct Iter<'a> {
-    bufmgr: &'a BufferPoolManager,
-    buffer: Option<OwnedRwLockReadGuard<RawRwLock, Buffer>>,
-    index: usize,
-}
-impl<'a> Iter<'a> {
-    pub fn next(&mut self, buf: &mut Vec<u8>) -> Result<Option<Key>, Error> {
-        if let Some(ro_buffer) = &self.buffer {
-            let node_page = node::NodePage::new(ro_buffer.page.as_ref()).unwrap();
-            let leaf = node_page.node().try_into_leaf().ok().unwrap();
-            if self.index < leaf.num_records() {
-                let record = leaf.record(self.index);
-                self.index += 1;
-                buf.extend(record.value);
-                Ok(Some(record.key()))
-            } else {
-                self.buffer = match leaf.next_page_id() {
-                    Some(next_page_id) => Some(self.bufmgr.fetch_page(next_page_id)?.read_owned()),
-                    None => None,
-                };
-                self.index = 0;
-                self.next(buf)
+    fn put_checked(&self, key: Key<N>, value: &[u8], append_only: bool) -> Result<bool, Error> {
+        check_value_size::<N>(value)?;
+        self.check_writable()?;
+        let mut rw_meta_buffer = self.write_meta()?;
+        let mut btree = BTreePage {
+            data: &mut rw_meta_buffer.page[..],
+        };
+        if let Some(leaf_page_id) = btree.rightmost_leaf_page_id() {
+            if self.try_append(leaf_page_id, key, value)? {
+                btree.set_record_count(btree.record_count() + 1);
+                rw_meta_buffer.mark_dirty();
+                return Ok(true);
             }
-        } else {
-            Ok(None)
         }
+        if append_only && self.last_key_from(btree.root_page_id())? >= Some(key) {
+            return Err(Error::KeyNotIncreasing);
+        }
+        self.put_from_root(&mut rw_meta_buffer, key, |_| Some(Cow::Borrowed(value)))
     }
-}
 
-pub struct IterRev<'a> {
-    bufmgr: &'a BufferPoolManager,
-    buffer: Option<OwnedRwLockReadGuard<RawRwLock, Buffer>>,
-    index: isize,
-}
-impl<'a> IterRev<'a> {
-    pub fn next(&mut self, buf: &mut Vec<u8>) -> Result<Option<Key>, Error> {
-        if let Some(ro_buffer) = &self.buffer {
-            let node_page = node::NodePage::new(ro_buffer.page.as_ref()).unwrap();
-            let leaf = node_page.node().try_into_leaf().ok().unwrap();
-            if self.index >= 0 {
-                let record = leaf.record(self.index as usize);
-                self.index -= 1;
-                buf.extend(record.value);
-                Ok(Some(record.key()))
-            } else {
-                self.buffer = match leaf.prev_page_id() {
-                    Some(prev_page_id) => {
-                        let ro_prev_buffer = self.bufmgr.fetch_page(prev_page_id)?.read_owned();
-                        let prev_node_page = node::NodePage::new(ro_prev_buffer.page.as_ref()).unwrap();
-                        let leaf = prev_node_page.node().try_into_leaf().ok().unwrap();
-                        self.index = leaf.num_records() as isize - 1;
-                        Some(ro_prev_buffer)
-                    }
-                    None => None,
-                };
-                self.next(buf)
+    /// Replaces the value of `key`, or creates it, with what `update` makes
+    /// of the current value, or of `None` if there is none. `update` runs
+    /// under the leaf's write latch, so the value is read and resized in
+    /// place in a single descent, and the leaf splits if it no longer fits.
+    /// Nothing changes if `update` returns `None`. Returns `true` if the key
+    /// was created.
+    ///
+    /// As with `put`, keys greater than every key in the tree go straight to
+    /// the rightmost leaf.
+    pub fn update(
+        &self,
+        key: Key<N>,
+        update: impl FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<bool, Error> {
+        self.check_writable()?;
+        let mut rw_meta_buffer = self.write_meta()?;
+        let mut btree = BTreePage {
+            data: &mut rw_meta_buffer.page[..],
+        };
+        let leaf_page_id = match btree.rightmost_leaf_page_id() {
+            Some(leaf_page_id) if self.is_past_end(leaf_page_id, key)? => leaf_page_id,
+            _ => {
+                return self.put_from_root(&mut rw_meta_buffer, key, |current| {
+                    update(current).map(Cow::Owned)
+                })
             }
-        } else {
-            Ok(None)
+        };
+        // The key is absent, as the meta page's latch holds off other writers.
+        let value = match update(None) {
+            Some(value) => value,
+            None => return Ok(false),
+        };
+        check_value_size::<N>(&value)?;
+        if self.try_append(leaf_page_id, key, &value)? {
+            btree.set_record_count(btree.record_count() + 1);
+            rw_meta_buffer.mark_dirty();
+            return Ok(true);
         }
+        self.put_from_root(&mut rw_meta_buffer, key, |_| Some(Cow::Owned(value)))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use tempfile::tempfile;
+    /// Whether `key` is greater than every key in the rightmost leaf
+    /// `leaf_page_id`, and so in the tree. See `try_append`.
+    fn is_past_end(&self, leaf_page_id: PageId, key: Key<N>) -> Result<bool, Error> {
+        let ro_leaf_buffer = self.bufmgr.fetch_page(leaf_page_id)?.read_owned();
+        let node = node::NodePage::<_, N>::new(ro_leaf_buffer.page.as_ref()).unwrap();
+        verify_leaf(self.bufmgr, leaf_page_id, &node)?;
+        let leaf = match node.node().try_into_leaf() {
+            Ok(leaf) => leaf,
+            Err(_) => return Ok(false),
+        };
+        let num_records = leaf.num_records();
+        Ok(leaf.next_page_id().is_none()
+            && num_records > 0
+            && leaf.record(num_records - 1).key() < key)
+    }
+
+    /// Descends from the root to put the value `new_value` makes, with the
+    /// meta page held.
+    fn put_from_root<'v>(
+        &self,
+        rw_meta_buffer: &mut OwnedRwLockWriteGuard<RawRwLock, Buffer>,
+        key: Key<N>,
+        new_value: impl FnOnce(Option<&[u8]>) -> Option<Cow<'v, [u8]>>,
+    ) -> Result<bool, Error> {
+        let mut btree = BTreePage {
+            data: &mut rw_meta_buffer.page[..],
+        };
+        let root_page_id = btree.root_page_id();
+        let root_page = self.bufmgr.fetch_page(root_page_id)?.write_owned();
+        let mut state = PutState::default();
+        // A split of the root takes a page for the new root.
+        let split = self.put_internal(root_page_id, root_page, key, new_value, 1, &mut state)?;
+        let created = state.created;
+        let rightmost_leaf = state
+            .rightmost_leaf
+            .filter(|&leaf_page_id| btree.rightmost_leaf_page_id() != Some(leaf_page_id));
+        if let Some(leaf_page_id) = rightmost_leaf {
+            btree.set_rightmost_leaf_page_id(leaf_page_id);
+        }
+        if let Some((key, child)) = split {
+            let (new_root_page_id, new_root_page) = state.new_pages.pop().unwrap();
+            let mut new_root_page = new_root_page.write_owned();
+            let mut node_page = node::NodePage::<_, N>::new(new_root_page.page.as_mut()).unwrap();
+            let mut branch = node_page.initialize_as_branch();
+            branch.initialize(key, root_page_id, child);
+            new_root_page.mark_dirty();
+            btree.set_root_page_id(new_root_page_id);
+            self.notify(Mutation::NewRoot {
+                page_id: new_root_page_id,
+            });
+        }
+        if created {
+            btree.set_record_count(btree.record_count() + 1);
+        }
+        if split.is_some() || created || rightmost_leaf.is_some() {
+            rw_meta_buffer.mark_dirty();
+        }
+        Ok(created)
+    }
+
+    fn delete_internal(
+        &self,
+        mut rw_node_buffer: OwnedRwLockWriteGuard<RawRwLock, Buffer>,
+        key: Key<N>,
+        matches: impl FnOnce(&[u8]) -> bool,
+    ) -> Result<bool, Error> {
+        let page_id = rw_node_buffer.page_id();
+        let mut node = node::NodePage::<_, N>::new(rw_node_buffer.page.as_mut()).unwrap();
+        verify_leaf(self.bufmgr, page_id, &node)?;
+        match node.node_mut() {
+            node::Node::Leaf(mut leaf) => {
+                let found = match leaf.find(key) {
+                    Ok(index) => matches(leaf.record(index).value) && leaf.remove(key),
+                    Err(_) => false,
+                };
+                if found {
+                    stamp_leaf(self.bufmgr, &mut node);
+                    self.notify(Mutation::Delete { key });
+                    rw_node_buffer.mark_dirty();
+                }
+                Ok(found)
+            }
+            node::Node::Branch(branch) => {
+                let index = branch.find(key);
+                let child_page_id = branch.pair(index).child();
+                let child_node_page = self.bufmgr.fetch_page(child_page_id)?.write_owned();
+                drop(rw_node_buffer);
+                self.delete_internal(child_node_page, key, matches)
+            }
+        }
+    }
+
+    /// Removes `key` from the tree. Returns `true` if the key was present.
+    ///
+    /// Leaves are never merged, so a tree with many deletions may contain
+    /// sparsely populated or empty leaves.
+    pub fn delete(&self, key: Key<N>) -> Result<bool, Error> {
+        self.delete_if(key, |_| true)
+    }
+
+    /// Like `delete`, but only removes `key` if `matches` accepts its value,
+    /// which is checked under the same leaf write latch. Returns `true` if
+    /// the key was removed.
+    pub fn delete_if(
+        &self,
+        key: Key<N>,
+        matches: impl FnOnce(&[u8]) -> bool,
+    ) -> Result<bool, Error> {
+        self.check_writable()?;
+        let mut rw_meta_buffer = self.write_meta()?;
+        let mut btree = BTreePage {
+            data: &mut rw_meta_buffer.page[..],
+        };
+        let root_page_id = btree.root_page_id();
+        let root_page = self.bufmgr.fetch_page(root_page_id)?.write_owned();
+        let found = self.delete_internal(root_page, key, matches)?;
+        if found {
+            btree.set_record_count(btree.record_count().saturating_sub(1));
+            rw_meta_buffer.mark_dirty();
+        }
+        Ok(found)
+    }
+
+    /// Removes every key in `[start, end)`. Returns how many were removed.
+    ///
+    /// The leaves covering the range are visited along the leaf chain, each
+    /// one pruned under a single write latch. As with `delete`, emptied
+    /// leaves are left in place.
+    pub fn delete_range(&self, start: Key<N>, end: Key<N>) -> Result<u64, Error> {
+        if start >= end {
+            return Ok(0);
+        }
+        self.check_writable()?;
+        let mut rw_meta_buffer = self.write_meta()?;
+        let mut btree = BTreePage {
+            data: &mut rw_meta_buffer.page[..],
+        };
+        let root_page_id = btree.root_page_id();
+        let mut rw_node_buffer = self.bufmgr.fetch_page(root_page_id)?.write_owned();
+        let mut removed = 0;
+        loop {
+            let page_id = rw_node_buffer.page_id();
+            let mut node = node::NodePage::<_, N>::new(rw_node_buffer.page.as_mut()).unwrap();
+            verify_leaf(self.bufmgr, page_id, &node)?;
+            let (next_page_id, leaf_removed) = match node.node_mut() {
+                node::Node::Leaf(mut leaf) => {
+                    let leaf_removed = leaf.remove_range(start, end);
+                    let past_end = leaf
+                        .num_records()
+                        .checked_sub(1)
+                        .is_some_and(|last| leaf.record(last).key() >= end);
+                    let next_page_id = if past_end { None } else { leaf.next_page_id() };
+                    (next_page_id, leaf_removed)
+                }
+                node::Node::Branch(branch) => {
+                    let index = branch.find(start);
+                    (Some(branch.pair(index).child()), 0)
+                }
+            };
+            if leaf_removed > 0 {
+                stamp_leaf(self.bufmgr, &mut node);
+                rw_node_buffer.mark_dirty();
+                removed += leaf_removed as u64;
+            }
+            match next_page_id {
+                Some(page_id) => rw_node_buffer = self.bufmgr.fetch_page(page_id)?.write_owned(),
+                None => break,
+            }
+        }
+        if removed > 0 {
+            self.notify(Mutation::DeleteRange { start, end });
+            btree.set_record_count(btree.record_count().saturating_sub(removed));
+            rw_meta_buffer.mark_dirty();
+        }
+        Ok(removed)
+    }
+
+    /// Copies every record into a new tree with fully packed leaves. This
+    /// tree is left as it is.
+    pub fn rebuild(&self) -> Result<Access<'a, N>, Error> {
+        self.rebuild_into(self.bufmgr)
+    }
+
+    /// Like `rebuild`, but the new tree is created in `bufmgr`, such as that
+    /// of another data file.
+    pub fn rebuild_into<'b>(&self, bufmgr: &'b BufferPoolManager) -> Result<Access<'b, N>, Error> {
+        let new_tree = Access::create(bufmgr)?.with_fill_factor(100);
+        let mut iter = self.iter(None)?;
+        let mut buf = vec![];
+        while let Some(key) = iter.next(&mut buf)? {
+            new_tree.put(key, &buf)?;
+            buf.clear();
+        }
+        Ok(Access::open(bufmgr, new_tree.btree_page_id))
+    }
+
+    /// Reads the leaves holding the keys from `start` up to `end` into the
+    /// buffer pool, stopping after `max_leaves`. Returns the leaves read.
+    pub fn warm(
+        &self,
+        start: Option<Key<N>>,
+        end: Option<Key<N>>,
+        max_leaves: u64,
+    ) -> Result<u64, Error> {
+        if max_leaves == 0 {
+            return Ok(0);
+        }
+        let mut iter = self.iter(start)?;
+        while let Some(key) = iter.next_key()? {
+            if end.is_some_and(|end| key >= end) || iter.pages_visited() >= max_leaves {
+                break;
+            }
+        }
+        Ok(iter.pages_visited())
+    }
+
+    /// Counts the leaves by walking the leaf chain, empty ones included.
+    pub fn leaf_count(&self) -> Result<u64, Error> {
+        let mut iter = self.iter(None)?;
+        let mut buf = vec![];
+        while iter.next(&mut buf)?.is_some() {
+            buf.clear();
+        }
+        Ok(iter.pages_visited())
+    }
+
+    /// A page id the tree's owner keeps in the meta page. The tree itself
+    /// makes no use of it.
+    pub fn extra_page_id(&self) -> Result<Option<PageId>, Error> {
+        let ro_meta_buffer = self.read_meta()?;
+        let btree = BTreePage {
+            data: &ro_meta_buffer.page[..],
+        };
+        Ok(btree.extra_page_id())
+    }
+
+    pub fn set_extra_page_id(&self, page_id: PageId) -> Result<(), Error> {
+        self.check_writable()?;
+        let mut rw_meta_buffer = self.write_meta()?;
+        let mut btree = BTreePage {
+            data: &mut rw_meta_buffer.page[..],
+        };
+        btree.set_extra_page_id(page_id);
+        rw_meta_buffer.mark_dirty();
+        Ok(())
+    }
+
+    /// Up to `MAX_NOTE_LEN` bytes the tree's owner keeps in the meta page,
+    /// such as an operation to finish after a crash. Like `extra_page_id`,
+    /// the tree makes no use of it.
+    pub fn note(&self) -> Result<Option<Vec<u8>>, Error> {
+        let ro_meta_buffer = self.read_meta()?;
+        let btree = BTreePage {
+            data: &ro_meta_buffer.page[..],
+        };
+        Ok(btree.note().map(<[u8]>::to_vec))
+    }
+
+    /// Replaces the note, an empty one clearing it. Panics if `note` is
+    /// longer than `MAX_NOTE_LEN`.
+    pub fn set_note(&self, note: &[u8]) -> Result<(), Error> {
+        self.check_writable()?;
+        let mut rw_meta_buffer = self.write_meta()?;
+        let mut btree = BTreePage {
+            data: &mut rw_meta_buffer.page[..],
+        };
+        btree.set_note(note);
+        rw_meta_buffer.mark_dirty();
+        Ok(())
+    }
+
+    /// The page descents start from, which changes as the root splits.
+    pub fn root_page_id(&self) -> Result<PageId, Error> {
+        let ro_meta_buffer = self.read_meta()?;
+        let btree = BTreePage {
+            data: &ro_meta_buffer.page[..],
+        };
+        Ok(btree.root_page_id())
+    }
+
+    /// Returns the number of records in the tree in O(1).
+    pub fn count(&self) -> Result<u64, Error> {
+        let ro_meta_buffer = self.read_meta()?;
+        let btree = BTreePage {
+            data: &ro_meta_buffer.page[..],
+        };
+        Ok(btree.record_count())
+    }
+
+    /// Walks the whole tree, collecting every flaw in its structure instead
+    /// of failing on the first. Stamped leaves are checked against their
+    /// checksums whether or not `bufmgr` verifies leaves. Nothing may write
+    /// to the tree meanwhile.
+    pub fn verify(&self) -> Result<Verification, Error> {
+        let mut verifier = Verifier::<N> {
+            bufmgr: self.bufmgr,
+            num_pages: self.bufmgr.disk_usage()?.allocated_pages,
+            verification: Verification::default(),
+            leaves: vec![],
+        };
+        if !verifier.reach(self.btree_page_id, self.btree_page_id) {
+            return Ok(verifier.verification);
+        }
+        let (root_page_id, rightmost_leaf_page_id) = {
+            let ro_meta_buffer = self.read_meta()?;
+            let btree = BTreePage {
+                data: &ro_meta_buffer.page[..],
+            };
+            (btree.root_page_id(), btree.rightmost_leaf_page_id())
+        };
+        verifier.visit(self.btree_page_id, root_page_id, None, None)?;
+        verifier.check_links();
+        // Only checked in an otherwise sound tree, as any flaw above would
+        // likely throw it off too.
+        let last_leaf = verifier.leaves.last().map(|&(page_id, _)| page_id);
+        if verifier.verification.problems.is_empty()
+            && rightmost_leaf_page_id.is_some_and(|page_id| Some(page_id) != last_leaf)
+        {
+            verifier.report(Problem::BrokenLink {
+                page_id: self.btree_page_id,
+            });
+        }
+        Ok(verifier.verification)
+    }
+}
+
+fn is_sorted<const N: usize>(keys: &[Key<N>]) -> bool {
+    keys.windows(2).all(|pair| pair[0] < pair[1])
+}
+
+/// State of `Access::verify`.
+struct Verifier<'a, const N: usize> {
+    bufmgr: &'a BufferPoolManager,
+    num_pages: u64,
+    verification: Verification,
+    /// Leaves in key order, with their sibling links if they could be read.
+    leaves: Vec<(PageId, Option<SiblingLinks>)>,
+}
+
+/// The previous and next page ids of a leaf.
+type SiblingLinks = (Option<PageId>, Option<PageId>);
+
+impl<const N: usize> Verifier<'_, N> {
+    fn report(&mut self, problem: Problem) {
+        self.verification.problems.push(problem);
+    }
+
+    /// Adds `page_id`, linked to from `from`, to the pages of the tree.
+    /// Returns `false` and reports a broken link if it can't be one of them.
+    fn reach(&mut self, from: PageId, page_id: PageId) -> bool {
+        if page_id.0 >= self.num_pages || !self.verification.pages.insert(page_id) {
+            self.report(Problem::BrokenLink { page_id: from });
+            return false;
+        }
+        true
+    }
+
+    /// Checks the subtree at `page_id`, whose keys must lie in `[low, high)`.
+    fn visit(
+        &mut self,
+        parent_page_id: PageId,
+        page_id: PageId,
+        low: Option<Key<N>>,
+        high: Option<Key<N>>,
+    ) -> Result<(), Error> {
+        if !self.reach(parent_page_id, page_id) {
+            return Ok(());
+        }
+        let in_range =
+            |key: Key<N>| low.is_none_or(|low| low <= key) && high.is_none_or(|high| key < high);
+        let children = {
+            let buffer = self.bufmgr.fetch_page(page_id)?;
+            let ro_buffer = buffer.read();
+            let node_page = node::NodePage::<_, N>::new(ro_buffer.page.as_ref()).unwrap();
+            if !node_page.is_node() {
+                self.report(Problem::Malformed { page_id });
+                return Ok(());
+            }
+            match node_page.node() {
+                node::Node::Leaf(leaf) => {
+                    if !leaf.is_readable() {
+                        self.report(Problem::Malformed { page_id });
+                        self.leaves.push((page_id, None));
+                        return Ok(());
+                    }
+                    let checksum = node_page.checksum();
+                    if checksum != 0 && checksum != node_page.compute_checksum() {
+                        self.report(Problem::ChecksumMismatch { page_id });
+                    }
+                    let keys = leaf
+                        .iter_records()
+                        .map(|record| record.key())
+                        .collect::<Vec<_>>();
+                    if !is_sorted(&keys) || !keys.into_iter().all(in_range) {
+                        self.report(Problem::Unsorted { page_id });
+                    }
+                    let links = (leaf.prev_page_id(), leaf.next_page_id());
+                    self.leaves.push((page_id, Some(links)));
+                    return Ok(());
+                }
+                node::Node::Branch(branch) => {
+                    if !branch.is_readable() {
+                        self.report(Problem::Malformed { page_id });
+                        return Ok(());
+                    }
+                    branch.iter_pairs().collect::<Vec<_>>()
+                }
+            }
+        };
+        // The first key is unused, so the first child starts at `low`.
+        let keys = children[1..]
+            .iter()
+            .map(|&(key, _)| key)
+            .collect::<Vec<_>>();
+        if !is_sorted(&keys) || !keys.iter().copied().all(in_range) {
+            self.report(Problem::Unsorted { page_id });
+        }
+        for (index, &(_, child)) in children.iter().enumerate() {
+            let child_low = if index == 0 {
+                low
+            } else {
+                Some(keys[index - 1])
+            };
+            let child_high = keys.get(index).copied().or(high);
+            self.visit(page_id, child, child_low, child_high)?;
+        }
+        Ok(())
+    }
+
+    /// Reports the leaves whose sibling links don't match the order they
+    /// were reached in from the root.
+    fn check_links(&mut self) {
+        let mut broken = vec![];
+        for (index, &(page_id, links)) in self.leaves.iter().enumerate() {
+            let prev = index.checked_sub(1).map(|index| self.leaves[index].0);
+            let next = self.leaves.get(index + 1).map(|&(page_id, _)| page_id);
+            if links.is_some_and(|links| links != (prev, next)) {
+                broken.push(Problem::BrokenLink { page_id });
+            }
+        }
+        self.verification.problems.extend(broken);
+    }
+}
+
+/// Forward iterator over a tree. The current leaf is pinned but only latched
+/// within `next`, so writers may modify it between calls. Moving on to the
+/// next leaf latches it before the current one is released, in the same
+/// left-to-right order as writers. The iterator
+/// resumes from the last returned key, re-seeking from the root when the
+/// leaf chain no longer leads to its successor. Every key present for the
+/// whole scan is returned exactly once, in ascending order; keys inserted
+/// or deleted meanwhile may or may not be.
+pub struct Iter<'a, const N: usize = 8> {
+    bufmgr: &'a BufferPoolManager,
+    btree_page_id: PageId,
+    buffer: Option<Arc<RwLock<Buffer>>>,
+    index: usize,
+    last_key: Option<Key<N>>,
+    meta_buffer: Option<OwnedRwLockReadGuard<RawRwLock, Buffer>>,
+    pages_visited: u64,
+}
+impl<'a, const N: usize> Iter<'a, N> {
+    /// Number of leaves the iterator has moved through so far, counting a
+    /// leaf again if it had to re-seek to it.
+    pub fn pages_visited(&self) -> u64 {
+        self.pages_visited
+    }
+
+    pub fn next(&mut self, buf: &mut Vec<u8>) -> Result<Option<Key<N>>, Error> {
+        self.advance(Some(buf))
+    }
+
+    /// Like `next`, but leaves the value unread.
+    pub fn next_key(&mut self) -> Result<Option<Key<N>>, Error> {
+        self.advance(None)
+    }
+
+    fn advance(&mut self, buf: Option<&mut Vec<u8>>) -> Result<Option<Key<N>>, Error> {
+        while let Some(buffer) = self.buffer.clone() {
+            let ro_buffer = buffer.read_owned();
+            let node_page = node::NodePage::<_, N>::new(ro_buffer.page.as_ref()).unwrap();
+            let leaf = match node_page.node().try_into_leaf() {
+                Ok(leaf) => leaf,
+                Err(_) => {
+                    drop(ro_buffer);
+                    self.reseek()?;
+                    continue;
+                }
+            };
+            if let Some(last_key) = self.last_key {
+                let in_place = 0 < self.index
+                    && self.index <= leaf.num_records()
+                    && leaf.record(self.index - 1).key() == last_key;
+                if !in_place {
+                    self.index = leaf
+                        .find(last_key)
+                        .map(|index| index + 1)
+                        .unwrap_or_else(|index| index);
+                }
+            }
+            if self.index < leaf.num_records() {
+                let record = leaf.record(self.index);
+                self.index += 1;
+                self.last_key = Some(record.key());
+                if let Some(buf) = buf {
+                    buf.extend(record.value);
+                }
+                return Ok(Some(record.key()));
+            }
+            let next_buffer = match leaf.next_page_id() {
+                Some(next_page_id) => self.bufmgr.fetch_page(next_page_id)?,
+                None => {
+                    self.buffer = None;
+                    break;
+                }
+            };
+            let ro_next_buffer = next_buffer.clone().read_owned();
+            drop(ro_buffer);
+            let next_node_page = node::NodePage::<_, N>::new(ro_next_buffer.page.as_ref()).unwrap();
+            verify_leaf(self.bufmgr, ro_next_buffer.page_id(), &next_node_page)?;
+            let expected = match (next_node_page.node().try_into_leaf(), self.last_key) {
+                (Ok(next_leaf), Some(last_key)) => {
+                    next_leaf.num_records() == 0 || next_leaf.record(0).key() > last_key
+                }
+                (Ok(_), None) => true,
+                (Err(_), _) => false,
+            };
+            drop(ro_next_buffer);
+            if expected {
+                self.buffer = Some(next_buffer);
+                self.index = 0;
+                self.pages_visited += 1;
+            } else {
+                self.reseek()?;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Descends from the root again to the successor of the last returned
+    /// key.
+    fn reseek(&mut self) -> Result<(), Error> {
+        self.buffer = None;
+        let key = match self.last_key {
+            Some(last_key) => match successor(last_key) {
+                Some(successor) => Some(successor),
+                None => return Ok(()),
+            },
+            None => None,
+        };
+        let access = Access::<N>::open(self.bufmgr, self.btree_page_id);
+        let iter = match &self.meta_buffer {
+            // The meta page can't be latched again while a stable scan holds
+            // it, as a waiting writer would deadlock us.
+            Some(ro_meta_buffer) => {
+                let btree = BTreePage {
+                    data: &ro_meta_buffer.page[..],
+                };
+                let root_page = self.bufmgr.fetch_page(btree.root_page_id())?.read_owned();
+                access.iter_internal(root_page, key)?
+            }
+            None => access.iter(key)?,
+        };
+        self.buffer = iter.buffer;
+        self.index = iter.index;
+        self.pages_visited += iter.pages_visited;
+        Ok(())
+    }
+}
+
+/// The smallest key greater than `key`, or `None` if `key` is the largest.
+fn successor<const N: usize>(mut key: Key<N>) -> Option<Key<N>> {
+    for byte in key.iter_mut().rev() {
+        let (incremented, overflowed) = byte.overflowing_add(1);
+        *byte = incremented;
+        if !overflowed {
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// Reverse counterpart of `Iter`, with the same guarantees. Unlike `Iter`,
+/// it never latches two leaves at once, as that would go against the
+/// left-to-right order writers latch them in. A leaf is trusted to hold
+/// every key below the last returned one for as long as its next link is
+/// unchanged, since only splits move keys out of a leaf, and always to a
+/// new leaf on its right.
+pub struct IterRev<'a, const N: usize = 8> {
+    bufmgr: &'a BufferPoolManager,
+    btree_page_id: PageId,
+    buffer: Option<Arc<RwLock<Buffer>>>,
+    /// The next link the current leaf had when the iterator moved to it.
+    next_page_id: Option<PageId>,
+    index: isize,
+    /// Where the scan started, used until a key has been returned.
+    start: Option<Key<N>>,
+    last_key: Option<Key<N>>,
+    meta_buffer: Option<OwnedRwLockReadGuard<RawRwLock, Buffer>>,
+    pages_visited: u64,
+}
+impl<'a, const N: usize> IterRev<'a, N> {
+    /// Number of leaves the iterator has moved through so far, counting a
+    /// leaf again if it had to re-seek to it.
+    pub fn pages_visited(&self) -> u64 {
+        self.pages_visited
+    }
+
+    pub fn next(&mut self, buf: &mut Vec<u8>) -> Result<Option<Key<N>>, Error> {
+        self.advance(Some(buf))
+    }
+
+    /// See `Iter::next_key`.
+    pub fn next_key(&mut self) -> Result<Option<Key<N>>, Error> {
+        self.advance(None)
+    }
+
+    fn advance(&mut self, buf: Option<&mut Vec<u8>>) -> Result<Option<Key<N>>, Error> {
+        while let Some(buffer) = self.buffer.clone() {
+            let ro_buffer = buffer.read_owned();
+            let node_page = node::NodePage::<_, N>::new(ro_buffer.page.as_ref()).unwrap();
+            let leaf = match node_page.node().try_into_leaf() {
+                Ok(leaf) if leaf.next_page_id() == self.next_page_id => leaf,
+                _ => {
+                    drop(ro_buffer);
+                    self.reseek()?;
+                    continue;
+                }
+            };
+            let num_records = leaf.num_records() as isize;
+            self.index = match (self.last_key, self.start) {
+                (Some(last_key), _) => {
+                    let in_place = self.index + 1 < num_records
+                        && leaf.record((self.index + 1) as usize).key() == last_key;
+                    if in_place {
+                        self.index
+                    } else {
+                        leaf.find(last_key).unwrap_or_else(|index| index) as isize - 1
+                    }
+                }
+                (None, Some(start)) => leaf
+                    .find(start)
+                    .map(|index| index as isize)
+                    .unwrap_or_else(|index| index as isize - 1),
+                (None, None) => num_records - 1,
+            };
+            if self.index >= 0 {
+                let record = leaf.record(self.index as usize);
+                self.index -= 1;
+                self.last_key = Some(record.key());
+                if let Some(buf) = buf {
+                    buf.extend(record.value);
+                }
+                return Ok(Some(record.key()));
+            }
+            let prev_page_id = match leaf.prev_page_id() {
+                Some(prev_page_id) => prev_page_id,
+                None => {
+                    self.buffer = None;
+                    break;
+                }
+            };
+            let page_id = ro_buffer.page_id();
+            drop(ro_buffer);
+            // The previous leaf is only known to be adjacent if it still
+            // links back to this one.
+            let prev_buffer = self.bufmgr.fetch_page(prev_page_id)?;
+            let ro_prev_buffer = prev_buffer.clone().read_owned();
+            let prev_node_page = node::NodePage::<_, N>::new(ro_prev_buffer.page.as_ref()).unwrap();
+            verify_leaf(self.bufmgr, prev_page_id, &prev_node_page)?;
+            let adjacent = match prev_node_page.node().try_into_leaf() {
+                Ok(prev_leaf) if prev_leaf.next_page_id() == Some(page_id) => {
+                    Some(prev_leaf.num_records() as isize - 1)
+                }
+                _ => None,
+            };
+            drop(ro_prev_buffer);
+            if let Some(index) = adjacent {
+                self.buffer = Some(prev_buffer);
+                self.next_page_id = Some(page_id);
+                self.index = index;
+                self.pages_visited += 1;
+            } else {
+                self.reseek()?;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Descends from the root again to the predecessor of the last returned
+    /// key, or to the start if there is none.
+    fn reseek(&mut self) -> Result<(), Error> {
+        self.buffer = None;
+        let key = match self.last_key {
+            Some(last_key) => match predecessor(last_key) {
+                Some(predecessor) => Some(predecessor),
+                None => return Ok(()),
+            },
+            None => self.start,
+        };
+        let access = Access::<N>::open(self.bufmgr, self.btree_page_id);
+        let iter = match &self.meta_buffer {
+            // See `Iter::reseek`.
+            Some(ro_meta_buffer) => {
+                let btree = BTreePage {
+                    data: &ro_meta_buffer.page[..],
+                };
+                let root_page = self.bufmgr.fetch_page(btree.root_page_id())?.read_owned();
+                access.iter_rev_internal(root_page, key)?
+            }
+            None => access.iter_rev(key)?,
+        };
+        self.buffer = iter.buffer;
+        self.next_page_id = iter.next_page_id;
+        self.index = iter.index;
+        self.pages_visited += iter.pages_visited;
+        Ok(())
+    }
+}
+
+/// The largest key less than `key`, or `None` if `key` is the smallest.
+fn predecessor<const N: usize>(mut key: Key<N>) -> Option<Key<N>> {
+    for byte in key.iter_mut().rev() {
+        let (decremented, overflowed) = byte.overflowing_sub(1);
+        *byte = decremented;
+        if !overflowed {
+            return Some(key);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Barrier, thread, time::Duration};
 
-    use crate::{buffer::BufferPool, disk::DiskManager};
+    use tempfile::tempfile;
+
+    use crate::{
+        buffer::BufferPool,
+        disk::{DiskManager, MemoryDiskManager},
+    };
 
     use super::*;
     #[test]
@@ -458,4 +1933,1127 @@ mod tests {
         buf.clear();
         assert_eq!(None, iter.next(&mut buf).unwrap());
     }
+
+    #[test]
+    fn test_seek() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1500];
+        for i in (1u64..=5).map(|i| i * 10) {
+            btree_access.put(i.to_be_bytes(), &long_padding).unwrap();
+        }
+        btree_access.put(30u64.to_be_bytes(), b"thirty").unwrap();
+
+        let seek = |key: u64, direction| {
+            btree_access
+                .seek(key.to_be_bytes(), direction)
+                .unwrap()
+                .map(|(key, _)| u64::from_be_bytes(key))
+        };
+        assert_eq!(None, seek(5, Direction::Floor));
+        assert_eq!(Some(10), seek(5, Direction::Ceiling));
+        assert_eq!(Some(20), seek(25, Direction::Floor));
+        assert_eq!(Some(30), seek(25, Direction::Ceiling));
+        assert_eq!(Some(30), seek(30, Direction::Floor));
+        assert_eq!(Some(30), seek(30, Direction::Ceiling));
+        assert_eq!(Some(50), seek(55, Direction::Floor));
+        assert_eq!(None, seek(55, Direction::Ceiling));
+        assert_eq!(
+            Some((30u64.to_be_bytes(), b"thirty".to_vec())),
+            btree_access
+                .seek(31u64.to_be_bytes(), Direction::Floor)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_iter_stable() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1000];
+        for i in (0u64..10).map(|i| i * 2) {
+            btree_access.put(i.to_be_bytes(), &long_padding).unwrap();
+        }
+
+        let barrier = Barrier::new(2);
+        let keys = thread::scope(|s| {
+            let writer = s.spawn(|| {
+                let btree_access = Access::open(&bufmgr, btree_access.btree_page_id);
+                barrier.wait();
+                for i in (0u64..10).map(|i| i * 2 + 1) {
+                    btree_access.put(i.to_be_bytes(), &long_padding).unwrap();
+                }
+            });
+            let mut iter = btree_access.iter_stable(None).unwrap();
+            barrier.wait();
+            let mut keys = vec![];
+            let mut buf = vec![];
+            while let Some(key) = iter.next(&mut buf).unwrap() {
+                thread::sleep(Duration::from_millis(1));
+                keys.push(u64::from_be_bytes(key));
+            }
+            drop(iter);
+            writer.join().unwrap();
+            keys
+        });
+        assert_eq!((0u64..10).map(|i| i * 2).collect::<Vec<_>>(), keys);
+
+        let mut iter = btree_access.iter(None).unwrap();
+        let mut buf = vec![];
+        let mut count = 0;
+        while iter.next(&mut buf).unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(20, count);
+    }
+
+    #[test]
+    fn test_count() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        assert_eq!(0, btree_access.count().unwrap());
+        let long_padding = vec![0xDEu8; 1500];
+        assert!(btree_access.put(6u64.to_be_bytes(), &long_padding).unwrap());
+        assert!(btree_access.put(3u64.to_be_bytes(), &long_padding).unwrap());
+        assert!(btree_access.put(8u64.to_be_bytes(), &long_padding).unwrap());
+        assert!(btree_access.put(4u64.to_be_bytes(), &long_padding).unwrap());
+        assert!(!btree_access.put(3u64.to_be_bytes(), b"hello").unwrap());
+        assert_eq!(4, btree_access.count().unwrap());
+
+        assert!(btree_access.delete(3u64.to_be_bytes()).unwrap());
+        assert!(!btree_access.delete(3u64.to_be_bytes()).unwrap());
+        assert!(btree_access.delete(8u64.to_be_bytes()).unwrap());
+        assert_eq!(2, btree_access.count().unwrap());
+
+        let mut buf = vec![];
+        assert!(!btree_access.get(3u64.to_be_bytes(), &mut buf).unwrap());
+        assert!(btree_access.get(4u64.to_be_bytes(), &mut buf).unwrap());
+        assert_eq!(&long_padding, &buf);
+    }
+
+    #[test]
+    fn test_iter_concurrent_insert() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(20);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let padding = vec![0xDEu8; 500];
+        for i in (0u64..50).map(|i| i * 4) {
+            btree_access.put(i.to_be_bytes(), &padding).unwrap();
+        }
+
+        let barrier = Barrier::new(2);
+        let keys = thread::scope(|s| {
+            s.spawn(|| {
+                let btree_access = Access::open(&bufmgr, btree_access.btree_page_id);
+                barrier.wait();
+                // Fill in the gaps so that leaves keep splitting under the
+                // scan, including the one it is positioned on.
+                for i in (0u64..150).map(|i| i / 3 * 4 + i % 3 + 1) {
+                    btree_access.put(i.to_be_bytes(), &padding).unwrap();
+                }
+            });
+            let mut iter = btree_access.iter(None).unwrap();
+            barrier.wait();
+            let mut keys = vec![];
+            let mut buf = vec![];
+            while let Some(key) = iter.next(&mut buf).unwrap() {
+                thread::sleep(Duration::from_micros(200));
+                keys.push(u64::from_be_bytes(key));
+            }
+            keys
+        });
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        for i in (0u64..50).map(|i| i * 4) {
+            assert!(keys.contains(&i), "{} was skipped", i);
+        }
+    }
+
+    #[test]
+    fn test_iter_reseek() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let padding = vec![0xDEu8; 1000];
+        for i in (0u64..3).map(|i| i * 10) {
+            btree_access.put(i.to_be_bytes(), &padding).unwrap();
+        }
+        let mut iter = btree_access.iter(None).unwrap();
+        let mut buf = vec![];
+        assert_eq!(Some(0u64.to_be_bytes()), iter.next(&mut buf).unwrap());
+        assert_eq!(Some(10u64.to_be_bytes()), iter.next(&mut buf).unwrap());
+        // Splits the leaf under the iterator, moving 10 and 20 to a new leaf.
+        for i in 1u64..4 {
+            btree_access.put(i.to_be_bytes(), &padding).unwrap();
+        }
+        assert_eq!(Some(20u64.to_be_bytes()), iter.next(&mut buf).unwrap());
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_pool_exhausted() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(4);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1500];
+        // Splitting a leaf that has a right sibling under a root branch needs
+        // five pages, so inserting in descending order soon runs out.
+        let mut inserted = vec![];
+        for i in (0u64..100).rev() {
+            match btree_access.put(i.to_be_bytes(), &long_padding) {
+                Ok(_) => inserted.push(i),
+                Err(Error::Buffer(buffer::Error::NoFreeBuffer { .. })) => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        assert!(inserted.len() < 100);
+        assert_eq!(inserted.len() as u64, btree_access.count().unwrap());
+
+        inserted.reverse();
+        let mut iter = btree_access.iter(None).unwrap();
+        let mut buf = vec![];
+        for i in &inserted {
+            assert_eq!(Some(i.to_be_bytes()), iter.next(&mut buf).unwrap());
+            assert_eq!(&long_padding, &buf);
+            buf.clear();
+        }
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+        drop(iter);
+        assert!(btree_access.put(1000u64.to_be_bytes(), b"hello").unwrap());
+    }
+
+    #[test]
+    fn test_append() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let n = 10_000u64;
+        let fetches = bufmgr.stats().fetches;
+        for i in 0..n {
+            assert!(btree_access.put(i.to_be_bytes(), &i.to_le_bytes()).unwrap());
+        }
+        // Every append fetches only the meta page and the rightmost leaf; only
+        // leaf splits descend through the branches.
+        assert!(bufmgr.stats().fetches - fetches < n * 2 + n / 10);
+        assert!(!btree_access.put(5u64.to_be_bytes(), b"replaced").unwrap());
+        assert_eq!(n, btree_access.count().unwrap());
+
+        let mut iter = btree_access.iter(None).unwrap();
+        let mut buf = vec![];
+        for i in 0..n {
+            buf.clear();
+            assert_eq!(Some(i.to_be_bytes()), iter.next(&mut buf).unwrap());
+            if i == 5 {
+                assert_eq!(b"replaced", &*buf);
+            } else {
+                assert_eq!(&i.to_le_bytes(), &*buf);
+            }
+        }
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let n = 3000u64;
+        for i in 0..n {
+            assert!(btree_access.put(i.to_be_bytes(), &i.to_le_bytes()).unwrap());
+        }
+        let (start, end) = (n / 3, n * 2 / 3);
+        assert_eq!(
+            end - start,
+            btree_access
+                .delete_range(start.to_be_bytes(), end.to_be_bytes())
+                .unwrap()
+        );
+        assert_eq!(n - (end - start), btree_access.count().unwrap());
+        assert_eq!(
+            0,
+            btree_access
+                .delete_range(start.to_be_bytes(), end.to_be_bytes())
+                .unwrap()
+        );
+
+        let mut iter = btree_access.iter(None).unwrap();
+        let mut buf = vec![];
+        for i in (0..start).chain(end..n) {
+            buf.clear();
+            assert_eq!(Some(i.to_be_bytes()), iter.next(&mut buf).unwrap());
+            assert_eq!(&i.to_le_bytes(), &*buf);
+        }
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_observer() {
+        #[derive(Default)]
+        struct Recorder(parking_lot::Mutex<Vec<String>>);
+        impl MutationObserver for Recorder {
+            fn on_mutation(&self, mutation: Mutation<'_>) {
+                let event = match mutation {
+                    Mutation::Put { key, .. } => format!("put {}", u64::from_be_bytes(key)),
+                    Mutation::Delete { key } => format!("delete {}", u64::from_be_bytes(key)),
+                    Mutation::DeleteRange { .. } => "delete range".to_string(),
+                    Mutation::Split { .. } => "split".to_string(),
+                    Mutation::NewRoot { .. } => "new root".to_string(),
+                };
+                self.0.lock().push(event);
+            }
+        }
+
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let recorder = Recorder::default();
+        let btree_access = Access::create(&bufmgr).unwrap().with_observer(&recorder);
+        let long_padding = vec![0xDEu8; 1500];
+        btree_access.put(6u64.to_be_bytes(), &long_padding).unwrap();
+        btree_access.put(3u64.to_be_bytes(), &long_padding).unwrap();
+        btree_access.put(8u64.to_be_bytes(), &long_padding).unwrap();
+        btree_access.put(9u64.to_be_bytes(), b"hello").unwrap();
+        btree_access.delete(3u64.to_be_bytes()).unwrap();
+        btree_access.delete(3u64.to_be_bytes()).unwrap();
+        assert_eq!(
+            vec!["put 6", "put 3", "split", "put 8", "new root", "put 9", "delete 3"],
+            *recorder.0.lock()
+        );
+    }
+
+    #[test]
+    fn test_value_size_boundary() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let max = max_value_size::<8>();
+        // A leaf fits exactly two records of the largest size, so these puts
+        // keep splitting.
+        for i in [5u64, 1, 9, 3, 7, 2, 8] {
+            assert!(btree_access
+                .put(i.to_be_bytes(), &vec![i as u8; max])
+                .unwrap());
+        }
+        assert!(matches!(
+            btree_access.put(4u64.to_be_bytes(), &vec![0; max + 1]),
+            Err(Error::ValueTooLarge { len, max: reported }) if len == max + 1 && reported == max
+        ));
+        assert_eq!(7, btree_access.count().unwrap());
+
+        let mut iter = btree_access.iter(None).unwrap();
+        let mut buf = vec![];
+        for i in [1u64, 2, 3, 5, 7, 8, 9] {
+            buf.clear();
+            assert_eq!(Some(i.to_be_bytes()), iter.next(&mut buf).unwrap());
+            assert_eq!(vec![i as u8; max], buf);
+        }
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_fill_factor() {
+        let leaves = |fill_factor| {
+            let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+            let pool = BufferPool::new(10);
+            let bufmgr = BufferPoolManager::new(disk, pool);
+            let btree_access = Access::create(&bufmgr)
+                .unwrap()
+                .with_fill_factor(fill_factor);
+            for i in 0..1000u64 {
+                btree_access.put(i.to_be_bytes(), &[0xDE; 100]).unwrap();
+            }
+            let mut iter = btree_access.iter(None).unwrap();
+            let mut buf = vec![];
+            while iter.next(&mut buf).unwrap().is_some() {}
+            iter.pages_visited()
+        };
+        let even = leaves(DEFAULT_FILL_FACTOR);
+        let packed = leaves(90);
+        assert!(packed * 3 < even * 2, "{} leaves vs {}", packed, even);
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        for i in 0..1000u64 {
+            btree_access.put(i.to_be_bytes(), &[0xDE; 100]).unwrap();
+        }
+        for i in (0..1000u64).filter(|i| i % 4 != 0) {
+            assert!(btree_access.delete(i.to_be_bytes()).unwrap());
+        }
+
+        let rebuilt = btree_access.rebuild().unwrap();
+        assert_ne!(btree_access.btree_page_id, rebuilt.btree_page_id);
+        assert_eq!(250, rebuilt.count().unwrap());
+        assert!(rebuilt.leaf_count().unwrap() * 2 < btree_access.leaf_count().unwrap());
+        let mut iter = rebuilt.iter(None).unwrap();
+        let mut buf = vec![];
+        for i in (0..1000u64).step_by(4) {
+            buf.clear();
+            assert_eq!(Some(i.to_be_bytes()), iter.next(&mut buf).unwrap());
+            assert_eq!(&[0xDE; 100][..], &buf[..]);
+        }
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+        assert_eq!(250, btree_access.count().unwrap());
+    }
+
+    #[test]
+    fn test_empty() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let mut buf = vec![];
+        assert!(!btree_access.get(1u64.to_be_bytes(), &mut buf).unwrap());
+        assert_eq!(0, btree_access.count().unwrap());
+        for start in [None, Some(1u64.to_be_bytes())] {
+            assert_eq!(
+                None,
+                btree_access.iter(start).unwrap().next(&mut buf).unwrap()
+            );
+            assert_eq!(
+                None,
+                btree_access
+                    .iter_rev(start)
+                    .unwrap()
+                    .next(&mut buf)
+                    .unwrap()
+            );
+        }
+        for direction in [Direction::Floor, Direction::Ceiling] {
+            assert_eq!(
+                None,
+                btree_access.seek(1u64.to_be_bytes(), direction).unwrap()
+            );
+        }
+        assert_eq!(None, btree_access.first_key().unwrap());
+        assert_eq!(None, btree_access.last_key().unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_empty_values() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        // Odd keys get empty values. There are enough of them, at a key's
+        // size each, to split leaves made up mostly of empty records.
+        let value = |i: u64| {
+            if i % 2 == 1 {
+                vec![]
+            } else {
+                vec![i as u8; 100]
+            }
+        };
+        for i in (0..2000u64).rev() {
+            assert!(btree_access.put(i.to_be_bytes(), &value(i)).unwrap());
+        }
+        assert!(btree_access.leaf_count().unwrap() > 1);
+        let mut buf = vec![];
+        for i in [1u64, 2, 1999] {
+            buf.clear();
+            assert!(btree_access.get(i.to_be_bytes(), &mut buf).unwrap());
+            assert_eq!(value(i), buf);
+        }
+        assert!(!btree_access.get(2000u64.to_be_bytes(), &mut buf).unwrap());
+
+        let mut iter = btree_access.iter(None).unwrap();
+        for i in 0..2000u64 {
+            buf.clear();
+            assert_eq!(Some(i.to_be_bytes()), iter.next(&mut buf).unwrap());
+            assert_eq!(value(i), buf);
+        }
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+
+        // Emptying a value and filling an empty one in place.
+        assert!(!btree_access.put(2u64.to_be_bytes(), b"").unwrap());
+        assert!(!btree_access.put(3u64.to_be_bytes(), b"filled").unwrap());
+        buf.clear();
+        assert!(btree_access.get(2u64.to_be_bytes(), &mut buf).unwrap());
+        assert!(buf.is_empty());
+        assert!(btree_access.get(3u64.to_be_bytes(), &mut buf).unwrap());
+        assert_eq!(b"filled", &buf[..]);
+
+        for i in (1..2000u64).step_by(2) {
+            assert!(btree_access.delete(i.to_be_bytes()).unwrap());
+        }
+        assert!(!btree_access.get(1u64.to_be_bytes(), &mut buf).unwrap());
+        assert_eq!(1000, btree_access.count().unwrap());
+    }
+
+    #[test]
+    fn test_uuid_keys() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::<16>::create(&bufmgr).unwrap();
+        // Scattered like random UUIDs, so the inserts split all over the tree.
+        let mut keys = (0..500u128)
+            .map(|i| {
+                i.wrapping_mul(0x9E37_79B9_7F4A_7C15_F39C_C060_5CED_C835)
+                    .to_be_bytes()
+            })
+            .collect::<Vec<_>>();
+        for key in &keys {
+            assert!(btree_access.put(*key, &key[..]).unwrap());
+        }
+        keys.sort();
+
+        let mut buf = vec![];
+        for key in &keys {
+            assert!(btree_access.get(*key, &mut buf).unwrap());
+            assert_eq!(&key[..], &buf[..]);
+            buf.clear();
+        }
+        let mut iter = btree_access.iter(None).unwrap();
+        for key in &keys {
+            assert_eq!(Some(*key), iter.next(&mut buf).unwrap());
+            buf.clear();
+        }
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+        let mut iter = btree_access.iter_rev(None).unwrap();
+        for key in keys.iter().rev() {
+            assert_eq!(Some(*key), iter.next(&mut buf).unwrap());
+            buf.clear();
+        }
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+        assert!(btree_access.leaf_count().unwrap() > 1);
+
+        let mut key = [0xFF; 16];
+        key[0] = 0;
+        let mut expected = [0; 16];
+        expected[0] = 1;
+        assert_eq!(Some(expected), successor(key));
+        assert_eq!(None, successor([0xFF; 16]));
+    }
+
+    #[test]
+    fn test_key_width() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let legacy = Access::<8>::create(&bufmgr).unwrap();
+        let btree_access = Access::<16>::create(&bufmgr).unwrap();
+        // Wider keys leave less of a leaf to values.
+        let max = max_value_size::<16>();
+        assert!(max < max_value_size::<8>());
+        assert!(matches!(
+            btree_access.put([1; 16], &vec![0; max_value_size::<8>()]),
+            Err(Error::ValueTooLarge { max: reported, .. }) if reported == max
+        ));
+        assert!(matches!(
+            btree_access.update([2; 16], |_| Some(vec![0; max + 1])),
+            Err(Error::ValueTooLarge { .. })
+        ));
+        for i in 1..=4 {
+            assert!(btree_access.put([i; 16], &vec![i; max]).unwrap());
+        }
+        assert_eq!(4, btree_access.count().unwrap());
+
+        let narrow = Access::<8>::open(&bufmgr, btree_access.btree_page_id);
+        assert!(matches!(
+            narrow.get([1; 8], &mut vec![]),
+            Err(Error::KeyWidthMismatch {
+                expected: 8,
+                found: 16,
+                ..
+            })
+        ));
+        assert!(matches!(
+            narrow.put([1; 8], b""),
+            Err(Error::KeyWidthMismatch {
+                expected: 8,
+                found: 16,
+                ..
+            })
+        ));
+        // Neither the width nor a note hides a tree from recovery.
+        btree_access.set_note(b"note").unwrap();
+        let num_pages = bufmgr.disk_usage().unwrap().allocated_pages;
+        let meta_pages = find_meta_pages(&bufmgr, num_pages).unwrap();
+        assert_eq!(vec![btree_access.btree_page_id], meta_pages);
+
+        // Trees from before the width was recorded have 8-byte keys.
+        legacy.put([1; 8], b"one").unwrap();
+        {
+            let mut rw_meta_buffer = bufmgr
+                .fetch_page(legacy.btree_page_id)
+                .unwrap()
+                .write_owned();
+            rw_meta_buffer.page[KEY_WIDTH_OFFSET] = 0;
+        }
+        let mut buf = vec![];
+        assert!(legacy.get([1; 8], &mut buf).unwrap());
+        assert_eq!(b"one", &buf[..]);
+        assert!(matches!(
+            Access::<16>::open(&bufmgr, legacy.btree_page_id).count(),
+            Err(Error::KeyWidthMismatch {
+                expected: 16,
+                found: 8,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_iter_rev_multi_level() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        // Few records per leaf, so that the leaves overflow a single branch
+        // and the root ends up two branches above them.
+        let value = vec![0xDEu8; 1000];
+        let num_keys = 2000u64;
+        for i in 0..num_keys {
+            let key = (i * 7919 % num_keys).to_be_bytes();
+            btree_access.put(key, &value).unwrap();
+        }
+        let mut page = [0u8; PAGE_SIZE];
+        let max_pairs = branch::Branch::<_, 8>::new(&mut page[..])
+            .unwrap()
+            .max_pairs();
+        assert!(btree_access.leaf_count().unwrap() > max_pairs as u64);
+
+        let mut iter = btree_access.iter_rev(None).unwrap();
+        let mut buf = vec![];
+        for i in (0..num_keys).rev() {
+            assert_eq!(Some(i.to_be_bytes()), iter.next(&mut buf).unwrap());
+            assert_eq!(value, buf);
+            buf.clear();
+        }
+        assert_eq!(None, iter.next(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_first_last_key() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1500];
+        for i in 10..30u64 {
+            btree_access.put(i.to_be_bytes(), &long_padding).unwrap();
+        }
+        assert!(btree_access.leaf_count().unwrap() > 2);
+        assert_eq!(Some(10u64.to_be_bytes()), btree_access.first_key().unwrap());
+        assert_eq!(Some(29u64.to_be_bytes()), btree_access.last_key().unwrap());
+
+        // Empties the edge leaves, which the descent alone would miss.
+        for i in (10..14u64).chain(26..30) {
+            btree_access.delete(i.to_be_bytes()).unwrap();
+        }
+        assert_eq!(Some(14u64.to_be_bytes()), btree_access.first_key().unwrap());
+        assert_eq!(Some(25u64.to_be_bytes()), btree_access.last_key().unwrap());
+    }
+
+    #[test]
+    fn test_append_only() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap().with_fill_factor(100);
+        let long_padding = vec![0xDEu8; 1500];
+        for i in 0..20u64 {
+            btree_access.append(i.to_be_bytes(), &long_padding).unwrap();
+        }
+        for i in [19u64, 5] {
+            assert!(matches!(
+                btree_access.append(i.to_be_bytes(), b"late"),
+                Err(Error::KeyNotIncreasing)
+            ));
+        }
+        // Empties the rightmost leaf, so the check has to look past it.
+        btree_access
+            .delete_range(10u64.to_be_bytes(), 20u64.to_be_bytes())
+            .unwrap();
+        assert!(matches!(
+            btree_access.append(9u64.to_be_bytes(), b"late"),
+            Err(Error::KeyNotIncreasing)
+        ));
+        btree_access.append(10u64.to_be_bytes(), b"again").unwrap();
+        assert_eq!(11, btree_access.count().unwrap());
+    }
+
+    #[test]
+    fn test_split_allocation_failure() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(6);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1500];
+        for i in [10u64, 30, 40] {
+            btree_access.put(i.to_be_bytes(), &long_padding).unwrap();
+        }
+        let left_page_id = btree_access
+            .leaf_stats(10u64.to_be_bytes())
+            .unwrap()
+            .page_id;
+        let links = |page_id| {
+            let buffer = bufmgr.fetch_page(page_id).unwrap();
+            let ro_buffer = buffer.read();
+            let node_page = node::NodePage::<_>::new(ro_buffer.page.as_ref()).unwrap();
+            let leaf = node_page.node().try_into_leaf().ok().unwrap();
+            (leaf.prev_page_id(), leaf.next_page_id())
+        };
+        let left_links = links(left_page_id);
+        let right_page_id = left_links.1.unwrap();
+        let right_links = links(right_page_id);
+
+        // A put into the left leaf pins the meta page, the root and both
+        // leaves, and pinning two more pages leaves no frame for the split.
+        let pinned = [bufmgr.create_page().unwrap(), bufmgr.create_page().unwrap()];
+        let mut inserted = 0u64;
+        let err = loop {
+            match btree_access.put((11 + inserted).to_be_bytes(), &long_padding) {
+                Ok(_) => inserted += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(
+            err,
+            Error::Buffer(buffer::Error::NoFreeBuffer { .. })
+        ));
+        assert_eq!(left_links, links(left_page_id));
+        assert_eq!(right_links, links(right_page_id));
+        assert_eq!(3 + inserted, btree_access.count().unwrap());
+
+        drop(pinned);
+        btree_access
+            .put(20u64.to_be_bytes(), &long_padding)
+            .unwrap();
+        let mut iter = btree_access.iter(None).unwrap();
+        let mut count = 0;
+        while iter.next(&mut vec![]).unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(4 + inserted, count);
+    }
+
+    #[test]
+    fn test_leaf_checksums() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool).with_leaf_checksums(true);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        for i in 0u64..10 {
+            btree_access.put(i.to_be_bytes(), b"value").unwrap();
+        }
+        let mut buf = vec![];
+        assert!(btree_access.get(3u64.to_be_bytes(), &mut buf).unwrap());
+
+        // Flips a bit of a record behind the tree's back.
+        let page_id = btree_access.leaf_stats(3u64.to_be_bytes()).unwrap().page_id;
+        let buffer = bufmgr.fetch_page(page_id).unwrap();
+        let position = {
+            let page = &buffer.read().page;
+            page.windows(5)
+                .rposition(|window| window == b"value")
+                .unwrap()
+        };
+        buffer.write().page[position] ^= 1;
+        drop(buffer);
+
+        let is_mismatch = |result: Result<_, Error>| matches!(result, Err(Error::ChecksumMismatch { page_id: id }) if id == page_id);
+        assert!(is_mismatch(
+            btree_access.get(3u64.to_be_bytes(), &mut buf).map(|_| ())
+        ));
+        assert!(is_mismatch(btree_access.iter(None).map(|_| ())));
+        assert!(is_mismatch(btree_access.iter_rev(None).map(|_| ())));
+        assert!(is_mismatch(
+            btree_access.put(10u64.to_be_bytes(), b"value").map(|_| ())
+        ));
+        assert!(is_mismatch(
+            btree_access.delete(3u64.to_be_bytes()).map(|_| ())
+        ));
+    }
+
+    #[test]
+    fn test_next_key() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1500];
+        for i in [6u64, 3, 8, 4, 5] {
+            btree_access.put(i.to_be_bytes(), &long_padding).unwrap();
+        }
+
+        let mut iter = btree_access.iter(Some(4u64.to_be_bytes())).unwrap();
+        for i in [4u64, 5, 6, 8] {
+            assert_eq!(Some(i.to_be_bytes()), iter.next_key().unwrap());
+        }
+        assert_eq!(None, iter.next_key().unwrap());
+        let mut iter = btree_access.iter_rev(None).unwrap();
+        let mut buf = vec![];
+        assert_eq!(Some(8u64.to_be_bytes()), iter.next_key().unwrap());
+        assert_eq!(Some(6u64.to_be_bytes()), iter.next(&mut buf).unwrap());
+        assert_eq!(&long_padding, &buf);
+        for i in [5u64, 4, 3] {
+            assert_eq!(Some(i.to_be_bytes()), iter.next_key().unwrap());
+        }
+        assert_eq!(None, iter.next_key().unwrap());
+    }
+
+    #[test]
+    fn test_check_pool_size() {
+        let min = min_pool_size::<8>();
+        // Wider keys mean fewer pairs in a branch, and so deeper trees.
+        assert!(min_pool_size::<128>() > min);
+        let bufmgr = |sizes: &[usize]| {
+            let shards = sizes.iter().map(|&size| BufferPool::new(size)).collect();
+            BufferPoolManager::with_shards(MemoryDiskManager::new(), shards)
+        };
+        assert!(check_pool_size::<8>(&bufmgr(&[min])).is_ok());
+        assert!(check_pool_size::<8>(&bufmgr(&[min, min])).is_ok());
+        assert!(matches!(
+            check_pool_size::<128>(&bufmgr(&[min])),
+            Err(Error::PoolTooSmall { pool_size, .. }) if pool_size == min
+        ));
+        // Plenty in total, but a put may need every frame of one shard.
+        assert!(matches!(
+            check_pool_size::<8>(&bufmgr(&[min * 4, min - 1])),
+            Err(Error::PoolTooSmall { pool_size, min: reported })
+                if pool_size == min - 1 && reported == min
+        ));
+    }
+
+    #[test]
+    fn test_scan_small_pool() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(min_pool_size::<8>());
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1500];
+        for i in 0..100u64 {
+            btree_access.put(i.to_be_bytes(), &long_padding).unwrap();
+        }
+        assert!(btree_access.leaf_count().unwrap() > min_pool_size::<8>() as u64);
+
+        for _ in 0..2 {
+            let mut iter = btree_access.iter(None).unwrap();
+            let mut iter_rev = btree_access.iter_rev(None).unwrap();
+            let mut buf = vec![];
+            for i in 0..100u64 {
+                assert_eq!(Some(i.to_be_bytes()), iter.next(&mut buf).unwrap());
+                assert_eq!(Some((99 - i).to_be_bytes()), iter_rev.next_key().unwrap());
+                let mut value = vec![];
+                assert!(btree_access.get(i.to_be_bytes(), &mut value).unwrap());
+            }
+            assert_eq!(None, iter.next(&mut buf).unwrap());
+        }
+        assert_eq!(0, bufmgr.stats().exhaustions);
+    }
+
+    #[test]
+    fn test_paused_scan_holds_no_latch() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(20);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let padding = vec![0xDEu8; 1000];
+        for i in (0u64..12).map(|i| i * 4) {
+            btree_access.put(i.to_be_bytes(), &padding).unwrap();
+        }
+
+        for backward in [false, true] {
+            let (mut iter, mut iter_rev) = if backward {
+                (None, Some(btree_access.iter_rev(None).unwrap()))
+            } else {
+                (Some(btree_access.iter(None).unwrap()), None)
+            };
+            let next = |iter: &mut Option<Iter>, iter_rev: &mut Option<IterRev>| {
+                let mut buf = vec![];
+                match (iter, iter_rev) {
+                    (Some(iter), _) => iter.next(&mut buf).unwrap(),
+                    (_, Some(iter_rev)) => iter_rev.next(&mut buf).unwrap(),
+                    _ => unreachable!(),
+                }
+            };
+            let mut keys = vec![];
+            for _ in 0..3 {
+                keys.push(u64::from_be_bytes(next(&mut iter, &mut iter_rev).unwrap()));
+            }
+            let (sender, receiver) = std::sync::mpsc::channel();
+            thread::scope(|s| {
+                s.spawn(|| {
+                    let btree_access = Access::open(&bufmgr, btree_access.btree_page_id);
+                    // Splits every leaf, the ones the scan is on and has
+                    // passed included.
+                    for i in 0u64..48 {
+                        if i % 4 != 0 {
+                            btree_access.put(i.to_be_bytes(), &padding).unwrap();
+                        }
+                    }
+                    sender.send(()).unwrap();
+                });
+                let written = receiver.recv_timeout(Duration::from_secs(10));
+                if written.is_err() {
+                    // Let the writer finish so that the scope can end.
+                    iter = None;
+                    iter_rev = None;
+                    panic!("the writer was blocked by the paused scan");
+                }
+            });
+            while let Some(key) = next(&mut iter, &mut iter_rev) {
+                keys.push(u64::from_be_bytes(key));
+            }
+            if backward {
+                keys.reverse();
+            }
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+            for i in (0u64..12).map(|i| i * 4) {
+                assert!(keys.contains(&i), "{} was skipped", i);
+            }
+            for i in 0u64..48 {
+                if i % 4 != 0 {
+                    btree_access.delete(i.to_be_bytes()).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_splits_never_hang() {
+        const WRITERS: u64 = 4;
+        const KEYS_PER_WRITER: u64 = 100;
+        // Leaked so that threads stuck on a latch don't keep the test from
+        // failing.
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let bufmgr: &'static BufferPoolManager =
+            Box::leak(Box::new(BufferPoolManager::new(disk, BufferPool::new(64))));
+        let btree_page_id = Access::<8>::create(bufmgr).unwrap().btree_page_id;
+        let padding = vec![0xDEu8; 500];
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let spawn = |work: Box<dyn FnOnce(Access<'static>) + Send>| {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                work(Access::open(bufmgr, btree_page_id));
+                sender.send(()).unwrap();
+            });
+        };
+        // Interleaved keys, so that every writer splits leaves the others
+        // are about to write to.
+        for writer in 0..WRITERS {
+            let padding = padding.clone();
+            spawn(Box::new(move |btree_access| {
+                for i in 0..KEYS_PER_WRITER {
+                    let key = i * WRITERS + writer;
+                    btree_access.put(key.to_be_bytes(), &padding).unwrap();
+                }
+            }));
+        }
+        // Keys of its own past the writers', put and deleted again.
+        spawn(Box::new(move |btree_access| {
+            let base = WRITERS * KEYS_PER_WRITER;
+            for i in 0..50u64 {
+                btree_access
+                    .put((base + i).to_be_bytes(), &padding)
+                    .unwrap();
+                if i % 2 == 0 {
+                    assert!(btree_access.delete((base + i).to_be_bytes()).unwrap());
+                }
+            }
+            let removed = btree_access
+                .delete_range(base.to_be_bytes(), u64::MAX.to_be_bytes())
+                .unwrap();
+            assert_eq!(25, removed);
+        }));
+        for backward in [false, true] {
+            spawn(Box::new(move |btree_access| {
+                let mut buf = vec![];
+                for _ in 0..20 {
+                    let mut keys = vec![];
+                    if backward {
+                        let mut iter = btree_access.iter_rev(None).unwrap();
+                        while let Some(key) = iter.next(&mut buf).unwrap() {
+                            keys.push(u64::from_be_bytes(key));
+                        }
+                        keys.reverse();
+                    } else {
+                        let mut iter = btree_access.iter(None).unwrap();
+                        while let Some(key) = iter.next(&mut buf).unwrap() {
+                            keys.push(u64::from_be_bytes(key));
+                        }
+                    }
+                    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+                }
+            }));
+        }
+        drop(sender);
+        for _ in 0..WRITERS + 3 {
+            receiver
+                .recv_timeout(Duration::from_secs(30))
+                .expect("a thread hung or panicked");
+        }
+
+        let btree_access = Access::open(bufmgr, btree_page_id);
+        let mut iter = btree_access.iter(None).unwrap();
+        let mut buf = vec![];
+        let mut keys = vec![];
+        while let Some(key) = iter.next(&mut buf).unwrap() {
+            keys.push(u64::from_be_bytes(key));
+        }
+        assert_eq!((0..WRITERS * KEYS_PER_WRITER).collect::<Vec<_>>(), keys);
+        assert_eq!(WRITERS * KEYS_PER_WRITER, btree_access.count().unwrap());
+    }
+
+    #[test]
+    fn test_update() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        let append = |key: u64, suffix: &[u8]| {
+            btree_access.update(key.to_be_bytes(), |current| {
+                let mut value = current.unwrap_or_default().to_vec();
+                value.extend_from_slice(suffix);
+                Some(value)
+            })
+        };
+        for i in 1u64..=8 {
+            assert!(append(i, &[i as u8; 400]).unwrap());
+        }
+        // Growing the second value past what the leaf holds splits it.
+        for _ in 0..4 {
+            assert!(!append(2, &[0xFF; 400]).unwrap());
+        }
+        assert!(!btree_access.update(3u64.to_be_bytes(), |_| None).unwrap());
+        assert!(!btree_access.update(5u64.to_be_bytes(), |_| None).unwrap());
+        assert!(matches!(
+            btree_access.update(1u64.to_be_bytes(), |_| Some(vec![
+                0;
+                max_value_size::<8>() + 1
+            ])),
+            Err(Error::ValueTooLarge { .. })
+        ));
+        assert_eq!(8, btree_access.count().unwrap());
+
+        let mut buf = vec![];
+        for i in 1u64..=8 {
+            buf.clear();
+            assert!(btree_access.get(i.to_be_bytes(), &mut buf).unwrap());
+            let mut expected = vec![i as u8; 400];
+            if i == 2 {
+                expected.extend_from_slice(&[0xFF; 1600]);
+            }
+            assert_eq!(expected, buf);
+        }
+        let mut iter = btree_access.iter(None).unwrap();
+        while iter.next(&mut buf).unwrap().is_some() {}
+        assert!(iter.pages_visited() > 1);
+    }
+
+    #[test]
+    fn test_leaf_links() {
+        const COUNT: u64 = 3000;
+        let orders: [fn(u64) -> u64; 3] = [|i| i, |i| COUNT - 1 - i, |i| i * 1237 % COUNT];
+        for order in orders {
+            let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+            let pool = BufferPool::new(10);
+            let bufmgr = BufferPoolManager::new(disk, pool);
+            let btree_access = Access::create(&bufmgr).unwrap();
+            for i in 0..COUNT {
+                btree_access
+                    .put(order(i).to_be_bytes(), &[0xDE; 200])
+                    .unwrap();
+            }
+            let links = |page_id| {
+                let buffer = bufmgr.fetch_page(page_id).unwrap();
+                let ro_buffer = buffer.read();
+                let node_page = node::NodePage::<_>::new(ro_buffer.page.as_ref()).unwrap();
+                let leaf = node_page.node().try_into_leaf().ok().unwrap();
+                (leaf.prev_page_id(), leaf.next_page_id(), leaf.num_records())
+            };
+
+            // Every leaf is reached both ways, each `next` being matched by a
+            // `prev` pointing back.
+            let first = btree_access.start_leaf_stats(None, false).unwrap().page_id;
+            let last = btree_access.start_leaf_stats(None, true).unwrap().page_id;
+            let mut forward = vec![];
+            let mut records = 0;
+            let mut prev = None;
+            let mut next = Some(first);
+            while let Some(page_id) = next {
+                let (prev_page_id, next_page_id, num_records) = links(page_id);
+                assert_eq!(prev, prev_page_id);
+                forward.push(page_id);
+                records += num_records;
+                prev = Some(page_id);
+                next = next_page_id;
+            }
+            assert_eq!(Some(last), prev);
+            assert_eq!(COUNT as usize, records);
+            assert!(forward.len() > 100, "{} leaves", forward.len());
+
+            let mut backward = vec![];
+            let mut next = None;
+            let mut prev = Some(last);
+            while let Some(page_id) = prev {
+                let (prev_page_id, next_page_id, _) = links(page_id);
+                assert_eq!(next, next_page_id);
+                backward.push(page_id);
+                next = Some(page_id);
+                prev = prev_page_id;
+            }
+            backward.reverse();
+            assert_eq!(forward, backward);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let btree_access = Access::create(&bufmgr).unwrap();
+        // Out of order, so that leaves split in the middle too.
+        for i in 0..5000u64 {
+            let key = (i * 7919 % 5000).to_be_bytes();
+            btree_access.put(key, &[1; 100]).unwrap();
+        }
+        btree_access
+            .delete_range(1000u64.to_be_bytes(), 2000u64.to_be_bytes())
+            .unwrap();
+        let verification = btree_access.verify().unwrap();
+        assert_eq!(Vec::<Problem>::new(), verification.problems);
+        let leaves = btree_access.leaf_count().unwrap() as usize;
+        // The meta page, the root and the leaves.
+        assert_eq!(leaves + 2, verification.pages.len());
+
+        let with_leaf = |key: u64, f: &dyn Fn(&mut leaf::Leaf<&mut [u8]>)| {
+            let page_id = btree_access.leaf_stats(key.to_be_bytes()).unwrap().page_id;
+            let buffer = bufmgr.fetch_page(page_id).unwrap();
+            let mut rw_buffer = buffer.write();
+            let mut node_page = node::NodePage::<_>::new(rw_buffer.page.as_mut()).unwrap();
+            f(&mut node_page.node_mut().try_into_leaf().ok().unwrap());
+            page_id
+        };
+        // A key that belongs in a later leaf, and a link skipping a leaf.
+        let unsorted = with_leaf(0, &|leaf| assert!(leaf.put(4999u64.to_be_bytes(), b"")));
+        let unlinked = with_leaf(4999, &|leaf| leaf.set_prev_page_id(None));
+        assert_eq!(
+            vec![
+                Problem::Unsorted { page_id: unsorted },
+                Problem::BrokenLink { page_id: unlinked },
+            ],
+            btree_access.verify().unwrap().problems
+        );
+    }
 }
@@ -1,9 +1,14 @@
-use std::{ops::Range, convert::TryInto, mem::size_of, ops::{Deref, DerefMut}};
+use std::{
+    convert::TryInto,
+    mem::size_of,
+    ops::Range,
+    ops::{Deref, DerefMut},
+};
 
 use zerocopy::{AsBytes, ByteSlice, ByteSliceMut, FromBytes, LayoutVerified};
 
-use crate::disk::PageId;
 use super::Key;
+use crate::disk::PageId;
 
 #[derive(Debug, FromBytes, AsBytes)]
 #[repr(C)]
@@ -11,15 +16,16 @@ pub struct Header {
     num_pairs: u16,
 }
 
-pub struct Pair<T> {
+/// A key of `N` bytes and the child holding the keys from it onwards.
+pub struct Pair<T, const N: usize = 8> {
     data: T,
 }
 
-impl Pair<()> {
-    const SIZE: usize = size_of::<Key>() + size_of::<PageId>();
+impl<const N: usize> Pair<(), N> {
+    const SIZE: usize = N + size_of::<PageId>();
 
     fn offset(index: usize) -> usize {
-        index as usize * Self::SIZE
+        index * Self::SIZE
     }
 
     fn range(range: Range<usize>) -> Range<usize> {
@@ -27,75 +33,102 @@ impl Pair<()> {
     }
 }
 
-impl<'a> Pair<&'a [u8]> {
+impl<'a, const N: usize> Pair<&'a [u8], N> {
     fn read(slice: &'a [u8], index: usize) -> Self {
         Pair {
-            data: &slice[Pair::range(index..index + 1)]
+            data: &slice[Pair::<(), N>::range(index..index + 1)],
         }
     }
 }
 
-impl<'a> Pair<&'a mut [u8]> {
+impl<'a, const N: usize> Pair<&'a mut [u8], N> {
     fn read_mut(slice: &'a mut [u8], index: usize) -> Self {
         Pair {
-            data: &mut slice[Pair::range(index..index + 1)]
+            data: &mut slice[Pair::<(), N>::range(index..index + 1)],
         }
     }
 }
 
-impl<T> Pair<T>
+impl<T, const N: usize> Pair<T, N>
 where
-    T: Deref<Target = [u8]>
+    T: Deref<Target = [u8]>,
 {
-    pub fn key(&self) -> Key {
-        self.data[..size_of::<Key>()].try_into().unwrap()
+    pub fn key(&self) -> Key<N> {
+        self.data[..N].try_into().unwrap()
     }
 
     pub fn child(&self) -> PageId {
-        let bytes: [u8; 8] = self.data[size_of::<Key>()..].try_into().unwrap();
+        let bytes: [u8; 8] = self.data[N..].try_into().unwrap();
         bytes.into()
     }
 }
 
-impl<T> Pair<T>
+impl<T, const N: usize> Pair<T, N>
 where
-    T: DerefMut<Target = [u8]>
+    T: DerefMut<Target = [u8]>,
 {
-    pub fn set_key(&mut self, key: Key) {
-        self.data[..size_of::<Key>()].copy_from_slice(&key);
+    pub fn set_key(&mut self, key: Key<N>) {
+        self.data[..N].copy_from_slice(&key);
     }
 
     pub fn set_child(&mut self, child: PageId) {
         let bytes: [u8; 8] = child.into();
-        self.data[size_of::<Key>()..].copy_from_slice(&bytes);
+        self.data[N..].copy_from_slice(&bytes);
     }
 }
 
-pub struct Branch<B> {
+pub struct Branch<B, const N: usize = 8> {
     header: LayoutVerified<B, Header>,
     body: B,
 }
 
-impl<B: ByteSlice> Branch<B> {
+impl<B: ByteSlice, const N: usize> Branch<B, N> {
     pub fn new(bytes: B) -> Option<Self> {
         let (header, body) = LayoutVerified::new_from_prefix(bytes)?;
         Some(Self { header, body })
     }
 
-    pub fn pair(&self, index: usize) -> Pair<&[u8]> {
+    pub fn pair(&self, index: usize) -> Pair<&[u8], N> {
         Pair::read(&self.body, index)
     }
 
+    /// Bytes available to pairs.
+    pub fn usable_bytes(&self) -> usize {
+        self.body.len()
+    }
+
     pub fn max_pairs(&self) -> usize {
-        self.body.len() / Pair::SIZE
+        self.usable_bytes() / Pair::<(), N>::SIZE
     }
 
     pub fn num_pairs(&self) -> usize {
         self.header.num_pairs as usize
     }
 
-    pub fn find(&self, key: Key)  -> usize {
+    /// Whether the pairs can be read without panicking, which those of a
+    /// damaged page may not.
+    pub fn is_readable(&self) -> bool {
+        (1..=self.max_pairs()).contains(&self.num_pairs())
+    }
+
+    /// Pairs in key order. The first key is unused, as everything below the
+    /// second key belongs to the first child.
+    #[allow(dead_code)]
+    pub fn iter_pairs(&self) -> impl Iterator<Item = (Key<N>, PageId)> + '_ {
+        (0..self.num_pairs()).map(move |index| {
+            let pair = self.pair(index);
+            (pair.key(), pair.child())
+        })
+    }
+
+    /// Index of the pair whose child holds `key`. A branch with a single pair,
+    /// as a split of three leaves behind, sends every key to its only child.
+    pub fn find(&self, key: Key<N>) -> usize {
         use std::cmp::Ordering::{Equal, Greater};
+        // The search below starts at the second pair.
+        if self.num_pairs() <= 1 {
+            return 0;
+        }
         let mut base = 1usize;
         let mut size = self.num_pairs() - 1;
         while size > 1 {
@@ -117,8 +150,8 @@ impl<B: ByteSlice> Branch<B> {
     }
 }
 
-impl<B: ByteSliceMut> Branch<B> {
-    pub fn initialize(&mut self, key: Key, left_child: PageId, right_child: PageId) {
+impl<B: ByteSliceMut, const N: usize> Branch<B, N> {
+    pub fn initialize(&mut self, key: Key<N>, left_child: PageId, right_child: PageId) {
         self.header.num_pairs = 2;
         self.pair_mut(0).set_child(left_child);
         let mut right = self.pair_mut(1);
@@ -126,27 +159,30 @@ impl<B: ByteSliceMut> Branch<B> {
         right.set_child(right_child);
     }
 
-    pub fn pair_mut(&mut self, index: usize) -> Pair<&mut [u8]> {
+    pub fn pair_mut(&mut self, index: usize) -> Pair<&mut [u8], N> {
         Pair::read_mut(&mut self.body, index)
     }
 
-    pub fn insert(&mut self, index: usize, key: Key, child: PageId) {
+    pub fn insert(&mut self, index: usize, key: Key<N>, child: PageId) {
         let num_children = self.num_pairs();
-        self.body.copy_within(Pair::range(index..num_children), Pair::offset(index + 1));
+        self.body.copy_within(
+            Pair::<(), N>::range(index..num_children),
+            Pair::<(), N>::offset(index + 1),
+        );
         let mut pair = self.pair_mut(index);
         pair.set_key(key);
         pair.set_child(child);
         self.header.num_pairs += 1;
     }
 
-    pub fn split(&mut self, new_branch: &mut Branch<B>) -> Key {
+    pub fn split(&mut self, new_branch: &mut Branch<B, N>) -> Key<N> {
         let num_keys = self.num_pairs();
-        let mid = num_keys  / 2;
+        let mid = num_keys / 2;
         let mid_key = self.pair(mid).key();
-        let src = &self.body[Pair::range(mid..num_keys)];
-        new_branch.body[0..src.len()].copy_from_slice(&src);
+        let src = &self.body[Pair::<(), N>::range(mid..num_keys)];
+        new_branch.body[0..src.len()].copy_from_slice(src);
         new_branch.header.num_pairs = (num_keys - mid) as u16;
-        self.header.num_pairs = (mid - 1) as u16;
+        self.header.num_pairs = mid as u16;
         mid_key
     }
 }
@@ -171,6 +207,43 @@ mod tests {
         assert_eq!(3, branch.find(12u64.to_be_bytes()));
     }
 
+    #[test]
+    fn test_find_single_pair() {
+        let mut data = vec![0u8; 100];
+        let mut new_data = vec![0u8; 100];
+        let mut branch = Branch::new(data.as_mut_slice()).unwrap();
+        let mut new_branch = Branch::new(new_data.as_mut_slice()).unwrap();
+        branch.initialize(5u64.to_be_bytes(), PageId(1), PageId(2));
+        branch.insert(2, 8u64.to_be_bytes(), PageId(3));
+        assert_eq!(5u64.to_be_bytes(), branch.split(&mut new_branch));
+        assert_eq!(1, branch.num_pairs());
+        for probe in [0, 1, 5, 8, u64::MAX] {
+            assert_eq!(0, branch.find(probe.to_be_bytes()));
+        }
+        assert_eq!(
+            PageId(1),
+            branch.pair(branch.find(9u64.to_be_bytes())).child()
+        );
+    }
+
+    #[test]
+    fn test_iter_pairs() {
+        let mut data = vec![0u8; 100];
+        let mut branch = Branch::new(data.as_mut_slice()).unwrap();
+        branch.initialize(5u64.to_be_bytes(), PageId(1), PageId(2));
+        branch.insert(2, 8u64.to_be_bytes(), PageId(3));
+        branch.insert(3, 11u64.to_be_bytes(), PageId(4));
+        let pairs = branch.iter_pairs().collect::<Vec<_>>();
+        assert_eq!(
+            vec![PageId(1), PageId(2), PageId(3), PageId(4)],
+            pairs.iter().map(|(_, child)| *child).collect::<Vec<_>>()
+        );
+        for (index, (key, child)) in pairs.into_iter().enumerate().skip(1) {
+            assert_eq!(index, branch.find(key));
+            assert_eq!(child, branch.pair(branch.find(key)).child());
+        }
+    }
+
     #[test]
     fn test_split() {
         let mut data = vec![0u8; 100];
@@ -182,6 +255,8 @@ mod tests {
         let mut branch2 = Branch::new(data2.as_mut_slice()).unwrap();
         let mid_key = branch.split(&mut branch2);
         assert_eq!(8u64.to_be_bytes(), mid_key);
+        assert_eq!(2, branch.num_pairs());
+        assert_eq!(2, branch2.num_pairs());
         assert_eq!(0, branch.find(1u64.to_be_bytes()));
         assert_eq!(1, branch.find(5u64.to_be_bytes()));
         assert_eq!(1, branch.find(6u64.to_be_bytes()));
@@ -191,4 +266,25 @@ mod tests {
         assert_eq!(1, branch2.find(11u64.to_be_bytes()));
         assert_eq!(1, branch2.find(12u64.to_be_bytes()));
     }
+
+    #[test]
+    fn test_fill() {
+        let mut data = vec![0u8; 100];
+        let mut branch = Branch::new(data.as_mut_slice()).unwrap();
+        branch.initialize(1u64.to_be_bytes(), PageId(1), PageId(2));
+        while branch.num_pairs() < branch.max_pairs() {
+            let num_pairs = branch.num_pairs() as u64;
+            branch.insert(
+                num_pairs as usize,
+                num_pairs.to_be_bytes(),
+                PageId(num_pairs + 1),
+            );
+        }
+        assert!(branch.num_pairs() * Pair::<(), 8>::SIZE <= branch.usable_bytes());
+        assert!((branch.num_pairs() + 1) * Pair::<(), 8>::SIZE > branch.usable_bytes());
+        assert_eq!(
+            PageId(branch.num_pairs() as u64),
+            branch.pair(branch.num_pairs() - 1).child()
+        );
+    }
 }
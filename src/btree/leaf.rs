@@ -1,6 +1,4 @@
-use std::{
-    mem::size_of,
-};
+use std::{convert::TryInto, mem::size_of};
 
 use zerocopy::{AsBytes, ByteSlice, ByteSliceMut, FromBytes, LayoutVerified};
 
@@ -14,34 +12,42 @@ pub struct Header {
     prev_page_id: PageId,
     next_page_id: PageId,
 }
-pub struct Record<B> {
-    key: LayoutVerified<B, Key>,
+/// A key of `N` bytes followed by its value.
+pub struct Record<B, const N: usize = 8> {
+    key: B,
     pub value: B,
 }
 
-impl<B: ByteSlice> Record<B> {
+impl<B: ByteSlice, const N: usize> Record<B, N> {
     pub fn new(bytes: B) -> Option<Self> {
-        let (key, value) = LayoutVerified::new_from_prefix(bytes)?;
+        if bytes.len() < N {
+            return None;
+        }
+        let (key, value) = bytes.split_at(N);
         Some(Self { key, value })
     }
 
     pub fn len(&self) -> usize {
-        size_of::<Key>() + self.value.len()
+        N + self.value.len()
     }
 
-    pub fn key(&self) -> Key {
-        let mut key = Key::default();
-        key.copy_from_slice(&self.key[..]);
-        key
+    pub fn key(&self) -> Key<N> {
+        self.key[..].try_into().unwrap()
     }
 }
 
-pub struct Leaf<B> {
+/// Bytes taken by a record with an `N`-byte key and a value `value_len`
+/// bytes long, including its slot pointer.
+pub fn record_size<const N: usize>(value_len: usize) -> usize {
+    size_of::<slotted::Pointer>() + N + value_len
+}
+
+pub struct Leaf<B, const N: usize = 8> {
     header: LayoutVerified<B, Header>,
     body: Slotted<B>,
 }
 
-impl<B: ByteSlice> Leaf<B> {
+impl<B: ByteSlice, const N: usize> Leaf<B, N> {
     pub fn new(bytes: B) -> Option<Self> {
         let (header, body) = LayoutVerified::new_from_prefix(bytes)?;
         let body = Slotted::new(body)?;
@@ -60,7 +66,7 @@ impl<B: ByteSlice> Leaf<B> {
         self.body.num_slots()
     }
 
-    pub fn find(&self, key: Key) -> Result<usize, usize> {
+    pub fn find(&self, key: Key<N>) -> Result<usize, usize> {
         use std::cmp::Ordering::{Equal, Less};
         if self.num_records() == 0 {
             return Err(0);
@@ -70,14 +76,14 @@ impl<B: ByteSlice> Leaf<B> {
         while size > 1 {
             let half = size / 2;
             let mid = base + half;
-            base = if self.record(mid).key.as_ref() > key.as_ref() {
+            base = if self.record(mid).key > &key[..] {
                 base
             } else {
                 mid
             };
             size -= half;
         }
-        let cmp = self.record(base).key.cmp(&key);
+        let cmp = self.record(base).key.cmp(&key[..]);
         if cmp == Equal {
             Ok(base)
         } else {
@@ -85,21 +91,44 @@ impl<B: ByteSlice> Leaf<B> {
         }
     }
 
-    pub fn get(&self, key: Key) -> Option<&[u8]> {
+    pub fn get(&self, key: Key<N>) -> Option<&[u8]> {
         let slot_id = self.find(key).ok()?;
-        Some(&self.record(slot_id).value)
+        Some(self.record(slot_id).value)
     }
 
-    pub fn record(&self, slot_id: usize) -> Record<&[u8]> {
+    pub fn record(&self, slot_id: usize) -> Record<&[u8], N> {
         Record::new(&self.body[slot_id]).unwrap()
     }
 
+    /// Whether the records can be read without panicking, which those of a
+    /// damaged page may not.
+    pub fn is_readable(&self) -> bool {
+        self.body.layout_problem().is_none()
+            && (0..self.num_records()).all(|slot_id| self.body[slot_id].len() >= N)
+    }
+
+    /// Records in key order.
+    #[allow(dead_code)]
+    pub fn iter_records(&self) -> impl Iterator<Item = Record<&[u8], N>> + '_ {
+        (0..self.num_records()).map(move |slot_id| self.record(slot_id))
+    }
+
+    /// Bytes shared by the records, so the sum of their `record_size`s and
+    /// `free_space` always equals this.
+    pub fn usable_bytes(&self) -> usize {
+        self.body.capacity()
+    }
+
+    pub fn free_space(&self) -> usize {
+        self.body.free_space()
+    }
+
     pub fn max_value_size(&self) -> usize {
-        self.body.capacity() / 2 - size_of::<slotted::Pointer>() - size_of::<Key>()
+        self.usable_bytes() / 2 - record_size::<N>(0)
     }
 }
 
-impl<B: ByteSliceMut> Leaf<B> {
+impl<B: ByteSliceMut, const N: usize> Leaf<B, N> {
     pub fn initialize(&mut self) {
         self.header.prev_page_id = PageId::INVALID_PAGE_ID;
         self.header.next_page_id = PageId::INVALID_PAGE_ID;
@@ -114,29 +143,25 @@ impl<B: ByteSliceMut> Leaf<B> {
         self.header.next_page_id = next_page_id.into()
     }
 
-    fn record_mut(&mut self, slot_id: usize) -> Record<&mut [u8]> {
+    fn record_mut(&mut self, slot_id: usize) -> Record<&mut [u8], N> {
         Record::new(&mut self.body[slot_id]).unwrap()
     }
 
     #[must_use = "insertion may fail"]
-    pub fn put(&mut self, key: Key, value: &[u8]) -> bool {
+    pub fn put(&mut self, key: Key<N>, value: &[u8]) -> bool {
         assert!(value.len() <= self.max_value_size());
         match self.find(key) {
             Ok(index) => {
-                if self
-                    .body
-                    .resize(index, size_of::<Key>() + value.len())
-                    .is_some()
-                {
-                    let mut record = self.record_mut(index);
+                if self.body.resize(index, N + value.len()).is_some() {
+                    let record = self.record_mut(index);
                     record.key.copy_from_slice(&key);
                     record.value.copy_from_slice(value);
                     return true;
                 }
             }
             Err(index) => {
-                if self.body.insert(index, size_of::<Key>() + value.len()).is_some() {
-                    let mut record = self.record_mut(index);
+                if self.body.insert(index, N + value.len()).is_some() {
+                    let record = self.record_mut(index);
                     record.key.copy_from_slice(&key);
                     record.value.copy_from_slice(value);
                     return true;
@@ -146,30 +171,76 @@ impl<B: ByteSliceMut> Leaf<B> {
         false
     }
 
-    fn allocate_last(&mut self, len: usize) -> Record<&mut [u8]> {
+    pub fn remove(&mut self, key: Key<N>) -> bool {
+        match self.find(key) {
+            Ok(index) => {
+                self.body.remove(index);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Removes the records with keys in `[start, end)`. Returns how many were
+    /// removed.
+    pub fn remove_range(&mut self, start: Key<N>, end: Key<N>) -> usize {
+        let index = match self.find(start) {
+            Ok(index) | Err(index) => index,
+        };
+        let mut removed = 0;
+        while index < self.num_records() && self.record(index).key() < end {
+            self.body.remove(index);
+            removed += 1;
+        }
+        removed
+    }
+
+    fn allocate_last(&mut self, len: usize) -> Record<&mut [u8], N> {
         let next = self.num_records();
         self.body.insert(next, len).unwrap();
         self.record_mut(next)
     }
 
-    fn push_record(&mut self, record: &Record<&[u8]>) {
-        let mut target = self.allocate_last(record.len());
-        target.key.copy_from_slice(record.key.as_ref());
+    fn push_record(&mut self, record: &Record<&[u8], N>) {
+        let target = self.allocate_last(record.len());
+        target.key.copy_from_slice(record.key);
         target.value.copy_from_slice(record.value);
     }
 
-    fn push_key_value(&mut self, key: Key, value: &[u8]) {
+    fn push_key_value(&mut self, key: Key<N>, value: &[u8]) {
         let record = Record {
-            key: LayoutVerified::new(&key[..]).unwrap(),
-            value
+            key: &key[..],
+            value,
         };
         self.push_record(&record);
     }
 
-    pub fn split_put(&mut self, new_leaf: &mut Leaf<B>, new_key: Key, new_value: &[u8]) -> Key {
+    /// Whether `self` holds at most `fill_factor` percent of the bytes in
+    /// `self` and `new_leaf` together.
+    fn is_balanced(&self, new_leaf: &Leaf<B, N>, fill_factor: u8) -> bool {
+        let used = self.usable_bytes() - self.free_space();
+        let new_used = new_leaf.usable_bytes() - new_leaf.free_space();
+        used * (100 - fill_factor as usize) < new_used * fill_factor as usize
+    }
+
+    /// Moves the upper records to `new_leaf` until `self` holds about
+    /// `fill_factor` percent of the bytes, which must be between 50 and 100,
+    /// and puts the new record in whichever leaf it belongs to.
+    pub fn split_put(
+        &mut self,
+        new_leaf: &mut Leaf<B, N>,
+        new_key: Key<N>,
+        new_value: &[u8],
+        fill_factor: u8,
+    ) -> Key<N> {
         use std::cmp::Ordering;
+        assert!((50..=100).contains(&fill_factor));
         loop {
-            if self.body.free_space() > new_leaf.body.free_space() {
+            // Above 50 percent, `self` may be balanced yet too full for the
+            // new record.
+            if self.is_balanced(new_leaf, fill_factor)
+                && self.free_space() >= record_size::<N>(new_value.len())
+            {
                 break;
             }
             let num_records = self.num_records();
@@ -178,7 +249,7 @@ impl<B: ByteSliceMut> Leaf<B> {
             }
             let last = num_records - 1;
             let record = self.record(last);
-            let cmp = new_key.cmp(&record.key);
+            let cmp = new_key[..].cmp(record.key);
             if cmp == Ordering::Less {
                 new_leaf.push_record(&record);
                 self.body.remove(last);
@@ -188,7 +259,7 @@ impl<B: ByteSliceMut> Leaf<B> {
                     self.body.remove(last);
                 }
                 loop {
-                    if self.body.free_space() > new_leaf.body.free_space() {
+                    if self.is_balanced(new_leaf, fill_factor) {
                         break;
                     }
                     let num_records = self.num_records();
@@ -197,6 +268,11 @@ impl<B: ByteSliceMut> Leaf<B> {
                     }
                     let last = num_records - 1;
                     let record = self.record(last);
+                    // The new record may have left too little room to even
+                    // out the halves any further.
+                    if new_leaf.free_space() < record_size::<N>(record.value.len()) {
+                        break;
+                    }
                     new_leaf.push_record(&record);
                     self.body.remove(last);
                 }
@@ -214,8 +290,23 @@ impl<B: ByteSliceMut> Leaf<B> {
 
 #[cfg(test)]
 mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
     use super::*;
 
+    fn assert_consistent<B: ByteSlice>(leaf: &Leaf<B>) {
+        let used: usize = leaf
+            .iter_records()
+            .map(|record| record_size::<8>(record.value.len()))
+            .sum();
+        assert_eq!(leaf.usable_bytes(), used + leaf.free_space());
+        let keys = leaf
+            .iter_records()
+            .map(|record| record.key())
+            .collect::<Vec<_>>();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
     #[test]
     fn test_leaf_find() {
         let mut page_data = vec![0; 100];
@@ -243,6 +334,32 @@ mod tests {
         assert_eq!(Some(&b"hello"[..]), leaf_page.get(*b"beefdead"));
     }
 
+    #[test]
+    fn test_iter_records() {
+        let mut page_data = vec![0; 200];
+        let mut leaf_page = Leaf::new(page_data.as_mut_slice()).unwrap();
+        leaf_page.initialize();
+        assert_eq!(0, leaf_page.iter_records().count());
+        assert!(leaf_page.put(*b"deadbeef", b"world"));
+        assert!(leaf_page.put(*b"facebook", b"!"));
+        assert!(leaf_page.put(*b"beefdead", b"hello"));
+        let records = leaf_page
+            .iter_records()
+            .map(|record| (record.key(), record.value.to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (*b"beefdead", b"hello".to_vec()),
+                (*b"deadbeef", b"world".to_vec()),
+                (*b"facebook", b"!".to_vec()),
+            ],
+            records
+        );
+        for (key, value) in records {
+            assert_eq!(Some(&value[..]), leaf_page.get(key));
+        }
+    }
+
     #[test]
     fn test_leaf_split_insert() {
         let mut page_data = vec![0; 54];
@@ -255,7 +372,94 @@ mod tests {
         let mut new_page_data = vec![0; 54];
         let mut new_leaf_page = Leaf::new(new_page_data.as_mut_slice()).unwrap();
         new_leaf_page.initialize();
-        leaf_page.split_put(&mut new_leaf_page, *b"beefdead", b"hello");
+        leaf_page.split_put(&mut new_leaf_page, *b"beefdead", b"hello", 50);
         assert_eq!(Some(&b"world"[..]), leaf_page.get(*b"deadbeef"));
     }
+
+    #[test]
+    fn test_leaf_fill_exactly() {
+        let mut page_data = vec![0; 200];
+        let mut leaf_page = Leaf::new(page_data.as_mut_slice()).unwrap();
+        leaf_page.initialize();
+        assert_eq!(leaf_page.usable_bytes(), leaf_page.free_space());
+        let mut key = 0u64;
+        while leaf_page.free_space() > record_size::<8>(leaf_page.max_value_size()) {
+            assert!(leaf_page.put(key.to_be_bytes(), &[0xAB; 10]));
+            key += 1;
+        }
+        let last_value_len = leaf_page.free_space() - record_size::<8>(0);
+        assert!(!leaf_page.put(key.to_be_bytes(), &vec![0xAB; last_value_len + 1]));
+        assert!(leaf_page.put(key.to_be_bytes(), &vec![0xAB; last_value_len]));
+        assert_eq!(0, leaf_page.free_space());
+        assert!(!leaf_page.put((key + 1).to_be_bytes(), b""));
+        assert_consistent(&leaf_page);
+    }
+
+    #[test]
+    fn test_leaf_empty_values() {
+        let mut page_data = vec![0; 256];
+        let mut leaf_page = Leaf::new(page_data.as_mut_slice()).unwrap();
+        leaf_page.initialize();
+        let mut key = 0u64;
+        while leaf_page.put((key * 2).to_be_bytes(), b"") {
+            key += 1;
+        }
+        assert_eq!(key as usize, leaf_page.num_records());
+        assert_eq!(Some(&b""[..]), leaf_page.get(0u64.to_be_bytes()));
+        assert_eq!(None, leaf_page.get(1u64.to_be_bytes()));
+        assert_consistent(&leaf_page);
+
+        let mut new_page_data = vec![0; 256];
+        let mut new_leaf_page = Leaf::new(new_page_data.as_mut_slice()).unwrap();
+        new_leaf_page.initialize();
+        leaf_page.split_put(&mut new_leaf_page, 3u64.to_be_bytes(), b"", 50);
+        assert_consistent(&leaf_page);
+        assert_consistent(&new_leaf_page);
+        assert_eq!(
+            key as usize + 1,
+            leaf_page.num_records() + new_leaf_page.num_records()
+        );
+        for leaf in [&leaf_page, &new_leaf_page] {
+            assert!(leaf.iter_records().all(|record| record.value.is_empty()));
+        }
+        assert_eq!(Some(&b""[..]), leaf_page.get(3u64.to_be_bytes()));
+    }
+
+    #[test]
+    fn test_leaf_split_put_random() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let mut page_data = vec![0; 256];
+            let mut leaf_page = Leaf::new(page_data.as_mut_slice()).unwrap();
+            leaf_page.initialize();
+            let max_value_size = leaf_page.max_value_size();
+            let (key, value) = loop {
+                let key = rng.gen::<u64>().to_be_bytes();
+                let value = vec![0xAB; rng.gen_range(0, max_value_size + 1)];
+                if !leaf_page.put(key, &value) {
+                    break (key, value);
+                }
+                assert_consistent(&leaf_page);
+            };
+            let num_records = leaf_page.num_records();
+            let mut new_page_data = vec![0; 256];
+            let mut new_leaf_page = Leaf::new(new_page_data.as_mut_slice()).unwrap();
+            new_leaf_page.initialize();
+            let fill_factor = rng.gen_range(50, 101);
+            let new_leaf_first_key =
+                leaf_page.split_put(&mut new_leaf_page, key, &value, fill_factor);
+
+            assert_consistent(&leaf_page);
+            assert_consistent(&new_leaf_page);
+            assert!(leaf_page.num_records() > 0 && new_leaf_page.num_records() > 0);
+            assert_eq!(new_leaf_first_key, new_leaf_page.record(0).key());
+            assert!(leaf_page.record(leaf_page.num_records() - 1).key() < new_leaf_first_key);
+            assert_eq!(
+                num_records + 1,
+                leaf_page.num_records() + new_leaf_page.num_records()
+            );
+            let stored = leaf_page.get(key).or_else(|| new_leaf_page.get(key));
+            assert_eq!(Some(&value[..]), stored);
+        }
+    }
 }
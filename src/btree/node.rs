@@ -14,18 +14,23 @@ pub enum NodeType {
 #[repr(C)]
 pub struct Header {
     node_type: u8,
-    _pad: [u8; 7],
+    checksum: [u8; 4],
+    _pad: [u8; 3],
 }
 
 impl Header {
-    fn node_type(&self) -> NodeType {
+    fn try_node_type(&self) -> Option<NodeType> {
         if self.node_type == NodeType::Leaf as u8 {
-            return NodeType::Leaf;
+            return Some(NodeType::Leaf);
         }
         if self.node_type == NodeType::Branch as u8 {
-            return NodeType::Branch;
+            return Some(NodeType::Branch);
         }
-        unreachable!()
+        None
+    }
+
+    fn node_type(&self) -> NodeType {
+        self.try_node_type().unwrap_or_else(|| unreachable!())
     }
 
     fn set_node_type(&mut self, node_type: NodeType) {
@@ -33,18 +38,42 @@ impl Header {
     }
 }
 
-pub struct NodePage<B> {
+pub struct NodePage<B, const N: usize = 8> {
     header: LayoutVerified<B, Header>,
     body: B,
 }
 
-impl<B: ByteSlice> NodePage<B> {
+impl<B: ByteSlice, const N: usize> NodePage<B, N> {
     pub fn new(bytes: B) -> Option<Self> {
         let (header, body) = LayoutVerified::new_from_prefix(bytes)?;
         Some(Self { header, body })
     }
 
-    pub fn node(&self) -> Node<&[u8]> {
+    /// Whether the page is tagged as a node at all, which a meta page or a
+    /// damaged one is not.
+    pub fn is_node(&self) -> bool {
+        self.header.try_node_type().is_some()
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.header.try_node_type() == Some(NodeType::Leaf)
+    }
+
+    /// As last set by `set_checksum`, or zero if the node was never stamped.
+    pub fn checksum(&self) -> u32 {
+        u32::from_be_bytes(self.header.checksum)
+    }
+
+    /// FNV-1a over the body, which is never zero so that zero can stand for
+    /// no checksum.
+    pub fn compute_checksum(&self) -> u32 {
+        let hash = self.body.iter().fold(0x811c_9dc5u32, |hash, &byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        });
+        hash.max(1)
+    }
+
+    pub fn node(&self) -> Node<&[u8], N> {
         match self.header.node_type() {
             NodeType::Leaf => Node::Leaf(Leaf::new(self.body.deref()).unwrap()),
             NodeType::Branch => Node::Branch(Branch::new(self.body.deref()).unwrap()),
@@ -52,18 +81,31 @@ impl<B: ByteSlice> NodePage<B> {
     }
 }
 
-impl<B: ByteSliceMut> NodePage<B> {
-    pub fn initialize_as_leaf(&mut self) -> Leaf<&mut [u8]> {
-        self.header.set_node_type(NodeType::Leaf);
+impl<B: ByteSliceMut, const N: usize> NodePage<B, N> {
+    /// Zeroes the whole page, so that nothing of its previous occupant
+    /// lingers in the regions the new node has yet to fill.
+    fn reset(&mut self, node_type: NodeType) {
+        self.header.set_node_type(node_type);
+        self.header.checksum = [0; 4];
+        self.header._pad = [0; 3];
+        self.body.fill(0);
+    }
+
+    pub fn set_checksum(&mut self, checksum: u32) {
+        self.header.checksum = checksum.to_be_bytes();
+    }
+
+    pub fn initialize_as_leaf(&mut self) -> Leaf<&mut [u8], N> {
+        self.reset(NodeType::Leaf);
         Leaf::new(self.body.deref_mut()).unwrap()
     }
 
-    pub fn initialize_as_branch(&mut self) -> Branch<&mut [u8]> {
-        self.header.set_node_type(NodeType::Branch);
+    pub fn initialize_as_branch(&mut self) -> Branch<&mut [u8], N> {
+        self.reset(NodeType::Branch);
         Branch::new(self.body.deref_mut()).unwrap()
     }
 
-    pub fn node_mut(&mut self) -> Node<&mut [u8]> {
+    pub fn node_mut(&mut self) -> Node<&mut [u8], N> {
         match self.header.node_type() {
             NodeType::Leaf => Node::Leaf(Leaf::new(self.body.deref_mut()).unwrap()),
             NodeType::Branch => Node::Branch(Branch::new(self.body.deref_mut()).unwrap()),
@@ -71,13 +113,13 @@ impl<B: ByteSliceMut> NodePage<B> {
     }
 }
 
-pub enum Node<T> {
-    Leaf(Leaf<T>),
-    Branch(Branch<T>),
+pub enum Node<T, const N: usize = 8> {
+    Leaf(Leaf<T, N>),
+    Branch(Branch<T, N>),
 }
 
-impl<T> Node<T> {
-    pub fn try_into_leaf(self) -> Result<Leaf<T>, Self> {
+impl<T, const N: usize> Node<T, N> {
+    pub fn try_into_leaf(self) -> Result<Leaf<T, N>, Self> {
         match self {
             Node::Leaf(leaf) => Ok(leaf),
             _ => Err(self),
@@ -85,10 +127,39 @@ impl<T> Node<T> {
     }
 
     #[allow(dead_code)]
-    pub fn try_into_branch(self) -> Result<Branch<T>, Self> {
+    pub fn try_into_branch(self) -> Result<Branch<T, N>, Self> {
         match self {
             Node::Branch(branch) => Ok(branch),
             _ => Err(self),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_initialize_zeroes_stale_bytes() {
+        let mut page = vec![0u8; 256];
+        let write_leaf = |page: &mut [u8]| {
+            let mut node_page = NodePage::<_>::new(page).unwrap();
+            let mut leaf = node_page.initialize_as_leaf();
+            leaf.initialize();
+            assert!(leaf.put(1u64.to_be_bytes(), b"stale"));
+        };
+
+        write_leaf(&mut page);
+        let mut node_page = NodePage::<_>::new(page.as_mut_slice()).unwrap();
+        let branch = node_page.initialize_as_branch();
+        assert_eq!(0, branch.num_pairs());
+        assert!(page[1..].iter().all(|&byte| byte == 0));
+
+        write_leaf(&mut page);
+        let mut node_page = NodePage::<_>::new(page.as_mut_slice()).unwrap();
+        let mut leaf = node_page.initialize_as_leaf();
+        leaf.initialize();
+        assert_eq!(0, leaf.num_records());
+        assert!(!page.windows(5).any(|window| window == b"stale"));
+    }
+}
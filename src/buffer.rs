@@ -1,9 +1,19 @@
-use std::{collections::HashMap, io, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Mutex, RawRwLock, RwLock};
 use thiserror::Error;
 
-use crate::disk::{DiskManager, PageId, PAGE_SIZE};
+use crate::{
+    disk::{self, Disk, PageId, PAGE_SIZE},
+    latch::{OwnedRwLockExt, OwnedRwLockWriteGuard},
+};
 
 pub type Page = [u8; PAGE_SIZE];
 
@@ -11,158 +21,484 @@ pub type Page = [u8; PAGE_SIZE];
 pub enum Error {
     #[error(transparent)]
     Io(#[from] io::Error),
-    #[error("no free buffer available in buffer pool")]
-    NoFreeBuffer,
+    #[error(transparent)]
+    Disk(#[from] disk::Error),
+    /// Every frame is pinned. If they are pinned for longer than a request,
+    /// a page handle is being leaked; otherwise the pool is too small for
+    /// the load.
+    #[error("no free buffer available in buffer pool ({pinned} of {pool_size} frames pinned)")]
+    NoFreeBuffer { pool_size: usize, pinned: usize },
+    #[error("database is open read-only")]
+    ReadOnly,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct BufferId(usize);
 
+type DirtyBuffers = Arc<Mutex<HashSet<BufferId>>>;
+
 #[derive(Debug)]
+#[repr(C, align(8))]
 pub struct Buffer {
     pub page: Page,
-    pub is_dirty: bool,
+    /// The page held in the buffer. Kept under the buffer's latch so that it
+    /// always agrees with `page`.
+    page_id: PageId,
+    is_dirty: bool,
+    buffer_id: BufferId,
+    dirty_buffers: DirtyBuffers,
 }
 
-impl Default for Buffer {
-    fn default() -> Self {
+impl Buffer {
+    fn new(buffer_id: BufferId, dirty_buffers: DirtyBuffers) -> Self {
         Self {
             page: [0u8; PAGE_SIZE],
+            page_id: PageId::default(),
             is_dirty: false,
+            buffer_id,
+            dirty_buffers,
+        }
+    }
+
+    pub fn page_id(&self) -> PageId {
+        self.page_id
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.is_dirty
+    }
+
+    /// Schedules the page to be written back on eviction or flush.
+    pub fn mark_dirty(&mut self) {
+        if !self.is_dirty {
+            self.is_dirty = true;
+            self.dirty_buffers.lock().insert(self.buffer_id);
         }
     }
+
+    fn mark_clean(&mut self) {
+        self.is_dirty = false;
+        self.dirty_buffers.lock().remove(&self.buffer_id);
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Frame {
-    usage_count: u64,
-    page_id: PageId,
+    usage_count: AtomicU64,
     buffer: Arc<RwLock<Buffer>>,
 }
 
+/// Counters of `fetch_page` calls since the pool was created.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Stats {
+    pub fetches: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Fetches and allocations that failed with `Error::NoFreeBuffer`.
+    pub exhaustions: u64,
+}
+
+/// `Stats` kept in atomics, so that counting a fetch takes no lock.
+#[derive(Debug, Default)]
+struct StatCounters {
+    fetches: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    exhaustions: AtomicU64,
+}
+
+impl StatCounters {
+    fn load(&self) -> Stats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Stats {
+            fetches: load(&self.fetches),
+            hits: load(&self.hits),
+            misses: load(&self.misses),
+            exhaustions: load(&self.exhaustions),
+        }
+    }
+}
+
+/// Cache hits only take the page table shared. Misses and allocations are
+/// serialized by `next_victim`, which they hold across their disk I/O, and
+/// lock the page table exclusively just long enough to pick a victim and to
+/// publish the page they loaded.
 pub struct BufferPool {
-    page_table: HashMap<PageId, BufferId>,
-    next_victim: usize,
+    page_table: RwLock<HashMap<PageId, BufferId>>,
+    next_victim: Mutex<usize>,
     buffers: Vec<Frame>,
+    /// Frames whose buffer is dirty, so that `flush` doesn't have to visit
+    /// every frame.
+    dirty_buffers: DirtyBuffers,
+    stats: StatCounters,
 }
 
 impl BufferPool {
     pub fn new(pool_size: usize) -> Self {
-        let page_table = HashMap::new();
-        let next_victim = 0;
-        let mut buffers = vec![];
-        buffers.resize_with(pool_size, Default::default);
+        let page_table = RwLock::default();
+        let next_victim = Mutex::new(0);
+        let dirty_buffers = DirtyBuffers::default();
+        let buffers = (0..pool_size)
+            .map(|index| Frame {
+                usage_count: AtomicU64::new(0),
+                buffer: Arc::new(RwLock::new(Buffer::new(
+                    BufferId(index),
+                    dirty_buffers.clone(),
+                ))),
+            })
+            .collect();
         Self {
             page_table,
             next_victim,
             buffers,
+            dirty_buffers,
+            stats: StatCounters::default(),
         }
     }
 
-    fn evict(&mut self) -> Option<(BufferId, &mut Frame)> {
+    /// Number of frames in the pool.
+    pub fn size(&self) -> usize {
+        self.buffers.len()
+    }
+
+    fn lookup(&self, page_id: PageId) -> Option<Arc<RwLock<Buffer>>> {
+        let page_table = self.page_table.read();
+        let frame = &self.buffers[page_table.get(&page_id)?.0];
+        frame.usage_count.fetch_add(1, Ordering::Relaxed);
+        Some(frame.buffer.clone())
+    }
+
+    /// Picks an unpinned buffer and unmaps its page. The buffer is returned
+    /// latched, so the caller may write it back and reuse it without holding
+    /// the page table. Its page starts with the usage of a single hit, and
+    /// the clock hand moves past it so that this isn't taken away again by
+    /// the very next eviction.
+    fn evict(
+        &self,
+        next_victim: &mut usize,
+    ) -> Result<(BufferId, OwnedRwLockWriteGuard<RawRwLock, Buffer>), Error> {
+        let mut page_table = self.page_table.write();
         let pool_size = self.buffers.len();
+        if pool_size == 0 {
+            return Err(self.exhausted());
+        }
         let mut consecutive_used = 0;
-        let victim_idx = loop {
-            let frame = &mut self.buffers[self.next_victim];
-            if frame.usage_count == 0 {
-                break self.next_victim;
-            }
-            if Arc::get_mut(&mut frame.buffer).is_some() {
-                frame.usage_count -= 1;
+        // Pins are only taken under the page table lock, so the strong count
+        // of a buffer can't grow while it is held exclusively.
+        let (victim_idx, rw_buffer) = loop {
+            let frame = &self.buffers[*next_victim];
+            let unpinned = Arc::strong_count(&frame.buffer) == 1;
+            if unpinned && frame.usage_count.load(Ordering::Relaxed) > 0 {
+                frame.usage_count.fetch_sub(1, Ordering::Relaxed);
                 consecutive_used = 0;
+            } else if let Some(rw_buffer) = unpinned
+                .then(|| frame.buffer.clone().try_write_owned())
+                .flatten()
+            {
+                break (*next_victim, rw_buffer);
             } else {
                 consecutive_used += 1;
                 if consecutive_used >= pool_size {
-                    return None;
+                    return Err(self.exhausted());
                 }
             }
-            self.next_victim = (self.next_victim + 1) % pool_size;
+            *next_victim = (*next_victim + 1) % pool_size;
         };
-        let frame = &mut self.buffers[victim_idx];
-        frame.usage_count = 1;
-        let victim_page_id = frame.page_id;
-        self.page_table.remove(&victim_page_id);
-        Some((BufferId(victim_idx), frame))
+        self.buffers[victim_idx]
+            .usage_count
+            .store(1, Ordering::Relaxed);
+        *next_victim = (victim_idx + 1) % pool_size;
+        page_table.remove(&rw_buffer.page_id);
+        Ok((BufferId(victim_idx), rw_buffer))
+    }
+
+    /// Counts the failure and describes the pins that caused it.
+    fn exhausted(&self) -> Error {
+        self.stats.exhaustions.fetch_add(1, Ordering::Relaxed);
+        let pinned = self
+            .buffers
+            .iter()
+            .filter(|frame| Arc::strong_count(&frame.buffer) > 1)
+            .count();
+        Error::NoFreeBuffer {
+            pool_size: self.buffers.len(),
+            pinned,
+        }
     }
 }
 
 pub struct BufferPoolManager {
-    disk: Mutex<DiskManager>,
-    pool: Mutex<BufferPool>,
+    disk: Box<dyn Disk>,
+    /// Held by `create_page` from checking which shard the next page id
+    /// belongs to until allocating it.
+    allocation: Mutex<()>,
+    /// Page `n` is only ever cached in shard `n % shards.len()`.
+    shards: Vec<BufferPool>,
+    read_only: bool,
+    verifies_leaves: bool,
+    max_dirty_pages: Option<usize>,
 }
 
 impl BufferPoolManager {
-    pub fn new(disk: DiskManager, pool: BufferPool) -> Self {
+    pub fn new(disk: impl Disk + 'static, pool: BufferPool) -> Self {
+        Self::with_shards(disk, vec![pool])
+    }
+
+    /// Spreads pages over independent pools by page id, so that fetches of
+    /// different pages rarely contend for the same locks.
+    pub fn with_shards(disk: impl Disk + 'static, shards: Vec<BufferPool>) -> Self {
+        assert!(!shards.is_empty());
         Self {
-            disk: Mutex::new(disk),
-            pool: Mutex::new(pool),
+            read_only: disk.is_read_only(),
+            disk: Box::new(disk),
+            allocation: Mutex::new(()),
+            shards,
+            verifies_leaves: false,
+            max_dirty_pages: None,
+        }
+    }
+
+    /// Has B-tree leaves stamped with a checksum whenever they are written
+    /// and checked against it whenever they are read, to catch pages
+    /// corrupted while cached. Costs a pass over the page each time.
+    pub fn with_leaf_checksums(mut self, enabled: bool) -> Self {
+        self.verifies_leaves = enabled;
+        self
+    }
+
+    pub fn verifies_leaves(&self) -> bool {
+        self.verifies_leaves
+    }
+
+    /// Sets how many pages may be dirty before `over_dirty_limit` asks for a
+    /// flush, bounding the work a crash can lose.
+    pub fn with_max_dirty_pages(mut self, max_dirty_pages: usize) -> Self {
+        self.max_dirty_pages = Some(max_dirty_pages);
+        self
+    }
+
+    /// Number of pages modified since they were last written back.
+    pub fn dirty_pages(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|pool| pool.dirty_buffers.lock().len())
+            .sum()
+    }
+
+    /// Whether more pages are dirty than `with_max_dirty_pages` allows. Pages
+    /// are marked dirty under their latch, which a flush would wait for, so
+    /// this is checked by callers once they hold no latch instead.
+    pub fn over_dirty_limit(&self) -> bool {
+        self.max_dirty_pages
+            .is_some_and(|max_dirty_pages| self.dirty_pages() > max_dirty_pages)
+    }
+
+    /// Whether the disk was opened read-only, in which case no page may be
+    /// created or modified.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Whether no page has been allocated yet, as in a brand-new file.
+    pub fn is_empty(&self) -> bool {
+        self.disk.is_empty()
+    }
+
+    fn shard_index(&self, PageId(page_id): PageId) -> usize {
+        (page_id % self.shards.len() as u64) as usize
+    }
+
+    fn shard(&self, page_id: PageId) -> &BufferPool {
+        &self.shards[self.shard_index(page_id)]
+    }
+
+    /// Writes back the victim's page if it is dirty. On failure the page is
+    /// mapped again, since it is still the only up-to-date copy.
+    fn write_back(
+        disk: &dyn Disk,
+        pool: &BufferPool,
+        buffer_id: BufferId,
+        rw_buffer: &mut Buffer,
+    ) -> Result<(), Error> {
+        if !rw_buffer.is_dirty {
+            return Ok(());
+        }
+        // Nothing should have been modified, and there is nowhere to write
+        // it anyway.
+        if disk.is_read_only() {
+            rw_buffer.mark_clean();
+            return Ok(());
         }
+        if let Err(err) = disk.write_page_data(rw_buffer.page_id, &rw_buffer.page) {
+            let mut page_table = pool.page_table.write();
+            page_table.insert(rw_buffer.page_id, buffer_id);
+            return Err(err.into());
+        }
+        rw_buffer.mark_clean();
+        Ok(())
     }
 
     pub fn fetch_page(&self, page_id: PageId) -> Result<Arc<RwLock<Buffer>>, Error> {
-        let mut locked_pool = self.pool.lock();
-        if let Some(&frame_id) = locked_pool.page_table.get(&page_id) {
-            let frame = &mut locked_pool.buffers[frame_id.0];
-            frame.usage_count += 1;
-            return Ok(frame.buffer.clone());
-        }
-        let (frame_id, frame) = locked_pool.evict().ok_or(Error::NoFreeBuffer)?;
-        let evict_page_id = frame.page_id;
-        {
-            let buffer = Arc::get_mut(&mut frame.buffer).unwrap().get_mut();
-            let mut locked_disk = self.disk.lock();
-            if buffer.is_dirty {
-                locked_disk.write_page_data(evict_page_id, &buffer.page)?;
-            }
-            frame.page_id = page_id;
-            buffer.is_dirty = false;
-            locked_disk.read_page_data(page_id, &mut buffer.page)?;
+        let pool = self.shard(page_id);
+        pool.stats.fetches.fetch_add(1, Ordering::Relaxed);
+        if let Some(buffer) = pool.lookup(page_id) {
+            pool.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(buffer);
+        }
+        let mut next_victim = pool.next_victim.lock();
+        // Another miss may have loaded the page while we waited.
+        if let Some(buffer) = pool.lookup(page_id) {
+            pool.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(buffer);
         }
-        let page = Arc::clone(&frame.buffer);
-        locked_pool.page_table.remove(&evict_page_id);
-        locked_pool.page_table.insert(page_id, frame_id);
-        Ok(page)
+        pool.stats.misses.fetch_add(1, Ordering::Relaxed);
+        let (buffer_id, mut rw_buffer) = pool.evict(&mut next_victim)?;
+        Self::write_back(&*self.disk, pool, buffer_id, &mut rw_buffer)?;
+        rw_buffer.page_id = PageId::INVALID_PAGE_ID;
+        self.disk.read_page_data(page_id, &mut rw_buffer.page)?;
+        rw_buffer.page_id = page_id;
+        let mut page_table = pool.page_table.write();
+        page_table.insert(page_id, buffer_id);
+        Ok(pool.buffers[buffer_id.0].buffer.clone())
     }
 
+    /// Allocates a page and hands out a zeroed buffer for it. The buffer
+    /// starts clean, so nothing reaches the disk unless the caller fills it
+    /// in and marks it dirty.
     pub fn create_page(&self) -> Result<(PageId, Arc<RwLock<Buffer>>), Error> {
-        let mut locked_pool = self.pool.lock();
-        let (frame_id, frame) = locked_pool.evict().ok_or(Error::NoFreeBuffer)?;
-        let evict_page_id = frame.page_id;
-        let page_id = {
-            let buffer = Arc::get_mut(&mut frame.buffer).unwrap().get_mut();
-            let mut locked_disk = self.disk.lock();
-            if buffer.is_dirty {
-                locked_disk.write_page_data(evict_page_id, &buffer.page)?;
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        // The shard is picked by the id the page is about to get, and checked
+        // again under `allocation` in case another allocation got there
+        // first. The victim is then simply left unused.
+        loop {
+            let shard_index = self.shard_index(self.disk.next_page_id());
+            let pool = &self.shards[shard_index];
+            let mut next_victim = pool.next_victim.lock();
+            let (buffer_id, mut rw_buffer) = pool.evict(&mut next_victim)?;
+            Self::write_back(&*self.disk, pool, buffer_id, &mut rw_buffer)?;
+            rw_buffer.page_id = PageId::INVALID_PAGE_ID;
+            let _allocation = self.allocation.lock();
+            if self.shard_index(self.disk.next_page_id()) != shard_index {
+                continue;
             }
-            let page_id = locked_disk.allocate_page();
-            frame.page_id = page_id;
-            *buffer = Buffer::default();
-            buffer.is_dirty = true;
-            page_id
-        };
-        let buffer = Arc::clone(&frame.buffer);
-        locked_pool.page_table.remove(&evict_page_id);
-        locked_pool.page_table.insert(page_id, frame_id);
-        Ok((page_id, buffer))
+            let page_id = self.disk.allocate_page()?;
+            rw_buffer.page_id = page_id;
+            rw_buffer.page = [0u8; PAGE_SIZE];
+            let mut page_table = pool.page_table.write();
+            page_table.insert(page_id, buffer_id);
+            return Ok((page_id, pool.buffers[buffer_id.0].buffer.clone()));
+        }
+    }
+
+    /// Copies every allocated page to `target`, which must be empty, and
+    /// syncs it. Dirty pages are written back first, so the copy is only of
+    /// a single point in time if no page is modified meanwhile. Returns the
+    /// number of pages copied.
+    pub fn backup(&self, target: &dyn Disk) -> Result<u64, Error> {
+        debug_assert!(target.is_empty());
+        if !self.read_only {
+            self.flush()?;
+        }
+        let page_count = self.disk.next_page_id().0;
+        let mut page = [0u8; PAGE_SIZE];
+        for page_id in 0..page_count {
+            self.disk.read_page_data(PageId(page_id), &mut page)?;
+            let copy_page_id = target.allocate_page()?;
+            target.write_page_data(copy_page_id, &page)?;
+        }
+        target.flush()?;
+        Ok(page_count)
+    }
+
+    /// Frames across every shard.
+    pub fn pool_size(&self) -> usize {
+        self.shards.iter().map(BufferPool::size).sum()
+    }
+
+    /// Frames in each shard.
+    pub fn shard_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.shards.iter().map(BufferPool::size)
+    }
+
+    /// Counters summed over all shards.
+    pub fn stats(&self) -> Stats {
+        self.shards
+            .iter()
+            .map(|pool| pool.stats.load())
+            .fold(Stats::default(), |total, stats| Stats {
+                fetches: total.fetches + stats.fetches,
+                hits: total.hits + stats.hits,
+                misses: total.misses + stats.misses,
+                exhaustions: total.exhaustions + stats.exhaustions,
+            })
+    }
+
+    pub fn disk_stats(&self) -> disk::Stats {
+        self.disk.stats()
+    }
+
+    pub fn disk_usage(&self) -> Result<disk::Usage, Error> {
+        Ok(self.disk.usage()?)
     }
 
     pub fn flush(&self) -> Result<(), Error> {
-        let locked_pool = self.pool.lock();
-        let mut locked_disk = self.disk.lock();
-        for (page_id, frame_id) in locked_pool.page_table.iter() {
-            let frame = &locked_pool.buffers[frame_id.0];
-            let mut rw_buffer = frame.buffer.write();
-            locked_disk.write_page_data(*page_id, &rw_buffer.page)?;
-            rw_buffer.is_dirty = false;
-        }
-        locked_disk.flush()?;
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        // The pages whose latches are free are written together, so that the
+        // disk can combine contiguous ones. Those latches are only tried, as
+        // holding some while waiting for another could deadlock with a
+        // writer, and the busy pages are waited for one at a time afterwards.
+        let mut busy = vec![];
+        let mut latched = vec![];
+        for pool in &self.shards {
+            // Copied out so that the set isn't locked while waiting for a page
+            // latch, whose holder may be marking its page dirty.
+            let dirty_buffers: Vec<_> = pool.dirty_buffers.lock().iter().copied().collect();
+            for buffer_id in dirty_buffers {
+                let buffer = &pool.buffers[buffer_id.0].buffer;
+                match buffer.try_write() {
+                    Some(rw_buffer) if rw_buffer.is_dirty => latched.push(rw_buffer),
+                    Some(_) => {}
+                    None => busy.push(buffer),
+                }
+            }
+        }
+        let pages: Vec<_> = latched
+            .iter()
+            .map(|rw_buffer| (rw_buffer.page_id, &rw_buffer.page[..]))
+            .collect();
+        self.disk.write_pages(&pages)?;
+        for rw_buffer in &mut latched {
+            rw_buffer.mark_clean();
+        }
+        drop(latched);
+        for buffer in busy {
+            let mut rw_buffer = buffer.write();
+            if rw_buffer.is_dirty {
+                self.disk
+                    .write_page_data(rw_buffer.page_id, &rw_buffer.page)?;
+                rw_buffer.mark_clean();
+            }
+        }
+        self.disk.flush()?;
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use parking_lot::Condvar;
+
     use super::*;
+    use crate::disk::{DiskManager, MemoryDiskManager};
     use tempfile::tempfile;
 
     #[test]
@@ -174,7 +510,7 @@ mod tests {
         world.extend_from_slice(b"world");
         world.resize(PAGE_SIZE, 0);
 
-        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let disk = MemoryDiskManager::new();
         let pool = BufferPool::new(1);
         let bufmgr = BufferPoolManager::new(disk, pool);
         let page1_id = {
@@ -182,7 +518,7 @@ mod tests {
             assert!(bufmgr.create_page().is_err());
             let mut rw_buffer = buffer.write();
             rw_buffer.page.copy_from_slice(&hello);
-            rw_buffer.is_dirty = true;
+            rw_buffer.mark_dirty();
             page_id
         };
         {
@@ -194,7 +530,7 @@ mod tests {
             let (page_id, buffer) = bufmgr.create_page().unwrap();
             let mut rw_buffer = buffer.write();
             rw_buffer.page.copy_from_slice(&world);
-            rw_buffer.is_dirty = true;
+            rw_buffer.mark_dirty();
             page_id
         };
         {
@@ -208,4 +544,359 @@ mod tests {
             assert_eq!(&world, &ro_buffer.page);
         }
     }
+
+    #[test]
+    fn test_exhausted() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(3);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let mut handles = (0..3)
+            .map(|_| bufmgr.create_page().unwrap())
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            bufmgr.create_page(),
+            Err(Error::NoFreeBuffer {
+                pool_size: 3,
+                pinned: 3
+            })
+        ));
+        assert_eq!(1, bufmgr.stats().exhaustions);
+
+        let (page_id, _) = handles.pop().unwrap();
+        assert!(bufmgr.create_page().is_ok());
+        assert!(bufmgr.fetch_page(page_id).is_ok());
+        assert_eq!(1, bufmgr.stats().exhaustions);
+
+        let empty = BufferPoolManager::new(
+            DiskManager::new(tempfile().unwrap()).unwrap(),
+            BufferPool::new(0),
+        );
+        assert!(matches!(
+            empty.create_page(),
+            Err(Error::NoFreeBuffer {
+                pool_size: 0,
+                pinned: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn test_flush_dirty_only() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let page_ids = (0..5)
+            .map(|_| {
+                let (page_id, buffer) = bufmgr.create_page().unwrap();
+                buffer.write().mark_dirty();
+                page_id
+            })
+            .collect::<Vec<_>>();
+        bufmgr.flush().unwrap();
+        let writes = bufmgr.disk_stats().writes;
+        assert_eq!(5, writes);
+        // The pages are contiguous.
+        assert_eq!(1, bufmgr.disk_stats().write_calls);
+
+        for page_id in &page_ids {
+            let buffer = bufmgr.fetch_page(*page_id).unwrap();
+            assert!(!buffer.read().is_dirty());
+        }
+        {
+            let buffer = bufmgr.fetch_page(page_ids[2]).unwrap();
+            let mut rw_buffer = buffer.write();
+            rw_buffer.page[..5].copy_from_slice(b"hello");
+            rw_buffer.mark_dirty();
+        }
+        bufmgr.flush().unwrap();
+        assert_eq!(writes + 1, bufmgr.disk_stats().writes);
+        bufmgr.flush().unwrap();
+        assert_eq!(writes + 1, bufmgr.disk_stats().writes);
+    }
+
+    #[test]
+    fn test_flush_skips_clean_pages() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let (clean_page_id, _) = bufmgr.create_page().unwrap();
+        let (dirty_page_id, _) = bufmgr.create_page().unwrap();
+        bufmgr.flush().unwrap();
+
+        {
+            let buffer = bufmgr.fetch_page(clean_page_id).unwrap();
+            // Modified without being marked dirty, so it must not reach disk.
+            buffer.write().page[..5].copy_from_slice(b"stale");
+            let buffer = bufmgr.fetch_page(dirty_page_id).unwrap();
+            let mut rw_buffer = buffer.write();
+            rw_buffer.page[..5].copy_from_slice(b"fresh");
+            rw_buffer.mark_dirty();
+        }
+        bufmgr.flush().unwrap();
+
+        let mut page = vec![0; PAGE_SIZE];
+        bufmgr
+            .disk
+            .read_page_data(clean_page_id, &mut page)
+            .unwrap();
+        assert_eq!(&[0; 5], &page[..5]);
+        bufmgr
+            .disk
+            .read_page_data(dirty_page_id, &mut page)
+            .unwrap();
+        assert_eq!(b"fresh", &page[..5]);
+    }
+
+    #[test]
+    fn test_create_page_clean() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(1);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let (abandoned_page_id, buffer) = bufmgr.create_page().unwrap();
+        assert!(!buffer.read().is_dirty());
+        drop(buffer);
+        bufmgr.flush().unwrap();
+        assert_eq!(0, bufmgr.disk_stats().writes);
+
+        // Evicting the abandoned page doesn't write it either.
+        let (page_id, buffer) = bufmgr.create_page().unwrap();
+        assert_eq!(0, bufmgr.disk_stats().writes);
+        assert!(bufmgr.shards[0].lookup(abandoned_page_id).is_none());
+        let mut rw_buffer = buffer.write();
+        rw_buffer.page[..5].copy_from_slice(b"hello");
+        rw_buffer.mark_dirty();
+        drop(rw_buffer);
+        bufmgr.flush().unwrap();
+        assert_eq!(1, bufmgr.disk_stats().writes);
+        let mut page = vec![0; PAGE_SIZE];
+        bufmgr.disk.read_page_data(page_id, &mut page).unwrap();
+        assert_eq!(b"hello", &page[..5]);
+    }
+
+    /// Calls `on_read` before every read, so that tests can stall or observe
+    /// disk I/O.
+    struct HookedDisk<F> {
+        inner: MemoryDiskManager,
+        on_read: F,
+    }
+
+    impl<F: Fn() + Send + Sync> Disk for HookedDisk<F> {
+        fn read_page_data(&self, page_id: PageId, data: &mut [u8]) -> Result<(), disk::Error> {
+            (self.on_read)();
+            self.inner.read_page_data(page_id, data)
+        }
+
+        fn write_page_data(&self, page_id: PageId, data: &[u8]) -> io::Result<()> {
+            self.inner.write_page_data(page_id, data)
+        }
+
+        fn is_read_only(&self) -> bool {
+            self.inner.is_read_only()
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            self.inner.flush()
+        }
+
+        fn stats(&self) -> disk::Stats {
+            self.inner.stats()
+        }
+
+        fn usage(&self) -> io::Result<disk::Usage> {
+            self.inner.usage()
+        }
+
+        fn is_empty(&self) -> bool {
+            self.inner.is_empty()
+        }
+
+        fn next_page_id(&self) -> PageId {
+            self.inner.next_page_id()
+        }
+
+        fn allocate_page(&self) -> io::Result<PageId> {
+            self.inner.allocate_page()
+        }
+    }
+
+    #[test]
+    fn test_hit_during_eviction() {
+        let gate = Arc::new(Mutex::new(()));
+        let disk = HookedDisk {
+            inner: MemoryDiskManager::new(),
+            on_read: {
+                let gate = gate.clone();
+                move || drop(gate.lock())
+            },
+        };
+        let pool = BufferPool::new(2);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let (cached_page_id, _) = bufmgr.create_page().unwrap();
+        let uncached_page_id = bufmgr.disk.allocate_page().unwrap();
+        bufmgr
+            .disk
+            .write_page_data(uncached_page_id, &[1; PAGE_SIZE])
+            .unwrap();
+
+        // Stalls the miss below in the middle of its disk I/O.
+        let locked_gate = gate.lock();
+        thread::scope(|s| {
+            let miss = s.spawn(|| bufmgr.fetch_page(uncached_page_id).unwrap());
+            while !bufmgr.shards[0].next_victim.is_locked() {
+                thread::yield_now();
+            }
+            let hits = bufmgr.stats().hits;
+            bufmgr.fetch_page(cached_page_id).unwrap();
+            assert_eq!(hits + 1, bufmgr.stats().hits);
+            drop(locked_gate);
+            let buffer = miss.join().unwrap();
+            assert_eq!(&[1; PAGE_SIZE], &buffer.read().page);
+        });
+    }
+
+    #[test]
+    fn test_concurrent_misses() {
+        // Each read waits for a while until two reads have been in flight at
+        // once.
+        let in_flight = Arc::new((Mutex::new((0, 0)), Condvar::new()));
+        let disk = HookedDisk {
+            inner: MemoryDiskManager::new(),
+            on_read: {
+                let in_flight = in_flight.clone();
+                move || {
+                    let (counts, overlapped) = &*in_flight;
+                    let mut counts = counts.lock();
+                    let (current, max) = &mut *counts;
+                    *current += 1;
+                    *max = (*max).max(*current);
+                    overlapped.notify_all();
+                    let deadline = Instant::now() + Duration::from_secs(5);
+                    while counts.1 < 2 {
+                        if overlapped.wait_until(&mut counts, deadline).timed_out() {
+                            break;
+                        }
+                    }
+                    counts.0 -= 1;
+                }
+            },
+        };
+        let shards = (0..2).map(|_| BufferPool::new(2)).collect();
+        let bufmgr = BufferPoolManager::with_shards(disk, shards);
+        let page_ids = (0..2)
+            .map(|_| bufmgr.disk.allocate_page().unwrap())
+            .collect::<Vec<_>>();
+        let bufmgr = &bufmgr;
+        thread::scope(|s| {
+            for &page_id in &page_ids {
+                s.spawn(move || bufmgr.fetch_page(page_id).unwrap());
+            }
+        });
+        // The two pages live in different shards, so nothing serializes
+        // their reads.
+        assert_eq!(2, in_flight.0.lock().1);
+    }
+
+    /// Fetches every page from several threads at once, checking contents.
+    fn fetch_concurrently(bufmgr: &BufferPoolManager) {
+        let page_ids = (0..32u8)
+            .map(|i| {
+                let (page_id, buffer) = bufmgr.create_page().unwrap();
+                let mut rw_buffer = buffer.write();
+                rw_buffer.page[0] = i;
+                rw_buffer.mark_dirty();
+                page_id
+            })
+            .collect::<Vec<_>>();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        for (i, page_id) in page_ids.iter().enumerate() {
+                            let buffer = bufmgr.fetch_page(*page_id).unwrap();
+                            assert_eq!(i as u8, buffer.read().page[0]);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_concurrent_write_back() {
+        let (data_file, data_file_path) = tempfile::NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file).unwrap();
+        // Far fewer frames than pages, so that every round evicts, writing
+        // back and reading pages on all shards at once.
+        let shards = (0..4).map(|_| BufferPool::new(2)).collect();
+        let bufmgr = BufferPoolManager::with_shards(disk, shards);
+        let page_ids = (0..64)
+            .map(|_| bufmgr.create_page().unwrap().0)
+            .collect::<Vec<_>>();
+        let fill = |thread_index: usize, round: u8| (thread_index as u8 * 64).wrapping_add(round);
+        // Each thread owns every fourth page, and so a shard of its own, and
+        // keeps rewriting its pages and reading them back.
+        thread::scope(|s| {
+            for thread_index in 0..4 {
+                let bufmgr = &bufmgr;
+                let page_ids = &page_ids;
+                s.spawn(move || {
+                    for round in 0..20 {
+                        let fill = fill(thread_index, round);
+                        for page_id in page_ids.iter().skip(thread_index).step_by(4) {
+                            let buffer = bufmgr.fetch_page(*page_id).unwrap();
+                            let mut rw_buffer = buffer.write();
+                            rw_buffer.page.fill(fill);
+                            rw_buffer.mark_dirty();
+                        }
+                        for page_id in page_ids.iter().skip(thread_index).step_by(4) {
+                            let buffer = bufmgr.fetch_page(*page_id).unwrap();
+                            assert!(buffer.read().page.iter().all(|byte| *byte == fill));
+                        }
+                    }
+                });
+            }
+        });
+        bufmgr.flush().unwrap();
+        drop(bufmgr);
+
+        let disk = DiskManager::open(&data_file_path).unwrap();
+        let mut page = vec![0; PAGE_SIZE];
+        for (index, page_id) in page_ids.iter().enumerate() {
+            disk.read_page_data(*page_id, &mut page).unwrap();
+            assert_eq!(vec![fill(index % 4, 19); PAGE_SIZE], page);
+        }
+    }
+
+    #[test]
+    fn test_shards() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let bufmgr = BufferPoolManager::new(disk, BufferPool::new(16));
+        fetch_concurrently(&bufmgr);
+
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let shards = (0..4).map(|_| BufferPool::new(4)).collect();
+        let bufmgr = BufferPoolManager::with_shards(disk, shards);
+        fetch_concurrently(&bufmgr);
+        for (shard_index, pool) in bufmgr.shards.iter().enumerate() {
+            for page_id in pool.page_table.read().keys() {
+                assert_eq!(shard_index, bufmgr.shard_index(*page_id));
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_page_survives_next_eviction() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let bufmgr = BufferPoolManager::new(disk, BufferPool::new(2));
+        let (page0_id, _) = bufmgr.create_page().unwrap();
+        bufmgr.create_page().unwrap();
+        bufmgr.fetch_page(page0_id).unwrap();
+        bufmgr.fetch_page(page0_id).unwrap();
+        let (page2_id, _) = bufmgr.create_page().unwrap();
+        // Page 0 was last used before page 2 was created, so it goes first.
+        bufmgr.create_page().unwrap();
+        let hits = bufmgr.stats().hits;
+        bufmgr.fetch_page(page2_id).unwrap();
+        assert_eq!(hits + 1, bufmgr.stats().hits);
+    }
 }
@@ -0,0 +1,922 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::{TryFrom, TryInto},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use thiserror::Error;
+
+use crate::{
+    btree,
+    buffer::BufferPoolManager,
+    disk::PageId,
+    encoding::{KeyType, MAX_KEY_FIELDS},
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    BTree(#[from] btree::Error),
+    #[error("malformed catalog entry")]
+    MalformedEntry,
+    /// The entry is tagged with a format this version doesn't know, likely
+    /// written by a newer one.
+    #[error("catalog entry is in unknown format {tag} ({len} bytes)")]
+    UnknownEntryFormat { tag: u8, len: usize },
+    #[error("catalog entry points at reserved page {0:?}")]
+    ReservedPage(PageId),
+    #[error("malformed note in catalog meta page")]
+    MalformedNote,
+}
+
+/// Tags of the versioned catalog entry formats. Entries written before
+/// versioning are the bare 8-byte page id of the table's B-tree.
+const ENTRY_V1: u8 = 1;
+const ENTRY_V1_LEN: usize = 1 + 8 + 8;
+const ENTRY_V2: u8 = 2;
+const ENTRY_V2_LEN: usize = ENTRY_V1_LEN + 1;
+const ENTRY_V3: u8 = 3;
+const ENTRY_V3_LEN: usize = ENTRY_V2_LEN + 1;
+const ENTRY_V4: u8 = 4;
+const ENTRY_V4_LEN: usize = ENTRY_V3_LEN + 1;
+/// Adds the fields of composite keys.
+const ENTRY_V5: u8 = 5;
+const ENTRY_V5_LEN: usize = ENTRY_V4_LEN + MAX_KEY_FIELDS;
+
+/// Tags the note a catalog keeps in its meta page while a rename is under
+/// way, followed by both table ids and the entry. See `Catalog::rename`.
+const RENAME_NOTE: u8 = 1;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TableMeta {
+    pub btree_page_id: PageId,
+    /// Seconds since the Unix epoch, or zero for legacy entries.
+    pub created_at: u64,
+    pub key_type: KeyType,
+    /// Whether the executor stamps the time of each put into the values.
+    pub track_updated_at: bool,
+    /// Whether puts must come in increasing key order and deletes are
+    /// refused, as for an event log.
+    pub append_only: bool,
+}
+
+impl TableMeta {
+    pub fn new(btree_page_id: PageId, key_type: KeyType) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        Self {
+            btree_page_id,
+            created_at,
+            key_type,
+            track_updated_at: false,
+            append_only: false,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(ENTRY_V5_LEN);
+        bytes.push(ENTRY_V5);
+        bytes.extend_from_slice(&self.btree_page_id.0.to_be_bytes());
+        bytes.extend_from_slice(&self.created_at.to_be_bytes());
+        bytes.push(self.key_type.to_u8());
+        bytes.push(self.track_updated_at as u8);
+        bytes.push(self.append_only as u8);
+        bytes.extend_from_slice(&self.key_type.layout_to_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.len() {
+            8 => Ok(Self {
+                btree_page_id: bytes.try_into().unwrap(),
+                created_at: 0,
+                key_type: KeyType::Raw,
+                track_updated_at: false,
+                append_only: false,
+            }),
+            ENTRY_V1_LEN if bytes[0] == ENTRY_V1 => Ok(Self {
+                btree_page_id: bytes[1..9].try_into().unwrap(),
+                created_at: u64::from_be_bytes(bytes[9..17].try_into().unwrap()),
+                key_type: KeyType::Raw,
+                track_updated_at: false,
+                append_only: false,
+            }),
+            ENTRY_V2_LEN if bytes[0] == ENTRY_V2 => Ok(Self {
+                btree_page_id: bytes[1..9].try_into().unwrap(),
+                created_at: u64::from_be_bytes(bytes[9..17].try_into().unwrap()),
+                key_type: KeyType::from_u8(bytes[17]).ok_or(Error::MalformedEntry)?,
+                track_updated_at: false,
+                append_only: false,
+            }),
+            ENTRY_V3_LEN if bytes[0] == ENTRY_V3 => Ok(Self {
+                btree_page_id: bytes[1..9].try_into().unwrap(),
+                created_at: u64::from_be_bytes(bytes[9..17].try_into().unwrap()),
+                key_type: KeyType::from_u8(bytes[17]).ok_or(Error::MalformedEntry)?,
+                track_updated_at: decode_flag(bytes[18])?,
+                append_only: false,
+            }),
+            ENTRY_V4_LEN if bytes[0] == ENTRY_V4 => Ok(Self {
+                btree_page_id: bytes[1..9].try_into().unwrap(),
+                created_at: u64::from_be_bytes(bytes[9..17].try_into().unwrap()),
+                key_type: KeyType::from_u8(bytes[17]).ok_or(Error::MalformedEntry)?,
+                track_updated_at: decode_flag(bytes[18])?,
+                append_only: decode_flag(bytes[19])?,
+            }),
+            ENTRY_V5_LEN if bytes[0] == ENTRY_V5 => Ok(Self {
+                btree_page_id: bytes[1..9].try_into().unwrap(),
+                created_at: u64::from_be_bytes(bytes[9..17].try_into().unwrap()),
+                key_type: KeyType::from_bytes(bytes[17], bytes[20..].try_into().unwrap())
+                    .ok_or(Error::MalformedEntry)?,
+                track_updated_at: decode_flag(bytes[18])?,
+                append_only: decode_flag(bytes[19])?,
+            }),
+            // Each format so far has appended fields to the previous one.
+            len if len > ENTRY_V5_LEN && bytes[0] > ENTRY_V5 => {
+                Err(Error::UnknownEntryFormat { tag: bytes[0], len })
+            }
+            _ => Err(Error::MalformedEntry),
+        }
+    }
+}
+
+fn decode_flag(byte: u8) -> Result<bool, Error> {
+    match byte {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(Error::MalformedEntry),
+    }
+}
+
+fn decode_entry(bytes: &[u8]) -> Result<TableMeta, Error> {
+    let meta = TableMeta::decode(bytes)?;
+    check_page_id(meta.btree_page_id)?;
+    Ok(meta)
+}
+
+fn check_page_id(page_id: PageId) -> Result<(), Error> {
+    // Operating on either would corrupt the catalog or crash.
+    if page_id == PageId::CATALOG_PAGE_ID || page_id == PageId::INVALID_PAGE_ID {
+        return Err(Error::ReservedPage(page_id));
+    }
+    Ok(())
+}
+
+/// The outcome of `Catalog::rebuild`, as table ids.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Recovery {
+    /// Tables of the default namespace found under their own ids.
+    pub recovered: Vec<btree::Key>,
+    /// Likewise for the other namespaces, each table with its namespace.
+    pub recovered_in_namespaces: Vec<(btree::Key, btree::Key)>,
+    /// Trees no surviving entry pointed at.
+    pub orphaned: Vec<btree::Key>,
+}
+
+/// The outcome of `Catalog::verify`.
+#[derive(Debug, Default)]
+pub struct Check {
+    /// Each with the namespace and table of the tree it was found in. The
+    /// table is `None` for a catalog's own tree, and so is the namespace for
+    /// the tree listing the namespaces.
+    pub problems: Vec<(Option<btree::Key>, Option<btree::Key>, btree::Problem)>,
+    /// Allocated pages no tree reaches, such as those of trees replaced by
+    /// `Vacuum` and `TruncateTable`.
+    pub unreachable_pages: u64,
+}
+
+/// The id a tree without a surviving entry is registered under: its meta
+/// page id with the top byte set, which is unlikely to clash with real ids.
+pub fn orphan_table_id(btree_page_id: PageId) -> btree::Key {
+    (0xFF << 56 | btree_page_id.0).to_be_bytes()
+}
+
+pub struct Catalog<'a> {
+    bufmgr: &'a BufferPoolManager,
+    access: btree::Access<'a>,
+}
+
+impl<'a> Catalog<'a> {
+    /// Initializes the catalog of a brand-new database. It must be the first
+    /// thing allocated so that it lands on `PageId::CATALOG_PAGE_ID`.
+    pub fn create(bufmgr: &'a BufferPoolManager) -> Result<Self, Error> {
+        let access = btree::Access::create(bufmgr)?;
+        assert_eq!(PageId::CATALOG_PAGE_ID, access.btree_page_id);
+        Ok(Self { bufmgr, access })
+    }
+
+    /// Rebuilds damaged catalogs from a scan of every allocated page.
+    ///
+    /// Trees are found by the structure of their meta pages. Table ids are
+    /// taken from whatever catalog leaves are still readable: a leaf counts
+    /// as one if each of its records decodes as an entry pointing at one of
+    /// the trees. The remaining trees, which include copies left behind by
+    /// vacuums, are registered under `orphan_table_id`.
+    ///
+    /// The namespaces are kept if the tree listing them is still linked from
+    /// the default catalog's meta page. A leaf still reached from a
+    /// namespace's catalog is recovered into that namespace, and every other
+    /// one into the default namespace. Nothing else may use the database
+    /// meanwhile.
+    pub fn rebuild(bufmgr: &'a BufferPoolManager) -> Result<Recovery, Error> {
+        let num_pages = bufmgr
+            .disk_usage()
+            .map_err(btree::Error::from)?
+            .allocated_pages;
+        let meta_pages = btree::find_meta_pages(bufmgr, num_pages)?;
+        let (directory, namespaces) = Self::find_namespaces(bufmgr, &meta_pages);
+        // The listing's pages hold no entries, and those of each namespace's
+        // catalog hold its own.
+        let mut skipped = HashSet::new();
+        let mut owners = HashMap::new();
+        if let Some(directory) = &directory {
+            skipped.extend(directory.verify()?.pages);
+        }
+        for &(namespace, page_id) in &namespaces {
+            let catalog: btree::Access = btree::Access::open(bufmgr, page_id);
+            for page_id in catalog.verify()?.pages {
+                owners.insert(page_id, namespace);
+            }
+        }
+        let mut entries = BTreeMap::new();
+        for page_id in (1..num_pages).map(PageId) {
+            if skipped.contains(&page_id) {
+                continue;
+            }
+            let records = match btree::read_leaf::<8>(bufmgr, page_id)? {
+                Some(records) if !records.is_empty() => records,
+                _ => continue,
+            };
+            let decoded: Result<Vec<_>, _> = records
+                .iter()
+                .map(|(table_id, bytes)| decode_entry(bytes).map(|meta| (*table_id, meta)))
+                .collect();
+            let decoded = match decoded {
+                Ok(decoded) => decoded,
+                Err(_) => continue,
+            };
+            if decoded
+                .iter()
+                .all(|(_, meta)| meta_pages.contains(&meta.btree_page_id))
+            {
+                let namespace = owners.get(&page_id).copied();
+                entries.extend(
+                    decoded
+                        .into_iter()
+                        .map(|(table_id, meta)| ((namespace, table_id), meta)),
+                );
+            }
+        }
+
+        let access: btree::Access = btree::Access::open(bufmgr, PageId::CATALOG_PAGE_ID);
+        access.reset()?;
+        let directory_page_id = directory.map_or(PageId(0), |directory| directory.btree_page_id);
+        access.set_extra_page_id(directory_page_id)?;
+        let catalog = Self { bufmgr, access };
+        let mut catalogs = HashMap::new();
+        for &(namespace, page_id) in &namespaces {
+            let access = btree::Access::open(bufmgr, page_id);
+            access.reset()?;
+            catalogs.insert(namespace, Self { bufmgr, access });
+        }
+        let mut recovery = Recovery::default();
+        for (&(namespace, table_id), meta) in &entries {
+            match namespace {
+                Some(namespace) => {
+                    catalogs[&namespace].put(table_id, meta)?;
+                    recovery.recovered_in_namespaces.push((namespace, table_id));
+                }
+                None => {
+                    catalog.put(table_id, meta)?;
+                    recovery.recovered.push(table_id);
+                }
+            }
+        }
+        for &page_id in &meta_pages {
+            let is_catalog = page_id == directory_page_id
+                || namespaces.iter().any(|&(_, catalog)| catalog == page_id);
+            if is_catalog || entries.values().any(|meta| meta.btree_page_id == page_id) {
+                continue;
+            }
+            let table_id = orphan_table_id(page_id);
+            let meta = TableMeta {
+                created_at: 0,
+                ..TableMeta::new(page_id, KeyType::Raw)
+            };
+            catalog.put(table_id, &meta)?;
+            recovery.orphaned.push(table_id);
+        }
+        bufmgr.flush().map_err(btree::Error::from)?;
+        Ok(recovery)
+    }
+
+    /// The tree listing the namespaces, and each namespace with its catalog's
+    /// meta page, as far as `rebuild` can trust them: the listing must be a
+    /// tree linked from the default catalog's meta page, and each catalog a
+    /// tree too. Neither is returned if the listing can't be read.
+    fn find_namespaces(
+        bufmgr: &'a BufferPoolManager,
+        meta_pages: &[PageId],
+    ) -> (Option<btree::Access<'a>>, Vec<(btree::Key, PageId)>) {
+        let directory = match Self::open(bufmgr).access.extra_page_id() {
+            Ok(Some(page_id)) if meta_pages.contains(&page_id) => {
+                btree::Access::open(bufmgr, page_id)
+            }
+            _ => return (None, vec![]),
+        };
+        let read = || -> Result<_, btree::Error> {
+            let mut namespaces = vec![];
+            let mut iter = directory.iter(None)?;
+            let mut buf = vec![];
+            while let Some(namespace) = iter.next(&mut buf)? {
+                if let Ok(bytes) = <[u8; 8]>::try_from(&buf[..]) {
+                    let page_id = PageId(u64::from_be_bytes(bytes));
+                    if meta_pages.contains(&page_id) {
+                        namespaces.push((namespace, page_id));
+                    }
+                }
+                buf.clear();
+            }
+            Ok(namespaces)
+        };
+        match read() {
+            Ok(namespaces) => (Some(directory), namespaces),
+            Err(_) => (None, vec![]),
+        }
+    }
+
+    /// Opens the catalog of the default namespace, first creating it if the
+    /// database is brand new.
+    pub fn open_or_create(bufmgr: &'a BufferPoolManager) -> Result<Self, Error> {
+        if bufmgr.is_empty() {
+            return Self::create(bufmgr);
+        }
+        Ok(Self::open(bufmgr))
+    }
+
+    /// Finishes the renames a crash interrupted, in every namespace. A
+    /// read-only database is left as it is.
+    pub fn recover(bufmgr: &'a BufferPoolManager) -> Result<(), Error> {
+        if bufmgr.is_read_only() {
+            return Ok(());
+        }
+        Self::open(bufmgr).finish_noted()?;
+        for namespace in Self::namespace_ids(bufmgr)? {
+            if let Some(catalog) = Self::open_namespace(bufmgr, namespace)? {
+                catalog.finish_noted()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the catalog of the default namespace.
+    pub fn open(bufmgr: &'a BufferPoolManager) -> Self {
+        Self {
+            bufmgr,
+            access: btree::Access::open(bufmgr, PageId::CATALOG_PAGE_ID),
+        }
+    }
+
+    /// Opens a catalog kept in the tree at `btree_page_id`, such as one being
+    /// assembled for `set_root`.
+    #[cfg(test)]
+    pub fn open_at(bufmgr: &'a BufferPoolManager, btree_page_id: PageId) -> Self {
+        Self {
+            bufmgr,
+            access: btree::Access::open(bufmgr, btree_page_id),
+        }
+    }
+
+    /// Makes the tree rooted at `root_page_id` the contents of the default
+    /// catalog, whose meta page stays on `PageId::CATALOG_PAGE_ID`. The
+    /// namespaces are kept. See `btree::Access::set_root_page_id`.
+    #[cfg(any(test, feature = "admin"))]
+    pub fn set_root(bufmgr: &'a BufferPoolManager, root_page_id: PageId) -> Result<(), Error> {
+        check_page_id(root_page_id)?;
+        Self::open(bufmgr).access.set_root_page_id(root_page_id)?;
+        Ok(())
+    }
+
+    /// The tree mapping namespaces to their catalogs' meta pages, whose own
+    /// meta page id is kept in the default catalog's meta page. It is only
+    /// created along with the first namespace.
+    fn namespaces(bufmgr: &'a BufferPoolManager) -> Result<Option<btree::Access<'a>>, Error> {
+        let page_id = match Self::open(bufmgr).access.extra_page_id()? {
+            Some(page_id) => page_id,
+            None => return Ok(None),
+        };
+        check_page_id(page_id)?;
+        Ok(Some(btree::Access::open(bufmgr, page_id)))
+    }
+
+    /// Every namespace, in key order.
+    pub fn namespace_ids(bufmgr: &'a BufferPoolManager) -> Result<Vec<btree::Key>, Error> {
+        let namespaces = match Self::namespaces(bufmgr)? {
+            Some(namespaces) => namespaces,
+            None => return Ok(vec![]),
+        };
+        let mut namespace_ids = vec![];
+        let mut iter = namespaces.iter(None)?;
+        while let Some(namespace) = iter.next_key()? {
+            namespace_ids.push(namespace);
+        }
+        Ok(namespace_ids)
+    }
+
+    /// Copies every namespace and table into the brand-new database behind
+    /// `target`, each tree rebuilt with packed leaves. Returns the number of
+    /// tables copied. Nothing may write to `source` meanwhile.
+    pub fn compact_into(
+        source: &'a BufferPoolManager,
+        target: &'a BufferPoolManager,
+    ) -> Result<usize, Error> {
+        let mut copies = vec![(Self::open(source), Self::create(target)?)];
+        for namespace in Self::namespace_ids(source)? {
+            if let Some(catalog) = Self::open_namespace(source, namespace)? {
+                copies.push((catalog, Self::create_namespace(target, namespace)?));
+            }
+        }
+        let mut tables = 0;
+        for (catalog, copy) in copies {
+            for (table_id, meta) in catalog.tables()? {
+                let table: btree::Access = btree::Access::open(source, meta.btree_page_id);
+                let new_table = table.rebuild_into(target)?;
+                let new_meta = TableMeta {
+                    btree_page_id: new_table.btree_page_id,
+                    ..meta
+                };
+                copy.put(table_id, &new_meta)?;
+                tables += 1;
+            }
+        }
+        target.flush().map_err(btree::Error::from)?;
+        Ok(tables)
+    }
+
+    /// Checks the structure of every catalog and table with
+    /// `btree::Access::verify`. The tables of a catalog found damaged are
+    /// skipped, as its entries can't be trusted. Nothing may write to the
+    /// database meanwhile.
+    pub fn verify(bufmgr: &'a BufferPoolManager) -> Result<Check, Error> {
+        let mut check = Check::default();
+        let mut reachable = HashSet::new();
+        // Returns whether the tree is sound.
+        let mut record = |namespace, table_id, verification: btree::Verification| {
+            let sound = verification.problems.is_empty();
+            let problems = verification.problems.into_iter();
+            check
+                .problems
+                .extend(problems.map(|problem| (namespace, table_id, problem)));
+            reachable.extend(verification.pages);
+            sound
+        };
+        let mut catalogs = vec![(None, Self::open(bufmgr))];
+        if let Some(namespaces) = Self::namespaces(bufmgr)? {
+            if record(None, None, namespaces.verify()?) {
+                for namespace in Self::namespace_ids(bufmgr)? {
+                    if let Some(catalog) = Self::open_namespace(bufmgr, namespace)? {
+                        catalogs.push((Some(namespace), catalog));
+                    }
+                }
+            }
+        }
+        for (namespace, catalog) in catalogs {
+            if !record(namespace, None, catalog.access.verify()?) {
+                continue;
+            }
+            for (table_id, meta) in catalog.tables()? {
+                let table: btree::Access = btree::Access::open(bufmgr, meta.btree_page_id);
+                record(namespace, Some(table_id), table.verify()?);
+            }
+        }
+        let num_pages = bufmgr
+            .disk_usage()
+            .map_err(btree::Error::from)?
+            .allocated_pages;
+        check.unreachable_pages = num_pages - reachable.len() as u64;
+        Ok(check)
+    }
+
+    /// Opens the catalog of `namespace`, which holds tables of its own apart
+    /// from the default one's. Returns `None` if it doesn't exist.
+    pub fn open_namespace(
+        bufmgr: &'a BufferPoolManager,
+        namespace: btree::Key,
+    ) -> Result<Option<Self>, Error> {
+        let namespaces = match Self::namespaces(bufmgr)? {
+            Some(namespaces) => namespaces,
+            None => return Ok(None),
+        };
+        let mut buf = vec![];
+        if !namespaces.get(namespace, &mut buf)? {
+            return Ok(None);
+        }
+        let bytes: [u8; 8] = buf.try_into().map_err(|_| Error::MalformedEntry)?;
+        let page_id = PageId(u64::from_be_bytes(bytes));
+        check_page_id(page_id)?;
+        Ok(Some(Self {
+            bufmgr,
+            access: btree::Access::open(bufmgr, page_id),
+        }))
+    }
+
+    /// Like `open_namespace`, but creates the namespace if needed. Like in
+    /// `create_table`, new trees are synced before anything points at them.
+    /// Must not run concurrently with itself.
+    pub fn create_namespace(
+        bufmgr: &'a BufferPoolManager,
+        namespace: btree::Key,
+    ) -> Result<Self, Error> {
+        if let Some(catalog) = Self::open_namespace(bufmgr, namespace)? {
+            return Ok(catalog);
+        }
+        let namespaces = match Self::namespaces(bufmgr)? {
+            Some(namespaces) => namespaces,
+            None => {
+                let namespaces: btree::Access = btree::Access::create(bufmgr)?;
+                bufmgr.flush().map_err(btree::Error::from)?;
+                Self::open(bufmgr)
+                    .access
+                    .set_extra_page_id(namespaces.btree_page_id)?;
+                namespaces
+            }
+        };
+        let access = btree::Access::create(bufmgr)?;
+        bufmgr.flush().map_err(btree::Error::from)?;
+        namespaces.put(namespace, &access.btree_page_id.0.to_be_bytes())?;
+        Ok(Self { bufmgr, access })
+    }
+
+    pub fn get(&self, table_id: btree::Key) -> Result<Option<TableMeta>, Error> {
+        let mut buf = vec![];
+        if !self.access.get(table_id, &mut buf)? {
+            return Ok(None);
+        }
+        Ok(Some(decode_entry(&buf)?))
+    }
+
+    /// Every table with its metadata, in table id order.
+    pub fn tables(&self) -> Result<Vec<(btree::Key, TableMeta)>, Error> {
+        let mut tables = vec![];
+        let mut iter = self.access.iter(None)?;
+        let mut buf = vec![];
+        while let Some(table_id) = iter.next(&mut buf)? {
+            tables.push((table_id, decode_entry(&buf)?));
+            buf.clear();
+        }
+        Ok(tables)
+    }
+
+    pub fn put(&self, table_id: btree::Key, meta: &TableMeta) -> Result<(), Error> {
+        self.access.put(table_id, &meta.encode())?;
+        Ok(())
+    }
+
+    /// Removes the entry of `table_id`, leaving its tree's pages unused.
+    /// Returns `true` if there was one.
+    pub fn delete(&self, table_id: btree::Key) -> Result<bool, Error> {
+        Ok(self.access.delete(table_id)?)
+    }
+
+    /// Moves the entry `meta` of `from` to `to`, replacing any entry there.
+    ///
+    /// The two entries may reach the disk in either order, so the rename is
+    /// first noted in the catalog's meta page and synced. If a crash comes
+    /// before the note is cleared, `recover` finishes the rename.
+    pub fn rename(&self, from: btree::Key, to: btree::Key, meta: &TableMeta) -> Result<(), Error> {
+        let mut note = vec![RENAME_NOTE];
+        note.extend_from_slice(&from);
+        note.extend_from_slice(&to);
+        note.extend_from_slice(&meta.encode());
+        self.access.set_note(&note)?;
+        self.sync()?;
+        self.finish_rename(from, to, meta)
+    }
+
+    /// Puts and deletes afresh, so it doesn't matter how much of the rename
+    /// had been done.
+    fn finish_rename(
+        &self,
+        from: btree::Key,
+        to: btree::Key,
+        meta: &TableMeta,
+    ) -> Result<(), Error> {
+        self.put(to, meta)?;
+        self.delete(from)?;
+        self.sync()?;
+        self.access.set_note(&[])?;
+        self.sync()
+    }
+
+    fn finish_noted(&self) -> Result<(), Error> {
+        let note = match self.access.note()? {
+            Some(note) => note,
+            None => return Ok(()),
+        };
+        match note.split_first() {
+            Some((&RENAME_NOTE, rest)) if rest.len() > 16 => {
+                let from = rest[..8].try_into().unwrap();
+                let to = rest[8..16].try_into().unwrap();
+                let meta = decode_entry(&rest[16..])?;
+                self.finish_rename(from, to, &meta)
+            }
+            _ => Err(Error::MalformedNote),
+        }
+    }
+
+    fn sync(&self) -> Result<(), Error> {
+        self.bufmgr.flush().map_err(btree::Error::from)?;
+        Ok(())
+    }
+
+    /// Creates an empty B-tree and registers it under `table_id`, replacing
+    /// any existing entry.
+    ///
+    /// The tree is synced before the entry is written, so the entry never
+    /// reaches the disk ahead of the pages it points at. A failure or crash
+    /// in between only leaks the tree's pages.
+    pub fn create_table(
+        &self,
+        table_id: btree::Key,
+        key_type: KeyType,
+        track_updated_at: bool,
+        append_only: bool,
+    ) -> Result<TableMeta, Error> {
+        let new_table: btree::Access = btree::Access::create(self.bufmgr)?;
+        self.bufmgr.flush().map_err(btree::Error::from)?;
+        let meta = TableMeta {
+            track_updated_at,
+            append_only,
+            ..TableMeta::new(new_table.btree_page_id, key_type)
+        };
+        self.put(table_id, &meta)?;
+        Ok(meta)
+    }
+
+    pub fn open_table(&self, table_id: btree::Key) -> Result<Option<btree::Access<'a>>, Error> {
+        let meta = self.get(table_id)?;
+        Ok(meta.map(|meta| btree::Access::open(self.bufmgr, meta.btree_page_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{tempfile, NamedTempFile};
+
+    use crate::{
+        buffer::{self, BufferPool},
+        disk::DiskManager,
+        encoding::KeyField,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_open_or_create() {
+        let data_file = NamedTempFile::new().unwrap();
+        for round in 0..2 {
+            let disk = DiskManager::open(data_file.path()).unwrap();
+            let bufmgr = BufferPoolManager::new(disk, BufferPool::new(10));
+            let catalog = Catalog::open_or_create(&bufmgr).unwrap();
+            if round == 0 {
+                catalog
+                    .create_table(*b"newtable", KeyType::Raw, false, false)
+                    .unwrap();
+                let table = catalog.open_table(*b"newtable").unwrap().unwrap();
+                table.put(*b"key00001", b"value").unwrap();
+                bufmgr.flush().unwrap();
+            } else {
+                // Reopening finds the catalog rather than starting over.
+                let table = catalog.open_table(*b"newtable").unwrap().unwrap();
+                let mut value = vec![];
+                assert!(table.get(*b"key00001", &mut value).unwrap());
+                assert_eq!(b"value", &value[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = BufferPoolManager::new(disk, pool);
+        let catalog_access = btree::Access::create(&bufmgr).unwrap();
+        assert_eq!(PageId::CATALOG_PAGE_ID, catalog_access.btree_page_id);
+
+        let catalog = Catalog::open(&bufmgr);
+        let meta = TableMeta::new(PageId(42), KeyType::I64);
+        catalog.put(*b"newtable", &meta).unwrap();
+        let tracked = TableMeta {
+            track_updated_at: true,
+            ..TableMeta::new(PageId(43), KeyType::Raw)
+        };
+        catalog.put(*b"tracked!", &tracked).unwrap();
+        let log = TableMeta {
+            append_only: true,
+            ..TableMeta::new(PageId(44), KeyType::U64)
+        };
+        catalog.put(*b"logtable", &log).unwrap();
+        let mut v2_entry = meta.encode();
+        v2_entry[0] = ENTRY_V2;
+        v2_entry.truncate(ENTRY_V2_LEN);
+        catalog_access.put(*b"v2_table", &v2_entry).unwrap();
+        let mut v3_entry = tracked.encode();
+        v3_entry[0] = ENTRY_V3;
+        v3_entry.truncate(ENTRY_V3_LEN);
+        catalog_access.put(*b"v3_table", &v3_entry).unwrap();
+        let mut v4_entry = log.encode();
+        v4_entry[0] = ENTRY_V4;
+        v4_entry.truncate(ENTRY_V4_LEN);
+        catalog_access.put(*b"v4_table", &v4_entry).unwrap();
+        let layout = vec![KeyField::Unsigned(2), KeyField::String(6)];
+        let composite = TableMeta::new(PageId(45), KeyType::Composite(layout.try_into().unwrap()));
+        catalog.put(*b"compound", &composite).unwrap();
+        catalog_access
+            .put(*b"oldtable", &PageId(7).0.to_be_bytes())
+            .unwrap();
+        catalog_access.put(*b"badtable", b"garbage").unwrap();
+        let mut v6_entry = log.encode();
+        v6_entry[0] = ENTRY_V5 + 1;
+        v6_entry.extend_from_slice(&[0; 8]);
+        catalog_access.put(*b"v6_table", &v6_entry).unwrap();
+        let mut short_entry = log.encode();
+        short_entry.pop();
+        catalog_access.put(*b"short_v5", &short_entry).unwrap();
+        let mut bad_layout = composite.encode();
+        bad_layout[ENTRY_V4_LEN] = 0x70;
+        catalog_access.put(*b"badfield", &bad_layout).unwrap();
+
+        assert_eq!(Some(meta), catalog.get(*b"newtable").unwrap());
+        assert_eq!(Some(tracked), catalog.get(*b"tracked!").unwrap());
+        assert_eq!(Some(log), catalog.get(*b"logtable").unwrap());
+        assert_eq!(Some(meta), catalog.get(*b"v2_table").unwrap());
+        assert_eq!(Some(tracked), catalog.get(*b"v3_table").unwrap());
+        assert_eq!(Some(log), catalog.get(*b"v4_table").unwrap());
+        assert_eq!(Some(composite), catalog.get(*b"compound").unwrap());
+        let legacy = catalog.get(*b"oldtable").unwrap().unwrap();
+        assert_eq!(PageId(7), legacy.btree_page_id);
+        assert_eq!(0, legacy.created_at);
+        assert_eq!(KeyType::Raw, legacy.key_type);
+        assert!(matches!(
+            catalog.get(*b"badtable"),
+            Err(Error::MalformedEntry)
+        ));
+        assert!(matches!(
+            catalog.get(*b"v6_table"),
+            Err(Error::UnknownEntryFormat { tag: 6, len }) if len == ENTRY_V5_LEN + 8
+        ));
+        for malformed in [*b"short_v5", *b"badfield"] {
+            assert!(matches!(catalog.get(malformed), Err(Error::MalformedEntry)));
+        }
+        assert_eq!(None, catalog.get(*b"notfound").unwrap());
+    }
+
+    #[test]
+    fn test_create_table_failure() {
+        let data_file = NamedTempFile::new().unwrap();
+        {
+            let disk = DiskManager::open(data_file.path()).unwrap();
+            let bufmgr = BufferPoolManager::new(disk, BufferPool::new(10));
+            Catalog::create(&bufmgr).unwrap();
+            bufmgr.flush().unwrap();
+        }
+
+        // Three frames are enough to create a table but not to split the
+        // catalog's root, so the first split fails after the new tree exists.
+        let disk = DiskManager::open(data_file.path()).unwrap();
+        let bufmgr = BufferPoolManager::new(disk, BufferPool::new(3));
+        let catalog = Catalog::open(&bufmgr);
+        let mut created = 0u64;
+        loop {
+            match catalog.create_table(created.to_be_bytes(), KeyType::Raw, false, false) {
+                Ok(_) => created += 1,
+                Err(Error::BTree(btree::Error::Buffer(buffer::Error::NoFreeBuffer { .. }))) => {
+                    break
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+        assert!(created > 0);
+        // Crashes without flushing.
+        drop(bufmgr);
+
+        let disk = DiskManager::open(data_file.path()).unwrap();
+        let bufmgr = BufferPoolManager::new(disk, BufferPool::new(10));
+        let catalog = Catalog::open(&bufmgr);
+        assert_eq!(None, catalog.get(created.to_be_bytes()).unwrap());
+        for table_id in 0..created {
+            let table = catalog.open_table(table_id.to_be_bytes()).unwrap().unwrap();
+            assert_eq!(0, table.count().unwrap());
+            assert_eq!(None, table.iter(None).unwrap().next(&mut vec![]).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_set_root() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let bufmgr = BufferPoolManager::new(disk, BufferPool::new(10));
+        let catalog = Catalog::create(&bufmgr).unwrap();
+        let original = catalog
+            .create_table(*b"original", KeyType::Raw, false, false)
+            .unwrap();
+        let namespace = Catalog::create_namespace(&bufmgr, *b"namespce").unwrap();
+        namespace
+            .create_table(*b"inspace!", KeyType::Raw, false, false)
+            .unwrap();
+
+        // A replacement assembled on the side, holding a copy of one entry
+        // and a table of its own.
+        let copy: btree::Access = btree::Access::create(&bufmgr).unwrap();
+        let replacement = Catalog::open_at(&bufmgr, copy.btree_page_id);
+        replacement.put(*b"renamed!", &original).unwrap();
+        let added = replacement
+            .create_table(*b"added!!!", KeyType::U64, false, false)
+            .unwrap();
+        Catalog::set_root(&bufmgr, copy.root_page_id().unwrap()).unwrap();
+
+        let catalog = Catalog::open(&bufmgr);
+        assert_eq!(None, catalog.get(*b"original").unwrap());
+        assert_eq!(Some(original), catalog.get(*b"renamed!").unwrap());
+        assert_eq!(Some(added), catalog.get(*b"added!!!").unwrap());
+        let table = catalog.open_table(*b"added!!!").unwrap().unwrap();
+        assert_eq!(added.btree_page_id, table.btree_page_id);
+        assert_eq!(2, catalog.access.count().unwrap());
+        // New entries land in the relocated tree.
+        catalog
+            .create_table(*b"another!", KeyType::Raw, false, false)
+            .unwrap();
+        assert_eq!(3, catalog.tables().unwrap().len());
+        let namespace = Catalog::open_namespace(&bufmgr, *b"namespce")
+            .unwrap()
+            .unwrap();
+        assert!(namespace.get(*b"inspace!").unwrap().is_some());
+
+        assert!(matches!(
+            Catalog::set_root(&bufmgr, PageId::CATALOG_PAGE_ID),
+            Err(Error::ReservedPage(_))
+        ));
+    }
+
+    #[test]
+    fn test_rename() {
+        let data_file = NamedTempFile::new().unwrap();
+        let open = || {
+            let disk = DiskManager::open(data_file.path()).unwrap();
+            BufferPoolManager::new(disk, BufferPool::new(10))
+        };
+        let note = |from: btree::Key, to: btree::Key, meta: &TableMeta| {
+            let mut note = vec![RENAME_NOTE];
+            note.extend_from_slice(&from);
+            note.extend_from_slice(&to);
+            note.extend_from_slice(&meta.encode());
+            note
+        };
+        let (original, moved) = {
+            let bufmgr = open();
+            let catalog = Catalog::create(&bufmgr).unwrap();
+            let renamed = catalog
+                .create_table(*b"renamed?", KeyType::Raw, false, false)
+                .unwrap();
+            catalog
+                .rename(*b"renamed?", *b"renamed!", &renamed)
+                .unwrap();
+            assert_eq!(None, catalog.access.note().unwrap());
+            let original = catalog
+                .create_table(*b"original", KeyType::Raw, false, false)
+                .unwrap();
+            let namespace = Catalog::create_namespace(&bufmgr, *b"namespce").unwrap();
+            let moved = namespace
+                .create_table(*b"inspace!", KeyType::U64, false, false)
+                .unwrap();
+            bufmgr.flush().unwrap();
+
+            // As if crashes had come after the notes were synced, once with
+            // the new entry on disk too.
+            catalog
+                .access
+                .set_note(&note(*b"original", *b"renamed!", &original))
+                .unwrap();
+            catalog.put(*b"renamed!", &original).unwrap();
+            namespace
+                .access
+                .set_note(&note(*b"inspace!", *b"moved!!!", &moved))
+                .unwrap();
+            bufmgr.flush().unwrap();
+            (original, moved)
+        };
+
+        let bufmgr = open();
+        Catalog::recover(&bufmgr).unwrap();
+        let catalog = Catalog::open(&bufmgr);
+        assert_eq!(None, catalog.get(*b"original").unwrap());
+        assert_eq!(Some(original), catalog.get(*b"renamed!").unwrap());
+        assert_eq!(None, catalog.access.note().unwrap());
+        let namespace = Catalog::open_namespace(&bufmgr, *b"namespce")
+            .unwrap()
+            .unwrap();
+        assert_eq!(None, namespace.get(*b"inspace!").unwrap());
+        assert_eq!(Some(moved), namespace.get(*b"moved!!!").unwrap());
+        assert_eq!(None, namespace.access.note().unwrap());
+    }
+}
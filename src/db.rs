@@ -0,0 +1,381 @@
+use std::{mem, path::Path, sync::Arc};
+
+use crate::{
+    btree::{self, Key},
+    buffer::{BufferPool, BufferPoolManager},
+    catalog::Catalog,
+    disk::{Disk, DiskManager},
+    encoding::KeyType,
+    executor::{self, Executor},
+    query::{Request, Response},
+};
+
+/// Comfortably above `btree::min_pool_size()` for 8-byte keys.
+const DEFAULT_POOL_SIZE: usize = 64;
+
+/// Prepares the database behind `bufmgr` for use, creating the catalog if the
+/// data file is brand new. `Db` does this itself; a server must call it
+/// before taking requests.
+pub fn bootstrap(bufmgr: &BufferPoolManager) -> Result<(), anyhow::Error> {
+    Catalog::open_or_create(bufmgr)?;
+    Catalog::recover(bufmgr)?;
+    Ok(())
+}
+
+/// An embedded database, usable without going through the TCP server.
+///
+/// ```
+/// use qp::Db;
+///
+/// let data_file = tempfile::NamedTempFile::new()?;
+/// let db = Db::open(data_file.path())?;
+/// let table_id = *b"greeting";
+/// db.create_table(table_id)?;
+/// db.put(table_id, 1u64.to_be_bytes(), b"hello")?;
+/// assert_eq!(Some(b"hello".to_vec()), db.get(table_id, 1u64.to_be_bytes())?);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Dropping a `Db` flushes it, ignoring any error. Call `close` to see one.
+pub struct Db {
+    bufmgr: Arc<BufferPoolManager>,
+    executor: Executor,
+    /// Set by `close`, which has flushed already.
+    closed: bool,
+}
+
+impl Db {
+    /// Opens the database stored at `data_file_path`, creating it if needed.
+    pub fn open(data_file_path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let disk = DiskManager::open(data_file_path)?;
+        Self::new(disk, BufferPool::new(DEFAULT_POOL_SIZE))
+    }
+
+    /// Opens an existing database for reading only. Writes and flushes fail
+    /// with `buffer::Error::ReadOnly`, leaving the file untouched.
+    pub fn open_read_only(data_file_path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let disk = DiskManager::open_read_only(data_file_path)?;
+        Self::new(disk, BufferPool::new(DEFAULT_POOL_SIZE))
+    }
+
+    /// Fails if `pool` is too small for tables' trees. See
+    /// `btree::check_pool_size`.
+    pub fn new(disk: impl Disk + 'static, pool: BufferPool) -> Result<Self, anyhow::Error> {
+        let bufmgr = Arc::new(BufferPoolManager::new(disk, pool));
+        btree::check_pool_size::<8>(&bufmgr)?;
+        bootstrap(&bufmgr)?;
+        let executor = Executor::new(bufmgr.clone());
+        Ok(Self {
+            bufmgr,
+            executor,
+            closed: false,
+        })
+    }
+
+    /// Executes a request of the wire protocol.
+    pub fn execute(&self, request: Request) -> Response {
+        self.executor.execute(request)
+    }
+
+    pub fn create_table(&self, table_id: Key) -> Result<(), anyhow::Error> {
+        Catalog::open(&self.bufmgr).create_table(table_id, KeyType::Raw, false, false)?;
+        self.executor.forget_tables();
+        Ok(())
+    }
+
+    /// Inserts or replaces a value. Returns `true` if the key was newly
+    /// created.
+    ///
+    /// The value is stored as `PutItem` stores it, with a version, so that
+    /// requests see it as they see their own.
+    pub fn put(&self, table_id: Key, key: Key, value: &[u8]) -> Result<bool, anyhow::Error> {
+        self.executor.put_bytes(table_id, key, value)
+    }
+
+    pub fn get(&self, table_id: Key, key: Key) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let mut buf = vec![];
+        if !self.table(table_id)?.get(key, &mut buf)? {
+            return Ok(None);
+        }
+        Ok(Some(executor::decode_bytes(buf)))
+    }
+
+    /// Returns up to `limit` records starting at `start` (or at either end of
+    /// the table if `None`), in descending key order if `backward` is set.
+    pub fn scan(
+        &self,
+        table_id: Key,
+        start: Option<Key>,
+        backward: bool,
+        limit: usize,
+    ) -> Result<Vec<(Key, Vec<u8>)>, anyhow::Error> {
+        let table = self.table(table_id)?;
+        let mut items = vec![];
+        let mut buf = vec![];
+        if backward {
+            let mut iter = table.iter_rev(start)?;
+            while items.len() < limit {
+                match iter.next(&mut buf)? {
+                    Some(key) => items.push((key, executor::decode_bytes(mem::take(&mut buf)))),
+                    None => break,
+                }
+            }
+        } else {
+            let mut iter = table.iter(start)?;
+            while items.len() < limit {
+                match iter.next(&mut buf)? {
+                    Some(key) => items.push((key, executor::decode_bytes(mem::take(&mut buf)))),
+                    None => break,
+                }
+            }
+        }
+        Ok(items)
+    }
+
+    /// Removes a key. Returns `true` if it was present.
+    pub fn delete(&self, table_id: Key, key: Key) -> Result<bool, anyhow::Error> {
+        self.executor.delete_bytes(table_id, key)
+    }
+
+    pub fn count(&self, table_id: Key) -> Result<u64, anyhow::Error> {
+        Ok(self.table(table_id)?.count()?)
+    }
+
+    pub fn flush(&self) -> Result<(), anyhow::Error> {
+        self.bufmgr.flush()?;
+        Ok(())
+    }
+
+    /// Flushes and closes the database, unlike dropping it reporting
+    /// whether the flush succeeded.
+    pub fn close(mut self) -> Result<(), anyhow::Error> {
+        self.closed = true;
+        if self.bufmgr.is_read_only() {
+            return Ok(());
+        }
+        self.flush()
+    }
+
+    /// Makes the tree rooted at `root_page_id` the contents of the catalog.
+    /// See `btree::Access::set_root_page_id`.
+    #[cfg(feature = "admin")]
+    pub fn set_catalog_root(&self, root_page_id: crate::disk::PageId) -> Result<(), anyhow::Error> {
+        Catalog::set_root(&self.bufmgr, root_page_id)?;
+        self.executor.forget_tables();
+        Ok(())
+    }
+
+    /// Opens the tree whose meta page is `btree_page_id`, whether or not any
+    /// table refers to it.
+    #[cfg(feature = "admin")]
+    pub fn open_tree(&self, btree_page_id: crate::disk::PageId) -> btree::Access<'_> {
+        btree::Access::open(&self.bufmgr, btree_page_id)
+    }
+
+    fn table(&self, table_id: Key) -> Result<btree::Access<'_>, anyhow::Error> {
+        Catalog::open(&self.bufmgr)
+            .open_table(table_id)?
+            .ok_or_else(|| anyhow::anyhow!("no such table"))
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        if self.closed || self.bufmgr.is_read_only() {
+            return;
+        }
+        // Best effort, like closing a file; `close` reports failures.
+        let _ = self.bufmgr.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use crate::{buffer, disk::MemoryDiskManager, query};
+
+    use super::*;
+
+    #[test]
+    fn test() {
+        let data_file = NamedTempFile::new().unwrap();
+        let table_id = *b"table001";
+        {
+            let db = Db::open(data_file.path()).unwrap();
+            db.create_table(table_id).unwrap();
+            assert!(db.put(table_id, 2u64.to_be_bytes(), b"world").unwrap());
+            assert!(db.put(table_id, 1u64.to_be_bytes(), b"hello").unwrap());
+            assert!(db.put(table_id, 3u64.to_be_bytes(), b"!").unwrap());
+            assert!(db.delete(table_id, 3u64.to_be_bytes()).unwrap());
+            db.flush().unwrap();
+        }
+        let db = Db::open(data_file.path()).unwrap();
+        assert_eq!(2, db.count(table_id).unwrap());
+        assert_eq!(None, db.get(table_id, 3u64.to_be_bytes()).unwrap());
+        assert_eq!(
+            vec![
+                (2u64.to_be_bytes(), b"world".to_vec()),
+                (1u64.to_be_bytes(), b"hello".to_vec()),
+            ],
+            db.scan(table_id, None, true, 10).unwrap()
+        );
+        assert!(db.get(*b"notfound", 1u64.to_be_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_versioned_values() {
+        let db = Db::new(MemoryDiskManager::new(), BufferPool::new(DEFAULT_POOL_SIZE)).unwrap();
+        let table_id = *b"table001";
+        db.create_table(table_id).unwrap();
+        // Looks like the header of a versioned value.
+        let tagged = b"\xFF\0\0\0\0\0\0\0\x07tagged";
+        db.put(table_id, 1u64.to_be_bytes(), tagged).unwrap();
+        assert_eq!(
+            Some(tagged.to_vec()),
+            db.get(table_id, 1u64.to_be_bytes()).unwrap()
+        );
+        db.put(table_id, 2u64.to_be_bytes(), b"plain").unwrap();
+        db.put(table_id, 2u64.to_be_bytes(), b"again").unwrap();
+        let request = serde_json::from_value(serde_json::json!({
+            "type": "GetItem",
+            "table_id": "7461626C65303031",
+            "key": "0000000000000002",
+        }))
+        .unwrap();
+        match db.execute(request) {
+            Response::GetItem(output) => {
+                assert_eq!(Some(2), output.version);
+                assert_eq!("again", output.item.unwrap().value);
+            }
+            response => panic!("{:?}", response),
+        }
+
+        let request = serde_json::from_value(serde_json::json!({
+            "type": "PutItem",
+            "table_id": "7461626C65303031",
+            "item": { "key": "0000000000000003", "value": "request" },
+        }))
+        .unwrap();
+        db.execute(request);
+        assert_eq!(
+            Some(b"request".to_vec()),
+            db.get(table_id, 3u64.to_be_bytes()).unwrap()
+        );
+        assert_eq!(
+            vec![
+                (1u64.to_be_bytes(), tagged.to_vec()),
+                (2u64.to_be_bytes(), b"again".to_vec()),
+                (3u64.to_be_bytes(), b"request".to_vec()),
+            ],
+            db.scan(table_id, None, false, 10).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_delete_append_only() {
+        let db = Db::new(MemoryDiskManager::new(), BufferPool::new(DEFAULT_POOL_SIZE)).unwrap();
+        let table_id = *b"table001";
+        let request = serde_json::from_value(serde_json::json!({
+            "type": "CreateTable",
+            "table_id": "7461626C65303031",
+            "key_type": "U64",
+            "append_only": true,
+        }))
+        .unwrap();
+        db.execute(request);
+        db.put(table_id, 1u64.to_be_bytes(), b"logged").unwrap();
+        assert!(db.delete(table_id, 1u64.to_be_bytes()).is_err());
+        assert_eq!(
+            Some(b"logged".to_vec()),
+            db.get(table_id, 1u64.to_be_bytes()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_flush_on_drop() {
+        let data_file = NamedTempFile::new().unwrap();
+        let table_id = *b"table001";
+        {
+            let db = Db::open(data_file.path()).unwrap();
+            db.create_table(table_id).unwrap();
+            db.put(table_id, 1u64.to_be_bytes(), b"dropped").unwrap();
+        }
+        let db = Db::open(data_file.path()).unwrap();
+        assert_eq!(
+            Some(b"dropped".to_vec()),
+            db.get(table_id, 1u64.to_be_bytes()).unwrap()
+        );
+        db.put(table_id, 2u64.to_be_bytes(), b"closed").unwrap();
+        db.close().unwrap();
+        let db = Db::open_read_only(data_file.path()).unwrap();
+        assert_eq!(
+            Some(b"closed".to_vec()),
+            db.get(table_id, 2u64.to_be_bytes()).unwrap()
+        );
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_read_only() {
+        let data_file = NamedTempFile::new().unwrap();
+        let table_id = *b"table001";
+        {
+            let db = Db::open(data_file.path()).unwrap();
+            db.create_table(table_id).unwrap();
+            for i in 0..100u64 {
+                db.put(table_id, i.to_be_bytes(), b"value").unwrap();
+            }
+            db.flush().unwrap();
+        }
+        let contents = std::fs::read(data_file.path()).unwrap();
+
+        let db = Db::open_read_only(data_file.path()).unwrap();
+        assert_eq!(100, db.scan(table_id, None, false, 1000).unwrap().len());
+        assert_eq!(100, db.count(table_id).unwrap());
+        let read_only = buffer::Error::ReadOnly.to_string();
+        let err = db
+            .put(table_id, 1u64.to_be_bytes(), b"changed")
+            .unwrap_err();
+        assert_eq!(read_only, err.to_string());
+        let err = db.delete(table_id, 1u64.to_be_bytes()).unwrap_err();
+        assert_eq!(read_only, err.to_string());
+        let err = db.create_table(*b"table002").unwrap_err();
+        assert_eq!(read_only, err.to_string());
+        assert_eq!(read_only, db.flush().unwrap_err().to_string());
+        let put = serde_json::json!({
+            "type": "PutItem",
+            "table_id": "7461626C65303031",
+            "item": { "key": "0000000000000001", "value": "changed" },
+        });
+        let response = db.execute(serde_json::from_value(put).unwrap());
+        assert!(matches!(response, Response::Error(query::Error::ReadOnly)));
+        assert_eq!(
+            Some(b"value".to_vec()),
+            db.get(table_id, 1u64.to_be_bytes()).unwrap()
+        );
+        drop(db);
+        assert_eq!(contents, std::fs::read(data_file.path()).unwrap());
+    }
+
+    #[test]
+    fn test_pool_too_small() {
+        let min = btree::min_pool_size::<8>();
+        let err = Db::new(MemoryDiskManager::new(), BufferPool::new(min - 1))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(btree::Error::PoolTooSmall { pool_size, .. }) if *pool_size == min - 1
+        ));
+        assert_eq!(
+            format!(
+                "buffer pool of {} frames is below the minimum of {} for B-trees",
+                min - 1,
+                min
+            ),
+            err.to_string()
+        );
+        assert!(Db::new(MemoryDiskManager::new(), BufferPool::new(min)).is_ok());
+    }
+}
@@ -1,13 +1,58 @@
 use std::{
     convert::{TryFrom, TryInto},
-    io::{prelude::*, SeekFrom},
+    io::{self, prelude::*, SeekFrom},
+    sync::atomic::{AtomicU64, Ordering},
 };
 use std::{fs::File, fs::OpenOptions, path::Path};
 
+use parking_lot::{Mutex, RwLock};
+use thiserror::Error;
 use zerocopy::{AsBytes, FromBytes};
 
+#[cfg(feature = "compression")]
+mod compress;
+
 pub const PAGE_SIZE: usize = 4096;
 
+/// Starts the header page that precedes page 0 of a data file. Files written
+/// before the header existed start straight with page 0.
+const MAGIC: [u8; 6] = *b"qpdata";
+/// Version of the on-disk format, stored big-endian right after `MAGIC`.
+pub const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2;
+/// Offset of the big-endian `next_page_id` within the header page.
+const NEXT_PAGE_ID_OFFSET: u64 = HEADER_LEN as u64;
+
+/// Brings a data file written in an older format version, passed as the
+/// second argument, up to `FORMAT_VERSION`. Pages are at their usual place
+/// past the header page.
+pub type Upgrade<'a> = &'a dyn Fn(&File, u16) -> io::Result<()>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("page {page_id:?} has not been allocated")]
+    PageNotAllocated { page_id: PageId },
+    #[error("failed to read page {page_id:?}")]
+    Read {
+        page_id: PageId,
+        #[source]
+        source: io::Error,
+    },
+    /// Returned wrapped in an `io::Error` of the same kind as `source`.
+    #[error("failed to write page {page_id:?}")]
+    Write {
+        page_id: PageId,
+        #[source]
+        source: io::Error,
+    },
+    #[error("data file format version {found} is newer than the supported version {current}")]
+    NewerFormat { found: u16, current: u16 },
+    #[error("data file format version {found} is older than {current} and can't be upgraded")]
+    UpgradeUnavailable { found: u16, current: u16 },
+}
+
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, FromBytes, AsBytes)]
 #[repr(C)]
 pub struct PageId(pub u64);
@@ -52,53 +97,543 @@ impl<'a> TryFrom<&'a [u8]> for PageId {
     }
 }
 
+/// Counters of page I/O issued since the `DiskManager` was created.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Stats {
+    pub reads: u64,
+    /// Pages written.
+    pub writes: u64,
+    /// Writes issued to the file, fewer than `writes` when contiguous pages
+    /// are written together.
+    pub write_calls: u64,
+    pub syncs: u64,
+}
+
+/// How much of the disk a data file takes up.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Usage {
+    pub allocated_pages: u64,
+    /// Can be less than the allocated pages take, as pages that were never
+    /// written don't extend the file.
+    pub file_len: u64,
+}
+
+/// Page storage behind a `BufferPoolManager`. Methods take `&self` so that
+/// threads can read and write different pages at the same time; callers
+/// make sure no two of them access the same page at once.
+pub trait Disk: Send + Sync {
+    /// Reads a page. A page that has been allocated but never written reads
+    /// as zeros.
+    fn read_page_data(&self, page_id: PageId, data: &mut [u8]) -> Result<(), Error>;
+    fn write_page_data(&self, page_id: PageId, data: &[u8]) -> std::io::Result<()>;
+    /// Writes several pages, in any order. Implementations may combine the
+    /// writes of contiguous pages.
+    fn write_pages(&self, pages: &[(PageId, &[u8])]) -> std::io::Result<()> {
+        for &(page_id, data) in pages {
+            self.write_page_data(page_id, data)?;
+        }
+        Ok(())
+    }
+    /// Whether allocating, writing and flushing pages fail.
+    fn is_read_only(&self) -> bool;
+    /// Makes every written page durable.
+    fn flush(&self) -> std::io::Result<()>;
+    fn stats(&self) -> Stats;
+    fn usage(&self) -> std::io::Result<Usage>;
+    /// Returns `true` if no page has been allocated yet.
+    fn is_empty(&self) -> bool;
+    /// The id the next `allocate_page` will return.
+    fn next_page_id(&self) -> PageId;
+    /// Hands out the next page id.
+    fn allocate_page(&self) -> std::io::Result<PageId>;
+}
+
+/// Keeps the pages of a data file. Pages are read and written with
+/// positioned I/O, so that no lock or shared file cursor serializes them.
 pub struct DiskManager {
     data_file: File,
-    next_page_id: u64,
+    next_page_id: AtomicU64,
+    /// Where page 0 starts: past the header page, or at the beginning of
+    /// files that have none.
+    data_offset: u64,
+    read_only: bool,
+    /// Pages the file is grown by at a time, or zero to let writes grow it.
+    preallocation: u64,
+    /// Whether `flush` syncs the file.
+    sync: bool,
+    /// File length as of the last preallocation, so that allocations within
+    /// it don't need to query the file. Held across each allocation, which
+    /// serializes them.
+    preallocated_len: Mutex<u64>,
+    stats: Mutex<Stats>,
+    /// Write-locked while a page is written, as a page that outgrows its
+    /// record moves to the end of the file.
+    #[cfg(feature = "compression")]
+    page_map: Option<RwLock<compress::PageMap>>,
 }
 
 impl DiskManager {
+    /// Opens a data file, writing the header page if the file is empty.
+    /// Files without a header keep inferring `next_page_id` from their
+    /// length. Files in an older format version are upgraded with
+    /// `upgrade_format`.
     pub fn new(data_file: File) -> std::io::Result<Self> {
-        let next_page_id = data_file.metadata()?.len() / PAGE_SIZE as u64;
+        Self::new_with_upgrade(data_file, Some(&upgrade_format))
+    }
+
+    /// Like `new`, but files in an older format version are upgraded with
+    /// `upgrade`, or refused if there is none. Files in a newer one are
+    /// always refused.
+    pub fn new_with_upgrade(
+        mut data_file: File,
+        upgrade: Option<Upgrade<'_>>,
+    ) -> std::io::Result<Self> {
+        let file_len = data_file.metadata()?.len();
+        let mut header = [0u8; HEADER_LEN + 8];
+        let (data_offset, next_page_id) = if file_len == 0 {
+            header[..MAGIC.len()].copy_from_slice(&MAGIC);
+            header[MAGIC.len()..HEADER_LEN].copy_from_slice(&FORMAT_VERSION.to_be_bytes());
+            data_file.write_all(&header)?;
+            (PAGE_SIZE as u64, 0)
+        } else {
+            data_file.seek(SeekFrom::Start(0))?;
+            data_file.read_exact(&mut header)?;
+            if header[..MAGIC.len()] == MAGIC {
+                let version =
+                    u16::from_be_bytes(header[MAGIC.len()..HEADER_LEN].try_into().unwrap());
+                check_format_version(&data_file, version, upgrade)?;
+                let next_page_id = header[HEADER_LEN..].try_into().unwrap();
+                (PAGE_SIZE as u64, u64::from_be_bytes(next_page_id))
+            } else {
+                (0, file_len / PAGE_SIZE as u64)
+            }
+        };
         Ok(Self {
             data_file,
-            next_page_id,
+            next_page_id: AtomicU64::new(next_page_id),
+            data_offset,
+            read_only: false,
+            preallocation: 0,
+            sync: true,
+            preallocated_len: Mutex::new(0),
+            stats: Mutex::default(),
+            #[cfg(feature = "compression")]
+            page_map: None,
         })
     }
 
+    /// Grows the file `chunk_pages` pages at a time as pages are allocated,
+    /// instead of a page at a time as they are written. Files without a
+    /// header, or compressed ones, are left to grow on their own, since
+    /// their page count is inferred from the file.
+    pub fn with_preallocation(mut self, chunk_pages: u64) -> Self {
+        self.preallocation = chunk_pages;
+        self
+    }
+
+    /// Has `flush` skip syncing the file, leaving written pages to the OS to
+    /// write back whenever it likes. They are still read back within the
+    /// process, but a crash of the machine may lose any of them, or tear
+    /// the file between pages. Only for benchmarks and disposable data.
+    pub fn with_sync(mut self, enabled: bool) -> Self {
+        self.sync = enabled;
+        self
+    }
+
+    fn preallocate(&self, page_id: u64, preallocated_len: &mut u64) -> std::io::Result<()> {
+        if self.preallocation == 0 || self.data_offset == 0 {
+            return Ok(());
+        }
+        let required_len = self.data_offset + PAGE_SIZE as u64 * (page_id + 1);
+        if required_len <= *preallocated_len {
+            return Ok(());
+        }
+        let file_len = self.file_len()?;
+        if required_len > file_len {
+            let chunk_len = PAGE_SIZE as u64 * self.preallocation;
+            let new_len = self.data_offset
+                + (required_len - self.data_offset).div_ceil(chunk_len) * chunk_len;
+            self.data_file.set_len(new_len)?;
+            *preallocated_len = new_len;
+        } else {
+            *preallocated_len = file_len;
+        }
+        Ok(())
+    }
+
     pub fn open(data_file_path: impl AsRef<Path>) -> std::io::Result<Self> {
-        let data_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(data_file_path)?;
-        Self::new(data_file)
+        Self::new(open_data_file(data_file_path)?)
+    }
+
+    /// Opens an existing data file without write access. Allocating, writing
+    /// and flushing pages then fail, and so does opening a file in an older
+    /// format version, as it can't be upgraded.
+    pub fn open_read_only(data_file_path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let data_file = OpenOptions::new().read(true).open(data_file_path)?;
+        let mut disk = Self::new_with_upgrade(data_file, None)?;
+        disk.read_only = true;
+        Ok(disk)
+    }
+
+    fn check_writable(&self) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "data file is open read-only",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Like `new`, but pages are stored zstd-compressed as variable-length
+    /// records. The file must have been written in this mode too.
+    #[cfg(feature = "compression")]
+    pub fn new_compressed(mut data_file: File) -> std::io::Result<Self> {
+        let page_map = compress::PageMap::load(&mut data_file)?;
+        Ok(Self {
+            data_file,
+            next_page_id: AtomicU64::new(page_map.next_page_id()),
+            data_offset: 0,
+            read_only: false,
+            preallocation: 0,
+            sync: true,
+            preallocated_len: Mutex::new(0),
+            stats: Mutex::default(),
+            page_map: Some(RwLock::new(page_map)),
+        })
+    }
+
+    #[cfg(feature = "compression")]
+    pub fn open_compressed(data_file_path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::new_compressed(open_data_file(data_file_path)?)
+    }
+
+    /// Length of the data file in bytes, header included.
+    pub fn file_len(&self) -> std::io::Result<u64> {
+        Ok(self.data_file.metadata()?.len())
+    }
+
+    /// Number of page ids handed out so far.
+    pub fn allocated_pages(&self) -> u64 {
+        self.next_page_id.load(Ordering::Acquire)
+    }
+}
+
+impl Disk for DiskManager {
+    fn read_page_data(&self, page_id: PageId, data: &mut [u8]) -> Result<(), Error> {
+        if page_id.0 >= self.allocated_pages() {
+            return Err(Error::PageNotAllocated { page_id });
+        }
+        self.stats.lock().reads += 1;
+        let read_error = |source| Error::Read { page_id, source };
+        #[cfg(feature = "compression")]
+        if let Some(page_map) = &self.page_map {
+            return page_map
+                .read()
+                .read_page(&self.data_file, page_id, data)
+                .map_err(read_error);
+        }
+        let offset = self.data_offset + PAGE_SIZE as u64 * page_id.0;
+        match read_exact_at(&self.data_file, data, offset) {
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                if offset < self.data_file.metadata()?.len() {
+                    return Err(read_error(err));
+                }
+                data.fill(0);
+                Ok(())
+            }
+            result => result.map_err(read_error),
+        }
+    }
+
+    fn write_page_data(&self, page_id: PageId, data: &[u8]) -> std::io::Result<()> {
+        self.check_writable()?;
+        {
+            let mut stats = self.stats.lock();
+            stats.writes += 1;
+            stats.write_calls += 1;
+        }
+        #[cfg(feature = "compression")]
+        if let Some(page_map) = &self.page_map {
+            return page_map
+                .write()
+                .write_page(&self.data_file, page_id, data)
+                .map_err(write_error(page_id));
+        }
+        let offset = self.data_offset + PAGE_SIZE as u64 * page_id.0;
+        write_all_at(&self.data_file, data, offset).map_err(write_error(page_id))
     }
 
-    pub fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> std::io::Result<()> {
-        let offset = PAGE_SIZE as u64 * page_id.0;
-        self.data_file.seek(SeekFrom::Start(offset))?;
-        self.data_file.read_exact(data)
+    /// Sorts the pages by id and writes each run of contiguous ones with a
+    /// single positioned write. Compressed pages are written one by one, as
+    /// they aren't laid out by id.
+    fn write_pages(&self, pages: &[(PageId, &[u8])]) -> std::io::Result<()> {
+        self.check_writable()?;
+        #[cfg(feature = "compression")]
+        if self.page_map.is_some() {
+            for &(page_id, data) in pages {
+                self.write_page_data(page_id, data)?;
+            }
+            return Ok(());
+        }
+        let mut pages = pages.to_vec();
+        pages.sort_unstable_by_key(|&(page_id, _)| page_id);
+        let mut run_buf = vec![];
+        for run in pages.chunk_by(|(a, _), (b, _)| a.0 + 1 == b.0) {
+            {
+                let mut stats = self.stats.lock();
+                stats.writes += run.len() as u64;
+                stats.write_calls += 1;
+            }
+            // Errors name the first page of the run.
+            let first_page_id = run[0].0;
+            let offset = self.data_offset + PAGE_SIZE as u64 * first_page_id.0;
+            if let [(_, data)] = run {
+                write_all_at(&self.data_file, data, offset).map_err(write_error(first_page_id))?;
+                continue;
+            }
+            run_buf.clear();
+            for (_, data) in run {
+                run_buf.extend_from_slice(data);
+            }
+            write_all_at(&self.data_file, &run_buf, offset).map_err(write_error(first_page_id))?;
+        }
+        Ok(())
     }
 
-    pub fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> std::io::Result<()> {
-        let offset = PAGE_SIZE as u64 * page_id.0;
-        self.data_file.seek(SeekFrom::Start(offset))?;
-        self.data_file.write_all(data)
+    fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
-    pub fn flush(&mut self) -> std::io::Result<()> {
-        self.data_file.flush()?;
+    fn flush(&self) -> std::io::Result<()> {
+        self.check_writable()?;
+        if !self.sync {
+            return Ok(());
+        }
+        self.stats.lock().syncs += 1;
         self.data_file.sync_all()
     }
 
-    pub fn allocate_page(&mut self) -> PageId {
-        let page_id = self.next_page_id;
-        self.next_page_id += 1;
-        PageId(page_id)
+    fn stats(&self) -> Stats {
+        *self.stats.lock()
+    }
+
+    fn usage(&self) -> std::io::Result<Usage> {
+        Ok(Usage {
+            allocated_pages: self.allocated_pages(),
+            file_len: self.file_len()?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allocated_pages() == 0
+    }
+
+    fn next_page_id(&self) -> PageId {
+        PageId(self.allocated_pages())
+    }
+
+    fn allocate_page(&self) -> std::io::Result<PageId> {
+        self.check_writable()?;
+        let mut preallocated_len = self.preallocated_len.lock();
+        let page_id = self.allocated_pages();
+        self.preallocate(page_id, &mut preallocated_len)?;
+        // Records the new high-water mark in the header page, if any.
+        if self.data_offset > 0 {
+            write_all_at(
+                &self.data_file,
+                &(page_id + 1).to_be_bytes(),
+                NEXT_PAGE_ID_OFFSET,
+            )?;
+        }
+        self.next_page_id.store(page_id + 1, Ordering::Release);
+        Ok(PageId(page_id))
+    }
+}
+
+/// Keeps pages in memory, for tests and databases that needn't outlive the
+/// process. Flushing does nothing.
+#[derive(Default)]
+pub struct MemoryDiskManager {
+    pages: RwLock<Vec<Box<[u8; PAGE_SIZE]>>>,
+    stats: Mutex<Stats>,
+}
+
+impl MemoryDiskManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Disk for MemoryDiskManager {
+    fn read_page_data(&self, page_id: PageId, data: &mut [u8]) -> Result<(), Error> {
+        let pages = self.pages.read();
+        let page = pages
+            .get(page_id.0 as usize)
+            .ok_or(Error::PageNotAllocated { page_id })?;
+        self.stats.lock().reads += 1;
+        data.copy_from_slice(&page[..]);
+        Ok(())
+    }
+
+    fn write_page_data(&self, page_id: PageId, data: &[u8]) -> std::io::Result<()> {
+        let mut pages = self.pages.write();
+        let page = pages.get_mut(page_id.0 as usize).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "page not allocated")
+        })?;
+        {
+            let mut stats = self.stats.lock();
+            stats.writes += 1;
+            stats.write_calls += 1;
+        }
+        page.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        self.stats.lock().syncs += 1;
+        Ok(())
+    }
+
+    fn stats(&self) -> Stats {
+        *self.stats.lock()
+    }
+
+    fn usage(&self) -> std::io::Result<Usage> {
+        Ok(Usage {
+            allocated_pages: self.pages.read().len() as u64,
+            file_len: 0,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pages.read().is_empty()
+    }
+
+    fn next_page_id(&self) -> PageId {
+        PageId(self.pages.read().len() as u64)
+    }
+
+    fn allocate_page(&self) -> std::io::Result<PageId> {
+        let mut pages = self.pages.write();
+        pages.push(Box::new([0; PAGE_SIZE]));
+        Ok(PageId(pages.len() as u64 - 1))
     }
 }
 
+/// Wraps an error writing `page_id` in `Error::Write`.
+fn write_error(page_id: PageId) -> impl FnOnce(io::Error) -> io::Error {
+    move |source| io::Error::new(source.kind(), Error::Write { page_id, source })
+}
+
+/// Reads and writes at an offset, either of which may transfer only part of
+/// the buffer or be interrupted by a signal.
+trait PositionedIo {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize>;
+}
+
+impl PositionedIo for File {
+    #[cfg(unix)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+
+    #[cfg(unix)]
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::write_at(self, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_write(self, buf, offset)
+    }
+}
+
+/// Reads until `buf` is full, retrying interrupted reads.
+fn read_exact_at(file: &impl PositionedIo, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match file.read_at(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Writes the whole of `buf`, retrying interrupted writes.
+fn write_all_at(file: &impl PositionedIo, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match file.write_at(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Upgrades data files in an older format version. None has existed yet, so
+/// there is nothing to do.
+fn upgrade_format(_data_file: &File, _from: u16) -> io::Result<()> {
+    Ok(())
+}
+
+/// Runs `upgrade` on a file in an older format version and stamps it with
+/// the current one.
+fn check_format_version(
+    data_file: &File,
+    version: u16,
+    upgrade: Option<Upgrade<'_>>,
+) -> io::Result<()> {
+    let unsupported = |err: Error| io::Error::new(io::ErrorKind::InvalidData, err);
+    if version > FORMAT_VERSION {
+        return Err(unsupported(Error::NewerFormat {
+            found: version,
+            current: FORMAT_VERSION,
+        }));
+    }
+    if version < FORMAT_VERSION {
+        let upgrade = upgrade.ok_or_else(|| {
+            unsupported(Error::UpgradeUnavailable {
+                found: version,
+                current: FORMAT_VERSION,
+            })
+        })?;
+        upgrade(data_file, version)?;
+        data_file.sync_all()?;
+        write_all_at(data_file, &FORMAT_VERSION.to_be_bytes(), MAGIC.len() as u64)?;
+    }
+    Ok(())
+}
+
+fn open_data_file(data_file_path: impl AsRef<Path>) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(data_file_path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,23 +642,367 @@ mod tests {
     #[test]
     fn test() {
         let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
-        let mut disk = DiskManager::new(data_file).unwrap();
+        let disk = DiskManager::new(data_file).unwrap();
         let mut hello = Vec::with_capacity(PAGE_SIZE);
         hello.extend_from_slice(b"hello");
         hello.resize(PAGE_SIZE, 0);
-        let hello_page_id = disk.allocate_page();
+        let hello_page_id = disk.allocate_page().unwrap();
         disk.write_page_data(hello_page_id, &hello).unwrap();
         let mut world = Vec::with_capacity(PAGE_SIZE);
         world.extend_from_slice(b"world");
         world.resize(PAGE_SIZE, 0);
-        let world_page_id = disk.allocate_page();
+        let world_page_id = disk.allocate_page().unwrap();
         disk.write_page_data(world_page_id, &world).unwrap();
         drop(disk);
-        let mut disk2 = DiskManager::open(&data_file_path).unwrap();
+        let disk2 = DiskManager::open(&data_file_path).unwrap();
         let mut buf = vec![0; PAGE_SIZE];
         disk2.read_page_data(hello_page_id, &mut buf).unwrap();
         assert_eq!(hello, buf);
         disk2.read_page_data(world_page_id, &mut buf).unwrap();
         assert_eq!(world, buf);
     }
+
+    #[test]
+    fn test_next_page_id_persisted() {
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file).unwrap();
+        assert!(disk.is_empty());
+        let page_id = disk.allocate_page().unwrap();
+        disk.write_page_data(page_id, &[0xAB; PAGE_SIZE]).unwrap();
+        // Allocated but never written, so the file is shorter than the ids
+        // handed out suggest.
+        disk.allocate_page().unwrap();
+        disk.allocate_page().unwrap();
+        drop(disk);
+
+        let disk = DiskManager::open(&data_file_path).unwrap();
+        assert!(!disk.is_empty());
+        assert_eq!(PageId(3), disk.allocate_page().unwrap());
+        drop(disk);
+
+        // Nor does a file longer than its pages confuse the allocator.
+        OpenOptions::new()
+            .write(true)
+            .open(&data_file_path)
+            .unwrap()
+            .set_len(100 * PAGE_SIZE as u64)
+            .unwrap();
+        let disk = DiskManager::open(&data_file_path).unwrap();
+        assert_eq!(PageId(4), disk.allocate_page().unwrap());
+        let mut buf = vec![0; PAGE_SIZE];
+        disk.read_page_data(page_id, &mut buf).unwrap();
+        assert_eq!(vec![0xAB; PAGE_SIZE], buf);
+    }
+
+    #[test]
+    fn test_usage() {
+        let (data_file, _) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file).unwrap();
+        assert_eq!(0, disk.allocated_pages());
+        for _ in 0..5 {
+            disk.allocate_page().unwrap();
+        }
+        disk.write_page_data(PageId(2), &[1; PAGE_SIZE]).unwrap();
+        assert_eq!(
+            Usage {
+                allocated_pages: 5,
+                file_len: PAGE_SIZE as u64 * 4,
+            },
+            disk.usage().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_headerless_file() {
+        let (mut data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        data_file.write_all(&[0xAB; PAGE_SIZE * 2]).unwrap();
+        drop(data_file);
+        let disk = DiskManager::open(&data_file_path).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        disk.read_page_data(PageId(1), &mut buf).unwrap();
+        assert_eq!(vec![0xAB; PAGE_SIZE], buf);
+        assert_eq!(PageId(2), disk.allocate_page().unwrap());
+    }
+
+    /// Stamps a data file with `version` in place of the current one.
+    fn stamp_version(data_file_path: &Path, version: u16) {
+        let data_file = OpenOptions::new().write(true).open(data_file_path).unwrap();
+        write_all_at(&data_file, &version.to_be_bytes(), MAGIC.len() as u64).unwrap();
+    }
+
+    fn version_error(err: io::Error) -> Error {
+        *err.into_inner().unwrap().downcast::<Error>().unwrap()
+    }
+
+    #[test]
+    fn test_format_upgrade() {
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file).unwrap();
+        let page_id = disk.allocate_page().unwrap();
+        disk.write_page_data(page_id, &[0xAB; PAGE_SIZE]).unwrap();
+        drop(disk);
+        stamp_version(&data_file_path, FORMAT_VERSION - 1);
+
+        let err = DiskManager::open_read_only(&data_file_path).err().unwrap();
+        assert!(matches!(
+            version_error(err),
+            Error::UpgradeUnavailable { found, current: FORMAT_VERSION } if found == FORMAT_VERSION - 1
+        ));
+
+        let upgraded_from = std::cell::Cell::new(None);
+        let upgrade = |_: &File, from| {
+            upgraded_from.set(Some(from));
+            Ok(())
+        };
+        let data_file = open_data_file(&data_file_path).unwrap();
+        let disk = DiskManager::new_with_upgrade(data_file, Some(&upgrade)).unwrap();
+        assert_eq!(Some(FORMAT_VERSION - 1), upgraded_from.get());
+        let mut buf = vec![0; PAGE_SIZE];
+        disk.read_page_data(page_id, &mut buf).unwrap();
+        assert_eq!(vec![0xAB; PAGE_SIZE], buf);
+        drop(disk);
+
+        // The file now carries the current version, so it isn't upgraded again.
+        upgraded_from.set(None);
+        let data_file = open_data_file(&data_file_path).unwrap();
+        DiskManager::new_with_upgrade(data_file, Some(&upgrade)).unwrap();
+        assert_eq!(None, upgraded_from.get());
+        DiskManager::open_read_only(&data_file_path).unwrap();
+    }
+
+    #[test]
+    fn test_newer_format() {
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        drop(DiskManager::new(data_file).unwrap());
+        stamp_version(&data_file_path, FORMAT_VERSION + 1);
+        let err = DiskManager::open(&data_file_path).err().unwrap();
+        assert!(matches!(
+            version_error(err),
+            Error::NewerFormat { found, current: FORMAT_VERSION } if found == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_read_unwritten_page() {
+        let (data_file, _) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file).unwrap();
+        let written_page_id = disk.allocate_page().unwrap();
+        disk.write_page_data(written_page_id, &[1; PAGE_SIZE])
+            .unwrap();
+        let unwritten_page_id = disk.allocate_page().unwrap();
+
+        let mut buf = vec![0xFF; PAGE_SIZE];
+        disk.read_page_data(unwritten_page_id, &mut buf).unwrap();
+        assert_eq!(vec![0; PAGE_SIZE], buf);
+        assert!(matches!(
+            disk.read_page_data(PageId(2), &mut buf),
+            Err(Error::PageNotAllocated { page_id: PageId(2) })
+        ));
+    }
+
+    #[test]
+    fn test_write_pages() {
+        let write = |batched: bool| {
+            let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+            let disk = DiskManager::new(data_file).unwrap();
+            for _ in 0..8 {
+                disk.allocate_page().unwrap();
+            }
+            let pages = [3u8, 1, 2, 0, 6, 7]
+                .iter()
+                .map(|&i| (PageId(i as u64), [i + 1; PAGE_SIZE]))
+                .collect::<Vec<_>>();
+            if batched {
+                let pages = pages
+                    .iter()
+                    .map(|(page_id, data)| (*page_id, &data[..]))
+                    .collect::<Vec<_>>();
+                disk.write_pages(&pages).unwrap();
+            } else {
+                for (page_id, data) in &pages {
+                    disk.write_page_data(*page_id, data).unwrap();
+                }
+            }
+            (disk.stats(), std::fs::read(data_file_path).unwrap())
+        };
+        let (stats, contents) = write(false);
+        assert_eq!((6, 6), (stats.writes, stats.write_calls));
+        let (batched_stats, batched_contents) = write(true);
+        // Pages 0 to 3 and 6 to 7.
+        assert_eq!((6, 2), (batched_stats.writes, batched_stats.write_calls));
+        assert_eq!(contents, batched_contents);
+    }
+
+    #[test]
+    fn test_no_sync() {
+        let disk = DiskManager::new(tempfile::tempfile().unwrap())
+            .unwrap()
+            .with_sync(false);
+        for i in 0..10 {
+            let page_id = disk.allocate_page().unwrap();
+            disk.write_page_data(page_id, &[i; PAGE_SIZE]).unwrap();
+            disk.flush().unwrap();
+        }
+        assert_eq!(0, disk.stats().syncs);
+        assert_eq!(10, disk.stats().writes);
+        let mut buf = vec![0; PAGE_SIZE];
+        for i in 0..10 {
+            disk.read_page_data(PageId(i), &mut buf).unwrap();
+            assert_eq!(vec![i as u8; PAGE_SIZE], buf);
+        }
+
+        let disk = disk.with_sync(true);
+        disk.flush().unwrap();
+        assert_eq!(1, disk.stats().syncs);
+    }
+
+    #[test]
+    fn test_preallocation() {
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file).unwrap().with_preallocation(256);
+        let mut file_lens = vec![];
+        for _ in 0..1000 {
+            let page_id = disk.allocate_page().unwrap();
+            disk.write_page_data(page_id, &[1; PAGE_SIZE]).unwrap();
+            let file_len = disk.file_len().unwrap();
+            if file_lens.last() != Some(&file_len) {
+                file_lens.push(file_len);
+            }
+        }
+        let chunk_len = 256 * PAGE_SIZE as u64;
+        let header_len = PAGE_SIZE as u64;
+        assert_eq!(
+            vec![
+                header_len + chunk_len,
+                header_len + 2 * chunk_len,
+                header_len + 3 * chunk_len,
+                header_len + 4 * chunk_len,
+            ],
+            file_lens
+        );
+        drop(disk);
+
+        // The page count comes from the header, not the file length.
+        let disk = DiskManager::open(&data_file_path).unwrap();
+        assert_eq!(PageId(1000), disk.allocate_page().unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_io() {
+        let (data_file, _) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file).unwrap();
+        let page_ids = (0..64)
+            .map(|_| disk.allocate_page().unwrap())
+            .collect::<Vec<_>>();
+        // Each thread owns every fourth page and keeps rewriting and reading
+        // it back while the others do the same to theirs.
+        std::thread::scope(|s| {
+            for thread_index in 0..4u8 {
+                let disk = &disk;
+                let page_ids = &page_ids;
+                s.spawn(move || {
+                    let mut buf = vec![0; PAGE_SIZE];
+                    for round in 0..50u8 {
+                        for page_id in page_ids.iter().skip(thread_index as usize).step_by(4) {
+                            let fill = thread_index.wrapping_mul(64).wrapping_add(round);
+                            disk.write_page_data(*page_id, &[fill; PAGE_SIZE]).unwrap();
+                            disk.read_page_data(*page_id, &mut buf).unwrap();
+                            assert!(buf.iter().all(|byte| *byte == fill));
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(4 * 50 * 16, disk.stats().writes);
+        let mut buf = vec![0; PAGE_SIZE];
+        for (index, page_id) in page_ids.iter().enumerate() {
+            disk.read_page_data(*page_id, &mut buf).unwrap();
+            let last_fill = ((index % 4) as u8).wrapping_mul(64).wrapping_add(49);
+            assert_eq!(vec![last_fill; PAGE_SIZE], buf);
+        }
+    }
+
+    /// Backed by a buffer, transferring at most `chunk` bytes a call after
+    /// failing the first with `Interrupted`.
+    struct FlakyIo {
+        data: Mutex<Vec<u8>>,
+        chunk: usize,
+        interrupted: std::cell::Cell<bool>,
+    }
+
+    impl FlakyIo {
+        fn interrupt(&self) -> io::Result<()> {
+            if self.interrupted.replace(true) {
+                return Ok(());
+            }
+            Err(io::ErrorKind::Interrupted.into())
+        }
+    }
+
+    impl PositionedIo for FlakyIo {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.interrupt()?;
+            let data = self.data.lock();
+            let src = &data[offset as usize..];
+            let n = buf.len().min(src.len()).min(self.chunk);
+            buf[..n].copy_from_slice(&src[..n]);
+            Ok(n)
+        }
+
+        fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            self.interrupt()?;
+            let mut data = self.data.lock();
+            let n = buf.len().min(self.chunk);
+            let offset = offset as usize;
+            if data.len() < offset + n {
+                data.resize(offset + n, 0);
+            }
+            data[offset..offset + n].copy_from_slice(&buf[..n]);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_interrupted_io() {
+        let io = FlakyIo {
+            data: Mutex::default(),
+            chunk: 1000,
+            interrupted: Default::default(),
+        };
+        let page = (0..PAGE_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+        write_all_at(&io, &page, PAGE_SIZE as u64).unwrap();
+        assert_eq!(&page[..], &io.data.lock()[PAGE_SIZE..]);
+
+        io.interrupted.set(false);
+        let mut buf = vec![0; PAGE_SIZE];
+        read_exact_at(&io, &mut buf, PAGE_SIZE as u64).unwrap();
+        assert_eq!(page, buf);
+    }
+
+    #[test]
+    fn test_truncated_page() {
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file).unwrap();
+        for _ in 0..2 {
+            let page_id = disk.allocate_page().unwrap();
+            disk.write_page_data(page_id, &[1; PAGE_SIZE]).unwrap();
+        }
+        drop(disk);
+        // Cuts page 1 in half, past the header page and page 0.
+        OpenOptions::new()
+            .write(true)
+            .open(&data_file_path)
+            .unwrap()
+            .set_len(PAGE_SIZE as u64 * 5 / 2)
+            .unwrap();
+        let disk = DiskManager::open(&data_file_path).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        match disk.read_page_data(PageId(1), &mut buf) {
+            Err(Error::Read {
+                page_id: PageId(1),
+                source,
+            }) => assert_eq!(io::ErrorKind::UnexpectedEof, source.kind()),
+            result => panic!("{:?}", result),
+        }
+    }
 }
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, prelude::*, SeekFrom},
+    mem::size_of,
+};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use super::{read_exact_at, write_all_at, PageId, PAGE_SIZE};
+
+/// Granularity of the space allocated to a stored page.
+const SLOT_SIZE: u64 = 512;
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Precedes every stored page. A page whose `len` is `PAGE_SIZE` is stored
+/// uncompressed.
+#[derive(Debug, Default, FromBytes, AsBytes)]
+#[repr(C)]
+struct Header {
+    page_id: PageId,
+    /// Slots reserved for the page, which may be more than `len` needs once
+    /// the page has shrunk.
+    slots: u32,
+    len: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    slot: u64,
+    slots: u32,
+}
+
+/// Maps page ids to their records in a compressed data file. Records are
+/// aligned to `SLOT_SIZE`, rewritten in place while they fit and appended to
+/// the end of the file otherwise.
+pub struct PageMap {
+    locations: HashMap<PageId, Location>,
+    next_slot: u64,
+}
+
+impl PageMap {
+    /// Rebuilds the map by walking every record in the file. A relocated page
+    /// always lands after its stale record, so the last record wins.
+    pub fn load(data_file: &mut File) -> io::Result<Self> {
+        let file_len = data_file.metadata()?.len();
+        let mut locations = HashMap::new();
+        let mut slot = 0;
+        while slot * SLOT_SIZE < file_len {
+            let mut header = Header::default();
+            data_file.seek(SeekFrom::Start(slot * SLOT_SIZE))?;
+            data_file.read_exact(header.as_bytes_mut())?;
+            if header.slots == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "malformed page record",
+                ));
+            }
+            let location = Location {
+                slot,
+                slots: header.slots,
+            };
+            locations.insert(header.page_id, location);
+            slot += header.slots as u64;
+        }
+        Ok(Self {
+            locations,
+            next_slot: slot,
+        })
+    }
+
+    pub fn next_page_id(&self) -> u64 {
+        self.locations
+            .keys()
+            .map(|page_id| page_id.0 + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn read_page(&self, data_file: &File, page_id: PageId, data: &mut [u8]) -> io::Result<()> {
+        let location = match self.locations.get(&page_id) {
+            Some(location) => location,
+            None => {
+                data.fill(0);
+                return Ok(());
+            }
+        };
+        let mut header = Header::default();
+        let offset = location.slot * SLOT_SIZE;
+        read_exact_at(data_file, header.as_bytes_mut(), offset)?;
+        let payload_offset = offset + size_of::<Header>() as u64;
+        if header.len as usize == PAGE_SIZE {
+            return read_exact_at(data_file, data, payload_offset);
+        }
+        let mut compressed = vec![0; header.len as usize];
+        read_exact_at(data_file, &mut compressed, payload_offset)?;
+        if zstd::bulk::decompress_to_buffer(&compressed, data)? != data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated compressed page",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn write_page(&mut self, data_file: &File, page_id: PageId, data: &[u8]) -> io::Result<()> {
+        let compressed = zstd::bulk::compress(data, COMPRESSION_LEVEL)?;
+        let payload = if compressed.len() < data.len() {
+            &compressed[..]
+        } else {
+            data
+        };
+        let record_len = size_of::<Header>() + payload.len();
+        let needed_slots = (record_len as u64).div_ceil(SLOT_SIZE) as u32;
+        let location = match self.locations.get(&page_id) {
+            Some(location) if location.slots >= needed_slots => *location,
+            _ => Location {
+                slot: self.next_slot,
+                slots: needed_slots,
+            },
+        };
+        let header = Header {
+            page_id,
+            slots: location.slots,
+            len: payload.len() as u32,
+        };
+        let mut record = Vec::with_capacity(record_len);
+        record.extend_from_slice(header.as_bytes());
+        record.extend_from_slice(payload);
+        write_all_at(data_file, &record, location.slot * SLOT_SIZE)?;
+        self.next_slot = self.next_slot.max(location.slot + location.slots as u64);
+        self.locations.insert(page_id, location);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::super::{Disk, DiskManager};
+    use super::*;
+
+    fn incompressible_page() -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..PAGE_SIZE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new_compressed(data_file).unwrap();
+        let mut compressible = b"hello".repeat(PAGE_SIZE / 5);
+        compressible.resize(PAGE_SIZE, 0);
+        let incompressible = incompressible_page();
+        let compressible_page_id = disk.allocate_page().unwrap();
+        disk.write_page_data(compressible_page_id, &compressible)
+            .unwrap();
+        let incompressible_page_id = disk.allocate_page().unwrap();
+        disk.write_page_data(incompressible_page_id, &incompressible)
+            .unwrap();
+        disk.flush().unwrap();
+        drop(disk);
+        assert!(std::fs::metadata(&data_file_path).unwrap().len() < 2 * PAGE_SIZE as u64);
+
+        let disk = DiskManager::open_compressed(&data_file_path).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        disk.read_page_data(compressible_page_id, &mut buf).unwrap();
+        assert_eq!(compressible, buf);
+        disk.read_page_data(incompressible_page_id, &mut buf)
+            .unwrap();
+        assert_eq!(incompressible, buf);
+
+        // The page no longer fits in its slots and has to move.
+        disk.write_page_data(compressible_page_id, &incompressible)
+            .unwrap();
+        let new_page_id = disk.allocate_page().unwrap();
+        assert_eq!(PageId(2), new_page_id);
+        drop(disk);
+        let disk = DiskManager::open_compressed(&data_file_path).unwrap();
+        disk.read_page_data(compressible_page_id, &mut buf).unwrap();
+        assert_eq!(incompressible, buf);
+    }
+}
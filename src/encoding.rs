@@ -0,0 +1,635 @@
+use std::convert::{TryFrom, TryInto};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::btree;
+
+const SIGN_BIT: u64 = 1 << 63;
+/// Strings are packed into the key followed by their length in the last byte.
+const MAX_STRING_KEY_LEN: usize = 7;
+/// The standard alphabet of RFC 4648.
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// Length of a padded base64 raw key, which tells it apart from a hex one.
+const BASE64_KEY_LEN: usize = 12;
+/// Fields a composite key may be made of.
+pub const MAX_KEY_FIELDS: usize = 4;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{key_type:?} table requires {expected}")]
+    InvalidKey {
+        key_type: KeyType,
+        expected: &'static str,
+    },
+    #[error("invalid composite key layout: {0}")]
+    InvalidLayout(&'static str),
+    #[error("{0:?} table has no key prefixes")]
+    NoPrefix(KeyType),
+}
+
+/// A key as it appears on the wire, before it is encoded for a table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TypedKey {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Text(String),
+    /// The fields of a composite key, leading one first.
+    Fields(Vec<TypedKey>),
+}
+
+/// How raw keys are written in responses. Requests may use either.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum KeyEncoding {
+    /// 16 upper-case hexadecimal digits.
+    #[default]
+    Hex,
+    /// 12 characters of padded standard base64, three quarters the size.
+    Base64,
+}
+
+/// How a table maps wire keys to the raw bytes compared by the B-tree. Every
+/// encoding is order-preserving, so scans return keys in their natural order.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum KeyType {
+    /// 16 hexadecimal digits or 12 characters of base64, stored as-is.
+    #[default]
+    Raw,
+    U64,
+    /// Big-endian with the sign bit flipped so negative keys sort first.
+    I64,
+    /// UTF-8 strings of up to 7 bytes, zero-padded and followed by their
+    /// length.
+    String,
+    /// IEEE 754 bits with the sign bit flipped, and every other bit too for
+    /// negative numbers, so they sort by value. Negative zero is stored as
+    /// zero.
+    F64,
+    /// Several fields packed one after the other, so keys sort by the
+    /// leading field, then the next, and so on.
+    Composite(KeyLayout),
+}
+
+/// A field of a composite key and its width in bytes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum KeyField {
+    /// Big-endian.
+    Unsigned(u8),
+    /// Big-endian with the sign bit flipped, like `KeyType::I64`.
+    Signed(u8),
+    /// UTF-8 without NUL characters, zero-padded. Shorter strings sort
+    /// before the longer ones they are a prefix of.
+    String(u8),
+}
+
+impl KeyField {
+    fn width(self) -> usize {
+        match self {
+            KeyField::Unsigned(width) | KeyField::Signed(width) | KeyField::String(width) => {
+                width as usize
+            }
+        }
+    }
+
+    fn encode(self, value: &TypedKey, bytes: &mut [u8]) -> Option<()> {
+        let width = bytes.len();
+        let bits = 8 * width as u32;
+        let n = match (self, value) {
+            (KeyField::Unsigned(_), TypedKey::Unsigned(n)) => {
+                (bits == 64 || *n >> bits == 0).then_some(*n)?
+            }
+            (KeyField::Signed(_), TypedKey::Unsigned(n)) => {
+                let n = i64::try_from(*n).ok()?;
+                encode_signed_field(n, bits)?
+            }
+            (KeyField::Signed(_), TypedKey::Signed(n)) => encode_signed_field(*n, bits)?,
+            (KeyField::String(_), TypedKey::Text(text))
+                if text.len() <= width && !text.contains('\0') =>
+            {
+                bytes[..text.len()].copy_from_slice(text.as_bytes());
+                return Some(());
+            }
+            _ => return None,
+        };
+        bytes.copy_from_slice(&n.to_be_bytes()[8 - width..]);
+        Some(())
+    }
+
+    fn decode(self, bytes: &[u8]) -> TypedKey {
+        let mut padded = [0; 8];
+        padded[8 - bytes.len()..].copy_from_slice(bytes);
+        let n = u64::from_be_bytes(padded);
+        match self {
+            KeyField::Unsigned(_) => TypedKey::Unsigned(n),
+            KeyField::Signed(_) => {
+                let shift = 64 - 8 * bytes.len() as u32;
+                // Shifting back sign-extends.
+                TypedKey::Signed(((n ^ 1 << (63 - shift)) << shift) as i64 >> shift)
+            }
+            KeyField::String(_) => {
+                let len = bytes
+                    .iter()
+                    .rposition(|byte| *byte != 0)
+                    .map_or(0, |i| i + 1);
+                TypedKey::Text(String::from_utf8_lossy(&bytes[..len]).into_owned())
+            }
+        }
+    }
+
+    /// Kind in the high nibble and width in the low one, as stored in the
+    /// catalog. Zero means no field.
+    fn to_u8(self) -> u8 {
+        let kind = match self {
+            KeyField::Unsigned(_) => 1,
+            KeyField::Signed(_) => 2,
+            KeyField::String(_) => 3,
+        };
+        kind << 4 | self.width() as u8
+    }
+
+    fn from_u8(byte: u8) -> Option<Self> {
+        let width = byte & 0x0F;
+        match byte >> 4 {
+            1 => Some(KeyField::Unsigned(width)),
+            2 => Some(KeyField::Signed(width)),
+            3 => Some(KeyField::String(width)),
+            _ => None,
+        }
+    }
+}
+
+/// Returns `n` flipped like `KeyType::I64`, if it fits in `bits`.
+fn encode_signed_field(n: i64, bits: u32) -> Option<u64> {
+    let shift = 64 - bits;
+    if n << shift >> shift != n {
+        return None;
+    }
+    Some((n as u64 ^ 1 << (bits - 1)) & (u64::MAX >> shift))
+}
+
+/// The fields of a composite key, leading one first, which together take up
+/// to the whole key. Unused bytes at the end are zero.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<KeyField>", into = "Vec<KeyField>")]
+pub struct KeyLayout {
+    fields: [Option<KeyField>; MAX_KEY_FIELDS],
+}
+
+impl KeyLayout {
+    pub fn fields(&self) -> impl Iterator<Item = KeyField> + '_ {
+        self.fields.iter().map_while(|field| *field)
+    }
+
+    /// Packs the leading `fields`, leaving the bytes of the ones left out
+    /// zero, which sorts them before every key that shares the given ones.
+    /// Returns how many bytes the given fields took.
+    fn encode(&self, values: &[TypedKey], key: &mut btree::Key) -> Option<usize> {
+        if values.is_empty() || values.len() > self.fields().count() {
+            return None;
+        }
+        let mut offset = 0;
+        for (field, value) in self.fields().zip(values) {
+            let end = offset + field.width();
+            field.encode(value, &mut key[offset..end])?;
+            offset = end;
+        }
+        Some(offset)
+    }
+
+    fn decode(&self, key: &btree::Key) -> TypedKey {
+        let mut offset = 0;
+        let values = self
+            .fields()
+            .map(|field| {
+                let end = offset + field.width();
+                let value = field.decode(&key[offset..end]);
+                offset = end;
+                value
+            })
+            .collect();
+        TypedKey::Fields(values)
+    }
+}
+
+impl TryFrom<Vec<KeyField>> for KeyLayout {
+    type Error = Error;
+
+    fn try_from(fields: Vec<KeyField>) -> Result<Self, Error> {
+        if fields.is_empty() || fields.len() > MAX_KEY_FIELDS {
+            return Err(Error::InvalidLayout("a key has one to four fields"));
+        }
+        if fields.iter().any(|field| field.width() == 0) {
+            return Err(Error::InvalidLayout("fields must be at least a byte wide"));
+        }
+        let width = fields.iter().map(|field| field.width()).sum::<usize>();
+        if width > std::mem::size_of::<btree::Key>() {
+            return Err(Error::InvalidLayout("fields must fit in 8 bytes"));
+        }
+        let mut layout = KeyLayout {
+            fields: [None; MAX_KEY_FIELDS],
+        };
+        for (slot, field) in layout.fields.iter_mut().zip(fields) {
+            *slot = Some(field);
+        }
+        Ok(layout)
+    }
+}
+
+impl From<KeyLayout> for Vec<KeyField> {
+    fn from(layout: KeyLayout) -> Self {
+        layout.fields().collect()
+    }
+}
+
+impl KeyType {
+    pub fn encode(self, key: &TypedKey) -> Result<btree::Key, Error> {
+        match (self, key) {
+            (KeyType::Raw, TypedKey::Text(text)) if text.len() == BASE64_KEY_LEN => {
+                decode_base64(text).ok_or_else(|| self.invalid_key())
+            }
+            (KeyType::Raw, TypedKey::Text(text)) => {
+                let mut bytes = btree::Key::default();
+                hex::decode_to_slice(text, &mut bytes).map_err(|_| self.invalid_key())?;
+                Ok(bytes)
+            }
+            (KeyType::U64, TypedKey::Unsigned(n)) => Ok(n.to_be_bytes()),
+            (KeyType::I64, TypedKey::Unsigned(n)) if *n < SIGN_BIT => {
+                Ok((n ^ SIGN_BIT).to_be_bytes())
+            }
+            (KeyType::I64, TypedKey::Signed(n)) => Ok((*n as u64 ^ SIGN_BIT).to_be_bytes()),
+            (KeyType::F64, TypedKey::Float(x)) if !x.is_nan() => Ok(encode_f64(*x)),
+            // Integers that a float can't hold exactly would land on a
+            // neighbor's key.
+            (KeyType::F64, TypedKey::Unsigned(n)) => match exact_f64(*n as i128) {
+                Some(x) => Ok(encode_f64(x)),
+                None => Err(self.invalid_key()),
+            },
+            (KeyType::F64, TypedKey::Signed(n)) => match exact_f64(*n as i128) {
+                Some(x) => Ok(encode_f64(x)),
+                None => Err(self.invalid_key()),
+            },
+            (KeyType::String, TypedKey::Text(text)) if text.len() <= MAX_STRING_KEY_LEN => {
+                let mut bytes = btree::Key::default();
+                bytes[..text.len()].copy_from_slice(text.as_bytes());
+                bytes[MAX_STRING_KEY_LEN] = text.len() as u8;
+                Ok(bytes)
+            }
+            // A key that leaves out trailing fields would name the one with
+            // them zeroed, so only a prefix may be partial.
+            (KeyType::Composite(layout), TypedKey::Fields(values))
+                if values.len() == layout.fields().count() =>
+            {
+                Ok(self.encode_prefix(key)?.0)
+            }
+            _ => Err(self.invalid_key()),
+        }
+    }
+
+    /// Encodes the leading fields of a composite key, the rest left zero.
+    /// Returns the key and how many leading bytes the given fields fill.
+    pub fn encode_prefix(self, key: &TypedKey) -> Result<(btree::Key, usize), Error> {
+        let layout = match self {
+            KeyType::Composite(layout) => layout,
+            _ => return Err(Error::NoPrefix(self)),
+        };
+        let mut bytes = btree::Key::default();
+        let len = match key {
+            TypedKey::Fields(values) => layout.encode(values, &mut bytes),
+            _ => None,
+        };
+        let len = len.ok_or_else(|| self.invalid_key())?;
+        Ok((bytes, len))
+    }
+
+    pub fn decode(self, bytes: btree::Key) -> TypedKey {
+        self.decode_with(bytes, KeyEncoding::Hex)
+    }
+
+    /// Like `decode`, but raw keys are written in `encoding`.
+    pub fn decode_with(self, bytes: btree::Key, encoding: KeyEncoding) -> TypedKey {
+        match self {
+            KeyType::Raw => match encoding {
+                KeyEncoding::Hex => TypedKey::Text(hex::encode_upper(bytes)),
+                KeyEncoding::Base64 => TypedKey::Text(encode_base64(&bytes)),
+            },
+            KeyType::U64 => TypedKey::Unsigned(u64::from_be_bytes(bytes)),
+            KeyType::I64 => TypedKey::Signed((u64::from_be_bytes(bytes) ^ SIGN_BIT) as i64),
+            KeyType::String => {
+                let len = (bytes[MAX_STRING_KEY_LEN] as usize).min(MAX_STRING_KEY_LEN);
+                TypedKey::Text(String::from_utf8_lossy(&bytes[..len]).into_owned())
+            }
+            KeyType::F64 => {
+                let bits = u64::from_be_bytes(bytes);
+                let bits = if bits & SIGN_BIT != 0 {
+                    bits ^ SIGN_BIT
+                } else {
+                    !bits
+                };
+                TypedKey::Float(f64::from_bits(bits))
+            }
+            KeyType::Composite(layout) => layout.decode(&bytes),
+        }
+    }
+
+    fn invalid_key(self) -> Error {
+        let expected = match self {
+            KeyType::Raw => "a key of 16 hexadecimal digits or 12 base64 characters",
+            KeyType::U64 => "an unsigned integer key",
+            KeyType::I64 => "a signed 64-bit integer key",
+            KeyType::String => "a string key of at most 7 bytes",
+            KeyType::F64 => "a numeric key that a 64-bit float holds exactly",
+            KeyType::Composite(_) => "an array of fields matching its layout",
+        };
+        Error::InvalidKey {
+            key_type: self,
+            expected,
+        }
+    }
+
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            KeyType::Raw => 0,
+            KeyType::U64 => 1,
+            KeyType::I64 => 2,
+            KeyType::String => 3,
+            KeyType::F64 => 4,
+            KeyType::Composite(_) => 5,
+        }
+    }
+
+    /// The fields of a composite layout as stored in the catalog next to
+    /// the tag, all zero for other key types.
+    pub(crate) fn layout_to_bytes(self) -> [u8; MAX_KEY_FIELDS] {
+        let mut bytes = [0; MAX_KEY_FIELDS];
+        if let KeyType::Composite(layout) = self {
+            for (byte, field) in bytes.iter_mut().zip(layout.fields()) {
+                *byte = field.to_u8();
+            }
+        }
+        bytes
+    }
+
+    pub(crate) fn from_u8(tag: u8) -> Option<Self> {
+        Self::from_bytes(tag, [0; MAX_KEY_FIELDS])
+    }
+
+    /// Like `from_u8`, along with the bytes of `layout_to_bytes`.
+    pub(crate) fn from_bytes(tag: u8, layout: [u8; MAX_KEY_FIELDS]) -> Option<Self> {
+        match tag {
+            0 => Some(KeyType::Raw),
+            1 => Some(KeyType::U64),
+            2 => Some(KeyType::I64),
+            3 => Some(KeyType::String),
+            4 => Some(KeyType::F64),
+            5 => {
+                let fields = layout
+                    .iter()
+                    .take_while(|byte| **byte != 0)
+                    .map(|byte| KeyField::from_u8(*byte))
+                    .collect::<Option<Vec<_>>>()?;
+                KeyLayout::try_from(fields).ok().map(KeyType::Composite)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `n` as a float, unless rounding would change it. Every 64-bit integer
+/// fits in an i128, which converting back from the float can't saturate.
+fn exact_f64(n: i128) -> Option<f64> {
+    let x = n as f64;
+    (x as i128 == n).then_some(x)
+}
+
+fn encode_f64(x: f64) -> btree::Key {
+    // Adding zero turns negative zero into zero.
+    let bits = (x + 0.0).to_bits();
+    let bits = if bits & SIGN_BIT != 0 {
+        !bits
+    } else {
+        bits ^ SIGN_BIT
+    };
+    bits.to_be_bytes()
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 0x3F;
+                text.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn decode_base64(text: &str) -> Option<btree::Key> {
+    let digits = text.trim_end_matches('=');
+    if !text.len().is_multiple_of(4) || text.len() - digits.len() > 2 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    let (mut bits, mut bit_count) = (0u32, 0);
+    for digit in digits.bytes() {
+        let value = BASE64_ALPHABET.iter().position(|c| *c == digit)?;
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+    // Leftover bits must be zero for the encoding to be canonical.
+    if bits != 0 {
+        return None;
+    }
+    bytes.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_preserving() {
+        let signed = [i64::MIN, -300, -1, 0, 1, 300, i64::MAX];
+        let encoded = signed
+            .iter()
+            .map(|n| KeyType::I64.encode(&TypedKey::Signed(*n)).unwrap())
+            .collect::<Vec<_>>();
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        for (n, bytes) in signed.iter().zip(encoded) {
+            assert_eq!(TypedKey::Signed(*n), KeyType::I64.decode(bytes));
+        }
+
+        let strings = ["", "a", "a\0", "ab", "abc", "b", "zzzzzzz"];
+        let encoded = strings
+            .iter()
+            .map(|s| {
+                KeyType::String
+                    .encode(&TypedKey::Text(s.to_string()))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        for (s, bytes) in strings.iter().zip(encoded) {
+            assert_eq!(TypedKey::Text(s.to_string()), KeyType::String.decode(bytes));
+        }
+
+        let floats = [
+            f64::NEG_INFINITY,
+            -1e300,
+            -2.5,
+            -1e-300,
+            0.0,
+            f64::MIN_POSITIVE,
+            1.0,
+            1e300,
+            f64::INFINITY,
+        ];
+        let encoded = floats
+            .iter()
+            .map(|x| KeyType::F64.encode(&TypedKey::Float(*x)).unwrap())
+            .collect::<Vec<_>>();
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        for (x, bytes) in floats.iter().zip(encoded) {
+            assert_eq!(TypedKey::Float(*x), KeyType::F64.decode(bytes));
+        }
+        assert_eq!(
+            KeyType::F64.encode(&TypedKey::Float(0.0)).unwrap(),
+            KeyType::F64.encode(&TypedKey::Float(-0.0)).unwrap()
+        );
+        assert_eq!(
+            KeyType::F64.encode(&TypedKey::Float(-3.0)).unwrap(),
+            KeyType::F64.encode(&TypedKey::Signed(-3)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        for bytes in [[0; 8], [0xFF; 8], *b"\x00\x01\xfa\x7fqp!\x80"] {
+            let text = match KeyType::Raw.decode_with(bytes, KeyEncoding::Base64) {
+                TypedKey::Text(text) => text,
+                key => panic!("{:?}", key),
+            };
+            assert_eq!(BASE64_KEY_LEN, text.len());
+            assert_eq!(bytes, KeyType::Raw.encode(&TypedKey::Text(text)).unwrap());
+        }
+        assert_eq!("cXBkYXRhAAE=", encode_base64(b"qpdata\x00\x01"));
+        for invalid in ["cXBkYXRhAAF=", "cXBkYXRhAA==", "cXBkYXRhAA*="] {
+            assert!(KeyType::Raw
+                .encode(&TypedKey::Text(invalid.to_string()))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        let too_long = TypedKey::Text("abcdefgh".to_string());
+        assert!(KeyType::String.encode(&too_long).is_err());
+        assert!(KeyType::Raw.encode(&too_long).is_err());
+        assert!(KeyType::U64.encode(&TypedKey::Signed(-1)).is_err());
+        assert!(KeyType::I64.encode(&TypedKey::Unsigned(u64::MAX)).is_err());
+        assert!(KeyType::F64.encode(&TypedKey::Float(f64::NAN)).is_err());
+        assert!(KeyType::F64.encode(&too_long).is_err());
+        // Rounded to a float, these would collide with their neighbors.
+        for n in [(1 << 53) + 1, u64::MAX, u64::MAX - 1] {
+            assert!(
+                KeyType::F64.encode(&TypedKey::Unsigned(n)).is_err(),
+                "{}",
+                n
+            );
+        }
+        for n in [(1 << 53) + 1, -(1 << 53) - 1, i64::MAX] {
+            assert!(KeyType::F64.encode(&TypedKey::Signed(n)).is_err(), "{}", n);
+        }
+        for n in [1 << 53, 1 << 63] {
+            assert!(KeyType::F64.encode(&TypedKey::Unsigned(n)).is_ok(), "{}", n);
+        }
+        assert!(KeyType::F64.encode(&TypedKey::Signed(i64::MIN)).is_ok());
+    }
+
+    #[test]
+    fn test_composite() {
+        let layout = vec![
+            KeyField::Signed(2),
+            KeyField::String(3),
+            KeyField::Unsigned(3),
+        ];
+        let key_type = KeyType::Composite(layout.try_into().unwrap());
+        let fields = |signed: i64, text: &str, unsigned: u64| {
+            TypedKey::Fields(vec![
+                TypedKey::Signed(signed),
+                TypedKey::Text(text.to_string()),
+                TypedKey::Unsigned(unsigned),
+            ])
+        };
+        // Ordered by the leading field, then the next ones.
+        let keys = [
+            fields(-32768, "zzz", 0xFF_FFFF),
+            fields(-1, "", 0),
+            fields(0, "", 7),
+            fields(0, "a", 0),
+            fields(0, "a", 1),
+            fields(0, "ab", 0),
+            fields(0, "b", 0),
+            fields(1, "", 0),
+            fields(32767, "zzz", 0xFF_FFFF),
+        ];
+        let encoded = keys
+            .iter()
+            .map(|key| key_type.encode(key).unwrap())
+            .collect::<Vec<_>>();
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        for (key, bytes) in keys.iter().zip(encoded) {
+            let decoded = key_type.decode(bytes);
+            assert_eq!(key, &decoded);
+            // Non-negative signed fields come back from JSON as unsigned.
+            let json = serde_json::to_value(&decoded).unwrap();
+            let parsed = serde_json::from_value(json).unwrap();
+            assert_eq!(bytes, key_type.encode(&parsed).unwrap());
+        }
+
+        let (bytes, len) = key_type
+            .encode_prefix(&TypedKey::Fields(vec![TypedKey::Signed(-1)]))
+            .unwrap();
+        assert_eq!(2, len);
+        assert_eq!([0x7F, 0xFF, 0, 0, 0, 0, 0, 0], bytes);
+        assert!(KeyType::U64.encode_prefix(&TypedKey::Unsigned(1)).is_err());
+
+        for invalid in [
+            fields(32768, "", 0),
+            fields(0, "abcd", 0),
+            fields(0, "a\0", 0),
+            fields(0, "", 1 << 24),
+            TypedKey::Fields(vec![]),
+            TypedKey::Fields(vec![TypedKey::Signed(-1)]),
+            TypedKey::Unsigned(0),
+        ] {
+            assert!(key_type.encode(&invalid).is_err(), "{:?}", invalid);
+        }
+        for invalid in [
+            vec![],
+            vec![KeyField::Unsigned(0)],
+            vec![KeyField::Unsigned(8), KeyField::Unsigned(1)],
+            vec![KeyField::Unsigned(1); 5],
+        ] {
+            assert!(KeyLayout::try_from(invalid).is_err());
+        }
+        assert_eq!(
+            Some(key_type),
+            KeyType::from_bytes(key_type.to_u8(), key_type.layout_to_bytes())
+        );
+    }
+}
@@ -1,123 +1,1245 @@
-use std::{convert::TryInto, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryInto,
+    io,
+    mem::size_of,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::{Condvar, Mutex, RwLock};
+use thiserror::Error;
 
 use crate::{
     btree,
-    buffer::BufferPoolManager,
-    disk::PageId,
-    query::CreateTableOutput,
-    query::FlushInput,
+    buffer::{self, BufferPoolManager},
+    catalog::{self, Catalog, TableMeta},
+    disk::PAGE_SIZE,
+    encoding::{self, KeyEncoding, TypedKey},
+    flusher::Flusher,
+    metrics,
     query::{
-        self, CreateTableInput, DeleteItemInput, DeleteItemOutput, FlushOutput, GetItemInput,
-        GetItemOutput, PutItemInput, PutItemOutput, Request, Response, ScanItemInput,
-        ScanItemOutput,
+        self, FlushMode, LimitsInput, LimitsOutput, MetricsInput, MetricsOutput, PingInput,
+        PingOutput, Request, Response, ScanEndOutput,
     },
+    subscription::Subscribers,
 };
 
+pub use self::import::Import;
+
+mod admin;
+mod import;
+mod items;
+mod scan;
+mod tables;
+
+/// Returned when a request names a table that isn't in the catalog.
+#[derive(Debug, Error)]
+#[error("no such table")]
+struct NoSuchTable;
+
+/// Returned when renaming a table onto an existing one without `overwrite`.
+#[derive(Debug, Error)]
+#[error("table already exists")]
+struct TableAlreadyExists;
+
+/// Returned when deleting from, appending to, incrementing in, getting or
+/// creating in, or truncating an append-only table.
+#[derive(Debug, Error)]
+#[error("table is append-only")]
+struct AppendOnly;
+
+/// Returned when a conditional put finds the item at another version.
+#[derive(Debug, Error)]
+#[error("item is at version {0}")]
+struct VersionMismatch(u64);
+
+/// Returned when a conditional delete finds another value.
+#[derive(Debug, Error)]
+#[error("item doesn't have the expected value")]
+struct ConditionFailed;
+
+/// Returned when reading a value that isn't UTF-8, which only values put
+/// through the embedded API can be.
+#[derive(Debug, Error)]
+#[error("value of key {key:?} is not valid UTF-8")]
+struct NonUtf8Value {
+    key: TypedKey,
+}
+
+/// Returned when an increment finds a value that isn't an integer, or would
+/// overflow it.
+#[derive(Debug, Error)]
+#[error("value of key {key:?} is not a 64-bit integer")]
+struct NotAnInteger {
+    key: TypedKey,
+}
+
+/// Returned for a line of an import that isn't a valid item.
+#[derive(Debug, Error)]
+#[error("malformed item on line {line}: {detail}")]
+struct MalformedLine {
+    line: u64,
+    detail: String,
+}
+
+/// Returned for a `Backup` or `CompactAll` whose path isn't a file name, or
+/// when there is no backup directory to put it in.
+#[derive(Debug, Error)]
+#[error("{0}")]
+struct BadBackupPath(&'static str);
+
+/// Returned for a `BatchGet` with more than `MAX_BATCH_GET_KEYS` keys.
+#[derive(Debug, Error)]
+#[error("BatchGet takes at most {max} keys, not {len}")]
+struct TooManyKeys {
+    len: usize,
+    max: usize,
+}
+
+/// A streamed response could not be sent, so there is no point in
+/// reporting anything to the client.
+#[derive(Debug, Error)]
+#[error("failed to send a response")]
+struct SendFailed(#[source] io::Error);
+
+pub const DEFAULT_MAX_SCAN_BYTES: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_SCAN_LIMIT: usize = 10_000;
+/// Bounds the work and the response of a single `BatchGet`.
+pub const MAX_BATCH_GET_KEYS: usize = 1000;
+/// How many request ids are remembered for deduplicating retries.
+const RECENT_REQUESTS: usize = 1024;
+/// How many tables' catalog entries are cached before the cache starts over.
+const TABLE_CACHE_CAPACITY: usize = 1024;
+/// Starts the stored values that carry a version, which follows as a
+/// big-endian u64. Values without one are bare UTF-8, which never contains
+/// this byte.
+const VERSION_TAG: u8 = 0xFF;
+const VERSION_HEADER_LEN: usize = 1 + size_of::<u64>();
+/// Like `VERSION_TAG`, but the version is followed by the time of the put,
+/// in seconds since the Unix epoch as a big-endian u64.
+const TIMESTAMPED_TAG: u8 = 0xFE;
+const TIMESTAMPED_HEADER_LEN: usize = VERSION_HEADER_LEN + size_of::<u64>();
+/// Values are limited as if every table were timestamped, so that the limit
+/// doesn't depend on the table.
+const MAX_HEADER_LEN: usize = TIMESTAMPED_HEADER_LEN;
+
+#[derive(Clone)]
 pub struct Executor {
     bufmgr: Arc<BufferPoolManager>,
+    /// Started by the first request for a background flush, and shared by
+    /// every clone.
+    flusher: Arc<OnceLock<Flusher>>,
+    flush_mode: FlushMode,
+    max_scan_bytes: usize,
+    max_scan_limit: usize,
+    debug_requests: bool,
+    /// Where `Backup` and `CompactAll` write their copies. Without one,
+    /// they are refused.
+    backup_dir: Option<PathBuf>,
+    key_encoding: KeyEncoding,
+    recent_requests: Arc<RecentRequests>,
+    /// Read-locked by writes and write-locked by `Vacuum`, so that no write
+    /// lands in a tree that is being replaced, and by `Backup`, so that the
+    /// copy is consistent.
+    vacuum_lock: Arc<RwLock<()>>,
+    counters: Arc<metrics::Counters>,
+    subscribers: Arc<Subscribers>,
+    /// Catalog entries, cleared by every clone that changes a catalog.
+    tables: Arc<Mutex<TableCache>>,
+    /// Seconds since the Unix epoch, stamped on items of tables that track
+    /// `updated_at`.
+    clock: Clock,
 }
 
+type Clock = Arc<dyn Fn() -> u64 + Send + Sync>;
+
 impl Executor {
+    /// Requests on one data file must all go through clones of a single
+    /// executor: the vacuum lock, subscriptions and cached catalog entries
+    /// are shared by clones only, so another executor would keep serving
+    /// tables that a `RenameTable` or `Vacuum` moved.
     pub fn new(bufmgr: Arc<BufferPoolManager>) -> Self {
-        Self { bufmgr }
+        Self {
+            bufmgr,
+            flusher: Arc::default(),
+            flush_mode: FlushMode::default(),
+            max_scan_bytes: DEFAULT_MAX_SCAN_BYTES,
+            max_scan_limit: DEFAULT_MAX_SCAN_LIMIT,
+            debug_requests: false,
+            backup_dir: None,
+            key_encoding: KeyEncoding::default(),
+            recent_requests: Arc::new(RecentRequests::new(RECENT_REQUESTS)),
+            vacuum_lock: Arc::new(RwLock::new(())),
+            counters: Arc::default(),
+            subscribers: Arc::default(),
+            tables: Arc::default(),
+            clock: Arc::new(now),
+        }
+    }
+
+    /// Sets the mode of `Flush` requests that don't specify one.
+    pub fn with_flush_mode(mut self, flush_mode: FlushMode) -> Self {
+        self.flush_mode = flush_mode;
+        self
+    }
+
+    /// Caps the total size of the values in a `ScanItem` response.
+    pub fn with_max_scan_bytes(mut self, max_scan_bytes: usize) -> Self {
+        self.max_scan_bytes = max_scan_bytes;
+        self
+    }
+
+    /// Lowers the `limit` of `ScanItem` requests that ask for more items.
+    pub fn with_max_scan_limit(mut self, max_scan_limit: usize) -> Self {
+        self.max_scan_limit = max_scan_limit;
+        self
+    }
+
+    /// Serves requests that expose the physical layout, like `InspectLeaf`.
+    pub fn with_debug_requests(mut self, debug_requests: bool) -> Self {
+        self.debug_requests = debug_requests;
+        self
+    }
+
+    /// Serves `Backup` and `CompactAll` requests, writing the copies to
+    /// `backup_dir`.
+    pub fn with_backup_dir(mut self, backup_dir: impl Into<PathBuf>) -> Self {
+        self.backup_dir = Some(backup_dir.into());
+        self
+    }
+
+    /// Replaces the system clock, so that tests can control `updated_at`.
+    #[cfg(test)]
+    fn with_clock(mut self, clock: impl Fn() -> u64 + Send + Sync + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The time to stamp on an item put into a table of `meta`, if it tracks
+    /// one.
+    fn updated_at(&self, meta: &TableMeta) -> Option<u64> {
+        meta.track_updated_at.then(|| (self.clock)())
+    }
+
+    /// Sets how raw keys are written in responses.
+    pub fn with_key_encoding(mut self, key_encoding: KeyEncoding) -> Self {
+        self.key_encoding = key_encoding;
+        self
     }
 
     pub fn execute(&self, request: Request) -> query::Response {
+        let request_id = match request.request_id() {
+            Some(request_id) => request_id.to_owned(),
+            None => return self.dispatch(request),
+        };
+        let fingerprint = format!("{:?}", request);
+        let mut entries = self.recent_requests.entries.lock();
+        // A retry that comes while the original runs waits for it instead of
+        // applying the request a second time.
+        loop {
+            match entries.get(&request_id) {
+                None => break,
+                Some(entry) if entry.fingerprint != fingerprint => {
+                    let detail = format!("request_id {:?} was used by another request", request_id);
+                    return Response::Error(query::Error::BadRequest { detail });
+                }
+                Some(RequestEntry {
+                    response: Some(response),
+                    ..
+                }) => return response.clone(),
+                Some(_) => self.recent_requests.finished.wait(&mut entries),
+            }
+        }
+        entries.start(request_id.clone(), fingerprint);
+        drop(entries);
+        let mut running = RunningRequest {
+            recent_requests: &self.recent_requests,
+            request_id,
+            response: None,
+        };
+        let response = self.dispatch(request);
+        // Failed requests may be retried for real.
+        if !matches!(response, Response::Error(_)) {
+            running.response = Some(response.clone());
+        }
+        response
+    }
+
+    /// Like `execute`, but passes responses to `send` as they are produced.
+    /// `ScanStream` requests produce one per item and a final one, and
+    /// `Subscribe` requests one per change for as long as the subscription
+    /// lasts; every other request produces exactly one.
+    pub fn execute_streaming(
+        &self,
+        request: Request,
+        send: &mut dyn FnMut(Response) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let input = match request {
+            Request::ScanStream(input) => input,
+            Request::Subscribe(input) => return self.subscribe(input, send),
+            request => return send(self.execute(request)),
+        };
+        self.counters.count_scan();
+        match self.scan_stream(input, send) {
+            Ok(count) => send(Response::ScanEnd(ScanEndOutput { count })),
+            Err(err) => match err.downcast::<SendFailed>() {
+                Ok(SendFailed(err)) => Err(err),
+                Err(err) => {
+                    self.counters.count_error();
+                    send(Response::Error(query_error(err)))
+                }
+            },
+        }
+    }
+
+    fn dispatch(&self, request: Request) -> query::Response {
+        self.counters.count_request(&request);
         let resp = match request {
             Request::GetItem(input) => self.get_item(input).map(Response::GetItem),
+            Request::BatchGet(input) => self.batch_get(input).map(Response::BatchGet),
             Request::PutItem(input) => self.put_item(input).map(Response::PutItem),
+            Request::AppendItem(input) => self.append_item(input).map(Response::AppendItem),
+            Request::GetOrCreate(input) => self.get_or_create(input).map(Response::GetOrCreate),
+            Request::IncrItem(input) => self.incr_item(input).map(Response::IncrItem),
             Request::DeleteItem(input) => self.delete_item(input).map(Response::DeleteItem),
+            Request::DeleteRange(input) => self.delete_range(input).map(Response::DeleteRange),
             Request::CreateTable(input) => self.create_table(input).map(Response::CreateTable),
             Request::ScanItem(input) => self.scan_item(input).map(Response::ScanItem),
+            Request::ScanStream(_) => Ok(Response::Error(query::Error::BadRequest {
+                detail: "ScanStream is only served over a connection".to_string(),
+            })),
+            Request::Subscribe(_) => Ok(Response::Error(query::Error::BadRequest {
+                detail: "Subscribe is only served over a connection".to_string(),
+            })),
+            Request::CountItem(input) => self.count_item(input).map(Response::CountItem),
+            Request::TableExists(input) => self.table_exists(input).map(Response::TableExists),
+            Request::KeyRange(input) => self.key_range(input).map(Response::KeyRange),
+            Request::FindKeyInTables(input) => self
+                .find_key_in_tables(input)
+                .map(Response::FindKeyInTables),
+            Request::DescribeTables(input) => {
+                self.describe_tables(input).map(Response::DescribeTables)
+            }
+            Request::RenameTable(input) => self.rename_table(input).map(Response::RenameTable),
             Request::Flush(input) => self.flush(input).map(Response::Flush),
+            Request::Ping(input) => self.ping(input).map(Response::Ping),
+            Request::Limits(input) => self.limits(input).map(Response::Limits),
+            Request::Vacuum(input) => self.vacuum(input).map(Response::Vacuum),
+            Request::TruncateTable(input) => {
+                self.truncate_table(input).map(Response::TruncateTable)
+            }
+            Request::RebuildCatalog(input) => {
+                self.rebuild_catalog(input).map(Response::RebuildCatalog)
+            }
+            Request::Fsck(input) => self.fsck(input).map(Response::Fsck),
+            Request::Backup(input) => self.backup(input).map(Response::Backup),
+            Request::CompactAll(input) => self.compact_all(input).map(Response::CompactAll),
+            Request::DiskStats(input) => self.disk_stats(input).map(Response::DiskStats),
+            Request::Metrics(input) => self.metrics(input).map(Response::Metrics),
+            Request::InspectLeaf(_) if !self.debug_requests => {
+                Ok(Response::Error(query::Error::BadRequest {
+                    detail: "InspectLeaf requires debug requests to be enabled".to_string(),
+                }))
+            }
+            Request::InspectLeaf(input) => self.inspect_leaf(input).map(Response::InspectLeaf),
+            Request::LocateKey(_) if !self.debug_requests => {
+                Ok(Response::Error(query::Error::BadRequest {
+                    detail: "LocateKey requires debug requests to be enabled".to_string(),
+                }))
+            }
+            Request::LocateKey(input) => self.locate_key(input).map(Response::LocateKey),
+            Request::ExplainScan(_) if !self.debug_requests => {
+                Ok(Response::Error(query::Error::BadRequest {
+                    detail: "ExplainScan requires debug requests to be enabled".to_string(),
+                }))
+            }
+            Request::ExplainScan(input) => self.explain_scan(input).map(Response::ExplainScan),
+            Request::Warm(input) => self.warm(input).map(Response::Warm),
+            Request::UseTable(_) => Ok(Response::Error(query::Error::BadRequest {
+                detail: "UseTable is only served over a connection".to_string(),
+            })),
+            Request::UseKeyEncoding(_) => Ok(Response::Error(query::Error::BadRequest {
+                detail: "UseKeyEncoding is only served over a connection".to_string(),
+            })),
+            Request::Import(_) => Ok(Response::Error(query::Error::BadRequest {
+                detail: "Import is only served over a connection".to_string(),
+            })),
         };
-        resp.map_err(|err| match err.downcast_ref::<btree::Error>() {
-            Some(btree::Error::Deadlock) => query::Error::Deadlock,
-            _ => query::Error::Other {
-                message: err.to_string(),
-            },
+        let response = resp.map_err(query_error).unwrap_or_else(Response::Error);
+        self.counters.count_response(&response);
+        self.flush_if_over_dirty_limit();
+        response
+    }
+
+    /// Queues a flush if writes have left too many pages dirty. Called
+    /// between requests, when no page latch is held.
+    fn flush_if_over_dirty_limit(&self) {
+        if !self.bufmgr.is_read_only() && self.bufmgr.over_dirty_limit() {
+            // Pages that stay dirty are flushed by a later request.
+            let _ = self.request_flush();
+        }
+    }
+
+    /// Queues a flush on the background flusher. Should the flusher have
+    /// stopped, flushes right away instead, so that the flush still happens
+    /// and its failure is reported.
+    fn request_flush(&self) -> Result<(), buffer::Error> {
+        let flusher = self
+            .flusher
+            .get_or_init(|| Flusher::spawn(self.bufmgr.clone()));
+        if flusher.request().is_err() {
+            self.bufmgr.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The catalog of `namespace`, or `None` if no table was ever created in
+    /// it.
+    fn catalog(&self, namespace: Option<query::Key>) -> Result<Option<Catalog<'_>>, anyhow::Error> {
+        match namespace {
+            Some(namespace) => Ok(Catalog::open_namespace(&self.bufmgr, namespace.into())?),
+            None => Ok(Some(Catalog::open(&self.bufmgr))),
+        }
+    }
+
+    fn lookup_table(
+        &self,
+        namespace: Option<query::Key>,
+        table_id: btree::Key,
+    ) -> Result<TableMeta, anyhow::Error> {
+        let table = (namespace, table_id);
+        let generation = {
+            let tables = self.tables.lock();
+            if let Some(meta) = tables.entries.get(&table) {
+                return Ok(*meta);
+            }
+            tables.generation
+        };
+        let catalog = self.catalog(namespace)?.ok_or(NoSuchTable)?;
+        let meta = catalog.get(table_id)?.ok_or(NoSuchTable)?;
+        self.tables.lock().insert(generation, table, meta);
+        Ok(meta)
+    }
+
+    /// Drops the cached catalog entries. Called after every change to a
+    /// catalog.
+    pub(crate) fn forget_tables(&self) {
+        self.tables.lock().clear();
+    }
+
+    /// Answers without touching storage, so it stays cheap for health checks.
+    fn ping(&self, _input: PingInput) -> Result<PingOutput, anyhow::Error> {
+        Ok(PingOutput {
+            version: env!("CARGO_PKG_VERSION").to_string(),
         })
-        .unwrap_or_else(Response::Error)
     }
 
-    fn lookup_table(&self, table_id: btree::Key) -> Result<PageId, anyhow::Error> {
-        let catalog = btree::Access::open(&self.bufmgr, PageId::CATALOG_PAGE_ID);
-        let mut buf = vec![];
-        if !catalog.get(table_id, &mut buf)? {
-            return Err(anyhow::anyhow!("no such table"));
+    fn limits(&self, _input: LimitsInput) -> Result<LimitsOutput, anyhow::Error> {
+        Ok(LimitsOutput {
+            max_value_size: btree::max_value_size::<8>() - MAX_HEADER_LEN,
+            key_size: size_of::<btree::Key>(),
+            page_size: PAGE_SIZE,
+        })
+    }
+
+    fn metrics(&self, _input: MetricsInput) -> Result<MetricsOutput, anyhow::Error> {
+        let text = self.counters.render(
+            self.bufmgr.stats(),
+            self.bufmgr.disk_stats(),
+            self.bufmgr.disk_usage()?,
+        );
+        Ok(MetricsOutput { text })
+    }
+}
+
+/// Catalog entries of the tables requests have looked up, so that each
+/// request doesn't descend a catalog to find its table.
+#[derive(Default)]
+struct TableCache {
+    /// Bumped by `clear`, so that a lookup that read the catalog before a
+    /// change doesn't cache what it read after the change was made.
+    generation: u64,
+    entries: HashMap<(Option<query::Key>, btree::Key), TableMeta>,
+}
+
+impl TableCache {
+    fn insert(
+        &mut self,
+        generation: u64,
+        table: (Option<query::Key>, btree::Key),
+        meta: TableMeta,
+    ) {
+        if generation != self.generation {
+            return;
         }
-        Ok(buf[..].try_into()?)
+        if self.entries.len() == TABLE_CACHE_CAPACITY {
+            self.entries.clear();
+        }
+        self.entries.insert(table, meta);
+    }
+
+    fn clear(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
     }
+}
+
+/// The requests that carried an id and are running, and the responses of
+/// the last few that succeeded.
+struct RecentRequests {
+    entries: Mutex<RequestEntries>,
+    /// Notified whenever a request stops running.
+    finished: Condvar,
+}
 
-    fn get_item(&self, input: GetItemInput) -> Result<GetItemOutput, anyhow::Error> {
-        let page_id = self.lookup_table(input.table_id.into())?;
-        let table_access = btree::Access::open(&self.bufmgr, page_id);
-        let mut buf = vec![];
-        if !table_access.get(input.key.into(), &mut buf)? {
-            return Ok(GetItemOutput { item: None });
+impl RecentRequests {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(RequestEntries {
+                capacity,
+                order: VecDeque::with_capacity(capacity),
+                entries: HashMap::with_capacity(capacity),
+            }),
+            finished: Condvar::new(),
         }
-        let item = query::Item {
-            key: input.key,
-            value: String::from_utf8(buf)?,
+    }
+}
+
+struct RequestEntries {
+    capacity: usize,
+    /// Ids of the requests that succeeded, oldest first.
+    order: VecDeque<String>,
+    entries: HashMap<String, RequestEntry>,
+}
+
+impl RequestEntries {
+    fn get(&self, request_id: &str) -> Option<&RequestEntry> {
+        self.entries.get(request_id)
+    }
+
+    fn start(&mut self, request_id: String, fingerprint: String) {
+        let entry = RequestEntry {
+            fingerprint,
+            response: None,
         };
-        Ok(GetItemOutput { item: Some(item) })
+        self.entries.insert(request_id, entry);
     }
 
-    fn put_item(&self, input: PutItemInput) -> Result<PutItemOutput, anyhow::Error> {
-        let page_id = self.lookup_table(input.table_id.into())?;
-        let table_access = btree::Access::open(&self.bufmgr, page_id);
-        table_access.put(input.item.key.into(), input.item.value.as_bytes())?;
-        Ok(PutItemOutput)
+    /// Forgets the request if it has no response, as when it failed.
+    fn finish(&mut self, request_id: String, response: Option<Response>) {
+        let response = match response {
+            Some(response) => response,
+            None => {
+                self.entries.remove(&request_id);
+                return;
+            }
+        };
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.push_back(request_id.clone());
+        if let Some(entry) = self.entries.get_mut(&request_id) {
+            entry.response = Some(response);
+        }
     }
+}
+
+struct RequestEntry {
+    /// The request as `Debug` prints it, so that an id reused for another
+    /// request isn't taken for a retry.
+    fingerprint: String,
+    /// `None` while the request runs.
+    response: Option<Response>,
+}
 
-    fn delete_item(&self, _input: DeleteItemInput) -> Result<DeleteItemOutput, anyhow::Error> {
-        todo!();
+/// Finishes a request in `RecentRequests` when dropped, even by a panic.
+struct RunningRequest<'a> {
+    recent_requests: &'a RecentRequests,
+    request_id: String,
+    response: Option<Response>,
+}
+
+impl Drop for RunningRequest<'_> {
+    fn drop(&mut self) {
+        let request_id = std::mem::take(&mut self.request_id);
+        let mut entries = self.recent_requests.entries.lock();
+        entries.finish(request_id, self.response.take());
+        self.recent_requests.finished.notify_all();
     }
+}
 
-    fn scan_item(&self, input: ScanItemInput) -> Result<ScanItemOutput, anyhow::Error> {
-        let page_id = self.lookup_table(input.table_id.into())?;
-        let table_access = btree::Access::open(&self.bufmgr, page_id);
-        let mut items = vec![];
-        let mut buf = vec![];
-        let mut count = 0;
-        if input.backward {
-            let mut iter = table_access.iter_rev(input.start.map(Into::into))?;
-            while let Some(key) = iter.next(&mut buf)? {
-                let key = key.into();
-                let value = String::from_utf8(buf.clone())?;
-                buf.clear();
-                items.push(query::Item { key, value });
-                count += 1;
-                if count >= input.limit {
-                    break;
-                }
+/// Values are limited as if every table were timestamped. See
+/// `MAX_HEADER_LEN`.
+fn check_value_size(value: impl AsRef<[u8]>) -> Result<(), btree::Error> {
+    let len = MAX_HEADER_LEN + value.as_ref().len();
+    let max = btree::max_value_size::<8>();
+    if len > max {
+        return Err(btree::Error::ValueTooLarge { len, max });
+    }
+    Ok(())
+}
+
+/// A value as stored in a table, with its header split off.
+struct StoredValue {
+    version: u64,
+    updated_at: Option<u64>,
+    value: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+fn encode_value(version: u64, updated_at: Option<u64>, value: impl AsRef<[u8]>) -> Vec<u8> {
+    let value = value.as_ref();
+    let mut bytes = Vec::with_capacity(MAX_HEADER_LEN + value.len());
+    bytes.push(if updated_at.is_some() {
+        TIMESTAMPED_TAG
+    } else {
+        VERSION_TAG
+    });
+    bytes.extend_from_slice(&version.to_be_bytes());
+    if let Some(updated_at) = updated_at {
+        bytes.extend_from_slice(&updated_at.to_be_bytes());
+    }
+    bytes.extend_from_slice(value);
+    bytes
+}
+
+/// The version and the time of the put in the header of a stored value,
+/// which needn't be UTF-8, and the length of the header.
+fn read_header(bytes: &[u8]) -> (u64, Option<u64>, usize) {
+    let read_u64 = |at: usize| u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap());
+    match bytes.first() {
+        Some(&VERSION_TAG) if bytes.len() >= VERSION_HEADER_LEN => {
+            (read_u64(1), None, VERSION_HEADER_LEN)
+        }
+        Some(&TIMESTAMPED_TAG) if bytes.len() >= TIMESTAMPED_HEADER_LEN => (
+            read_u64(1),
+            Some(read_u64(VERSION_HEADER_LEN)),
+            TIMESTAMPED_HEADER_LEN,
+        ),
+        _ => (0, None, 0),
+    }
+}
+
+fn decode_version(bytes: &[u8]) -> u64 {
+    read_header(bytes).0
+}
+
+/// Strips the header off a stored value, for `Db`, whose values are bytes.
+pub(crate) fn decode_bytes(mut bytes: Vec<u8>) -> Vec<u8> {
+    let (_, _, header_len) = read_header(&bytes);
+    bytes.drain(..header_len);
+    bytes
+}
+
+/// Fails with the key of the value, so that the client can tell which item
+/// can't be read.
+fn decode_value(mut bytes: Vec<u8>, key: &TypedKey) -> Result<StoredValue, NonUtf8Value> {
+    let (version, updated_at, header_len) = read_header(&bytes);
+    bytes.drain(..header_len);
+    Ok(StoredValue {
+        version,
+        updated_at,
+        value: String::from_utf8(bytes).map_err(|_| NonUtf8Value { key: key.clone() })?,
+    })
+}
+
+/// Classifies a failed request for the client.
+fn query_error(err: anyhow::Error) -> query::Error {
+    // Failures to access the catalog's tree don't mean it's corrupted.
+    let btree_error = match err.downcast_ref::<catalog::Error>() {
+        Some(catalog::Error::BTree(btree_error)) => Some(btree_error),
+        _ => err.downcast_ref::<btree::Error>(),
+    };
+    let buffer_error = match btree_error {
+        Some(btree::Error::Buffer(buffer_error)) => Some(buffer_error),
+        _ => err.downcast_ref::<buffer::Error>(),
+    };
+    match buffer_error {
+        Some(buffer::Error::ReadOnly) => return query::Error::ReadOnly,
+        // Unlike I/O errors, exhaustion passes as the load does.
+        Some(buffer::Error::NoFreeBuffer { .. }) => return query::Error::Busy,
+        _ => {}
+    }
+    match btree_error {
+        Some(btree::Error::ValueTooLarge { max, .. }) => {
+            return query::Error::ValueTooLarge {
+                max_value_size: max - MAX_HEADER_LEN,
             }
-        } else {
-            let mut iter = table_access.iter(input.start.map(Into::into))?;
-            while let Some(key) = iter.next(&mut buf)? {
-                let key = key.into();
-                let value = String::from_utf8(buf.clone())?;
-                buf.clear();
-                items.push(query::Item { key, value });
-                count += 1;
-                if count >= input.limit {
-                    break;
-                }
+        }
+        Some(btree::Error::KeyNotIncreasing) => return query::Error::KeyNotIncreasing,
+        #[allow(deprecated)]
+        Some(btree::Error::Deadlock) => return query::Error::Deadlock,
+        Some(btree::Error::ChecksumMismatch { .. }) => {
+            return query::Error::Corrupted {
+                detail: err.to_string(),
+            }
+        }
+        _ => {}
+    }
+    if let Some(VersionMismatch(current_version)) = err.downcast_ref() {
+        return query::Error::VersionMismatch {
+            current_version: *current_version,
+        };
+    }
+    if err.is::<NoSuchTable>() {
+        return query::Error::NoSuchTable;
+    }
+    if err.is::<TableAlreadyExists>() {
+        return query::Error::TableAlreadyExists;
+    }
+    if err.is::<AppendOnly>() {
+        return query::Error::AppendOnly;
+    }
+    if err.is::<ConditionFailed>() {
+        return query::Error::ConditionFailed;
+    }
+    if let Some(NonUtf8Value { key }) = err.downcast_ref() {
+        return query::Error::NonUtf8Value { key: key.clone() };
+    }
+    if let Some(NotAnInteger { key }) = err.downcast_ref() {
+        return query::Error::NotAnInteger { key: key.clone() };
+    }
+    if err.is::<MalformedLine>() || err.is::<BadBackupPath>() || err.is::<TooManyKeys>() {
+        return query::Error::BadRequest {
+            detail: err.to_string(),
+        };
+    }
+    // I/O errors reading the catalog's tree fall through like those of a
+    // table's.
+    if let Some(
+        catalog::Error::MalformedEntry
+        | catalog::Error::ReservedPage(_)
+        | catalog::Error::UnknownEntryFormat { .. }
+        | catalog::Error::MalformedNote,
+    ) = err.downcast_ref()
+    {
+        return query::Error::Corrupted {
+            detail: err.to_string(),
+        };
+    }
+    if err.is::<encoding::Error>() {
+        return query::Error::BadRequest {
+            detail: err.to_string(),
+        };
+    }
+    query::Error::Other {
+        message: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use serde::Serialize;
+    use serde_json::json;
+    use tempfile::tempfile;
+
+    use crate::{
+        buffer::BufferPool,
+        disk::{self, Disk, DiskManager, MemoryDiskManager, PageId},
+        encoding::KeyType,
+        query::PutItemOutput,
+    };
+
+    use super::*;
+
+    pub(super) fn executor() -> Executor {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        let bufmgr = Arc::new(BufferPoolManager::new(disk, pool));
+        Catalog::create(&bufmgr).unwrap();
+        Executor::new(bufmgr)
+    }
+
+    pub(super) fn execute(executor: &Executor, request: serde_json::Value) -> serde_json::Value {
+        let request = serde_json::from_value(request).unwrap();
+        serde_json::to_value(executor.execute(request)).unwrap()
+    }
+
+    pub(super) fn create_table(
+        executor: &Executor,
+        table_id: impl Serialize,
+        key_type: impl Serialize,
+    ) {
+        let request = json!({ "type": "CreateTable", "table_id": table_id, "key_type": key_type });
+        assert_eq!(json!({ "type": "CreateTable" }), execute(executor, request));
+    }
+
+    pub(super) fn put_item(
+        executor: &Executor,
+        table_id: &str,
+        item: serde_json::Value,
+    ) -> serde_json::Value {
+        let request = json!({ "type": "PutItem", "table_id": table_id, "item": item });
+        let response = execute(executor, request);
+        assert_eq!(json!("PutItem"), response["type"], "{}", response);
+        response
+    }
+
+    #[test]
+    fn test_flush_mode() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let bufmgr = Arc::new(BufferPoolManager::new(disk, BufferPool::new(10)));
+        let executor = Executor::new(bufmgr.clone());
+        let flushed = json!({ "type": "Flush" });
+        let (_, buffer) = bufmgr.create_page().unwrap();
+        // No flush can get past a dirty page while it is latched.
+        let mut rw_buffer = buffer.write();
+        rw_buffer.mark_dirty();
+        thread::scope(|s| {
+            let sync = s.spawn(|| execute(&executor, json!({ "type": "Flush", "mode": "Sync" })));
+            assert_eq!(
+                flushed,
+                execute(&executor, json!({ "type": "Flush", "mode": "Async" }))
+            );
+            thread::sleep(Duration::from_millis(50));
+            assert!(!sync.is_finished());
+            drop(rw_buffer);
+            assert_eq!(flushed, sync.join().unwrap());
+        });
+        assert!(bufmgr.disk_stats().syncs >= 1);
+
+        // The background flush catches up eventually.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while bufmgr.disk_stats().syncs < 2 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_busy() {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let bufmgr = Arc::new(BufferPoolManager::new(disk, BufferPool::new(2)));
+        Catalog::create(&bufmgr).unwrap();
+        let executor = Executor::new(bufmgr.clone());
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let put = json!({
+            "type": "PutItem",
+            "table_id": table_id,
+            "item": { "key": "0000000000000001", "value": "v" },
+        });
+        assert_eq!("PutItem", execute(&executor, put.clone())["type"]);
+
+        // Another request holding a page leaves a single frame, and a put
+        // needs two.
+        let pinned = bufmgr.fetch_page(PageId::CATALOG_PAGE_ID).unwrap();
+        assert_eq!(
+            json!({ "type": "Error", "error": "Busy" }),
+            execute(&executor, put.clone())
+        );
+        drop(pinned);
+        assert_eq!("PutItem", execute(&executor, put)["type"]);
+        let get = json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" });
+        let got = execute(&executor, get);
+        assert_eq!(json!("v"), got["item"]["value"]);
+        assert_eq!(json!(2), got["version"]);
+    }
+
+    #[test]
+    fn test_max_dirty_pages() {
+        // Returns the syncs issued before the puts, as creating a table
+        // flushes.
+        let write = |bufmgr: &Arc<BufferPoolManager>| {
+            Catalog::create(bufmgr).unwrap();
+            let executor = Executor::new(bufmgr.clone());
+            let table_id = "0000000000000001";
+            create_table(&executor, table_id, "Raw");
+            let syncs = bufmgr.disk_stats().syncs;
+            for i in 0..20u64 {
+                let put = json!({
+                    "type": "PutItem",
+                    "table_id": table_id,
+                    "item": { "key": format!("{:016x}", i), "value": "x".repeat(1000) },
+                });
+                assert_eq!("PutItem", execute(&executor, put)["type"]);
+            }
+            syncs
+        };
+
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let bufmgr = Arc::new(BufferPoolManager::new(disk, BufferPool::new(10)));
+        let syncs = write(&bufmgr);
+        assert!(bufmgr.dirty_pages() > 3);
+        assert_eq!(syncs, bufmgr.disk_stats().syncs);
+
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let bufmgr =
+            Arc::new(BufferPoolManager::new(disk, BufferPool::new(10)).with_max_dirty_pages(3));
+        let syncs = write(&bufmgr);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while bufmgr.disk_stats().syncs == syncs {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let executor = executor();
+        let limits = execute(&executor, json!({ "type": "Limits" }));
+        assert_eq!(json!(8), limits["key_size"]);
+        assert_eq!(json!(PAGE_SIZE), limits["page_size"]);
+        let max_value_size = limits["max_value_size"].as_u64().unwrap() as usize;
+
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let put = |len: usize| {
+            let item = json!({ "key": "0000000000000001", "value": "x".repeat(len) });
+            execute(
+                &executor,
+                json!({ "type": "PutItem", "table_id": table_id, "item": item }),
+            )
+        };
+        assert_eq!(
+            json!({ "type": "PutItem", "created": true }),
+            put(max_value_size)
+        );
+        assert_eq!(
+            json!({
+                "type": "Error",
+                "error": "ValueTooLarge",
+                "max_value_size": max_value_size,
+            }),
+            put(max_value_size + 1)
+        );
+    }
+
+    #[test]
+    fn test_corrupted_catalog() {
+        let executor = executor();
+        let catalog = Catalog::open(&executor.bufmgr);
+        for (table_id, page_id) in &[
+            (*b"catalog!", PageId::CATALOG_PAGE_ID),
+            (*b"invalid!", PageId::INVALID_PAGE_ID),
+        ] {
+            catalog
+                .put(*table_id, &TableMeta::new(*page_id, KeyType::Raw))
+                .unwrap();
+            let get = json!({
+                "type": "GetItem",
+                "table_id": hex::encode(table_id),
+                "key": "0000000000000001",
+            });
+            assert_eq!(json!("Corrupted"), execute(&executor, get)["error"]);
+        }
+    }
+
+    /// Fails every read once `failing` is set.
+    struct FailingDisk {
+        inner: MemoryDiskManager,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl Disk for FailingDisk {
+        fn read_page_data(&self, page_id: PageId, data: &mut [u8]) -> Result<(), disk::Error> {
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(io::Error::other("injected").into());
             }
+            self.inner.read_page_data(page_id, data)
+        }
+
+        fn write_page_data(&self, page_id: PageId, data: &[u8]) -> io::Result<()> {
+            self.inner.write_page_data(page_id, data)
+        }
+
+        fn is_read_only(&self) -> bool {
+            self.inner.is_read_only()
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            self.inner.flush()
+        }
+
+        fn stats(&self) -> disk::Stats {
+            self.inner.stats()
+        }
+
+        fn usage(&self) -> io::Result<disk::Usage> {
+            self.inner.usage()
+        }
+
+        fn is_empty(&self) -> bool {
+            self.inner.is_empty()
+        }
+
+        fn next_page_id(&self) -> PageId {
+            self.inner.next_page_id()
+        }
+
+        fn allocate_page(&self) -> io::Result<PageId> {
+            self.inner.allocate_page()
         }
-        Ok(ScanItemOutput { items })
     }
 
-    fn create_table(&self, input: CreateTableInput) -> Result<CreateTableOutput, anyhow::Error> {
-        let catalog = btree::Access::open(&self.bufmgr, PageId::CATALOG_PAGE_ID);
-        let new_table = btree::Access::create(&self.bufmgr)?;
-        let bytes: [u8; 8] = new_table.btree_page_id.into();
-        catalog.put(input.table_id.into(), &bytes)?;
-        Ok(CreateTableOutput)
+    #[test]
+    fn test_catalog_io_error() {
+        let failing = Arc::new(AtomicBool::new(false));
+        let disk = FailingDisk {
+            inner: MemoryDiskManager::new(),
+            failing: failing.clone(),
+        };
+        let bufmgr = Arc::new(BufferPoolManager::new(disk, BufferPool::new(10)));
+        Catalog::create(&bufmgr).unwrap();
+        let executor = Executor::new(bufmgr.clone());
+        // Pushes the catalog's pages out of the pool.
+        for _ in 0..20 {
+            bufmgr.create_page().unwrap();
+        }
+        failing.store(true, Ordering::Relaxed);
+        let get = json!({
+            "type": "GetItem",
+            "table_id": "0000000000000001",
+            "key": "0000000000000001",
+        });
+        assert_eq!(json!("Other"), execute(&executor, get)["error"]);
+    }
+
+    #[test]
+    fn test_no_such_table() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        let exists = json!({ "type": "TableExists", "table_id": table_id });
+        assert_eq!(
+            json!({ "type": "TableExists", "exists": false }),
+            execute(&executor, exists.clone())
+        );
+        assert_eq!(
+            json!({ "type": "Error", "error": "NoSuchTable" }),
+            execute(
+                &executor,
+                json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" }),
+            )
+        );
+
+        create_table(&executor, table_id, "Raw");
+        assert_eq!(
+            json!({ "type": "TableExists", "exists": true }),
+            execute(&executor, exists)
+        );
+        // A missing key is a result, not an error.
+        assert_eq!(
+            json!({ "type": "GetItem", "item": null }),
+            execute(
+                &executor,
+                json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" }),
+            )
+        );
+    }
+
+    #[test]
+    fn test_request_id() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let put = |value: &str, request_id: Option<&str>| {
+            let item = json!({ "key": "0000000000000001", "value": value });
+            execute(
+                &executor,
+                json!({ "type": "PutItem", "table_id": table_id, "item": item, "request_id": request_id }),
+            )
+        };
+        let delete = |request_id: &str| {
+            execute(
+                &executor,
+                json!({ "type": "DeleteItem", "table_id": table_id, "key": "0000000000000001", "request_id": request_id }),
+            )
+        };
+        let get = || {
+            execute(
+                &executor,
+                json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" }),
+            )["item"]["value"]
+                .clone()
+        };
+
+        put("first", Some("put-1"));
+        put("second", None);
+        assert_eq!(
+            json!({ "type": "PutItem", "created": true }),
+            put("first", Some("put-1"))
+        );
+        assert_eq!(json!("second"), get());
+        assert_eq!(
+            json!({ "type": "Error", "error": "BadRequest", "detail": "request_id \"put-1\" was used by another request" }),
+            put("other", Some("put-1"))
+        );
+
+        assert_eq!(
+            json!({ "type": "DeleteItem", "found": true }),
+            delete("delete-1")
+        );
+        put("third", None);
+        assert_eq!(
+            json!({ "type": "DeleteItem", "found": true }),
+            delete("delete-1")
+        );
+        assert_eq!(json!("third"), get());
+        assert_eq!(
+            json!({ "type": "DeleteItem", "found": true }),
+            delete("delete-2")
+        );
+        assert_eq!(json!(null), get());
+    }
+
+    #[test]
+    fn test_request_id_in_flight() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let item = json!({ "key": "0000000000000001", "value": "once" });
+        let put =
+            json!({ "type": "PutItem", "table_id": table_id, "item": item, "request_id": "put-1" });
+        // Keeps the first put running until the retry has come.
+        let vacuum = executor.vacuum_lock.write();
+        let responses = thread::scope(|s| {
+            let threads: Vec<_> = (0..2)
+                .map(|_| s.spawn(|| execute(&executor, put.clone())))
+                .collect();
+            thread::sleep(Duration::from_millis(50));
+            drop(vacuum);
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        for response in responses {
+            assert_eq!(json!({ "type": "PutItem", "created": true }), response);
+        }
+        let get = json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" });
+        assert_eq!(json!(1), execute(&executor, get)["version"]);
     }
 
-    fn flush(&self, _input: FlushInput) -> Result<query::FlushOutput, anyhow::Error> {
-        self.bufmgr.flush()?;
-        Ok(FlushOutput)
+    #[test]
+    fn test_recent_requests_capacity() {
+        let recent_requests = RecentRequests::new(2);
+        let mut entries = recent_requests.entries.lock();
+        for request_id in ["a", "b", "c", "d"] {
+            entries.start(request_id.to_string(), request_id.to_string());
+        }
+        for request_id in ["a", "b", "c"] {
+            let response = Response::PutItem(PutItemOutput { created: true });
+            entries.finish(request_id.to_string(), Some(response));
+        }
+        entries.finish("d".to_string(), None);
+        assert!(entries.get("a").is_none());
+        assert!(entries.get("b").is_some());
+        assert!(entries.get("c").is_some());
+        assert!(entries.get("d").is_none());
+    }
+
+    #[test]
+    fn test_metrics() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        for i in 0..3 {
+            let item = json!({ "key": format!("{:016X}", i), "value": "value" });
+            put_item(&executor, table_id, item);
+        }
+        execute(
+            &executor,
+            json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" }),
+        );
+        execute(
+            &executor,
+            json!({ "type": "GetItem", "table_id": "0000000000000002", "key": "0000000000000001" }),
+        );
+        let response = execute(&executor, json!({ "type": "Metrics" }));
+        let text = response["text"].as_str().unwrap();
+        for line in [
+            "# TYPE qp_requests_total counter",
+            "qp_requests_total{kind=\"get\"} 2",
+            "qp_requests_total{kind=\"put\"} 3",
+            "qp_requests_total{kind=\"delete\"} 0",
+            "qp_errors_total 1",
+            "qp_deadlocks_total 0",
+            "# TYPE qp_disk_allocated_pages gauge",
+        ] {
+            assert!(text.lines().any(|l| l == line), "{} not in {}", line, text);
+        }
+        for name in [
+            "qp_buffer_fetches_total",
+            "qp_buffer_hits_total",
+            "qp_buffer_misses_total",
+            "qp_disk_writes_total",
+        ] {
+            assert!(text.lines().any(|l| l.starts_with(&format!("{} ", name))));
+        }
+    }
+
+    #[test]
+    fn test_table_cache() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        let item = json!({ "key": 1, "value": "cached" });
+        put_item(&executor, table_id, item);
+        // Starts out with nothing cached.
+        let executor = Executor::new(executor.bufmgr.clone());
+        let get = json!({ "type": "GetItem", "table_id": table_id, "key": 1 });
+        let fetches = |executor: &Executor| {
+            let before = executor.bufmgr.stats().fetches;
+            assert_eq!(
+                json!("cached"),
+                execute(executor, get.clone())["item"]["value"]
+            );
+            executor.bufmgr.stats().fetches - before
+        };
+        let first = fetches(&executor);
+        for _ in 0..10 {
+            // The catalog's meta page and root leaf are only fetched once.
+            assert_eq!(first - 2, fetches(&executor));
+        }
+
+        // A clone that cached the entry sees the rename made by another.
+        let other = executor.clone();
+        fetches(&other);
+        let rename = json!({ "type": "RenameTable", "from": table_id, "to": "0000000000000002" });
+        execute(&executor, rename);
+        for executor in [&executor, &other] {
+            assert_eq!(
+                json!({ "type": "Error", "error": "NoSuchTable" }),
+                execute(executor, get.clone())
+            );
+        }
+        let get = json!({ "type": "GetItem", "table_id": "0000000000000002", "key": 1 });
+        assert_eq!(
+            json!("cached"),
+            execute(&executor, get.clone())["item"]["value"]
+        );
+
+        // Vacuum moves the table to a new tree, which a clone must follow.
+        assert_eq!(
+            json!("cached"),
+            execute(&other, get.clone())["item"]["value"]
+        );
+        let vacuum = json!({ "type": "Vacuum", "table_id": "0000000000000002" });
+        assert_eq!(json!("Vacuum"), execute(&executor, vacuum)["type"]);
+        let put = json!({
+            "type": "PutItem",
+            "table_id": "0000000000000002",
+            "item": { "key": 2, "value": "moved" },
+        });
+        assert_eq!(json!("PutItem"), execute(&other, put)["type"]);
+        let get = json!({ "type": "GetItem", "table_id": "0000000000000002", "key": 2 });
+        assert_eq!(json!("moved"), execute(&executor, get)["item"]["value"]);
     }
 }
@@ -0,0 +1,874 @@
+use std::{
+    fs::OpenOptions,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    btree,
+    buffer::{self, BufferPool, BufferPoolManager},
+    catalog::{Catalog, TableMeta},
+    disk::{DiskManager, PAGE_SIZE},
+    encoding::TypedKey,
+    query::{
+        self, BackupInput, BackupOutput, CompactAllInput, CompactAllOutput, DiskStatsInput,
+        DiskStatsOutput, ExplainScanInput, ExplainScanOutput, FlushInput, FlushMode, FlushOutput,
+        FsckInput, FsckOutput, FsckProblem, InspectLeafInput, InspectLeafOutput, LocateKeyInput,
+        LocateKeyOutput, NamespacedTable, RebuildCatalogInput, RebuildCatalogOutput, TreeProblem,
+        TruncateTableInput, TruncateTableOutput, VacuumInput, VacuumOutput, WarmInput, WarmOutput,
+    },
+};
+
+use super::{AppendOnly, BadBackupPath, Executor, NoSuchTable};
+
+impl Executor {
+    /// Rebuilds a table and points its catalog entry at the copy. The copy
+    /// is synced before the entry changes, so a crash midway leaves the old
+    /// table in place. The old pages are not reclaimed: there is no free
+    /// list to return them to, and scans that started earlier may still be
+    /// reading them.
+    pub(super) fn vacuum(&self, input: VacuumInput) -> Result<VacuumOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.write();
+        let table_id = input.table_id.into();
+        let meta = self.lookup_table(input.namespace, table_id)?;
+        let table_access: btree::Access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let new_table = table_access.rebuild()?;
+        self.bufmgr.flush()?;
+        let new_meta = TableMeta {
+            btree_page_id: new_table.btree_page_id,
+            ..meta
+        };
+        let catalog = self.catalog(input.namespace)?.ok_or(NoSuchTable)?;
+        catalog.put(table_id, &new_meta)?;
+        self.forget_tables();
+        self.bufmgr.flush()?;
+        Ok(VacuumOutput {
+            leaves_before: table_access.leaf_count()?,
+            leaves_after: new_table.leaf_count()?,
+            abandoned_pages: table_access.verify()?.pages.len() as u64,
+        })
+    }
+
+    /// Points the table's catalog entry at a new empty tree, synced first as
+    /// in `vacuum`, so a crash midway leaves the old tree in place. Its pages
+    /// are not reclaimed either.
+    pub(super) fn truncate_table(
+        &self,
+        input: TruncateTableInput,
+    ) -> Result<TruncateTableOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.write();
+        let table_id = input.table_id.into();
+        let meta = self.lookup_table(input.namespace, table_id)?;
+        if meta.append_only {
+            return Err(AppendOnly.into());
+        }
+        let table_access: btree::Access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let deleted = table_access.count()?;
+        let abandoned_pages = table_access.verify()?.pages.len() as u64;
+        let new_table: btree::Access = btree::Access::create(&self.bufmgr)?;
+        self.bufmgr.flush()?;
+        let new_meta = TableMeta {
+            btree_page_id: new_table.btree_page_id,
+            ..meta
+        };
+        let catalog = self.catalog(input.namespace)?.ok_or(NoSuchTable)?;
+        catalog.put(table_id, &new_meta)?;
+        self.forget_tables();
+        self.bufmgr.flush()?;
+        Ok(TruncateTableOutput {
+            deleted,
+            abandoned_pages,
+        })
+    }
+
+    pub(super) fn backup(&self, input: BackupInput) -> Result<BackupOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.write();
+        let data_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(self.backup_path(&input.path)?)?;
+        let pages = self.bufmgr.backup(&DiskManager::new(data_file)?)?;
+        Ok(BackupOutput { pages })
+    }
+
+    /// Where a copy named `name` goes in the backup directory. Clients name
+    /// files only there, so they can't overwrite or create anything else the
+    /// server can write to.
+    fn backup_path(&self, name: &str) -> Result<PathBuf, BadBackupPath> {
+        let backup_dir = self
+            .backup_dir
+            .as_ref()
+            .ok_or(BadBackupPath("no backup directory is configured"))?;
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(file_name)), None) => Ok(backup_dir.join(file_name)),
+            _ => Err(BadBackupPath("path must be a file name")),
+        }
+    }
+
+    /// Holds off writes to every table like `backup`.
+    pub(super) fn compact_all(
+        &self,
+        input: CompactAllInput,
+    ) -> Result<CompactAllOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.write();
+        let data_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(self.backup_path(&input.path)?)?;
+        let target = BufferPoolManager::new(
+            DiskManager::new(data_file)?,
+            BufferPool::new(btree::min_pool_size::<8>()),
+        );
+        let tables = Catalog::compact_into(&self.bufmgr, &target)?;
+        let before = self.bufmgr.disk_usage()?;
+        let after = target.disk_usage()?;
+        Ok(CompactAllOutput {
+            tables,
+            pages_before: before.allocated_pages,
+            pages_after: after.allocated_pages,
+            file_len_before: before.file_len,
+            file_len_after: after.file_len,
+        })
+    }
+
+    /// Holds off writes to every table, as the catalog is replaced.
+    pub(super) fn rebuild_catalog(
+        &self,
+        _input: RebuildCatalogInput,
+    ) -> Result<RebuildCatalogOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.write();
+        let recovery = Catalog::rebuild(&self.bufmgr);
+        // Even a failed rebuild may have changed some entries.
+        self.forget_tables();
+        let recovery = recovery?;
+        Ok(RebuildCatalogOutput {
+            recovered: recovery.recovered.into_iter().map(Into::into).collect(),
+            recovered_in_namespaces: recovery
+                .recovered_in_namespaces
+                .into_iter()
+                .map(|(namespace, table_id)| NamespacedTable {
+                    namespace: namespace.into(),
+                    table_id: table_id.into(),
+                })
+                .collect(),
+            orphaned: recovery.orphaned.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Holds off writes to every table, as the trees are walked.
+    pub(super) fn fsck(&self, _input: FsckInput) -> Result<FsckOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.write();
+        let check = Catalog::verify(&self.bufmgr)?;
+        let problems = check
+            .problems
+            .into_iter()
+            .map(|(namespace, table_id, problem)| {
+                let (problem, page_id) = match problem {
+                    btree::Problem::Malformed { page_id } => (TreeProblem::Malformed, page_id),
+                    btree::Problem::Unsorted { page_id } => (TreeProblem::Unsorted, page_id),
+                    btree::Problem::BrokenLink { page_id } => (TreeProblem::BrokenLink, page_id),
+                    btree::Problem::ChecksumMismatch { page_id } => {
+                        (TreeProblem::ChecksumMismatch, page_id)
+                    }
+                };
+                FsckProblem {
+                    namespace: namespace.map(Into::into),
+                    table_id: table_id.map(Into::into),
+                    problem,
+                    page_id: page_id.0,
+                }
+            })
+            .collect();
+        Ok(FsckOutput {
+            problems,
+            unreachable_pages: check.unreachable_pages,
+        })
+    }
+
+    pub(super) fn disk_stats(
+        &self,
+        _input: DiskStatsInput,
+    ) -> Result<DiskStatsOutput, anyhow::Error> {
+        let usage = self.bufmgr.disk_usage()?;
+        Ok(DiskStatsOutput {
+            allocated_pages: usage.allocated_pages,
+            allocated_bytes: usage.allocated_pages * PAGE_SIZE as u64,
+            file_len: usage.file_len,
+        })
+    }
+
+    /// Warming more leaves than there are frames would evict the first ones
+    /// read, so it stops once the frames a `put` may need are all that is
+    /// left.
+    pub(super) fn warm(&self, input: WarmInput) -> Result<WarmOutput, anyhow::Error> {
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access: btree::Access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let encode = |key: Option<TypedKey>| key.map(|key| meta.key_type.encode(&key)).transpose();
+        let max_leaves = self
+            .bufmgr
+            .pool_size()
+            .saturating_sub(btree::min_pool_size::<8>());
+        let pages =
+            table_access.warm(encode(input.start)?, encode(input.end)?, max_leaves as u64)?;
+        Ok(WarmOutput { pages })
+    }
+
+    pub(super) fn flush(&self, input: FlushInput) -> Result<query::FlushOutput, anyhow::Error> {
+        if self.bufmgr.is_read_only() {
+            return Err(buffer::Error::ReadOnly.into());
+        }
+        match input.mode.unwrap_or(self.flush_mode) {
+            FlushMode::Sync => self.bufmgr.flush()?,
+            FlushMode::Async => self.request_flush()?,
+        }
+        Ok(FlushOutput)
+    }
+
+    pub(super) fn inspect_leaf(
+        &self,
+        input: InspectLeafInput,
+    ) -> Result<InspectLeafOutput, anyhow::Error> {
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access: btree::Access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let stats = table_access.leaf_stats(meta.key_type.encode(&input.key)?)?;
+        let used = stats.usable_bytes - stats.free_space;
+        Ok(InspectLeafOutput {
+            page_id: stats.page_id.0,
+            num_slots: stats.num_slots,
+            free_space: stats.free_space,
+            fill_factor: (used * 100 / stats.usable_bytes) as u8,
+        })
+    }
+
+    pub(super) fn locate_key(
+        &self,
+        input: LocateKeyInput,
+    ) -> Result<LocateKeyOutput, anyhow::Error> {
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access: btree::Access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let stats = table_access.leaf_stats(meta.key_type.encode(&input.key)?)?;
+        let slot = stats.slot.unwrap();
+        Ok(LocateKeyOutput {
+            page_id: stats.page_id.0,
+            slot: slot.unwrap_or_else(|slot| slot),
+            found: slot.is_ok(),
+        })
+    }
+
+    pub(super) fn explain_scan(
+        &self,
+        input: ExplainScanInput,
+    ) -> Result<ExplainScanOutput, anyhow::Error> {
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access: btree::Access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let start = input
+            .start
+            .map(|start| meta.key_type.encode(&start))
+            .transpose()?;
+        let stats = table_access.start_leaf_stats(start, input.backward)?;
+        let estimated_items = table_access.count()?.min(input.limit as u64);
+        let items_per_page = stats.num_slots.max(1) as u64;
+        Ok(ExplainScanOutput {
+            start_page_id: stats.page_id.0,
+            backward: input.backward,
+            estimated_items,
+            estimated_pages: estimated_items.div_ceil(items_per_page).max(1),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use serde_json::json;
+
+    use crate::disk::PageId;
+
+    use super::{
+        super::tests::{create_table, execute, executor, put_item},
+        *,
+    };
+
+    #[test]
+    fn test_backup() {
+        let executor = executor();
+        let table_ids = ["0000000000000001", "0000000000000002"];
+        for table_id in table_ids {
+            create_table(&executor, table_id, "Raw");
+            // Enough to span several leaves, none of them flushed yet.
+            for i in 0..200u64 {
+                let item = json!({ "key": format!("{:016x}", i), "value": "x".repeat(100) });
+                put_item(&executor, table_id, item);
+            }
+        }
+        execute(
+            &executor,
+            json!({ "type": "DeleteItem", "table_id": table_ids[1], "key": "0000000000000007" }),
+        );
+
+        let backup = json!({ "type": "Backup", "path": "backup" });
+        assert_eq!(
+            json!({ "type": "Error", "error": "BadRequest", "detail": "no backup directory is configured" }),
+            execute(&executor, backup.clone())
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let executor = executor.with_backup_dir(dir.path());
+        for path in ["", "..", "/tmp/backup", "../backup", "sub/backup"] {
+            let response = execute(&executor, json!({ "type": "Backup", "path": path }));
+            assert_eq!(
+                json!({ "type": "Error", "error": "BadRequest", "detail": "path must be a file name" }),
+                response,
+                "{:?}",
+                path
+            );
+        }
+        assert_eq!("Backup", execute(&executor, backup.clone())["type"]);
+        // Never overwrites a file.
+        assert_eq!("Error", execute(&executor, backup)["type"]);
+
+        let path = dir.path().join("backup");
+
+        let disk = DiskManager::open_read_only(&path).unwrap();
+        let copy = Executor::new(Arc::new(BufferPoolManager::new(disk, BufferPool::new(10))));
+        for table_id in table_ids {
+            let scan = json!({ "type": "ScanItem", "table_id": table_id, "limit": 1000 });
+            let items = execute(&executor, scan.clone());
+            assert_eq!(items, execute(&copy, scan));
+            assert!(items["items"].as_array().unwrap().len() >= 199);
+        }
+    }
+
+    #[test]
+    fn test_compact_all() {
+        let executor = executor();
+        let tables = [
+            (None, "0000000000000001"),
+            (None, "0000000000000002"),
+            (Some("00000000000000AA"), "0000000000000001"),
+        ];
+        for (namespace, table_id) in tables {
+            execute(
+                &executor,
+                json!({ "type": "CreateTable", "namespace": namespace, "table_id": table_id }),
+            );
+            // Scattered puts leave half-full leaves, and the deletes empty
+            // most of them.
+            for i in 0..300u64 {
+                let key = format!("{:016x}", i * 7919 % 300);
+                let item = json!({ "key": key, "value": "x".repeat(200) });
+                execute(
+                    &executor,
+                    json!({ "type": "PutItem", "namespace": namespace, "table_id": table_id, "item": item }),
+                );
+            }
+            for i in (0..300u64).filter(|i| i % 10 != 0) {
+                let key = format!("{:016x}", i);
+                execute(
+                    &executor,
+                    json!({ "type": "DeleteItem", "namespace": namespace, "table_id": table_id, "key": key }),
+                );
+            }
+        }
+        execute(
+            &executor,
+            json!({ "type": "Vacuum", "table_id": "0000000000000002" }),
+        );
+
+        let compact_all = json!({ "type": "CompactAll", "path": "compacted" });
+        assert_eq!(
+            json!({ "type": "Error", "error": "BadRequest", "detail": "no backup directory is configured" }),
+            execute(&executor, compact_all.clone())
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let executor = executor.with_backup_dir(dir.path());
+        let response = execute(
+            &executor,
+            json!({ "type": "CompactAll", "path": "../compacted" }),
+        );
+        assert_eq!(json!("BadRequest"), response["error"]);
+        let output = execute(&executor, compact_all);
+        let path = dir.path().join("compacted");
+        assert_eq!(json!(3), output["tables"]);
+        let field = |name: &str| output[name].as_u64().unwrap();
+        assert!(
+            field("pages_after") < field("pages_before") / 2,
+            "{}",
+            output
+        );
+        assert!(
+            field("file_len_after") < field("file_len_before"),
+            "{}",
+            output
+        );
+        assert_eq!(
+            field("file_len_after"),
+            std::fs::metadata(&path).unwrap().len()
+        );
+
+        let disk = DiskManager::open_read_only(&path).unwrap();
+        let copy = Executor::new(Arc::new(BufferPoolManager::new(disk, BufferPool::new(10))));
+        for (namespace, table_id) in tables {
+            let scan = json!({ "type": "ScanItem", "namespace": namespace, "table_id": table_id });
+            let items = execute(&executor, scan.clone());
+            assert_eq!(items, execute(&copy, scan));
+            assert_eq!(30, items["items"].as_array().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_truncate_table() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        for key in 0..500u64 {
+            let item = json!({ "key": key, "value": "x".repeat(100) });
+            put_item(&executor, table_id, item);
+        }
+        let truncated = execute(
+            &executor,
+            json!({ "type": "TruncateTable", "table_id": table_id }),
+        );
+        assert_eq!(json!(500), truncated["deleted"]);
+        let fsck = execute(&executor, json!({ "type": "Fsck" }));
+        assert_eq!(fsck["unreachable_pages"], truncated["abandoned_pages"]);
+        assert!(truncated["abandoned_pages"].as_u64().unwrap() > 2);
+        let scan = json!({ "type": "ScanItem", "table_id": table_id });
+        assert_eq!(json!([]), execute(&executor, scan.clone())["items"]);
+        assert_eq!(
+            json!({ "type": "TableExists", "exists": true }),
+            execute(
+                &executor,
+                json!({ "type": "TableExists", "table_id": table_id }),
+            )
+        );
+        // Still keyed by integers.
+        let item = json!({ "key": 7, "value": "again" });
+        put_item(&executor, table_id, item.clone());
+        assert_eq!(json!([item]), execute(&executor, scan)["items"]);
+
+        let log_id = "0000000000000002";
+        execute(
+            &executor,
+            json!({ "type": "CreateTable", "table_id": log_id, "append_only": true }),
+        );
+        assert_eq!(
+            json!({ "type": "Error", "error": "AppendOnly" }),
+            execute(
+                &executor,
+                json!({ "type": "TruncateTable", "table_id": log_id }),
+            )
+        );
+    }
+
+    #[test]
+    fn test_vacuum() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        for key in 0..500u64 {
+            let item = json!({ "key": key, "value": "x".repeat(100) });
+            put_item(&executor, table_id, item);
+        }
+        for key in (0..500u64).filter(|key| key % 5 != 0) {
+            execute(
+                &executor,
+                json!({ "type": "DeleteItem", "table_id": table_id, "key": key }),
+            );
+        }
+        let scan = json!({ "type": "ScanItem", "table_id": table_id, "start": null, "backward": false, "limit": 1000 });
+        let before = execute(&executor, scan.clone());
+
+        let output = execute(&executor, json!({ "type": "Vacuum", "table_id": table_id }));
+        assert_eq!(json!("Vacuum"), output["type"]);
+        let leaves_before = output["leaves_before"].as_u64().unwrap();
+        let leaves_after = output["leaves_after"].as_u64().unwrap();
+        assert!(leaves_after * 2 < leaves_before);
+        // The leaves, a branch and the meta page, which nothing reaches now.
+        let abandoned_pages = output["abandoned_pages"].as_u64().unwrap();
+        assert!(abandoned_pages > leaves_before + 1);
+        let fsck = execute(&executor, json!({ "type": "Fsck" }));
+        assert_eq!(json!(abandoned_pages), fsck["unreachable_pages"]);
+        assert_eq!(before, execute(&executor, scan));
+        assert_eq!(100, before["items"].as_array().unwrap().len());
+        assert_eq!(
+            json!({ "type": "CountItem", "count": 100 }),
+            execute(
+                &executor,
+                json!({ "type": "CountItem", "table_id": table_id })
+            )
+        );
+    }
+
+    #[test]
+    fn test_disk_stats() {
+        let executor = executor();
+        let disk_stats = || execute(&executor, json!({ "type": "DiskStats" }));
+        // The catalog's meta page and root.
+        assert_eq!(json!(2), disk_stats()["allocated_pages"]);
+        for table_id in 1..=3u64 {
+            create_table(&executor, table_id, "Raw");
+        }
+        execute(&executor, json!({ "type": "Flush" }));
+        assert_eq!(
+            json!({
+                "type": "DiskStats",
+                "allocated_pages": 8,
+                "allocated_bytes": 8 * PAGE_SIZE,
+                "file_len": 9 * PAGE_SIZE,
+            }),
+            disk_stats()
+        );
+    }
+
+    #[test]
+    fn test_async_flush() {
+        let executor = executor();
+        let clone = executor.clone();
+        // Nothing is spawned until a background flush is asked for.
+        assert!(executor.flusher.get().is_none());
+        create_table(&executor, "0000000000000001", "Raw");
+        let flush = json!({ "type": "Flush", "mode": "Async" });
+        assert_eq!(
+            json!({ "type": "Flush" }),
+            execute(&executor, flush.clone())
+        );
+        assert_eq!(json!({ "type": "Flush" }), execute(&clone, flush));
+        // Shared by every clone.
+        assert!(clone.flusher.get().is_some());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while executor.bufmgr.disk_stats().writes == 0 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_locate_key() {
+        let table_id = "0000000000000001";
+        let locate = |executor: &Executor, key: u64| {
+            let key = format!("{:016x}", key);
+            execute(
+                executor,
+                json!({ "type": "LocateKey", "table_id": table_id, "key": key }),
+            )
+        };
+        assert_eq!(json!("BadRequest"), locate(&executor(), 1)["error"]);
+
+        let executor = executor().with_debug_requests(true);
+        create_table(&executor, table_id, "Raw");
+        let put = |key: u64| {
+            let item = json!({ "key": format!("{:016x}", key), "value": "x".repeat(1000) });
+            put_item(&executor, table_id, item);
+        };
+        put(2);
+        put(4);
+        let first = locate(&executor, 2);
+        assert_eq!(json!(0), first["slot"]);
+        assert_eq!(json!(true), first["found"]);
+        let second = locate(&executor, 4);
+        assert_eq!(first["page_id"], second["page_id"]);
+        assert_eq!(json!(1), second["slot"]);
+        let absent = locate(&executor, 3);
+        assert_eq!(first["page_id"], absent["page_id"]);
+        assert_eq!(json!(1), absent["slot"]);
+        assert_eq!(json!(false), absent["found"]);
+
+        // Four values this large don't fit in one leaf.
+        put(6);
+        put(8);
+        let last = locate(&executor, 8);
+        assert_eq!(json!(true), last["found"]);
+        assert_ne!(first["page_id"], last["page_id"]);
+        assert_eq!(first, locate(&executor, 2));
+    }
+
+    #[test]
+    fn test_inspect_leaf() {
+        let table_id = "0000000000000001";
+        let inspect =
+            json!({ "type": "InspectLeaf", "table_id": table_id, "key": "0000000000000001" });
+        let response = execute(&executor(), inspect.clone());
+        assert_eq!(json!("BadRequest"), response["error"]);
+
+        let executor = executor().with_debug_requests(true);
+        create_table(&executor, table_id, "Raw");
+        let empty = execute(&executor, inspect.clone());
+        assert_eq!(json!(0), empty["num_slots"]);
+        assert_eq!(json!(0), empty["fill_factor"]);
+        for key in ["0000000000000001", "0000000000000002"] {
+            let item = json!({ "key": key, "value": "x".repeat(100) });
+            put_item(&executor, table_id, item);
+        }
+        let filled = execute(&executor, inspect);
+        assert_eq!(empty["page_id"], filled["page_id"]);
+        assert_eq!(json!(2), filled["num_slots"]);
+        let free_space = |response: &serde_json::Value| response["free_space"].as_u64().unwrap();
+        // Both keys and values, plus their slots.
+        assert!(free_space(&empty) - free_space(&filled) > 2 * (8 + 100));
+    }
+
+    #[test]
+    fn test_explain_scan() {
+        let executor = executor().with_debug_requests(true);
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        for i in 1..=40 {
+            let item = json!({ "key": format!("{:016X}", i), "value": "x".repeat(500) });
+            put_item(&executor, table_id, item);
+        }
+        let inspect = |key: &serde_json::Value| {
+            let inspect = json!({ "type": "InspectLeaf", "table_id": table_id, "key": key });
+            execute(&executor, inspect)["page_id"].clone()
+        };
+        for (start, backward) in [
+            (json!(null), false),
+            (json!(null), true),
+            (json!("0000000000000014"), false),
+            (json!("0000000000000014"), true),
+        ] {
+            let params = json!({
+                "table_id": table_id,
+                "start": start,
+                "backward": backward,
+                "limit": 10,
+            });
+            let mut explain = params.clone();
+            explain["type"] = json!("ExplainScan");
+            let explained = execute(&executor, explain);
+            let mut scan = params;
+            scan["type"] = json!("ScanItem");
+            let first_key = execute(&executor, scan)["items"][0]["key"].clone();
+            assert_eq!(inspect(&first_key), explained["start_page_id"]);
+            assert_eq!(json!(backward), explained["backward"]);
+            assert_eq!(json!(10), explained["estimated_items"]);
+            assert!(explained["estimated_pages"].as_u64().unwrap() > 1);
+        }
+    }
+
+    #[test]
+    fn test_warm() {
+        let data_file = tempfile::NamedTempFile::new().unwrap();
+        let table_id = "0000000000000001";
+        let open = || {
+            let disk = DiskManager::open(data_file.path()).unwrap();
+            let bufmgr = Arc::new(BufferPoolManager::new(disk, BufferPool::new(64)));
+            crate::bootstrap(&bufmgr).unwrap();
+            Executor::new(bufmgr)
+        };
+        let executor = open();
+        create_table(&executor, table_id, "U64");
+        for key in 0..100 {
+            let item = json!({ "key": key, "value": "x".repeat(500) });
+            put_item(&executor, table_id, item);
+        }
+        execute(&executor, json!({ "type": "Flush" }));
+        drop(executor);
+
+        let scan = json!({ "type": "ScanItem", "table_id": table_id, "start": 20, "limit": 60 });
+        // Counts the misses and hits of the scan on a freshly opened pool,
+        // after running `before`.
+        let scan_stats = |before: Option<serde_json::Value>| {
+            let executor = open();
+            if let Some(before) = before {
+                let response = execute(&executor, before);
+                assert!(response["pages"].as_u64().unwrap() > 1, "{}", response);
+            }
+            let before = executor.bufmgr.stats();
+            assert_eq!(
+                60,
+                execute(&executor, scan.clone())["items"]
+                    .as_array()
+                    .unwrap()
+                    .len()
+            );
+            let after = executor.bufmgr.stats();
+            (after.misses - before.misses, after.hits - before.hits)
+        };
+        let (cold_misses, cold_hits) = scan_stats(None);
+        assert!(cold_misses > 1);
+        let warm = json!({ "type": "Warm", "table_id": table_id, "start": 20, "end": 80 });
+        let (warm_misses, warm_hits) = scan_stats(Some(warm));
+        assert_eq!(0, warm_misses);
+        // `Warm` left the table's catalog entry cached, so the scan skips
+        // the catalog's meta page and root leaf.
+        assert_eq!(cold_hits + cold_misses, warm_hits + 2);
+    }
+
+    #[test]
+    fn test_rebuild_catalog() {
+        let executor = executor();
+        for table_id in ["0000000000000001", "0000000000000002"] {
+            create_table(&executor, table_id, "U64");
+            for key in 0..200u64 {
+                let item = json!({ "key": key, "value": format!("{}:{}", table_id, key) });
+                put_item(&executor, table_id, item);
+            }
+        }
+        // Leaves the table's old tree behind.
+        let catalog = Catalog::open(&executor.bufmgr);
+        let old_page_id = catalog
+            .get(2u64.to_be_bytes())
+            .unwrap()
+            .unwrap()
+            .btree_page_id;
+        execute(
+            &executor,
+            json!({ "type": "Vacuum", "table_id": "0000000000000002" }),
+        );
+
+        executor
+            .bufmgr
+            .fetch_page(PageId::CATALOG_PAGE_ID)
+            .unwrap()
+            .write()
+            .page
+            .fill(0xAB);
+        let get = |key: u64| {
+            execute(
+                &executor,
+                json!({ "type": "GetItem", "table_id": "0000000000000002", "key": key }),
+            )
+        };
+        assert_eq!(json!("Error"), get(7)["type"]);
+
+        assert_eq!(
+            json!({
+                "type": "RebuildCatalog",
+                "recovered": ["0000000000000001", "0000000000000002"],
+                "orphaned": [format!("{:016X}", 0xFF << 56 | old_page_id.0)],
+            }),
+            execute(&executor, json!({ "type": "RebuildCatalog" }))
+        );
+        assert_eq!(json!("0000000000000002:7"), get(7)["item"]["value"]);
+        let count = execute(
+            &executor,
+            json!({ "type": "CountItem", "table_id": "0000000000000001" }),
+        );
+        assert_eq!(json!(200), count["count"]);
+        assert_eq!(
+            json!({ "type": "CreateTable" }),
+            execute(
+                &executor,
+                json!({ "type": "CreateTable", "table_id": "0000000000000003" }),
+            )
+        );
+    }
+
+    #[test]
+    fn test_rebuild_catalog_namespaces() {
+        let executor = executor();
+        let namespace = "00000000000000AA";
+        create_table(&executor, "0000000000000001", "U64");
+        for table_id in ["0000000000000001", "0000000000000002"] {
+            execute(
+                &executor,
+                json!({ "type": "CreateTable", "namespace": namespace, "table_id": table_id, "key_type": "U64" }),
+            );
+            let item = json!({ "key": 7, "value": table_id });
+            execute(
+                &executor,
+                json!({ "type": "PutItem", "namespace": namespace, "table_id": table_id, "item": item }),
+            );
+        }
+        // Loses the default catalog's root but not the link to the
+        // namespaces.
+        executor
+            .bufmgr
+            .fetch_page(PageId::CATALOG_PAGE_ID)
+            .unwrap()
+            .write()
+            .page[..8]
+            .fill(0xAB);
+
+        assert_eq!(
+            json!({
+                "type": "RebuildCatalog",
+                "recovered": ["0000000000000001"],
+                "recovered_in_namespaces": [
+                    { "namespace": namespace, "table_id": "0000000000000001" },
+                    { "namespace": namespace, "table_id": "0000000000000002" },
+                ],
+                "orphaned": [],
+            }),
+            execute(&executor, json!({ "type": "RebuildCatalog" }))
+        );
+        let get = json!({ "type": "GetItem", "namespace": namespace, "table_id": "0000000000000002", "key": 7 });
+        assert_eq!(
+            json!("0000000000000002"),
+            execute(&executor, get)["item"]["value"]
+        );
+        let fsck = execute(&executor, json!({ "type": "Fsck" }));
+        assert_eq!(json!([]), fsck["problems"]);
+    }
+
+    #[test]
+    fn test_fsck() {
+        let executor = executor();
+        for table_id in ["0000000000000001", "0000000000000002"] {
+            create_table(&executor, table_id, "U64");
+        }
+        for key in 0..200 {
+            let item = json!({ "key": key, "value": "x".repeat(100) });
+            execute(
+                &executor,
+                json!({ "type": "PutItem", "table_id": "0000000000000001", "item": item }),
+            );
+        }
+        let item = json!({ "key": 1, "value": "x" });
+        execute(
+            &executor,
+            json!({ "type": "PutItem", "table_id": "0000000000000002", "item": item }),
+        );
+        let fsck = json!({ "type": "Fsck" });
+        assert_eq!(
+            json!({ "type": "Fsck", "problems": [], "unreachable_pages": 0 }),
+            execute(&executor, fsck.clone())
+        );
+
+        let catalog = Catalog::open(&executor.bufmgr);
+        let table = |table_id: u64| {
+            let meta = catalog.get(table_id.to_be_bytes()).unwrap().unwrap();
+            btree::Access::<8>::open(&executor.bufmgr, meta.btree_page_id)
+        };
+        // Rooted at a leaf from the middle of the chain, whose siblings are
+        // then out of reach, and so is the old root.
+        let leaves = table(1).leaf_count().unwrap();
+        assert!(leaves > 2);
+        let middle = table(1).leaf_stats(100u64.to_be_bytes()).unwrap().page_id;
+        table(1).set_root_page_id(middle).unwrap();
+        // Wiped, so that it isn't a node any more.
+        let wiped = table(2).root_page_id().unwrap();
+        {
+            let buffer = executor.bufmgr.fetch_page(wiped).unwrap();
+            let mut rw_buffer = buffer.write();
+            rw_buffer.page.as_mut().fill(0);
+            rw_buffer.mark_dirty();
+        }
+        assert_eq!(
+            json!({
+                "type": "Fsck",
+                "problems": [
+                    {
+                        "table_id": "0000000000000001",
+                        "problem": "BrokenLink",
+                        "page_id": middle.0,
+                    },
+                    {
+                        "table_id": "0000000000000002",
+                        "problem": "Malformed",
+                        "page_id": wiped.0,
+                    },
+                ],
+                "unreachable_pages": leaves,
+            }),
+            execute(&executor, fsck)
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{
+    btree,
+    query::{self, ImportInput, ImportOutput, Response},
+};
+
+use super::{check_value_size, decode_version, encode_value, query_error, Executor, MalformedLine};
+
+/// An import in progress on a connection. See `ImportInput`.
+pub struct Import {
+    executor: Executor,
+    input: ImportInput,
+    lines: u64,
+    last_key: Option<btree::Key>,
+    /// Cleared for good once a key doesn't increase.
+    appending: bool,
+    output: ImportOutput,
+    /// Lines that follow a failure are ignored.
+    failure: Option<query::Error>,
+}
+
+impl Executor {
+    /// Starts an import. The table is looked up again for every line, as
+    /// it may be vacuumed in between.
+    pub fn import(&self, input: ImportInput) -> Import {
+        self.counters.count_other();
+        Import {
+            executor: self.clone(),
+            input,
+            lines: 0,
+            last_key: None,
+            appending: true,
+            output: ImportOutput::default(),
+            failure: None,
+        }
+    }
+}
+
+impl Import {
+    pub fn feed(&mut self, line: &str) {
+        if self.failure.is_some() {
+            return;
+        }
+        self.lines += 1;
+        if let Err(err) = self.import_line(line) {
+            self.fail(query_error(err));
+        }
+        self.executor.flush_if_over_dirty_limit();
+    }
+
+    /// Fails the import, leaving the items imported so far in place.
+    pub fn fail(&mut self, err: query::Error) {
+        self.executor.counters.count_error();
+        self.failure = Some(err);
+    }
+
+    pub fn finish(self) -> Response {
+        match self.failure {
+            Some(err) => Response::Error(err),
+            None => Response::Import(self.output),
+        }
+    }
+
+    fn import_line(&mut self, line: &str) -> Result<(), anyhow::Error> {
+        let executor = &self.executor;
+        let _vacuum = executor.vacuum_lock.read();
+        let meta = executor.lookup_table(self.input.namespace, self.input.table_id.into())?;
+        let parsed = serde_json::from_str::<query::NewItem>(line)
+            .map_err(|err| err.to_string())
+            .and_then(|item| match meta.key_type.encode(&item.key) {
+                Ok(key) => Ok((key, item.value)),
+                Err(err) => Err(err.to_string()),
+            });
+        let (key, value) = match parsed {
+            Ok(parsed) => parsed,
+            Err(detail) if self.input.abort_on_malformed => {
+                let line = self.lines;
+                return Err(MalformedLine { line, detail }.into());
+            }
+            Err(_) => {
+                self.output.skipped += 1;
+                return Ok(());
+            }
+        };
+        check_value_size(&value)?;
+        let increasing = self.last_key.is_none_or(|last_key| last_key < key);
+        self.last_key = Some(key);
+        self.appending &= increasing;
+        let table = (self.input.namespace, self.input.table_id.into());
+        let observer = executor.subscribers.observer(table);
+        let table_access =
+            btree::Access::open(&executor.bufmgr, meta.btree_page_id).with_observer(&observer);
+        let updated_at = executor.updated_at(&meta);
+        if self.appending || meta.append_only {
+            // An appended key is new, so this is its first version.
+            let encoded = encode_value(1, updated_at, &value);
+            let appender =
+                btree::Access::open(&executor.bufmgr, meta.btree_page_id).with_observer(&observer);
+            match appender.with_fill_factor(100).append(key, &encoded) {
+                Ok(()) => {
+                    self.output.imported += 1;
+                    self.output.appended += 1;
+                    return Ok(());
+                }
+                Err(btree::Error::KeyNotIncreasing) if !meta.append_only => {
+                    self.appending = false;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        table_access.update(key, |current| {
+            let version = current.map_or(0, decode_version);
+            Some(encode_value(version + 1, updated_at, &value))
+        })?;
+        self.output.imported += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,958 @@
+use crate::{
+    btree,
+    query::{
+        self, AppendItemInput, AppendItemOutput, BatchGetInput, BatchGetOutput, DeleteItemInput,
+        DeleteItemOutput, DeleteRangeInput, DeleteRangeOutput, GetItemInput, GetItemOutput,
+        GetOrCreateInput, GetOrCreateOutput, IncrItemInput, IncrItemOutput, PutItemInput,
+        PutItemOutput,
+    },
+};
+
+use super::{
+    check_value_size, decode_value, decode_version, encode_value, AppendOnly, ConditionFailed,
+    Executor, NotAnInteger, StoredValue, TooManyKeys, VersionMismatch, MAX_BATCH_GET_KEYS,
+};
+
+impl Executor {
+    pub(super) fn get_item(&self, input: GetItemInput) -> Result<GetItemOutput, anyhow::Error> {
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let mut buf = vec![];
+        if !table_access.get(meta.key_type.encode(&input.key)?, &mut buf)? {
+            return Ok(GetItemOutput {
+                item: None,
+                version: None,
+            });
+        }
+        let stored = decode_value(buf, &input.key)?;
+        let item = query::Item {
+            key: input.key,
+            value: stored.value,
+            updated_at: stored.updated_at,
+        };
+        Ok(GetItemOutput {
+            item: Some(item),
+            version: Some(stored.version),
+        })
+    }
+
+    /// Looks the keys up in key order, so that neighbor keys find their
+    /// pages still cached.
+    pub(super) fn batch_get(&self, input: BatchGetInput) -> Result<BatchGetOutput, anyhow::Error> {
+        if input.keys.len() > MAX_BATCH_GET_KEYS {
+            return Err(TooManyKeys {
+                len: input.keys.len(),
+                max: MAX_BATCH_GET_KEYS,
+            }
+            .into());
+        }
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let mut encoded = input
+            .keys
+            .iter()
+            .map(|key| meta.key_type.encode(key))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .enumerate()
+            .collect::<Vec<_>>();
+        encoded.sort_by_key(|&(_, key)| key);
+        let mut items = vec![None; input.keys.len()];
+        let mut buf = vec![];
+        for (index, key) in encoded {
+            buf.clear();
+            if table_access.get(key, &mut buf)? {
+                let stored = decode_value(std::mem::take(&mut buf), &input.keys[index])?;
+                items[index] = Some(query::Item {
+                    key: input.keys[index].clone(),
+                    value: stored.value,
+                    updated_at: stored.updated_at,
+                });
+            }
+        }
+        Ok(BatchGetOutput { items })
+    }
+
+    pub(super) fn put_item(&self, input: PutItemInput) -> Result<PutItemOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.read();
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let observer = self
+            .subscribers
+            .observer((input.namespace, input.table_id.into()));
+        let table_access =
+            btree::Access::open(&self.bufmgr, meta.btree_page_id).with_observer(&observer);
+        let key = meta.key_type.encode(&input.item.key)?;
+        check_value_size(&input.item.value)?;
+        let updated_at = self.updated_at(&meta);
+        let mismatch = |version| {
+            input
+                .expected_version
+                .is_some_and(|expected| expected != version)
+        };
+        // Keys of append-only tables are new whenever the put succeeds, so
+        // the lookup is skipped.
+        if meta.append_only {
+            if mismatch(0) {
+                return Err(VersionMismatch(0).into());
+            }
+            let value = encode_value(1, updated_at, &input.item.value);
+            table_access.with_fill_factor(100).append(key, &value)?;
+            return Ok(PutItemOutput { created: true });
+        }
+        // The version is checked and bumped under the leaf's latch, so that
+        // no write to the item slips in between.
+        let mut mismatched = None;
+        let created = table_access.update(key, |current| {
+            let version = current.map_or(0, decode_version);
+            if mismatch(version) {
+                mismatched = Some(VersionMismatch(version));
+                return None;
+            }
+            Some(encode_value(version + 1, updated_at, &input.item.value))
+        })?;
+        if let Some(err) = mismatched {
+            return Err(err.into());
+        }
+        Ok(PutItemOutput { created })
+    }
+
+    /// Like `put_item`, for `Db::put`, whose values are bytes.
+    pub(crate) fn put_bytes(
+        &self,
+        table_id: btree::Key,
+        key: btree::Key,
+        value: &[u8],
+    ) -> Result<bool, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.read();
+        let meta = self.lookup_table(None, table_id)?;
+        let observer = self.subscribers.observer((None, table_id));
+        let table_access =
+            btree::Access::open(&self.bufmgr, meta.btree_page_id).with_observer(&observer);
+        check_value_size(value)?;
+        let updated_at = self.updated_at(&meta);
+        if meta.append_only {
+            table_access
+                .with_fill_factor(100)
+                .append(key, &encode_value(1, updated_at, value))?;
+            return Ok(true);
+        }
+        Ok(table_access.update(key, |current| {
+            let version = current.map_or(0, decode_version);
+            Some(encode_value(version + 1, updated_at, value))
+        })?)
+    }
+
+    /// The value grows in place under the leaf's latch rather than being
+    /// read and put back.
+    pub(super) fn append_item(
+        &self,
+        input: AppendItemInput,
+    ) -> Result<AppendItemOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.read();
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        if meta.append_only {
+            return Err(AppendOnly.into());
+        }
+        let observer = self
+            .subscribers
+            .observer((input.namespace, input.table_id.into()));
+        let table_access =
+            btree::Access::open(&self.bufmgr, meta.btree_page_id).with_observer(&observer);
+        let key = meta.key_type.encode(&input.key)?;
+        check_value_size(&input.value)?;
+        let updated_at = self.updated_at(&meta);
+        let mut failed = None;
+        let created = table_access.update(key, |current| {
+            let (version, mut value) = match current {
+                Some(bytes) => match decode_value(bytes.to_vec(), &input.key) {
+                    Ok(stored) => (stored.version, stored.value),
+                    Err(err) => {
+                        failed = Some(anyhow::Error::from(err));
+                        return None;
+                    }
+                },
+                None => (0, String::new()),
+            };
+            value.push_str(&input.value);
+            if let Err(err) = check_value_size(&value) {
+                failed = Some(err.into());
+                return None;
+            }
+            Some(encode_value(version + 1, updated_at, &value))
+        })?;
+        if let Some(err) = failed {
+            return Err(err);
+        }
+        Ok(AppendItemOutput { created })
+    }
+
+    /// Like `append_item`, the value is read and rewritten under the leaf's
+    /// latch, so no concurrent increment is lost.
+    pub(super) fn incr_item(&self, input: IncrItemInput) -> Result<IncrItemOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.read();
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        if meta.append_only {
+            return Err(AppendOnly.into());
+        }
+        let observer = self
+            .subscribers
+            .observer((input.namespace, input.table_id.into()));
+        let table_access =
+            btree::Access::open(&self.bufmgr, meta.btree_page_id).with_observer(&observer);
+        let key = meta.key_type.encode(&input.key)?;
+        let updated_at = self.updated_at(&meta);
+        let mut result = Ok((0, 0));
+        table_access.update(key, |current| {
+            let incremented = match current {
+                Some(bytes) => decode_value(bytes.to_vec(), &input.key)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|stored| {
+                        let value = stored
+                            .value
+                            .parse::<i64>()
+                            .ok()
+                            .and_then(|value| value.checked_add(input.delta))
+                            .ok_or_else(|| NotAnInteger {
+                                key: input.key.clone(),
+                            })?;
+                        Ok((value, stored.version + 1))
+                    }),
+                None => Ok((input.delta, 1)),
+            };
+            result = incremented;
+            let (value, version) = result.as_ref().ok()?;
+            Some(encode_value(*version, updated_at, value.to_string()))
+        })?;
+        let (value, version) = result?;
+        Ok(IncrItemOutput { value, version })
+    }
+
+    /// The lookup and the put happen under the leaf's latch, so concurrent
+    /// requests for the same key agree on its value.
+    pub(super) fn get_or_create(
+        &self,
+        input: GetOrCreateInput,
+    ) -> Result<GetOrCreateOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.read();
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        if meta.append_only {
+            return Err(AppendOnly.into());
+        }
+        let observer = self
+            .subscribers
+            .observer((input.namespace, input.table_id.into()));
+        let table_access =
+            btree::Access::open(&self.bufmgr, meta.btree_page_id).with_observer(&observer);
+        let key = meta.key_type.encode(&input.key)?;
+        check_value_size(&input.default)?;
+        let updated_at = self.updated_at(&meta);
+        let mut existing = None;
+        let created = table_access.update(key, |current| match current {
+            Some(bytes) => {
+                existing = Some(bytes.to_vec());
+                None
+            }
+            None => Some(encode_value(1, updated_at, &input.default)),
+        })?;
+        let stored = match existing {
+            Some(bytes) => decode_value(bytes, &input.key)?,
+            None => StoredValue {
+                version: 1,
+                updated_at,
+                value: input.default,
+            },
+        };
+        Ok(GetOrCreateOutput {
+            item: query::Item {
+                key: input.key,
+                value: stored.value,
+                updated_at: stored.updated_at,
+            },
+            version: stored.version,
+            created,
+        })
+    }
+
+    pub(super) fn delete_item(
+        &self,
+        input: DeleteItemInput,
+    ) -> Result<DeleteItemOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.read();
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        if meta.append_only {
+            return Err(AppendOnly.into());
+        }
+        let observer = self
+            .subscribers
+            .observer((input.namespace, input.table_id.into()));
+        let table_access =
+            btree::Access::open(&self.bufmgr, meta.btree_page_id).with_observer(&observer);
+        let key = meta.key_type.encode(&input.key)?;
+        let expected = match &input.expected {
+            Some(expected) => expected,
+            None => {
+                let found = table_access.delete(key)?;
+                return Ok(DeleteItemOutput { found });
+            }
+        };
+        let matches = |bytes: &[u8]| {
+            decode_value(bytes.to_vec(), &input.key).is_ok_and(|stored| stored.value == *expected)
+        };
+        if !table_access.delete_if(key, matches)? {
+            return Err(ConditionFailed.into());
+        }
+        Ok(DeleteItemOutput { found: true })
+    }
+
+    /// Like `delete_item`, for `Db::delete`.
+    pub(crate) fn delete_bytes(
+        &self,
+        table_id: btree::Key,
+        key: btree::Key,
+    ) -> Result<bool, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.read();
+        let meta = self.lookup_table(None, table_id)?;
+        if meta.append_only {
+            return Err(AppendOnly.into());
+        }
+        let observer = self.subscribers.observer((None, table_id));
+        let table_access =
+            btree::Access::open(&self.bufmgr, meta.btree_page_id).with_observer(&observer);
+        Ok(table_access.delete(key)?)
+    }
+
+    pub(super) fn delete_range(
+        &self,
+        input: DeleteRangeInput,
+    ) -> Result<DeleteRangeOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.read();
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        if meta.append_only {
+            return Err(AppendOnly.into());
+        }
+        let observer = self
+            .subscribers
+            .observer((input.namespace, input.table_id.into()));
+        let table_access =
+            btree::Access::open(&self.bufmgr, meta.btree_page_id).with_observer(&observer);
+        let start = meta.key_type.encode(&input.start)?;
+        let end = meta.key_type.encode(&input.end)?;
+        let deleted = table_access.delete_range(start, end)?;
+        Ok(DeleteRangeOutput { deleted })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use serde_json::json;
+
+    use crate::catalog::Catalog;
+
+    use super::{
+        super::{
+            tests::{create_table, execute, executor, put_item},
+            MAX_HEADER_LEN,
+        },
+        *,
+    };
+
+    #[test]
+    fn test_typed_keys() {
+        let executor = executor();
+        let signed = "0000000000000001";
+        create_table(&executor, signed, "I64");
+        for key in &[3, -1, 0, -20, 7] {
+            let item = json!({ "key": key, "value": key.to_string() });
+            put_item(&executor, signed, item);
+        }
+        let scan = json!({
+            "type": "ScanItem",
+            "table_id": signed,
+            "start": -1,
+            "backward": false,
+            "limit": 3,
+        });
+        assert_eq!(
+            json!({ "type": "ScanItem", "items": [
+                { "key": -1, "value": "-1" },
+                { "key": 0, "value": "0" },
+                { "key": 3, "value": "3" },
+            ] }),
+            execute(&executor, scan)
+        );
+
+        let strings = "0000000000000002";
+        create_table(&executor, strings, "String");
+        for key in &["pear", "apple", "fig"] {
+            let item = json!({ "key": key, "value": "fruit" });
+            put_item(&executor, strings, item);
+        }
+        let scan = json!({
+            "type": "ScanItem",
+            "table_id": strings,
+            "start": null,
+            "backward": false,
+            "limit": 10,
+        });
+        let keys = execute(&executor, scan)["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["key"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![json!("apple"), json!("fig"), json!("pear")], keys);
+
+        let get = json!({ "type": "GetItem", "table_id": strings, "key": "pineapple" });
+        assert_eq!(json!("BadRequest"), execute(&executor, get)["error"]);
+
+        let floats = "0000000000000003";
+        create_table(&executor, floats, "F64");
+        for key in &[
+            json!(1e-9),
+            json!(-2.5),
+            json!(1e20),
+            json!(-7),
+            json!(0.25),
+        ] {
+            let item = json!({ "key": key, "value": "number" });
+            put_item(&executor, floats, item);
+        }
+        let scan = json!({
+            "type": "ScanItem",
+            "table_id": floats,
+            "start": null,
+            "backward": false,
+            "limit": 10,
+        });
+        let keys = execute(&executor, scan)["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["key"].as_f64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![-7.0, -2.5, 1e-9, 0.25, 1e20], keys);
+    }
+
+    #[test]
+    fn test_get_or_create() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        let get_or_create = |executor: &Executor, key: u64, default: &str| {
+            execute(
+                executor,
+                json!({ "type": "GetOrCreate", "table_id": table_id, "key": key, "default": default }),
+            )
+        };
+        assert_eq!(
+            json!({
+                "type": "GetOrCreate",
+                "item": { "key": 0, "value": "first" },
+                "version": 1,
+                "created": true,
+            }),
+            get_or_create(&executor, 0, "first")
+        );
+        let again = get_or_create(&executor, 0, "second");
+        assert_eq!(json!("first"), again["item"]["value"]);
+        assert_eq!(json!(false), again["created"]);
+
+        // Clients racing on each key: one creates it and both see its value.
+        for key in 1..100u64 {
+            let barrier = std::sync::Barrier::new(2);
+            let responses = thread::scope(|s| {
+                let racers = ["a", "b"].map(|default| {
+                    let (executor, barrier) = (executor.clone(), &barrier);
+                    s.spawn(move || {
+                        barrier.wait();
+                        get_or_create(&executor, key, default)
+                    })
+                });
+                racers.map(|racer| racer.join().unwrap())
+            });
+            let created = responses
+                .iter()
+                .filter(|response| response["created"] == json!(true))
+                .count();
+            assert_eq!(1, created);
+            assert_eq!(responses[0]["item"], responses[1]["item"]);
+        }
+    }
+
+    #[test]
+    fn test_incr_item() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        let incr = |executor: &Executor, key: u64, delta: i64| {
+            execute(
+                executor,
+                json!({ "type": "IncrItem", "table_id": table_id, "key": key, "delta": delta }),
+            )
+        };
+        execute(
+            &executor,
+            json!({ "type": "PutItem", "table_id": table_id, "item": { "key": 0, "value": "40" } }),
+        );
+        assert_eq!(
+            json!({ "type": "IncrItem", "value": 42, "version": 2 }),
+            incr(&executor, 0, 2)
+        );
+        assert_eq!(
+            json!({ "type": "IncrItem", "value": -5, "version": 1 }),
+            incr(&executor, 1, -5)
+        );
+        assert_eq!(
+            json!({ "type": "GetItem", "item": { "key": 1, "value": "-5" }, "version": 1 }),
+            execute(
+                &executor,
+                json!({ "type": "GetItem", "table_id": table_id, "key": 1 })
+            )
+        );
+
+        execute(
+            &executor,
+            json!({ "type": "PutItem", "table_id": table_id, "item": { "key": 2, "value": "two" } }),
+        );
+        let not_an_integer = json!({ "type": "Error", "error": "NotAnInteger", "key": 2 });
+        assert_eq!(not_an_integer, incr(&executor, 2, 1));
+        execute(
+            &executor,
+            json!({ "type": "PutItem", "table_id": table_id, "item": { "key": 2, "value": i64::MAX.to_string() } }),
+        );
+        assert_eq!(not_an_integer, incr(&executor, 2, 1));
+
+        // Concurrent increments of one key: none is lost.
+        thread::scope(|s| {
+            for _ in 0..4 {
+                let executor = executor.clone();
+                s.spawn(move || {
+                    for _ in 0..50 {
+                        incr(&executor, 3, 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(json!(200), incr(&executor, 3, 0)["value"]);
+    }
+
+    #[test]
+    fn test_append_item() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let append = |key: &str, value: &str| {
+            execute(
+                &executor,
+                json!({ "type": "AppendItem", "table_id": table_id, "key": key, "value": value }),
+            )
+        };
+        let get = |key: &str| {
+            execute(
+                &executor,
+                json!({ "type": "GetItem", "table_id": table_id, "key": key }),
+            )
+        };
+        let key = "0000000000000001";
+        assert_eq!(
+            json!({ "type": "AppendItem", "created": true }),
+            append(key, "a")
+        );
+        for value in ["b", "c"] {
+            assert_eq!(
+                json!({ "type": "AppendItem", "created": false }),
+                append(key, value)
+            );
+        }
+        let got = get(key);
+        assert_eq!(json!("abc"), got["item"]["value"]);
+        assert_eq!(json!(3), got["version"]);
+
+        // Growing every value in turn overflows the leaf they share.
+        let chunk = "x".repeat(300);
+        let keys = (2..=5).map(|i| format!("{:016x}", i)).collect::<Vec<_>>();
+        for _ in 0..4 {
+            for key in &keys {
+                assert_eq!("AppendItem", append(key, &chunk)["type"]);
+            }
+        }
+        for key in &keys {
+            assert_eq!(json!(chunk.repeat(4)), get(key)["item"]["value"]);
+        }
+        assert_eq!(json!("abc"), get(key)["item"]["value"]);
+        // Fits on its own, but not with what's already there.
+        let too_large =
+            "x".repeat(btree::max_value_size::<8>() - MAX_HEADER_LEN - chunk.len() * 4 + 1);
+        assert_eq!(
+            json!("ValueTooLarge"),
+            append(&keys[0], &too_large)["error"]
+        );
+        assert_eq!(json!(chunk.repeat(4)), get(&keys[0])["item"]["value"]);
+
+        let log_id = "0000000000000002";
+        execute(
+            &executor,
+            json!({ "type": "CreateTable", "table_id": log_id, "append_only": true }),
+        );
+        assert_eq!(
+            json!({ "type": "Error", "error": "AppendOnly" }),
+            execute(
+                &executor,
+                json!({ "type": "AppendItem", "table_id": log_id, "key": key, "value": "a" }),
+            )
+        );
+    }
+
+    #[test]
+    fn test_non_utf8_value() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        let catalog = Catalog::open(&executor.bufmgr);
+        let meta = catalog.get(1u64.to_be_bytes()).unwrap().unwrap();
+        let table: btree::Access = btree::Access::open(&executor.bufmgr, meta.btree_page_id);
+        table.put(7u64.to_be_bytes(), &[0x80, 0x81]).unwrap();
+
+        let non_utf8 = json!({ "type": "Error", "error": "NonUtf8Value", "key": 7 });
+        let get = json!({ "type": "GetItem", "table_id": table_id, "key": 7 });
+        assert_eq!(non_utf8, execute(&executor, get.clone()));
+        let scan = json!({ "type": "ScanItem", "table_id": table_id });
+        assert_eq!(non_utf8, execute(&executor, scan));
+        let batch_get = json!({ "type": "BatchGet", "table_id": table_id, "keys": [7] });
+        assert_eq!(non_utf8, execute(&executor, batch_get));
+
+        // Values that can't be read can still be replaced.
+        let item = json!({ "key": 7, "value": "fixed" });
+        put_item(&executor, table_id, item.clone());
+        assert_eq!(item, execute(&executor, get)["item"]);
+    }
+
+    #[test]
+    fn test_empty_value() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        for (key, value) in [(1, "one"), (2, ""), (3, "three")] {
+            execute(
+                &executor,
+                json!({ "type": "PutItem", "table_id": table_id, "item": { "key": key, "value": value } }),
+            );
+        }
+        let get = |key: u64| {
+            execute(
+                &executor,
+                json!({ "type": "GetItem", "table_id": table_id, "key": key }),
+            )
+        };
+        assert_eq!(
+            json!({ "type": "GetItem", "item": { "key": 2, "value": "" }, "version": 1 }),
+            get(2)
+        );
+        assert_eq!(json!({ "type": "GetItem", "item": null }), get(4));
+        let scanned = execute(
+            &executor,
+            json!({ "type": "ScanItem", "table_id": table_id }),
+        );
+        assert_eq!(
+            json!([
+                { "key": 1, "value": "one" },
+                { "key": 2, "value": "" },
+                { "key": 3, "value": "three" },
+            ]),
+            scanned["items"]
+        );
+        execute(
+            &executor,
+            json!({ "type": "DeleteItem", "table_id": table_id, "key": 2 }),
+        );
+        assert_eq!(json!({ "type": "GetItem", "item": null }), get(2));
+    }
+
+    #[test]
+    fn test_batch_get() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        for key in (0..300u64).step_by(3) {
+            let item = json!({ "key": key, "value": key.to_string() });
+            put_item(&executor, table_id, item);
+        }
+        let batch_get = json!({
+            "type": "BatchGet",
+            "table_id": table_id,
+            "keys": [297, 1, 0, 150, 299, 0, 30],
+        });
+        assert_eq!(
+            json!({ "type": "BatchGet", "items": [
+                { "key": 297, "value": "297" },
+                null,
+                { "key": 0, "value": "0" },
+                { "key": 150, "value": "150" },
+                null,
+                { "key": 0, "value": "0" },
+                { "key": 30, "value": "30" },
+            ] }),
+            execute(&executor, batch_get)
+        );
+
+        let empty = json!({ "type": "BatchGet", "table_id": table_id, "keys": [] });
+        assert_eq!(
+            json!({ "type": "BatchGet", "items": [] }),
+            execute(&executor, empty)
+        );
+        let bad_key = json!({ "type": "BatchGet", "table_id": table_id, "keys": [1, "x"] });
+        assert_eq!(json!("BadRequest"), execute(&executor, bad_key)["error"]);
+
+        let keys = (0..=MAX_BATCH_GET_KEYS as u64).collect::<Vec<_>>();
+        let too_many = json!({ "type": "BatchGet", "table_id": table_id, "keys": keys });
+        assert_eq!(
+            json!({
+                "type": "Error",
+                "error": "BadRequest",
+                "detail": format!(
+                    "BatchGet takes at most {} keys, not {}",
+                    MAX_BATCH_GET_KEYS,
+                    MAX_BATCH_GET_KEYS + 1
+                ),
+            }),
+            execute(&executor, too_many)
+        );
+        let keys = &keys[..MAX_BATCH_GET_KEYS];
+        let most = json!({ "type": "BatchGet", "table_id": table_id, "keys": keys });
+        let items = execute(&executor, most)["items"]
+            .as_array()
+            .unwrap()
+            .clone();
+        assert_eq!(MAX_BATCH_GET_KEYS, items.len());
+        assert_eq!(100, items.iter().filter(|item| !item.is_null()).count());
+    }
+
+    #[test]
+    fn test_conditional_put() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let get = json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" });
+        let put = |value: &str, expected_version: Option<u64>| {
+            let item = json!({ "key": "0000000000000001", "value": value });
+            execute(
+                &executor,
+                json!({
+                    "type": "PutItem",
+                    "table_id": table_id,
+                    "item": item,
+                    "expected_version": expected_version,
+                }),
+            )
+        };
+        let created = json!({ "type": "PutItem", "created": true });
+        let replaced = json!({ "type": "PutItem", "created": false });
+        assert_eq!(created, put("first", Some(0)));
+        assert_eq!(json!(1), execute(&executor, get.clone())["version"]);
+        assert_eq!(replaced, put("second", None));
+        assert_eq!(json!(2), execute(&executor, get.clone())["version"]);
+
+        assert_eq!(
+            json!({ "type": "Error", "error": "VersionMismatch", "current_version": 2 }),
+            put("stale", Some(1))
+        );
+        assert_eq!(replaced, put("third", Some(2)));
+        let response = execute(&executor, get);
+        assert_eq!(json!("third"), response["item"]["value"]);
+        assert_eq!(json!(3), response["version"]);
+    }
+
+    #[test]
+    fn test_conditional_delete() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let item = json!({ "key": "0000000000000001", "value": "first" });
+        put_item(&executor, table_id, item);
+        let item = json!({ "key": "0000000000000001", "value": "second" });
+        put_item(&executor, table_id, item);
+        let delete = |expected: &str| {
+            execute(
+                &executor,
+                json!({
+                    "type": "DeleteItem",
+                    "table_id": table_id,
+                    "key": "0000000000000001",
+                    "expected": expected,
+                }),
+            )
+        };
+        let condition_failed = json!({ "type": "Error", "error": "ConditionFailed" });
+        assert_eq!(condition_failed, delete("first"));
+        let get = json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" });
+        assert_eq!(
+            json!("second"),
+            execute(&executor, get.clone())["item"]["value"]
+        );
+        assert_eq!(
+            json!({ "type": "DeleteItem", "found": true }),
+            delete("second")
+        );
+        assert_eq!(json!(null), execute(&executor, get)["item"]);
+        assert_eq!(condition_failed, delete("second"));
+    }
+
+    #[test]
+    fn test_updated_at() {
+        let clock = Arc::new(AtomicU64::new(1_000_000));
+        let executor = executor().with_clock({
+            let clock = clock.clone();
+            move || clock.load(Ordering::Relaxed)
+        });
+        let table_id = "0000000000000001";
+        execute(
+            &executor,
+            json!({ "type": "CreateTable", "table_id": table_id, "track_updated_at": true }),
+        );
+        let key = "0000000000000001";
+        let put = |value: &str| {
+            let item = json!({ "key": key, "value": value });
+            put_item(&executor, table_id, item)
+        };
+        let get = json!({ "type": "GetItem", "table_id": table_id, "key": key });
+        put("first");
+        let first = execute(&executor, get.clone());
+        assert_eq!(json!("first"), first["item"]["value"]);
+        assert_eq!(json!(1_000_000), first["item"]["updated_at"]);
+
+        clock.store(1_000_042, Ordering::Relaxed);
+        put("second");
+        let second = execute(&executor, get);
+        assert_eq!(json!("second"), second["item"]["value"]);
+        assert_eq!(json!(2), second["version"]);
+        assert_eq!(json!(1_000_042), second["item"]["updated_at"]);
+
+        let scan = execute(
+            &executor,
+            json!({ "type": "ScanItem", "table_id": table_id }),
+        );
+        assert_eq!(json!(1_000_042), scan["items"][0]["updated_at"]);
+    }
+
+    #[test]
+    fn test_put_item_created() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let put = |key: &str| {
+            let item = json!({ "key": key, "value": "value" });
+            put_item(&executor, table_id, item)["created"].clone()
+        };
+        assert_eq!(json!(true), put("0000000000000001"));
+        assert_eq!(json!(false), put("0000000000000001"));
+        assert_eq!(json!(true), put("0000000000000002"));
+        execute(
+            &executor,
+            json!({ "type": "DeleteItem", "table_id": table_id, "key": "0000000000000001" }),
+        );
+        assert_eq!(json!(true), put("0000000000000001"));
+    }
+
+    #[test]
+    fn test_panicked_put() {
+        let executor = executor();
+        let (broken_id, table_id) = ("0000000000000001", "0000000000000002");
+        create_table(&executor, broken_id, "Raw");
+        create_table(&executor, table_id, "Raw");
+        let catalog = Catalog::open(&executor.bufmgr);
+        let broken: btree::Access = catalog.open_table(1u64.to_be_bytes()).unwrap().unwrap();
+        let root_page_id = broken.root_page_id().unwrap();
+        // Not a node type, which the descent takes for a bug.
+        executor
+            .bufmgr
+            .fetch_page(root_page_id)
+            .unwrap()
+            .write()
+            .page[0] = 0x7F;
+
+        let item = json!({ "key": "0000000000000001", "value": "value" });
+        let request = json!({ "type": "PutItem", "table_id": broken_id, "item": item, "request_id": "put-1" });
+        // The retry runs again rather than waiting for the put that panicked.
+        for _ in 0..2 {
+            let request = serde_json::from_value(request.clone()).unwrap();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| executor.execute(request)));
+            assert!(result.is_err());
+        }
+        // The panic released the locks it held, as the server carries on.
+        put_item(&executor, table_id, item.clone());
+        let get = json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" });
+        assert_eq!(item, execute(&executor, get)["item"]);
+    }
+
+    #[test]
+    fn test_append_only() {
+        let executor = executor();
+        let (log_id, table_id) = ("0000000000000001", "0000000000000002");
+        execute(
+            &executor,
+            json!({ "type": "CreateTable", "table_id": log_id, "key_type": "U64", "append_only": true }),
+        );
+        create_table(&executor, table_id, "U64");
+        for i in 0..500u64 {
+            for table_id in [log_id, table_id] {
+                let item = json!({ "key": i, "value": "x".repeat(100) });
+                assert_eq!(
+                    json!({ "type": "PutItem", "created": true }),
+                    put_item(&executor, table_id, item)
+                );
+            }
+        }
+        let leaf_count = |table_id: u64| {
+            let catalog = Catalog::open(&executor.bufmgr);
+            let table: btree::Access = catalog.open_table(table_id.to_be_bytes()).unwrap().unwrap();
+            table.leaf_count().unwrap()
+        };
+        assert!(leaf_count(1) * 3 < leaf_count(2) * 2);
+
+        let scan = execute(
+            &executor,
+            json!({ "type": "ScanItem", "table_id": log_id, "limit": 1000 }),
+        );
+        let keys: Vec<_> = scan["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["key"].as_u64().unwrap())
+            .collect();
+        assert_eq!((0..500).collect::<Vec<_>>(), keys);
+
+        for key in [499, 3] {
+            let item = json!({ "key": key, "value": "late" });
+            assert_eq!(
+                json!({ "type": "Error", "error": "KeyNotIncreasing" }),
+                execute(
+                    &executor,
+                    json!({ "type": "PutItem", "table_id": log_id, "item": item }),
+                )
+            );
+        }
+        assert_eq!(
+            json!({ "type": "Error", "error": "AppendOnly" }),
+            execute(
+                &executor,
+                json!({ "type": "DeleteItem", "table_id": log_id, "key": 3 }),
+            )
+        );
+        assert_eq!(
+            json!({ "type": "Error", "error": "AppendOnly" }),
+            execute(
+                &executor,
+                json!({ "type": "DeleteRange", "table_id": log_id, "start": 0, "end": 10 }),
+            )
+        );
+    }
+}
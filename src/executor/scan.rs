@@ -0,0 +1,618 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    btree,
+    encoding::{KeyEncoding, KeyType},
+    query::{
+        self, CountItemInput, CountItemOutput, KeyRangeInput, KeyRangeOutput, Response,
+        ScanItemInput, ScanItemOutput, ScanMetrics, ScanStreamInput, SubscribeInput,
+        SubscribeOutput,
+    },
+};
+
+use super::{decode_value, query_error, Executor, SendFailed};
+
+impl Executor {
+    pub(super) fn scan_item(&self, input: ScanItemInput) -> Result<ScanItemOutput, anyhow::Error> {
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let mut start = input
+            .start
+            .map(|start| meta.key_type.encode(&start))
+            .transpose()?;
+        let prefix = match &input.prefix {
+            Some(prefix) => {
+                let (mut bound, len) = meta.key_type.encode_prefix(prefix)?;
+                if input.backward {
+                    bound[len..].fill(0xFF);
+                }
+                start.get_or_insert(bound);
+                Some(bound[..len].to_vec())
+            }
+            None => None,
+        };
+        // Keys are ordered, so the first one without the prefix ends it.
+        let in_prefix = |key: Option<btree::Key>| {
+            key.filter(|key| prefix.as_ref().is_none_or(|prefix| key.starts_with(prefix)))
+        };
+        let limit = input.limit.min(self.max_scan_limit);
+        let clamped_limit = (limit < input.limit).then_some(limit);
+        let deadline = input
+            .deadline_ms
+            .map(|deadline_ms| Instant::now() + Duration::from_millis(deadline_ms));
+        if input.backward {
+            let mut iter = if input.stable {
+                table_access.iter_rev_stable(start)?
+            } else {
+                table_access.iter_rev(start)?
+            };
+            let mut output = if input.keys_only {
+                let next = |_: &mut Vec<u8>| iter.next_key().map(in_prefix);
+                self.collect_items(next, meta.key_type, limit, deadline, true)?
+            } else {
+                let next = |buf: &mut Vec<u8>| iter.next(buf).map(in_prefix);
+                self.collect_items(next, meta.key_type, limit, deadline, false)?
+            };
+            output.clamped_limit = clamped_limit;
+            if input.metrics {
+                output.metrics = Some(scan_metrics(&output, iter.pages_visited()));
+            }
+            Ok(output)
+        } else {
+            let mut iter = if input.stable {
+                table_access.iter_stable(start)?
+            } else {
+                table_access.iter(start)?
+            };
+            let mut output = if input.keys_only {
+                let next = |_: &mut Vec<u8>| iter.next_key().map(in_prefix);
+                self.collect_items(next, meta.key_type, limit, deadline, true)?
+            } else {
+                let next = |buf: &mut Vec<u8>| iter.next(buf).map(in_prefix);
+                self.collect_items(next, meta.key_type, limit, deadline, false)?
+            };
+            output.clamped_limit = clamped_limit;
+            if input.metrics {
+                output.metrics = Some(scan_metrics(&output, iter.pages_visited()));
+            }
+            Ok(output)
+        }
+    }
+
+    /// Sends a `Change` for every write to the table from now on, until
+    /// `send` fails or the subscriber falls too far behind, in which case
+    /// this fails too so that the connection is closed.
+    pub(super) fn subscribe(
+        &self,
+        input: SubscribeInput,
+        send: &mut dyn FnMut(Response) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.counters.count_other();
+        let table_id = input.table_id.into();
+        let meta = match self.lookup_table(input.namespace, table_id) {
+            Ok(meta) => meta,
+            Err(err) => {
+                self.counters.count_error();
+                return send(Response::Error(query_error(err)));
+            }
+        };
+        let changes = self.subscribers.subscribe(
+            (input.namespace, table_id),
+            meta.key_type,
+            self.key_encoding,
+        );
+        send(Response::Subscribe(SubscribeOutput))?;
+        for change in changes {
+            send(Response::Change(change))?;
+        }
+        Err(io::Error::other("subscriber fell behind"))
+    }
+
+    /// Sends items one at a time, so that only the current one is held in
+    /// memory. Returns how many were sent.
+    pub(super) fn scan_stream(
+        &self,
+        input: ScanStreamInput,
+        send: &mut dyn FnMut(Response) -> io::Result<()>,
+    ) -> Result<u64, anyhow::Error> {
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let start = input
+            .start
+            .map(|start| meta.key_type.encode(&start))
+            .transpose()?;
+        let limit = input.limit.unwrap_or(u64::MAX);
+        if input.backward {
+            let mut iter = table_access.iter_rev(start)?;
+            send_items(
+                |buf| iter.next(buf),
+                meta.key_type,
+                self.key_encoding,
+                limit,
+                send,
+            )
+        } else {
+            let mut iter = table_access.iter(start)?;
+            send_items(
+                |buf| iter.next(buf),
+                meta.key_type,
+                self.key_encoding,
+                limit,
+                send,
+            )
+        }
+    }
+
+    /// Collects up to `limit` items, stopping early with a cursor to resume
+    /// from once their values would exceed `max_scan_bytes` or `deadline`
+    /// has passed. At least one item is returned so that the scan always
+    /// makes progress. With `keys_only`, `next` is expected to leave values
+    /// unread and items are returned with empty ones.
+    pub(super) fn collect_items(
+        &self,
+        mut next: impl FnMut(&mut Vec<u8>) -> Result<Option<btree::Key>, btree::Error>,
+        key_type: KeyType,
+        limit: usize,
+        deadline: Option<Instant>,
+        keys_only: bool,
+    ) -> Result<ScanItemOutput, anyhow::Error> {
+        let mut items = vec![];
+        let mut bytes = 0;
+        let mut buf = vec![];
+        while items.len() < limit {
+            let key = match next(&mut buf)? {
+                Some(key) => key_type.decode_with(key, self.key_encoding),
+                None => break,
+            };
+            bytes += buf.len();
+            let timed_out = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if (bytes > self.max_scan_bytes || timed_out) && !items.is_empty() {
+                return Ok(ScanItemOutput {
+                    items,
+                    next_start: Some(key),
+                    timed_out,
+                    metrics: None,
+                    clamped_limit: None,
+                });
+            }
+            if keys_only {
+                items.push(query::Item {
+                    key,
+                    value: String::new(),
+                    updated_at: None,
+                });
+                continue;
+            }
+            let stored = decode_value(std::mem::take(&mut buf), &key)?;
+            items.push(query::Item {
+                key,
+                value: stored.value,
+                updated_at: stored.updated_at,
+            });
+        }
+        Ok(ScanItemOutput {
+            items,
+            next_start: None,
+            timed_out: false,
+            metrics: None,
+            clamped_limit: None,
+        })
+    }
+
+    pub(super) fn count_item(
+        &self,
+        input: CountItemInput,
+    ) -> Result<CountItemOutput, anyhow::Error> {
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access: btree::Access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        let count = table_access.count()?;
+        Ok(CountItemOutput { count })
+    }
+
+    pub(super) fn key_range(&self, input: KeyRangeInput) -> Result<KeyRangeOutput, anyhow::Error> {
+        let meta = self.lookup_table(input.namespace, input.table_id.into())?;
+        let table_access: btree::Access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+        Ok(KeyRangeOutput {
+            first: table_access
+                .first_key()?
+                .map(|key| meta.key_type.decode_with(key, self.key_encoding)),
+            last: table_access
+                .last_key()?
+                .map(|key| meta.key_type.decode_with(key, self.key_encoding)),
+        })
+    }
+}
+
+fn send_items(
+    mut next: impl FnMut(&mut Vec<u8>) -> Result<Option<btree::Key>, btree::Error>,
+    key_type: KeyType,
+    key_encoding: KeyEncoding,
+    limit: u64,
+    send: &mut dyn FnMut(Response) -> io::Result<()>,
+) -> Result<u64, anyhow::Error> {
+    let mut count = 0;
+    let mut buf = vec![];
+    while count < limit {
+        buf.clear();
+        let key = match next(&mut buf)? {
+            Some(key) => key_type.decode_with(key, key_encoding),
+            None => break,
+        };
+        let stored = decode_value(std::mem::take(&mut buf), &key)?;
+        let item = query::Item {
+            key,
+            value: stored.value,
+            updated_at: stored.updated_at,
+        };
+        send(Response::ScanRow(item)).map_err(SendFailed)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn scan_metrics(output: &ScanItemOutput, pages_visited: u64) -> ScanMetrics {
+    ScanMetrics {
+        pages_visited,
+        records_examined: output.items.len() as u64 + output.next_start.is_some() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::super::tests::{create_table, execute, executor, put_item};
+
+    #[test]
+    fn test_count_item() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        for key in &["0000000000000001", "0000000000000002", "0000000000000003"] {
+            let item = json!({ "key": key, "value": "hello" });
+            put_item(&executor, table_id, item);
+        }
+        let count = json!({ "type": "CountItem", "table_id": table_id });
+        assert_eq!(
+            json!({ "type": "CountItem", "count": 3 }),
+            execute(&executor, count.clone())
+        );
+
+        let delete =
+            json!({ "type": "DeleteItem", "table_id": table_id, "key": "0000000000000002" });
+        assert_eq!(
+            json!({ "type": "DeleteItem", "found": true }),
+            execute(&executor, delete.clone())
+        );
+        assert_eq!(
+            json!({ "type": "DeleteItem", "found": false }),
+            execute(&executor, delete)
+        );
+        assert_eq!(
+            json!({ "type": "CountItem", "count": 2 }),
+            execute(&executor, count)
+        );
+    }
+
+    #[test]
+    fn test_scan_item() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        for i in 1..=5 {
+            let item = json!({ "key": format!("{:016X}", i), "value": "x".repeat(i * 300) });
+            put_item(&executor, table_id, item);
+        }
+        let scan = |start: &str, backward: bool| {
+            let scan = json!({
+                "type": "ScanItem",
+                "table_id": table_id,
+                "start": start,
+                "backward": backward,
+                "limit": 3,
+            });
+            execute(&executor, scan)["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| {
+                    let value = item["value"].as_str().unwrap();
+                    assert!(value.chars().all(|c| c == 'x'));
+                    (item["key"].as_str().unwrap().to_string(), value.len())
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = |keys: &[usize]| {
+            keys.iter()
+                .map(|&i| (format!("{:016X}", i), i * 300))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(expected(&[2, 3, 4]), scan("0000000000000002", false));
+        assert_eq!(expected(&[4, 3, 2]), scan("0000000000000004", true));
+    }
+
+    #[test]
+    fn test_scan_prefix() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        let key_type = json!({ "Composite": [{ "Unsigned": 4 }, { "Signed": 4 }] });
+        create_table(&executor, table_id, key_type);
+        for tenant in [1, 2, 3] {
+            for id in [5, -5, 0] {
+                let item = json!({ "key": [tenant, id], "value": format!("{}/{}", tenant, id) });
+                put_item(&executor, table_id, item);
+            }
+        }
+        let scan = |prefix: serde_json::Value, start: Option<serde_json::Value>, backward: bool| {
+            let scan = json!({
+                "type": "ScanItem",
+                "table_id": table_id,
+                "prefix": prefix,
+                "start": start,
+                "backward": backward,
+            });
+            execute(&executor, scan)["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["key"].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![json!([2, -5]), json!([2, 0]), json!([2, 5])],
+            scan(json!([2]), None, false)
+        );
+        assert_eq!(
+            vec![json!([2, 5]), json!([2, 0]), json!([2, -5])],
+            scan(json!([2]), None, true)
+        );
+        assert_eq!(
+            vec![json!([3, 0]), json!([3, 5])],
+            scan(json!([3]), Some(json!([3, 0])), false)
+        );
+        assert_eq!(vec![json!([1, 5])], scan(json!([1, 5]), None, false));
+        assert!(scan(json!([4]), None, false).is_empty());
+
+        let response = execute(
+            &executor,
+            json!({ "type": "GetItem", "table_id": table_id, "key": [2] }),
+        );
+        assert_eq!(json!("BadRequest"), response["error"]);
+        let response = execute(
+            &executor,
+            json!({ "type": "PutItem", "table_id": table_id, "item": { "key": [2], "value": "" } }),
+        );
+        assert_eq!(json!("BadRequest"), response["error"]);
+        let response = execute(
+            &executor,
+            json!({ "type": "GetItem", "table_id": table_id, "key": 2 }),
+        );
+        assert_eq!(json!("BadRequest"), response["error"]);
+    }
+
+    #[test]
+    fn test_scan_max_bytes() {
+        let executor = executor().with_max_scan_bytes(1000);
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        for i in 0..10 {
+            let item = json!({ "key": i, "value": "x".repeat(300) });
+            put_item(&executor, table_id, item);
+        }
+
+        let mut keys = vec![];
+        let mut start = json!(null);
+        loop {
+            let scan = json!({
+                "type": "ScanItem",
+                "table_id": table_id,
+                "start": start,
+                "backward": false,
+                "limit": 100,
+            });
+            let response = execute(&executor, scan);
+            let items = response["items"].as_array().unwrap();
+            keys.extend(items.iter().map(|item| item["key"].as_u64().unwrap()));
+            match response.get("next_start") {
+                Some(next_start) => {
+                    assert_eq!(3, items.len());
+                    start = next_start.clone();
+                }
+                None => break,
+            }
+        }
+        assert_eq!((0..10).collect::<Vec<_>>(), keys);
+    }
+
+    #[test]
+    fn test_scan_deadline() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        for i in 0..3 {
+            let item = json!({ "key": i, "value": "hello" });
+            put_item(&executor, table_id, item);
+        }
+        let scan = |start: serde_json::Value| {
+            json!({
+                "type": "ScanItem",
+                "table_id": table_id,
+                "start": start,
+                "backward": false,
+                "limit": 10,
+                "deadline_ms": 0,
+            })
+        };
+        // An expired deadline still lets every scan return one item.
+        assert_eq!(
+            json!({
+                "type": "ScanItem",
+                "items": [{ "key": 0, "value": "hello" }],
+                "next_start": 1,
+                "timed_out": true,
+            }),
+            execute(&executor, scan(json!(null)))
+        );
+        assert_eq!(
+            json!({ "type": "ScanItem", "items": [{ "key": 2, "value": "hello" }] }),
+            execute(&executor, scan(json!(2)))
+        );
+    }
+
+    #[test]
+    fn test_scan_metrics() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "U64");
+        for i in 0..10 {
+            let item = json!({ "key": i, "value": "x".repeat(1500) });
+            put_item(&executor, table_id, item);
+        }
+        let metrics = |limit: usize, backward: bool| {
+            let scan = json!({
+                "type": "ScanItem",
+                "table_id": table_id,
+                "start": null,
+                "backward": backward,
+                "limit": limit,
+                "metrics": true,
+            });
+            execute(&executor, scan)["metrics"].clone()
+        };
+        assert_eq!(
+            json!({ "pages_visited": 1, "records_examined": 1 }),
+            metrics(1, false)
+        );
+        // At most two values of this size fit in a leaf.
+        let full_scan = metrics(10, false);
+        assert_eq!(json!(10), full_scan["records_examined"]);
+        assert!(full_scan["pages_visited"].as_u64().unwrap() >= 5);
+        assert_eq!(full_scan, metrics(10, true));
+    }
+
+    #[test]
+    fn test_empty_table() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        for backward in [false, true] {
+            for start in [json!(null), json!("0000000000000001")] {
+                let scan = json!({
+                    "type": "ScanItem",
+                    "table_id": table_id,
+                    "start": start,
+                    "backward": backward,
+                    "limit": 10,
+                });
+                assert_eq!(
+                    json!({ "type": "ScanItem", "items": [] }),
+                    execute(&executor, scan)
+                );
+            }
+        }
+        assert_eq!(
+            json!({ "type": "GetItem", "item": null }),
+            execute(
+                &executor,
+                json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" })
+            )
+        );
+        assert_eq!(
+            json!({ "type": "CountItem", "count": 0 }),
+            execute(
+                &executor,
+                json!({ "type": "CountItem", "table_id": table_id })
+            )
+        );
+    }
+
+    #[test]
+    fn test_max_scan_limit() {
+        let executor = executor().with_max_scan_limit(10);
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        for i in 0..20 {
+            let item = json!({ "key": format!("{:016X}", i), "value": "value" });
+            put_item(&executor, table_id, item);
+        }
+        let scan = |limit: u64| {
+            execute(
+                &executor,
+                json!({ "type": "ScanItem", "table_id": table_id, "limit": limit }),
+            )
+        };
+        let clamped = scan(u64::MAX);
+        assert_eq!(10, clamped["items"].as_array().unwrap().len());
+        assert_eq!(json!(10), clamped["clamped_limit"]);
+        let within = scan(5);
+        assert_eq!(5, within["items"].as_array().unwrap().len());
+        assert_eq!(None, within.get("clamped_limit"));
+    }
+
+    #[test]
+    fn test_key_range() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let key_range = json!({ "type": "KeyRange", "table_id": table_id });
+        assert_eq!(
+            json!({ "type": "KeyRange", "first": null, "last": null }),
+            execute(&executor, key_range.clone())
+        );
+        for i in 5..200 {
+            let item = json!({ "key": format!("{:016X}", i), "value": "x".repeat(100) });
+            put_item(&executor, table_id, item);
+        }
+        assert_eq!(
+            json!({
+                "type": "KeyRange",
+                "first": "0000000000000005",
+                "last": "00000000000000C7",
+            }),
+            execute(&executor, key_range)
+        );
+    }
+
+    #[test]
+    fn test_keys_only() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        create_table(&executor, table_id, "Raw");
+        let value = "x".repeat(1000);
+        for i in [3, 1, 4, 5, 9, 2, 6] {
+            let item = json!({ "key": format!("{:016X}", i), "value": value });
+            put_item(&executor, table_id, item);
+        }
+        for backward in [false, true] {
+            let scan = |keys_only: bool| {
+                execute(
+                    &executor,
+                    json!({
+                        "type": "ScanItem",
+                        "table_id": table_id,
+                        "backward": backward,
+                        "keys_only": keys_only,
+                    }),
+                )
+            };
+            let full = scan(false);
+            let keys_only = scan(true);
+            let keys = |output: &serde_json::Value| {
+                output["items"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|item| item["key"].clone())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(7, keys(&full).len());
+            assert_eq!(keys(&full), keys(&keys_only));
+            for item in keys_only["items"].as_array().unwrap() {
+                assert_eq!(json!(""), item["value"]);
+            }
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use crate::{
+    btree,
+    catalog::Catalog,
+    query::{
+        CreateTableInput, CreateTableOutput, DescribeTablesInput, DescribeTablesOutput,
+        FindKeyInTablesInput, FindKeyInTablesOutput, RenameTableInput, RenameTableOutput,
+        TableDescription, TableExistsInput, TableExistsOutput,
+    },
+};
+
+use super::{Executor, NoSuchTable, TableAlreadyExists};
+
+impl Executor {
+    pub(super) fn create_table(
+        &self,
+        input: CreateTableInput,
+    ) -> Result<CreateTableOutput, anyhow::Error> {
+        let catalog = match input.namespace {
+            Some(namespace) => match Catalog::open_namespace(&self.bufmgr, namespace.into())? {
+                Some(catalog) => catalog,
+                None => {
+                    // Two requests creating the same namespace at once would
+                    // each point it at a catalog of their own.
+                    let _vacuum = self.vacuum_lock.write();
+                    Catalog::create_namespace(&self.bufmgr, namespace.into())?
+                }
+            },
+            None => Catalog::open(&self.bufmgr),
+        };
+        let _vacuum = self.vacuum_lock.read();
+        catalog.create_table(
+            input.table_id.into(),
+            input.key_type,
+            input.track_updated_at,
+            input.append_only,
+        )?;
+        self.forget_tables();
+        Ok(CreateTableOutput)
+    }
+
+    pub(super) fn table_exists(
+        &self,
+        input: TableExistsInput,
+    ) -> Result<TableExistsOutput, anyhow::Error> {
+        let exists = match self.catalog(input.namespace)? {
+            Some(catalog) => catalog.get(input.table_id.into())?.is_some(),
+            None => false,
+        };
+        Ok(TableExistsOutput { exists })
+    }
+
+    /// Holds off writes to every table while the catalog is updated. See
+    /// `Catalog::rename` for what happens on a crash. The pages of a table
+    /// that is overwritten are left allocated, as in `vacuum`.
+    pub(super) fn rename_table(
+        &self,
+        input: RenameTableInput,
+    ) -> Result<RenameTableOutput, anyhow::Error> {
+        let _vacuum = self.vacuum_lock.write();
+        let (from, to) = (input.from.into(), input.to.into());
+        let meta = self.lookup_table(input.namespace, from)?;
+        if from == to {
+            return Ok(RenameTableOutput {
+                overwritten: false,
+                abandoned_pages: 0,
+            });
+        }
+        let catalog = self.catalog(input.namespace)?.ok_or(NoSuchTable)?;
+        let overwritten = catalog.get(to)?;
+        if overwritten.is_some() && !input.overwrite {
+            return Err(TableAlreadyExists.into());
+        }
+        let abandoned_pages = match overwritten {
+            Some(overwritten) => {
+                let table: btree::Access =
+                    btree::Access::open(&self.bufmgr, overwritten.btree_page_id);
+                table.verify()?.pages.len() as u64
+            }
+            None => 0,
+        };
+        catalog.rename(from, to, &meta)?;
+        self.forget_tables();
+        Ok(RenameTableOutput {
+            overwritten: overwritten.is_some(),
+            abandoned_pages,
+        })
+    }
+
+    pub(super) fn describe_tables(
+        &self,
+        input: DescribeTablesInput,
+    ) -> Result<DescribeTablesOutput, anyhow::Error> {
+        let tables = match self.catalog(input.namespace)? {
+            Some(catalog) => catalog.tables()?,
+            None => vec![],
+        };
+        let tables = tables
+            .into_iter()
+            .map(|(table_id, meta)| {
+                let table_access: btree::Access =
+                    btree::Access::open(&self.bufmgr, meta.btree_page_id);
+                let record_count = if input.skip_counts {
+                    None
+                } else {
+                    Some(table_access.count()?)
+                };
+                Ok(TableDescription {
+                    table_id: table_id.into(),
+                    root_page_id: table_access.root_page_id()?.0,
+                    record_count,
+                })
+            })
+            .collect::<Result<_, btree::Error>>()?;
+        Ok(DescribeTablesOutput { tables })
+    }
+
+    /// A point lookup per table, so it takes time in proportion to the
+    /// number of tables.
+    pub(super) fn find_key_in_tables(
+        &self,
+        input: FindKeyInTablesInput,
+    ) -> Result<FindKeyInTablesOutput, anyhow::Error> {
+        let tables = match self.catalog(input.namespace)? {
+            Some(catalog) => catalog.tables()?,
+            None => vec![],
+        };
+        let mut table_ids = vec![];
+        let mut buf = vec![];
+        for (table_id, meta) in tables {
+            let key = match meta.key_type.encode(&input.key) {
+                Ok(key) => key,
+                Err(_) => continue,
+            };
+            let table_access: btree::Access = btree::Access::open(&self.bufmgr, meta.btree_page_id);
+            if table_access.get(key, &mut buf)? {
+                table_ids.push(table_id.into());
+            }
+            buf.clear();
+        }
+        Ok(FindKeyInTablesOutput { table_ids })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{
+        super::tests::{create_table, execute, executor, put_item},
+        *,
+    };
+
+    #[test]
+    fn test_find_key_in_tables() {
+        let executor = executor();
+        let key = "00000000000000AA";
+        for table_id in ["0000000000000001", "0000000000000002", "0000000000000003"] {
+            create_table(&executor, table_id, "Raw");
+            let item = json!({ "key": "0000000000000001", "value": "other" });
+            put_item(&executor, table_id, item);
+        }
+        for table_id in ["0000000000000003", "0000000000000001"] {
+            let item = json!({ "key": key, "value": "found" });
+            put_item(&executor, table_id, item);
+        }
+        // Can't hold a raw key, so it is skipped rather than failing.
+        execute(
+            &executor,
+            json!({ "type": "CreateTable", "table_id": "0000000000000004", "key_type": "U64" }),
+        );
+        assert_eq!(
+            json!({
+                "type": "FindKeyInTables",
+                "table_ids": ["0000000000000001", "0000000000000003"],
+            }),
+            execute(&executor, json!({ "type": "FindKeyInTables", "key": key }))
+        );
+        let find = json!({ "type": "FindKeyInTables", "key": "00000000000000BB" });
+        assert_eq!(json!([]), execute(&executor, find)["table_ids"]);
+    }
+
+    #[test]
+    fn test_describe_tables() {
+        let executor = executor();
+        for (table_id, items) in [("0000000000000002", 3), ("0000000000000001", 200)] {
+            create_table(&executor, table_id, "Raw");
+            for i in 0..items {
+                let item = json!({ "key": format!("{:016X}", i), "value": "x".repeat(100) });
+                put_item(&executor, table_id, item);
+            }
+        }
+        let catalog = Catalog::open(&executor.bufmgr);
+        let root_page_id = |table_id: u64| {
+            let table: btree::Access = catalog.open_table(table_id.to_be_bytes()).unwrap().unwrap();
+            table.root_page_id().unwrap().0
+        };
+        assert_eq!(
+            json!({
+                "type": "DescribeTables",
+                "tables": [
+                    {
+                        "table_id": "0000000000000001",
+                        "root_page_id": root_page_id(1),
+                        "record_count": 200,
+                    },
+                    {
+                        "table_id": "0000000000000002",
+                        "root_page_id": root_page_id(2),
+                        "record_count": 3,
+                    },
+                ],
+            }),
+            execute(&executor, json!({ "type": "DescribeTables" }))
+        );
+
+        let described = execute(
+            &executor,
+            json!({ "type": "DescribeTables", "skip_counts": true }),
+        );
+        assert_eq!(None, described["tables"][0].get("record_count"));
+        assert_eq!(
+            json!(root_page_id(1)),
+            described["tables"][0]["root_page_id"]
+        );
+    }
+
+    #[test]
+    fn test_rename_table() {
+        let executor = executor();
+        let (production, temp) = ("0000000000000001", "0000000000000002");
+        let put = |table_id: &str, key: &str, value: &str| {
+            let item = json!({ "key": key, "value": value });
+            put_item(&executor, table_id, item);
+        };
+        let get = |table_id: &str, key: &str| {
+            execute(
+                &executor,
+                json!({ "type": "GetItem", "table_id": table_id, "key": key }),
+            )
+        };
+        for table_id in [production, temp] {
+            create_table(&executor, table_id, "Raw");
+        }
+        put(production, "0000000000000001", "old");
+        put(temp, "0000000000000002", "new");
+
+        let rename = |overwrite: bool| {
+            execute(
+                &executor,
+                json!({ "type": "RenameTable", "from": temp, "to": production, "overwrite": overwrite }),
+            )
+        };
+        assert_eq!(
+            json!({ "type": "Error", "error": "TableAlreadyExists" }),
+            rename(false)
+        );
+        assert_eq!(
+            json!("old"),
+            get(production, "0000000000000001")["item"]["value"]
+        );
+        assert_eq!(
+            // The dropped table's meta page and root.
+            json!({ "type": "RenameTable", "overwritten": true, "abandoned_pages": 2 }),
+            rename(true)
+        );
+        assert_eq!(json!(null), get(production, "0000000000000001")["item"]);
+        assert_eq!(
+            json!("new"),
+            get(production, "0000000000000002")["item"]["value"]
+        );
+        assert_eq!(json!("NoSuchTable"), get(temp, "0000000000000002")["error"]);
+        assert_eq!(json!("NoSuchTable"), rename(true)["error"]);
+        let described = execute(&executor, json!({ "type": "DescribeTables" }));
+        assert_eq!(1, described["tables"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let executor = executor();
+        let table_id = "0000000000000001";
+        let namespaces = [None, Some("0000000000000001"), Some("0000000000000002")];
+        assert_eq!(
+            json!(false),
+            execute(
+                &executor,
+                json!({ "type": "TableExists", "table_id": table_id, "namespace": namespaces[1] }),
+            )["exists"]
+        );
+        for (i, namespace) in namespaces.iter().enumerate() {
+            execute(
+                &executor,
+                json!({ "type": "CreateTable", "table_id": table_id, "namespace": namespace }),
+            );
+            for j in 0..=i {
+                let item = json!({ "key": format!("{:016X}", j), "value": format!("ns{}", i) });
+                execute(
+                    &executor,
+                    json!({
+                        "type": "PutItem",
+                        "table_id": table_id,
+                        "namespace": namespace,
+                        "item": item,
+                    }),
+                );
+            }
+        }
+        // The namespace is reused rather than created again.
+        execute(
+            &executor,
+            json!({
+                "type": "CreateTable",
+                "table_id": "0000000000000002",
+                "namespace": namespaces[1],
+            }),
+        );
+        for (i, namespace) in namespaces.iter().enumerate() {
+            let get = json!({
+                "type": "GetItem",
+                "table_id": table_id,
+                "namespace": namespace,
+                "key": "0000000000000000",
+            });
+            assert_eq!(
+                json!(format!("ns{}", i)),
+                execute(&executor, get)["item"]["value"]
+            );
+            let count =
+                json!({ "type": "CountItem", "table_id": table_id, "namespace": namespace });
+            assert_eq!(json!(i + 1), execute(&executor, count)["count"]);
+        }
+        let get = json!({
+            "type": "GetItem",
+            "table_id": table_id,
+            "namespace": "0000000000000003",
+            "key": "0000000000000000",
+        });
+        assert_eq!(json!("NoSuchTable"), execute(&executor, get)["error"]);
+        let describe = json!({ "type": "DescribeTables", "namespace": namespaces[1] });
+        assert_eq!(
+            2,
+            execute(&executor, describe)["tables"]
+                .as_array()
+                .unwrap()
+                .len()
+        );
+        let describe = json!({ "type": "DescribeTables", "namespace": namespaces[2] });
+        assert_eq!(
+            1,
+            execute(&executor, describe)["tables"]
+                .as_array()
+                .unwrap()
+                .len()
+        );
+    }
+}
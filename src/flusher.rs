@@ -0,0 +1,39 @@
+use std::{
+    sync::{mpsc, Arc},
+    thread,
+};
+
+use crate::buffer::BufferPoolManager;
+
+/// Flushes the buffer pool on a background thread, for clients that don't
+/// want to wait for the disk. Requests queued while a flush is running are
+/// served together by the next one. The thread exits once every handle has
+/// been dropped.
+#[derive(Clone)]
+pub struct Flusher {
+    sender: mpsc::Sender<()>,
+}
+
+/// The flusher thread is gone, which only happens if a flush panicked.
+#[derive(Debug)]
+pub struct Stopped;
+
+impl Flusher {
+    pub fn spawn(bufmgr: Arc<BufferPoolManager>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            while receiver.recv().is_ok() {
+                while receiver.try_recv().is_ok() {}
+                // Pages that failed to be written stay dirty, so the next
+                // flush retries them.
+                let _ = bufmgr.flush();
+            }
+        });
+        Self { sender }
+    }
+
+    /// Queues a flush without waiting for it.
+    pub fn request(&self) -> Result<(), Stopped> {
+        self.sender.send(()).map_err(|_| Stopped)
+    }
+}
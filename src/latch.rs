@@ -24,6 +24,7 @@ where
         unsafe { self.write_guard_owned() }
     }
 
+    #[allow(dead_code)]
     fn try_read_owned(self: Arc<Self>) -> Option<OwnedRwLockReadGuard<R, T>> {
         if unsafe { self.raw().try_lock_shared() } {
             Some(unsafe { self.read_guard_owned() })
@@ -89,6 +90,16 @@ where
     }
 }
 
+impl<R, T> OwnedRwLockReadGuard<R, T>
+where
+    R: RawRwLock,
+{
+    /// Returns the lock held by the guard, so it can be latched again later.
+    pub fn rwlock(this: &Self) -> &Arc<RwLock<R, T>> {
+        &this.rwlock
+    }
+}
+
 impl<R, T> Drop for OwnedRwLockReadGuard<R, T>
 where
     R: RawRwLock,
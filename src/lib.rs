@@ -0,0 +1,17 @@
+pub mod btree;
+pub mod buffer;
+mod catalog;
+mod db;
+pub mod disk;
+pub mod encoding;
+mod executor;
+mod flusher;
+mod latch;
+mod metrics;
+pub mod query;
+pub mod server;
+mod slotted;
+mod subscription;
+
+pub use db::{bootstrap, Db};
+pub use executor::Executor;
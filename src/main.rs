@@ -1,70 +1,202 @@
-mod btree;
-mod buffer;
-mod disk;
-mod executor;
-mod latch;
-mod query;
-mod slotted;
+use std::{env, ffi::OsString, net::TcpListener, str::FromStr, sync::Arc, time::Duration};
 
-use std::env;
-use std::thread;
-use std::{
-    io::Write,
-    io::{BufRead, BufReader},
-    net::TcpListener,
-    net::TcpStream,
-    sync::Arc,
-};
+use anyhow::Context;
 
-use buffer::{BufferPool, BufferPoolManager};
-use disk::DiskManager;
-use executor::Executor;
+use qp::{
+    btree,
+    buffer::{BufferPool, BufferPoolManager},
+    disk::DiskManager,
+    query::{FlushMode, FsckInput, Request},
+    server,
+};
 
-fn main() -> Result<(), anyhow::Error> {
-    let mut args = env::args_os();
-    args.next();
+const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:8124";
+const DEFAULT_POOL_SIZE: usize = 64;
 
-    let qp_filename = args.next().expect("qp filename is required");
-    let disk = DiskManager::open(qp_filename)?;
-    let pool = BufferPool::new(5);
-    let bufmgr = Arc::new(BufferPoolManager::new(disk, pool));
-    let listener = TcpListener::bind("0.0.0.0:8124")?;
+struct Args {
+    qp_filename: OsString,
+    listen: String,
+    /// Listens on this Unix domain socket instead of `listen`.
+    unix: Option<OsString>,
+    /// Serves `listen` from a tokio runtime.
+    use_async: bool,
+    read_only: bool,
+    /// Checksums B-tree leaves in the buffer pool.
+    verify_leaves: bool,
+    /// Flushes in the background once more pages than this are dirty.
+    max_dirty_pages: Option<usize>,
+    /// Never syncs the data file.
+    no_sync: bool,
+    /// Frames in the buffer pool.
+    pool_size: usize,
+    /// Checks every tree before serving, printing what a `Fsck` request
+    /// would answer.
+    fsck: bool,
+    server: server::Config,
+}
 
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-        let executor = Executor::new(bufmgr.clone());
-        thread::spawn(move || Handler::new(executor).handle(stream));
+impl Args {
+    fn parse(mut args: impl Iterator<Item = OsString>) -> Result<Self, anyhow::Error> {
+        let mut qp_filename = None;
+        let mut listen = DEFAULT_LISTEN_ADDR.to_string();
+        let mut unix = None;
+        let mut use_async = false;
+        let mut read_only = false;
+        let mut verify_leaves = false;
+        let mut max_dirty_pages = None;
+        let mut no_sync = false;
+        let mut pool_size = DEFAULT_POOL_SIZE;
+        let mut fsck = false;
+        let mut server = server::Config::default();
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--idle-timeout") => {
+                    let secs = flag_value(&mut args, "--idle-timeout")?;
+                    server.idle_timeout = Some(Duration::from_secs(secs));
+                }
+                Some("--max-connections") => {
+                    server.max_connections = flag_value(&mut args, "--max-connections")?;
+                }
+                Some("--max-scan-bytes") => {
+                    server.max_scan_bytes = flag_value(&mut args, "--max-scan-bytes")?;
+                }
+                Some("--max-scan-limit") => {
+                    server.max_scan_limit = flag_value(&mut args, "--max-scan-limit")?;
+                }
+                Some("--listen") => listen = flag_value(&mut args, "--listen")?,
+                Some("--unix") => {
+                    unix = Some(args.next().context("--unix requires a value")?);
+                }
+                Some("--async") => use_async = true,
+                Some("--read-only") => read_only = true,
+                Some("--verify-leaves") => verify_leaves = true,
+                Some("--max-dirty-pages") => {
+                    max_dirty_pages = Some(flag_value(&mut args, "--max-dirty-pages")?);
+                }
+                Some("--no-sync") => no_sync = true,
+                Some("--pool-size") => pool_size = flag_value(&mut args, "--pool-size")?,
+                Some("--fsck") => fsck = true,
+                Some("--debug") => server.debug_requests = true,
+                Some("--backup-dir") => {
+                    let backup_dir = args.next().context("--backup-dir requires a value")?;
+                    server.backup_dir = Some(backup_dir.into());
+                }
+                Some("--server-timing") => server.server_timing = true,
+                Some("--slow-request-ms") => {
+                    let millis = flag_value(&mut args, "--slow-request-ms")?;
+                    server.slow_request_threshold = Some(Duration::from_millis(millis));
+                }
+                Some("--flush-mode") => {
+                    let mode: String = flag_value(&mut args, "--flush-mode")?;
+                    server.flush_mode = match mode.as_str() {
+                        "sync" => FlushMode::Sync,
+                        "async" => FlushMode::Async,
+                        _ => anyhow::bail!("invalid value for --flush-mode: {}", mode),
+                    };
+                }
+                _ => qp_filename = Some(arg),
+            }
+        }
+        Ok(Self {
+            qp_filename: qp_filename.context("qp filename is required")?,
+            listen,
+            unix,
+            use_async,
+            read_only,
+            verify_leaves,
+            max_dirty_pages,
+            no_sync,
+            pool_size,
+            fsck,
+            server,
+        })
     }
-
-    Ok(())
 }
 
-struct Handler {
-    executor: Executor,
+fn flag_value<T>(args: &mut impl Iterator<Item = OsString>, flag: &str) -> Result<T, anyhow::Error>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = args
+        .next()
+        .and_then(|value| value.into_string().ok())
+        .with_context(|| format!("{} requires a value", flag))?;
+    value
+        .parse()
+        .with_context(|| format!("invalid value for {}: {}", flag, value))
 }
 
-impl Handler {
-    fn new(executor: Executor) -> Self {
-        Self { executor }
+fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse(env::args_os().skip(1))?;
+    let disk = if args.read_only {
+        DiskManager::open_read_only(&args.qp_filename)?
+    } else {
+        DiskManager::open(&args.qp_filename)?
+    };
+    if args.no_sync {
+        eprintln!("warning: --no-sync is set, so nothing written is durable");
     }
-
-    fn handle(&self, stream: TcpStream) -> Result<(), anyhow::Error> {
-        let buf_read = BufReader::new(&stream);
-        for line in buf_read.lines() {
-            let line = line?;
-            let response = self.handle_request(&line).unwrap_or_else(|err| {
-                query::Response::Error(query::Error::Other {
-                    message: err.to_string(),
-                })
-            });
-            serde_json::to_writer(&stream, &response)?;
-            (&stream).write_all(b"\n")?
-        }
-        Ok(())
+    let disk = disk.with_sync(!args.no_sync);
+    let pool = BufferPool::new(args.pool_size);
+    let mut bufmgr = BufferPoolManager::new(disk, pool).with_leaf_checksums(args.verify_leaves);
+    if let Some(max_dirty_pages) = args.max_dirty_pages {
+        bufmgr = bufmgr.with_max_dirty_pages(max_dirty_pages);
     }
-
-    fn handle_request(&self, line: &str) -> Result<query::Response, anyhow::Error> {
-        let request: query::Request = serde_json::from_str(&line)?;
-        Ok(self.executor.execute(request))
+    btree::check_pool_size::<8>(&bufmgr)?;
+    let bufmgr = Arc::new(bufmgr);
+    qp::bootstrap(&bufmgr)?;
+    if args.fsck {
+        let response = qp::Executor::new(bufmgr.clone()).execute(Request::Fsck(FsckInput));
+        eprintln!("fsck: {}", serde_json::to_string(&response)?);
     }
+    if let Some(path) = &args.unix {
+        return serve_unix(path, bufmgr, &args.server);
+    }
+    if args.use_async {
+        return serve_async(&args.listen, bufmgr, &args.server);
+    }
+    let listener = TcpListener::bind(&args.listen)?;
+    server::serve(listener, bufmgr, &args.server)
+}
+
+#[cfg(feature = "async")]
+fn serve_async(
+    listen: &str,
+    bufmgr: Arc<BufferPoolManager>,
+    config: &server::Config,
+) -> Result<(), anyhow::Error> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        server::tokio_server::serve(listener, bufmgr, config).await
+    })
+}
+
+#[cfg(not(feature = "async"))]
+fn serve_async(
+    _listen: &str,
+    _bufmgr: Arc<BufferPoolManager>,
+    _config: &server::Config,
+) -> Result<(), anyhow::Error> {
+    anyhow::bail!("--async requires the async feature")
+}
+
+#[cfg(unix)]
+fn serve_unix(
+    path: &OsString,
+    bufmgr: Arc<BufferPoolManager>,
+    config: &server::Config,
+) -> Result<(), anyhow::Error> {
+    let listener = server::bind_unix(path)?;
+    server::serve(listener, bufmgr, config)
+}
+
+#[cfg(not(unix))]
+fn serve_unix(
+    _path: &OsString,
+    _bufmgr: Arc<BufferPoolManager>,
+    _config: &server::Config,
+) -> Result<(), anyhow::Error> {
+    anyhow::bail!("--unix is only supported on Unix")
 }
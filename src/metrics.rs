@@ -0,0 +1,143 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    buffer, disk,
+    query::{Request, Response},
+};
+
+/// Request counters shared by every clone of an executor.
+#[derive(Debug, Default)]
+pub struct Counters {
+    gets: AtomicU64,
+    puts: AtomicU64,
+    deletes: AtomicU64,
+    scans: AtomicU64,
+    others: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Counters {
+    pub fn count_request(&self, request: &Request) {
+        let counter = match request {
+            Request::GetItem(_) | Request::BatchGet(_) => &self.gets,
+            Request::PutItem(_)
+            | Request::AppendItem(_)
+            | Request::IncrItem(_)
+            | Request::GetOrCreate(_) => &self.puts,
+            Request::DeleteItem(_) | Request::DeleteRange(_) => &self.deletes,
+            Request::ScanItem(_) | Request::ScanStream(_) => &self.scans,
+            _ => &self.others,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a scan that doesn't go through `count_request`, like a
+    /// streamed one.
+    pub fn count_scan(&self) {
+        self.scans.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Like `count_scan`, for an import.
+    pub fn count_other(&self) {
+        self.others.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_response(&self, response: &Response) {
+        if let Response::Error(_) = response {
+            self.count_error();
+        }
+    }
+
+    pub fn count_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the counters along with those of the buffer pool and the disk
+    /// in the Prometheus text exposition format.
+    pub fn render(&self, buffer: buffer::Stats, disk: disk::Stats, usage: disk::Usage) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut text = String::new();
+        metric(
+            &mut text,
+            "qp_requests_total",
+            "counter",
+            "Requests executed, by kind.",
+            &[
+                ("kind=\"get\"", load(&self.gets)),
+                ("kind=\"put\"", load(&self.puts)),
+                ("kind=\"delete\"", load(&self.deletes)),
+                ("kind=\"scan\"", load(&self.scans)),
+                ("kind=\"other\"", load(&self.others)),
+            ],
+        );
+        let counters = [
+            (
+                "qp_errors_total",
+                "Requests that failed.",
+                load(&self.errors),
+            ),
+            (
+                "qp_deadlocks_total",
+                "Deprecated and always 0, as requests wait for latches instead of failing.",
+                0,
+            ),
+            ("qp_buffer_fetches_total", "Page fetches.", buffer.fetches),
+            (
+                "qp_buffer_hits_total",
+                "Page fetches served from the pool.",
+                buffer.hits,
+            ),
+            (
+                "qp_buffer_misses_total",
+                "Page fetches read from disk.",
+                buffer.misses,
+            ),
+            (
+                "qp_buffer_exhaustions_total",
+                "Fetches and allocations that found no free frame.",
+                buffer.exhaustions,
+            ),
+            ("qp_disk_reads_total", "Pages read from disk.", disk.reads),
+            (
+                "qp_disk_writes_total",
+                "Pages written to disk.",
+                disk.writes,
+            ),
+            ("qp_disk_syncs_total", "Syncs of the data file.", disk.syncs),
+        ];
+        for (name, help, value) in counters {
+            metric(&mut text, name, "counter", help, &[("", value)]);
+        }
+        metric(
+            &mut text,
+            "qp_disk_allocated_pages",
+            "gauge",
+            "Pages allocated in the data file.",
+            &[("", usage.allocated_pages)],
+        );
+        metric(
+            &mut text,
+            "qp_disk_file_bytes",
+            "gauge",
+            "Size of the data file.",
+            &[("", usage.file_len)],
+        );
+        text
+    }
+}
+
+fn metric(text: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, u64)]) {
+    // Writing to a `String` can't fail.
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            writeln!(text, "{} {}", name, value).unwrap();
+        } else {
+            writeln!(text, "{}{{{}}} {}", name, labels, value).unwrap();
+        }
+    }
+}
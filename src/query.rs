@@ -1,21 +1,137 @@
 use serde::{Deserialize, Serialize};
 
 use crate::btree;
+pub use crate::encoding::{KeyEncoding, KeyType, TypedKey};
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
 pub enum Request {
     GetItem(GetItemInput),
+    BatchGet(BatchGetInput),
     PutItem(PutItemInput),
+    AppendItem(AppendItemInput),
+    GetOrCreate(GetOrCreateInput),
+    IncrItem(IncrItemInput),
     DeleteItem(DeleteItemInput),
+    DeleteRange(DeleteRangeInput),
     CreateTable(CreateTableInput),
     ScanItem(ScanItemInput),
+    ScanStream(ScanStreamInput),
+    Subscribe(SubscribeInput),
+    CountItem(CountItemInput),
+    TableExists(TableExistsInput),
+    KeyRange(KeyRangeInput),
+    DescribeTables(DescribeTablesInput),
+    FindKeyInTables(FindKeyInTablesInput),
+    RenameTable(RenameTableInput),
     Flush(FlushInput),
+    Ping(PingInput),
+    Limits(LimitsInput),
+    Vacuum(VacuumInput),
+    TruncateTable(TruncateTableInput),
+    RebuildCatalog(RebuildCatalogInput),
+    Fsck(FsckInput),
+    Backup(BackupInput),
+    CompactAll(CompactAllInput),
+    DiskStats(DiskStatsInput),
+    Metrics(MetricsInput),
+    InspectLeaf(InspectLeafInput),
+    LocateKey(LocateKeyInput),
+    ExplainScan(ExplainScanInput),
+    Warm(WarmInput),
+    UseTable(UseTableInput),
+    UseKeyEncoding(UseKeyEncodingInput),
+    Import(ImportInput),
 }
 
+impl Request {
+    /// Parses a single request line, classifying failures so clients can tell
+    /// an unsupported request type from a malformed one.
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        serde_json::from_str(line).map_err(|err| parse_error(line, err))
+    }
+
+    /// Like `parse`, but requests without a `table_id` get `table_id`.
+    pub fn parse_with_default_table(line: &str, table_id: Key) -> Result<Self, Error> {
+        let mut value = serde_json::from_str::<serde_json::Value>(line)
+            .map_err(|err| parse_error(line, err))?;
+        if let Some(object) = value.as_object_mut() {
+            object
+                .entry("table_id")
+                .or_insert_with(|| serde_json::to_value(table_id).unwrap());
+        }
+        serde_json::from_value(value).map_err(|err| parse_error(line, err))
+    }
+
+    /// The client-chosen id of a mutating request, if it carries one.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            Request::PutItem(input) => input.request_id.as_deref(),
+            Request::DeleteItem(input) => input.request_id.as_deref(),
+            Request::DeleteRange(input) => input.request_id.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// The `type` of every variant of `Request`.
+const REQUEST_TYPES: &[&str] = &[
+    "GetItem",
+    "BatchGet",
+    "PutItem",
+    "AppendItem",
+    "GetOrCreate",
+    "IncrItem",
+    "DeleteItem",
+    "DeleteRange",
+    "CreateTable",
+    "ScanItem",
+    "ScanStream",
+    "Subscribe",
+    "CountItem",
+    "TableExists",
+    "KeyRange",
+    "DescribeTables",
+    "FindKeyInTables",
+    "RenameTable",
+    "Flush",
+    "Ping",
+    "Limits",
+    "Vacuum",
+    "TruncateTable",
+    "RebuildCatalog",
+    "Fsck",
+    "Backup",
+    "CompactAll",
+    "DiskStats",
+    "Metrics",
+    "InspectLeaf",
+    "LocateKey",
+    "ExplainScan",
+    "Warm",
+    "UseTable",
+    "UseKeyEncoding",
+    "Import",
+];
+
+fn parse_error(line: &str, err: serde_json::Error) -> Error {
+    let request_type = serde_json::from_str::<serde_json::Value>(line)
+        .ok()
+        .and_then(|value| Some(value.get("type")?.as_str()?.to_owned()));
+    match request_type {
+        Some(request_type) if !REQUEST_TYPES.contains(&request_type.as_str()) => {
+            Error::UnknownRequestType { request_type }
+        }
+        _ => Error::BadRequest {
+            detail: err.to_string(),
+        },
+    }
+}
+
+/// A key of a tree with `N`-byte keys, written as hexadecimal.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct Key([u8; 8]);
-impl Serialize for Key {
+pub struct Key<const N: usize = 8>([u8; N]);
+impl<const N: usize> Serialize for Key<N> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
@@ -24,104 +140,1115 @@ impl Serialize for Key {
     }
 }
 
-impl<'de> Deserialize<'de> for Key {
+/// Accepts a string of `2 * N` hexadecimal digits, an array of `N` bytes or
+/// an unsigned integer, which is taken as big-endian.
+impl<'de, const N: usize> Deserialize<'de> for Key<N> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        Ok(Key(hex::deserialize(deserializer)?))
+        deserializer.deserialize_any(KeyVisitor::<N>)
     }
 }
-impl From<Key> for btree::Key {
-    fn from(Key(bytes): Key) -> Self {
+
+struct KeyVisitor<const N: usize>;
+
+impl<'de, const N: usize> serde::de::Visitor<'de> for KeyVisitor<N> {
+    type Value = Key<N>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            formatter,
+            "{} hexadecimal digits, an array of {} bytes or an unsigned integer",
+            N * 2,
+            N
+        )
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        let mut bytes = [0; N];
+        // The hex crate only reports "Invalid string length".
+        if value.len() != N * 2 {
+            let expected = format!("{} hexadecimal digits", N * 2);
+            return Err(E::invalid_length(value.len(), &expected.as_str()));
+        }
+        hex::decode_to_slice(value, &mut bytes).map_err(E::custom)?;
+        Ok(Key(bytes))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        let mut bytes = [0; N];
+        let be_bytes = value.to_be_bytes();
+        // Right-aligned, so narrower keys take the integers that fit them.
+        let skip = be_bytes.len().saturating_sub(N);
+        if be_bytes[..skip].iter().any(|&byte| byte != 0) {
+            return Err(E::invalid_value(
+                serde::de::Unexpected::Unsigned(value),
+                &self,
+            ));
+        }
+        bytes[N - (be_bytes.len() - skip)..].copy_from_slice(&be_bytes[skip..]);
+        Ok(Key(bytes))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+        }
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(serde::de::Error::invalid_length(bytes.len() + 1, &self));
+        }
+        Ok(Key(bytes))
+    }
+}
+impl<const N: usize> From<Key<N>> for btree::Key<N> {
+    fn from(Key(bytes): Key<N>) -> Self {
         bytes
     }
 }
-impl From<btree::Key> for Key {
-    fn from(bytes: btree::Key) -> Self {
+impl<const N: usize> From<btree::Key<N>> for Key<N> {
+    fn from(bytes: btree::Key<N>) -> Self {
         Key(bytes)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Item {
-    pub key: Key,
+    pub key: TypedKey,
+    pub value: String,
+    /// Seconds since the Unix epoch of the last put, for tables created with
+    /// `track_updated_at`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+}
+
+/// An item to write. Unlike `Item`, it has no `updated_at`, which is set by
+/// the server.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewItem {
+    pub key: TypedKey,
     pub value: String,
 }
 
+/// Fails with `Error::NoSuchTable` if the table doesn't exist. A missing key
+/// is not an error: see `GetItemOutput::item`.
 #[derive(Debug, Deserialize)]
 pub struct GetItemInput {
     pub table_id: Key,
-    pub key: Key,
+    /// The namespace holding the table, each having a catalog of its own.
+    /// The default namespace is used if omitted.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub key: TypedKey,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchGetInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    /// At most 1000 keys, or the request fails with `Error::BadRequest`.
+    pub keys: Vec<TypedKey>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PutItemInput {
     pub table_id: Key,
-    pub item: Item,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub item: NewItem,
+    /// Only puts the item if its current version is this, zero meaning that
+    /// the key must be absent.
+    #[serde(default)]
+    pub expected_version: Option<u64>,
+    /// A retry carrying the id of a recently applied request gets that
+    /// request's response back instead of being applied again. An id can't
+    /// be reused for a different request while it is remembered.
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+/// Appends `value` to the value of `key`, creating the item if it's absent.
+/// Bumps the version like a put.
+#[derive(Debug, Deserialize)]
+pub struct AppendItemInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub key: TypedKey,
+    pub value: String,
+}
+
+/// Returns the value of `key`, putting `default` first if it's absent.
+#[derive(Debug, Deserialize)]
+pub struct GetOrCreateInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub key: TypedKey,
+    pub default: String,
+}
+
+/// Adds `delta` to the value of `key`, read as a decimal `i64`, an absent key
+/// counting as zero. Bumps the version like a put.
+#[derive(Debug, Deserialize)]
+pub struct IncrItemInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub key: TypedKey,
+    pub delta: i64,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct DeleteItemInput {
     pub table_id: Key,
-    pub key: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub key: TypedKey,
+    /// Only deletes the item if this is its current value, the check and the
+    /// delete being atomic. Fails with `ConditionFailed` otherwise, including
+    /// when the key is absent.
+    #[serde(default)]
+    pub expected: Option<String>,
+    /// See `PutItemInput::request_id`.
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+/// Deletes every key in `[start, end)`.
+#[derive(Debug, Deserialize)]
+pub struct DeleteRangeInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub start: TypedKey,
+    pub end: TypedKey,
+    /// See `PutItemInput::request_id`.
+    #[serde(default)]
+    pub request_id: Option<String>,
 }
 
+/// Used for `ScanItemInput::limit` when a request leaves it out.
+pub const DEFAULT_SCAN_LIMIT: usize = 100;
+
+/// Scans forward from the first key, returning up to `DEFAULT_SCAN_LIMIT`
+/// items, unless told otherwise.
 #[derive(Debug, Deserialize)]
 pub struct ScanItemInput {
     pub table_id: Key,
-    pub start: Option<Key>,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    #[serde(default)]
+    pub start: Option<TypedKey>,
+    /// Leading fields of a composite key, to which the scan is confined.
+    /// It starts at the first or, scanning backward, the last key with
+    /// them unless `start` is given.
+    #[serde(default)]
+    pub prefix: Option<TypedKey>,
+    #[serde(default)]
     pub backward: bool,
+    #[serde(default = "default_scan_limit")]
     pub limit: usize,
+    /// Blocks writers to the table until the scan completes so the result
+    /// reflects a single point in time.
+    #[serde(default)]
+    pub stable: bool,
+    /// Milliseconds after which the scan stops and returns what it has
+    /// found so far.
+    #[serde(default)]
+    pub deadline_ms: Option<u64>,
+    /// Reports how much of the table the scan went through.
+    #[serde(default)]
+    pub metrics: bool,
+    /// Returns items with empty values, which are never read.
+    #[serde(default)]
+    pub keys_only: bool,
+}
+
+fn default_scan_limit() -> usize {
+    DEFAULT_SCAN_LIMIT
+}
+
+impl ScanItemInput {
+    pub fn new(table_id: Key) -> Self {
+        Self {
+            table_id,
+            namespace: None,
+            start: None,
+            prefix: None,
+            backward: false,
+            limit: DEFAULT_SCAN_LIMIT,
+            stable: false,
+            deadline_ms: None,
+            metrics: false,
+            keys_only: false,
+        }
+    }
+
+    pub fn with_start(mut self, start: TypedKey) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    pub fn with_backward(mut self, backward: bool) -> Self {
+        self.backward = backward;
+        self
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+/// Like `ScanItem`, but answered with a `ScanRow` per item as the scan
+/// reaches it, followed by a `ScanEnd`, or by an `Error` if the scan fails
+/// partway. Only served over a connection.
+#[derive(Debug, Deserialize)]
+pub struct ScanStreamInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    #[serde(default)]
+    pub start: Option<TypedKey>,
+    #[serde(default)]
+    pub backward: bool,
+    /// Unlimited if omitted.
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+/// Turns the connection into a stream of a `Change` for every later write
+/// to the table, by any connection, after a `Subscribe` response. A
+/// subscriber that falls behind is disconnected rather than holding up
+/// writers. Only served over a connection.
+#[derive(Debug, Deserialize)]
+pub struct SubscribeInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountItemInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TableExistsInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+}
+
+/// The smallest and largest keys of a table, found without a scan.
+#[derive(Debug, Deserialize)]
+pub struct KeyRangeInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+}
+
+/// Lists every table in the catalog.
+#[derive(Debug, Deserialize)]
+pub struct DescribeTablesInput {
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    /// Leaves out record counts, so that only the catalog is read.
+    #[serde(default)]
+    pub skip_counts: bool,
+}
+
+/// Looks `key` up in every table of the namespace, for tooling. Tables
+/// whose key type can't hold it are skipped.
+#[derive(Debug, Deserialize)]
+pub struct FindKeyInTablesInput {
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub key: TypedKey,
+}
+
+/// Moves a table to another id. With `overwrite`, a table already at `to`
+/// is dropped; its pages are not reused.
+#[derive(Debug, Deserialize)]
+pub struct RenameTableInput {
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub from: Key,
+    pub to: Key,
+    #[serde(default)]
+    pub overwrite: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateTableInput {
     pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    /// How keys of the new table are written on the wire and ordered.
+    #[serde(default)]
+    pub key_type: KeyType,
+    /// Stamps every put into the table with the time it was made.
+    #[serde(default)]
+    pub track_updated_at: bool,
+    /// Makes an event log of the table: each put must have a key greater
+    /// than any before it, and items can't be deleted. Puts land in the
+    /// last leaf and leaves are packed full.
+    #[serde(default)]
+    pub append_only: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlushInput {
+    /// Falls back to the server's default if omitted.
+    #[serde(default)]
+    pub mode: Option<FlushMode>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+pub enum FlushMode {
+    /// Responds once the data has been synced to disk.
+    #[default]
+    Sync,
+    /// Responds right away and leaves the write-back to a background thread.
+    Async,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct FlushInput;
+pub struct PingInput;
+
+#[derive(Debug, Deserialize)]
+pub struct LimitsInput;
+
+#[derive(Debug, Deserialize)]
+pub struct DiskStatsInput;
+
+/// Request, buffer pool and disk counters in the Prometheus text exposition
+/// format, for a scraper to pass on.
+#[derive(Debug, Deserialize)]
+pub struct MetricsInput;
+
+/// Describes the leaf page that holds `key`. Only served when debug requests
+/// are enabled.
+#[derive(Debug, Deserialize)]
+pub struct InspectLeafInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub key: TypedKey,
+}
+
+/// Tells where `key` is stored, or would be if it were put. Only served when
+/// debug requests are enabled.
+#[derive(Debug, Deserialize)]
+pub struct LocateKeyInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    pub key: TypedKey,
+}
+
+/// Describes how a `ScanItem` with the same parameters would run, without
+/// reading any items. Only served when debug requests are enabled.
+#[derive(Debug, Deserialize)]
+pub struct ExplainScanInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    #[serde(default)]
+    pub start: Option<TypedKey>,
+    #[serde(default)]
+    pub backward: bool,
+    #[serde(default = "default_scan_limit")]
+    pub limit: usize,
+}
+
+/// Loads the leaves holding the keys from `start` up to `end` into the
+/// buffer pool ahead of a scan, leaving enough frames free for the tree's
+/// own descents. Either end defaults to that of the table.
+#[derive(Debug, Deserialize)]
+pub struct WarmInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    #[serde(default)]
+    pub start: Option<TypedKey>,
+    /// Exclusive.
+    #[serde(default)]
+    pub end: Option<TypedKey>,
+}
 
-#[derive(Debug, Serialize)]
+/// Makes `table_id` the table of the connection's later requests that leave
+/// theirs out.
+#[derive(Debug, Deserialize)]
+pub struct UseTableInput {
+    pub table_id: Key,
+}
+
+/// Makes the connection's later responses write raw keys in `encoding`.
+#[derive(Debug, Deserialize)]
+pub struct UseKeyEncodingInput {
+    pub encoding: KeyEncoding,
+}
+
+/// Puts the items on the lines that follow, one JSON `NewItem` per line, until
+/// an empty line. Answered once, after the empty line. Items are appended
+/// to the end of the table for as long as their keys keep increasing past
+/// the last one, and put one by one from then on. Only served over a
+/// connection.
+#[derive(Debug, Deserialize)]
+pub struct ImportInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+    /// Fails the import on the first line that isn't a valid item, rather
+    /// than skipping it. Items before it stay imported either way.
+    #[serde(default)]
+    pub abort_on_malformed: bool,
+}
+
+/// Rewrites a table into tightly packed pages. Writes to every table wait
+/// until it is done.
+///
+/// The old pages stay allocated: there is no free list, and scans that
+/// started before the vacuum may still be reading them. `CompactAll` writes
+/// a copy of the database without them.
+#[derive(Debug, Deserialize)]
+pub struct VacuumInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+}
+
+/// Empties a table, keeping its id and settings. Writes to every table wait
+/// until it is done.
+///
+/// As with `VacuumInput`, the table's old pages stay allocated.
+#[derive(Debug, Deserialize)]
+pub struct TruncateTableInput {
+    pub table_id: Key,
+    /// See `GetItemInput::namespace`.
+    #[serde(default)]
+    pub namespace: Option<Key>,
+}
+
+/// Copies the database to a new data file on the server, as of a single
+/// point in time: writes to every table wait until it is done. Fails if the
+/// file exists, or if the server has no backup directory.
+#[derive(Debug, Deserialize)]
+pub struct BackupInput {
+    /// The name of the file in the server's backup directory.
+    pub path: String,
+}
+
+/// Like `Backup`, but every table is rebuilt with packed leaves, leaving
+/// out the pages of deleted records, old vacuums and truncations. The data
+/// file itself can't shrink, as pages are never freed for reuse: the
+/// compacted copy is meant to replace it while the server is stopped.
+#[derive(Debug, Deserialize)]
+pub struct CompactAllInput {
+    /// The name of the file in the server's backup directory.
+    pub path: String,
+}
+
+/// Recovers a damaged catalog by scanning every page for trees. Tables whose
+/// ids can't be recovered are listed as orphaned under stand-in ids. Meant
+/// for a server no one else is using.
+#[derive(Debug, Deserialize)]
+pub struct RebuildCatalogInput;
+
+/// Checks the structure of every catalog and table, reporting all the
+/// problems found rather than failing on the first, so that operators can
+/// judge whether to run `RebuildCatalog`. Writes to every table wait until
+/// it is done.
+#[derive(Debug, Deserialize)]
+pub struct FsckInput;
+
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "type")]
 pub enum Response {
     GetItem(GetItemOutput),
+    BatchGet(BatchGetOutput),
     PutItem(PutItemOutput),
+    AppendItem(AppendItemOutput),
+    GetOrCreate(GetOrCreateOutput),
+    IncrItem(IncrItemOutput),
     DeleteItem(DeleteItemOutput),
+    DeleteRange(DeleteRangeOutput),
     ScanItem(ScanItemOutput),
+    ScanRow(Item),
+    ScanEnd(ScanEndOutput),
+    Subscribe(SubscribeOutput),
+    Change(Change),
+    CountItem(CountItemOutput),
+    TableExists(TableExistsOutput),
+    KeyRange(KeyRangeOutput),
+    DescribeTables(DescribeTablesOutput),
+    FindKeyInTables(FindKeyInTablesOutput),
+    RenameTable(RenameTableOutput),
     CreateTable(CreateTableOutput),
     Flush(FlushOutput),
+    Ping(PingOutput),
+    Limits(LimitsOutput),
+    Vacuum(VacuumOutput),
+    TruncateTable(TruncateTableOutput),
+    RebuildCatalog(RebuildCatalogOutput),
+    Fsck(FsckOutput),
+    Backup(BackupOutput),
+    CompactAll(CompactAllOutput),
+    DiskStats(DiskStatsOutput),
+    Metrics(MetricsOutput),
+    InspectLeaf(InspectLeafOutput),
+    LocateKey(LocateKeyOutput),
+    ExplainScan(ExplainScanOutput),
+    Warm(WarmOutput),
+    UseTable(UseTableOutput),
+    UseKeyEncoding(UseKeyEncodingOutput),
+    Import(ImportOutput),
     Error(Error),
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct GetItemOutput {
+    /// `None` if the table has no such key.
     pub item: Option<Item>,
+    /// Bumped by every put of the item. Items put before versioning existed
+    /// are at version zero.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+}
+
+/// One entry per requested key, in the order they were requested.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchGetOutput {
+    pub items: Vec<Option<Item>>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct PutItemOutput;
+#[derive(Debug, Clone, Serialize)]
+pub struct PutItemOutput {
+    /// Whether the key was absent before the put, rather than replaced.
+    pub created: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppendItemOutput {
+    /// See `PutItemOutput::created`.
+    pub created: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GetOrCreateOutput {
+    pub item: Item,
+    /// See `GetItemOutput::version`.
+    pub version: u64,
+    /// Whether `default` was put, rather than the key being present. Of
+    /// concurrent requests for an absent key, exactly one creates it.
+    pub created: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IncrItemOutput {
+    /// The value after the increment.
+    pub value: i64,
+    /// See `GetItemOutput::version`.
+    pub version: u64,
+}
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DeleteItemOutput {
     pub found: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteRangeOutput {
+    pub deleted: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct ScanItemOutput {
     pub items: Vec<Item>,
+    /// Set when the response was cut short by the server's size cap or the
+    /// request's deadline. Passing it as `start` continues the scan.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_start: Option<TypedKey>,
+    /// Whether the scan was cut short by its deadline.
+    #[serde(skip_serializing_if = "is_false")]
+    pub timed_out: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<ScanMetrics>,
+    /// The limit applied instead of the requested one, which was above the
+    /// server's maximum.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clamped_limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscribeOutput;
+
+/// A write to a table, sent to the connections subscribed to it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "op")]
+pub enum Change {
+    Put {
+        key: TypedKey,
+    },
+    Delete {
+        key: TypedKey,
+    },
+    /// Every key in `[start, end)` was deleted.
+    DeleteRange {
+        start: TypedKey,
+        end: TypedKey,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanEndOutput {
+    /// Number of `ScanRow`s sent.
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanMetrics {
+    /// Leaf pages read, including the one the scan started in.
+    pub pages_visited: u64,
+    /// Records read, including the one a cut-short scan resumes from.
+    pub records_examined: u64,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CountItemOutput {
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableExistsOutput {
+    pub exists: bool,
+}
+
+/// Both are `None` if the table is empty.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyRangeOutput {
+    pub first: Option<TypedKey>,
+    pub last: Option<TypedKey>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RenameTableOutput {
+    /// Whether a table at `to` was dropped.
+    pub overwritten: bool,
+    /// Pages of the dropped table, which are left allocated.
+    pub abandoned_pages: u64,
+}
+
+/// In table id order.
+#[derive(Debug, Clone, Serialize)]
+pub struct DescribeTablesOutput {
+    pub tables: Vec<TableDescription>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FindKeyInTablesOutput {
+    /// The tables holding the key, in table id order.
+    pub table_ids: Vec<Key>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
+pub struct TableDescription {
+    pub table_id: Key,
+    pub root_page_id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record_count: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct CreateTableOutput;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct FlushOutput;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
+pub struct UseTableOutput;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UseKeyEncodingOutput;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportOutput {
+    pub imported: u64,
+    /// Of `imported`, the items that were appended.
+    pub appended: u64,
+    /// Lines skipped for not being valid items.
+    pub skipped: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PingOutput {
+    /// Version of the server crate.
+    pub version: String,
+}
+
+/// Sizes imposed by the page layout, so that clients can split values that
+/// are too large into several records.
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitsOutput {
+    pub max_value_size: usize,
+    pub key_size: usize,
+    pub page_size: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VacuumOutput {
+    pub leaves_before: u64,
+    pub leaves_after: u64,
+    /// Pages of the old tree, which are left allocated.
+    pub abandoned_pages: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TruncateTableOutput {
+    /// Items the table held.
+    pub deleted: u64,
+    /// Pages of the old tree, which are left allocated.
+    pub abandoned_pages: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupOutput {
+    pub pages: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactAllOutput {
+    /// Tables copied, across every namespace.
+    pub tables: usize,
+    pub pages_before: u64,
+    pub pages_after: u64,
+    pub file_len_before: u64,
+    pub file_len_after: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RebuildCatalogOutput {
+    /// Tables of the default namespace.
+    pub recovered: Vec<Key>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recovered_in_namespaces: Vec<NamespacedTable>,
+    pub orphaned: Vec<Key>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NamespacedTable {
+    pub namespace: Key,
+    pub table_id: Key,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FsckOutput {
+    pub problems: Vec<FsckProblem>,
+    /// Allocated pages no tree reaches. Vacuums and truncations leave these
+    /// behind, so they are no sign of damage by themselves.
+    pub unreachable_pages: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FsckProblem {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<Key>,
+    /// `None` for a catalog's own tree.
+    pub table_id: Option<Key>,
+    pub problem: TreeProblem,
+    pub page_id: u64,
+}
+
+/// See `btree::Problem`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub enum TreeProblem {
+    Malformed,
+    Unsorted,
+    BrokenLink,
+    ChecksumMismatch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskStatsOutput {
+    pub allocated_pages: u64,
+    pub allocated_bytes: u64,
+    /// Size of the data file, which pages allocated but not yet written
+    /// don't count towards.
+    pub file_len: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsOutput {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InspectLeafOutput {
+    pub page_id: u64,
+    pub num_slots: usize,
+    pub free_space: usize,
+    /// Percentage of the leaf's usable bytes taken by records and slots.
+    pub fill_factor: u8,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LocateKeyOutput {
+    /// The leaf the key belongs to.
+    pub page_id: u64,
+    /// The slot of the key in the leaf, or the one it would be inserted at.
+    pub slot: usize,
+    /// Whether the key is present.
+    pub found: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainScanOutput {
+    /// The leaf the scan descends to.
+    pub start_page_id: u64,
+    pub backward: bool,
+    /// The limit, capped at the number of items in the table.
+    pub estimated_items: u64,
+    /// Assumes the following leaves are as full as the first one.
+    pub estimated_pages: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WarmOutput {
+    /// Leaves read, which may fall short of the range if the pool is
+    /// smaller.
+    pub pages: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "error")]
 pub enum Error {
+    /// Deprecated and never sent: requests that used to fail with this now
+    /// wait for the latch instead. Kept so that clients matching on it still
+    /// build. Not marked `#[deprecated]`, as the derived `Serialize` would
+    /// trip the lint.
     Deadlock,
-    Other { message: String },
+    /// The request named a table that isn't in the catalog. Missing keys
+    /// are reported in the output instead, like `GetItemOutput::item`.
+    NoSuchTable,
+    /// A table already exists at the requested id.
+    TableAlreadyExists,
+    /// Deprecated and never sent: replaced by `Busy`, which is sent in the
+    /// same cases. Kept so that clients matching on it still build, but
+    /// clients that looked for it on the wire must look for `Busy` instead.
+    PoolExhausted,
+    /// Every frame of the buffer pool was pinned by requests in flight. The
+    /// request can be retried once the load drops. Puts and deletes of a
+    /// single item that hit this leave it as it was. Replaces
+    /// `PoolExhausted`.
+    Busy,
+    ValueTooLarge {
+        max_value_size: usize,
+    },
+    /// A conditional put found the item at another version.
+    VersionMismatch {
+        current_version: u64,
+    },
+    /// A conditional delete found the item with another value, or none.
+    ConditionFailed,
+    /// The value of `key` isn't UTF-8, so it can't be returned until values
+    /// may be binary.
+    NonUtf8Value {
+        key: TypedKey,
+    },
+    /// A put to an append-only table had a key no greater than the last.
+    KeyNotIncreasing,
+    /// A delete, an append, an increment, a `GetOrCreate` or a truncation
+    /// targeted an append-only table.
+    AppendOnly,
+    /// `IncrItem` found a value that isn't a decimal `i64`, or would
+    /// overflow one.
+    NotAnInteger {
+        key: TypedKey,
+    },
+    /// The stored data is inconsistent, e.g. a damaged catalog entry.
+    Corrupted {
+        detail: String,
+    },
+    TooManyConnections,
+    /// The request panicked. The connection stays open for the next one.
+    Internal {
+        detail: String,
+    },
+    /// The server was started on a read-only data file.
+    ReadOnly,
+    BadRequest {
+        detail: String,
+    },
+    UnknownRequestType {
+        request_type: String,
+    },
+    Other {
+        message: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse_error(line: &str) -> serde_json::Value {
+        serde_json::to_value(Request::parse(line).unwrap_err()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error = parse_error("{ not json");
+        assert_eq!(json!("BadRequest"), error["error"]);
+        assert!(error["detail"].is_string());
+
+        let error = parse_error(r#"{"type":"GetItem","table_id":"0000000000000001"}"#);
+        assert_eq!(json!("BadRequest"), error["error"]);
+        assert!(error["detail"].as_str().unwrap().contains("key"));
+
+        assert_eq!(
+            json!({ "error": "UnknownRequestType", "request_type": "DropDatabase" }),
+            parse_error(r#"{"type":"DropDatabase"}"#)
+        );
+        assert!(Request::parse(r#"{"type":"Flush"}"#).is_ok());
+        // Every listed type is one serde knows, missing fields aside.
+        for request_type in REQUEST_TYPES {
+            let line = json!({ "type": request_type }).to_string();
+            match serde_json::from_str::<Request>(&line) {
+                Ok(_) => {}
+                Err(err) => assert!(
+                    !err.to_string().starts_with("unknown variant"),
+                    "{}: {}",
+                    request_type,
+                    err
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_forms() {
+        let expected = Key([0, 0, 0, 0, 0, 0, 1, 0xFF]);
+        let forms = [
+            json!("00000000000001FF"),
+            json!([0, 0, 0, 0, 0, 0, 1, 255]),
+            json!(0x1FF),
+        ];
+        for form in forms {
+            assert_eq!(expected, serde_json::from_value::<Key>(form).unwrap());
+        }
+        assert!(serde_json::from_value::<Key>(json!([1, 2, 3])).is_err());
+        assert!(serde_json::from_value::<Key>(json!("1FF")).is_err());
+        assert!(serde_json::from_value::<Key>(json!(-1)).is_err());
+
+        for hex in ["1FF", "00000000000001FF00"] {
+            let err = serde_json::from_value::<Key>(json!(hex)).unwrap_err();
+            assert_eq!(
+                format!(
+                    "invalid length {}, expected 16 hexadecimal digits",
+                    hex.len()
+                ),
+                err.to_string()
+            );
+        }
+        let error = parse_error(r#"{"type":"CountItem","table_id":"0001"}"#);
+        assert_eq!(json!("BadRequest"), error["error"]);
+        assert!(error["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid length 4, expected 16 hexadecimal digits"));
+
+        let mut wide = [0; 16];
+        wide[14..].copy_from_slice(&[1, 0xFF]);
+        let wide = Key(wide);
+        assert_eq!(
+            wide,
+            serde_json::from_value::<Key<16>>(json!(0x1FF)).unwrap()
+        );
+        let hex = serde_json::to_value(wide).unwrap();
+        assert_eq!(json!("000000000000000000000000000001FF"), hex);
+        assert_eq!(wide, serde_json::from_value::<Key<16>>(hex).unwrap());
+        assert!(serde_json::from_value::<Key<16>>(json!("00000000000001FF")).is_err());
+        assert_eq!(
+            Key([1, 0xFF]),
+            serde_json::from_value::<Key<2>>(json!(0x1FF)).unwrap()
+        );
+        assert!(serde_json::from_value::<Key<2>>(json!(0x10000)).is_err());
+
+        let request = Request::parse(r#"{"type":"CountItem","table_id":511}"#).unwrap();
+        assert!(matches!(
+            request,
+            Request::CountItem(CountItemInput { table_id, .. }) if table_id == expected
+        ));
+    }
+
+    #[test]
+    fn test_scan_defaults() {
+        let request =
+            Request::parse(r#"{"type":"ScanItem","table_id":"0000000000000001"}"#).unwrap();
+        let input = match request {
+            Request::ScanItem(input) => input,
+            _ => panic!("expected ScanItem"),
+        };
+        assert_eq!(Key::from(1u64.to_be_bytes()), input.table_id);
+        assert_eq!(None, input.start);
+        assert!(!input.backward);
+        assert_eq!(DEFAULT_SCAN_LIMIT, input.limit);
+        assert!(!input.stable);
+        assert_eq!(None, input.deadline_ms);
+        assert!(!input.metrics);
+
+        let built = ScanItemInput::new(input.table_id)
+            .with_start(TypedKey::Unsigned(5))
+            .with_backward(true)
+            .with_limit(3);
+        assert_eq!(Some(TypedKey::Unsigned(5)), built.start);
+        assert!(built.backward);
+        assert_eq!(3, built.limit);
+    }
 }
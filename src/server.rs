@@ -0,0 +1,1164 @@
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    net::{UnixListener, UnixStream},
+};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{
+    any::Any,
+    io::{self, BufRead, BufReader},
+    io::{Read, Write},
+    net::TcpListener,
+    net::TcpStream,
+    panic::{self, AssertUnwindSafe},
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Arc,
+};
+
+use crate::{
+    buffer::BufferPoolManager,
+    executor::{Executor, Import, DEFAULT_MAX_SCAN_BYTES, DEFAULT_MAX_SCAN_LIMIT},
+    query::{self, FlushMode},
+};
+
+use serde::Serialize;
+
+#[cfg(feature = "async")]
+pub mod tokio_server;
+
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+/// How long to wait after failing to accept a connection. Failures like
+/// running out of file descriptors last until some connections close.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Idle connections are closed after this long without a request.
+    pub idle_timeout: Option<Duration>,
+    /// Connections beyond this many are turned away.
+    pub max_connections: usize,
+    /// Used for `Flush` requests that don't specify a mode.
+    pub flush_mode: FlushMode,
+    /// `ScanItem` responses stop once their values add up to this many bytes.
+    pub max_scan_bytes: usize,
+    /// `ScanItem` requests asking for more items get this many at most.
+    pub max_scan_limit: usize,
+    /// Serves debug requests such as `InspectLeaf` and `ExplainScan`.
+    pub debug_requests: bool,
+    /// Where `Backup` and `CompactAll` requests write. Without one, they
+    /// are refused.
+    pub backup_dir: Option<PathBuf>,
+    /// See `Handler::with_server_timing`.
+    pub server_timing: bool,
+    /// Requests taking longer than this are logged to stderr.
+    pub slow_request_threshold: Option<Duration>,
+}
+
+impl Config {
+    /// Shared by every connection so that there is a single background
+    /// flusher.
+    fn executor(&self, bufmgr: Arc<BufferPoolManager>) -> Executor {
+        let executor = Executor::new(bufmgr)
+            .with_flush_mode(self.flush_mode)
+            .with_max_scan_bytes(self.max_scan_bytes)
+            .with_max_scan_limit(self.max_scan_limit)
+            .with_debug_requests(self.debug_requests);
+        match &self.backup_dir {
+            Some(backup_dir) => executor.with_backup_dir(backup_dir),
+            None => executor,
+        }
+    }
+
+    fn handler(&self, executor: Executor) -> Handler {
+        let mut handler =
+            Handler::new(executor, self.idle_timeout).with_server_timing(self.server_timing);
+        if let Some(threshold) = self.slow_request_threshold {
+            handler = handler.with_slow_request_log(threshold, Arc::new(StderrLog));
+        }
+        handler
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            idle_timeout: None,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            flush_mode: FlushMode::default(),
+            max_scan_bytes: DEFAULT_MAX_SCAN_BYTES,
+            max_scan_limit: DEFAULT_MAX_SCAN_LIMIT,
+            debug_requests: false,
+            backup_dir: None,
+            server_timing: false,
+            slow_request_threshold: None,
+        }
+    }
+}
+
+/// A request that took longer than the threshold given to
+/// `Handler::with_slow_request_log`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowRequest {
+    /// Missing if the line wasn't a JSON object with a type.
+    pub request_type: Option<String>,
+    /// The table named by the request or set by `UseTable`, if any.
+    pub table_id: Option<query::Key>,
+    pub elapsed_us: u64,
+}
+
+pub trait SlowRequestLog: Send + Sync {
+    fn log(&self, request: &SlowRequest);
+}
+
+/// Writes each slow request to stderr as a line of JSON.
+pub struct StderrLog;
+
+impl SlowRequestLog for StderrLog {
+    fn log(&self, request: &SlowRequest) {
+        eprintln!("{}", serde_json::json!({ "slow_request": request }));
+    }
+}
+
+/// A socket the server accepts connections on.
+pub trait Listener {
+    type Connection: Connection;
+
+    fn accept(&self) -> io::Result<Self::Connection>;
+}
+
+/// A connected socket, carrying line-delimited requests and responses.
+pub trait Connection: Read + Write + Send + Sized + 'static {
+    /// Another handle to the same socket, so that it can be read and
+    /// written independently.
+    fn try_clone(&self) -> io::Result<Self>;
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Listener for TcpListener {
+    type Connection = TcpStream;
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        Ok(TcpListener::accept(self)?.0)
+    }
+}
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Connection = UnixStream;
+
+    fn accept(&self) -> io::Result<UnixStream> {
+        Ok(UnixListener::accept(self)?.0)
+    }
+}
+
+/// Binds a Unix domain socket at `path`, first removing a socket left there
+/// by a server that is no longer running. Fails if a server is listening on
+/// it, or if `path` is anything but a socket.
+#[cfg(unix)]
+pub fn bind_unix(path: impl AsRef<std::path::Path>) -> io::Result<UnixListener> {
+    let path = path.as_ref();
+    match UnixListener::bind(path) {
+        Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+            let is_socket = std::fs::symlink_metadata(path)?.file_type().is_socket();
+            let is_stale = match UnixStream::connect(path) {
+                Err(err) => err.kind() == io::ErrorKind::ConnectionRefused,
+                Ok(_) => false,
+            };
+            if !(is_socket && is_stale) {
+                return Err(err);
+            }
+            std::fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        result => result,
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+pub fn serve(
+    listener: impl Listener,
+    bufmgr: Arc<BufferPoolManager>,
+    config: &Config,
+) -> Result<(), anyhow::Error> {
+    let limit = Arc::new(ConnectionLimit::new(config.max_connections));
+    let executor = config.executor(bufmgr);
+    loop {
+        let stream = match listener.accept() {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("failed to accept a connection: {}", err);
+                thread::sleep(ACCEPT_RETRY_DELAY);
+                continue;
+            }
+        };
+        let guard = match limit.try_acquire() {
+            Some(guard) => guard,
+            None => {
+                reject(stream, query::Error::TooManyConnections);
+                continue;
+            }
+        };
+        let mut handler = config.handler(executor.clone());
+        thread::spawn(move || {
+            let _guard = guard;
+            handler.handle(stream)
+        });
+    }
+}
+
+/// Sends a single error response and closes the connection.
+fn reject(mut stream: impl Connection, error: query::Error) {
+    let response = query::Response::Error(error);
+    // The client is turned away either way, so write failures are ignored.
+    let _ = serde_json::to_writer(&mut stream, &response);
+    let _ = stream.write_all(b"\n");
+}
+
+/// Bounds the number of connections served at once. Each live handler holds
+/// a `ConnectionGuard`, which gives its slot back when dropped.
+struct ConnectionLimit {
+    max: usize,
+    active: AtomicUsize,
+}
+
+impl ConnectionLimit {
+    fn new(max: usize) -> Self {
+        Self {
+            max,
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    fn try_acquire(self: &Arc<Self>) -> Option<ConnectionGuard> {
+        let max = self.max;
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                if active < max {
+                    Some(active + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| ConnectionGuard(self.clone()))
+    }
+}
+
+struct ConnectionGuard(Arc<ConnectionLimit>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+pub struct Handler {
+    executor: Executor,
+    idle_timeout: Option<Duration>,
+    /// Set by `UseTable`.
+    default_table: Option<query::Key>,
+    /// Set by `Import` until the empty line that ends it.
+    import: Option<Import>,
+    server_timing: bool,
+    slow_request_log: Option<(Duration, Arc<dyn SlowRequestLog>)>,
+}
+
+impl Handler {
+    pub fn new(executor: Executor, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            executor,
+            idle_timeout,
+            default_table: None,
+            import: None,
+            server_timing: false,
+            slow_request_log: None,
+        }
+    }
+
+    /// Passes every request answered more than `threshold` after it was
+    /// read to `log`. Lines of an import are not requests of their own.
+    pub fn with_slow_request_log(
+        mut self,
+        threshold: Duration,
+        log: Arc<dyn SlowRequestLog>,
+    ) -> Self {
+        self.slow_request_log = Some((threshold, log));
+        self
+    }
+
+    /// Adds to every response a `server_time_us` field with the
+    /// microseconds, rounded up, from reading its request to writing it.
+    pub fn with_server_timing(mut self, enabled: bool) -> Self {
+        self.server_timing = enabled;
+        self
+    }
+
+    /// When a request read now started, if its responses are to be timed.
+    fn timing(&self) -> Option<Instant> {
+        self.server_timing.then(Instant::now)
+    }
+
+    /// Serves requests until the client disconnects or stays idle for longer
+    /// than `idle_timeout`, in which case the connection is closed.
+    pub fn handle(&mut self, stream: impl Connection) -> Result<(), anyhow::Error> {
+        stream.set_read_timeout(self.idle_timeout)?;
+        self.handle_lines(stream.try_clone()?, stream)
+    }
+
+    /// Answers each request line read from `reader` with response lines
+    /// written to `writer`, until `reader` runs out or times out. Takes any
+    /// reader and writer, such as in-memory buffers, so that requests can be
+    /// driven without a connection.
+    pub fn handle_lines(
+        &mut self,
+        reader: impl Read,
+        mut writer: impl Write,
+    ) -> Result<(), anyhow::Error> {
+        for line in BufReader::new(reader).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) if is_timeout(&err) => break,
+                Err(err) => return Err(err.into()),
+            };
+            let started = self.timing();
+            self.respond(&line, &mut |response| {
+                send_response(&mut writer, &response, started)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Answers a single request line, passing each response to `send`.
+    fn respond(
+        &mut self,
+        line: &str,
+        send: &mut dyn FnMut(query::Response) -> io::Result<()>,
+    ) -> Result<(), anyhow::Error> {
+        let (threshold, log) = match &self.slow_request_log {
+            Some((threshold, log)) if self.import.is_none() => (*threshold, log.clone()),
+            _ => return self.respond_untimed(line, send),
+        };
+        let started = Instant::now();
+        let result = self.respond_untimed(line, send);
+        let elapsed = started.elapsed();
+        if elapsed > threshold {
+            log.log(&self.slow_request(line, elapsed));
+        }
+        result
+    }
+
+    /// Only called for slow requests, so parsing the line again is fine.
+    fn slow_request(&self, line: &str, elapsed: Duration) -> SlowRequest {
+        let value = serde_json::from_str::<serde_json::Value>(line).ok();
+        let field = |name| value.as_ref()?.get(name);
+        let request_type = field("type")
+            .and_then(|request_type| request_type.as_str())
+            .map(str::to_owned);
+        let table_id = match field("table_id") {
+            Some(table_id) => serde_json::from_value(table_id.clone()).ok(),
+            None => self.default_table,
+        };
+        SlowRequest {
+            request_type,
+            table_id,
+            elapsed_us: elapsed.as_micros() as u64,
+        }
+    }
+
+    fn respond_untimed(
+        &mut self,
+        line: &str,
+        send: &mut dyn FnMut(query::Response) -> io::Result<()>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(import) = &mut self.import {
+            if !line.is_empty() {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| import.feed(line)));
+                if let Err(payload) = result {
+                    import.fail(query::Error::Internal {
+                        detail: panic_message(payload.as_ref()),
+                    });
+                }
+                return Ok(());
+            }
+            let import = self.import.take().unwrap();
+            send(import.finish())?;
+            return Ok(());
+        }
+        let request = match self.default_table {
+            Some(table_id) => query::Request::parse_with_default_table(line, table_id),
+            None => query::Request::parse(line),
+        };
+        match request {
+            Ok(query::Request::UseTable(input)) => {
+                self.default_table = Some(input.table_id);
+                send(query::Response::UseTable(query::UseTableOutput))?
+            }
+            Ok(query::Request::UseKeyEncoding(input)) => {
+                self.executor = self.executor.clone().with_key_encoding(input.encoding);
+                send(query::Response::UseKeyEncoding(query::UseKeyEncodingOutput))?
+            }
+            Ok(query::Request::Import(input)) => {
+                self.import = Some(self.executor.import(input));
+            }
+            Ok(request) => {
+                let executor = &self.executor;
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    executor.execute_streaming(request, send)
+                }));
+                match result {
+                    Ok(result) => result?,
+                    Err(payload) => send(query::Response::Error(query::Error::Internal {
+                        detail: panic_message(payload.as_ref()),
+                    }))?,
+                }
+            }
+            Err(err) => send(query::Response::Error(err))?,
+        }
+        Ok(())
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "request panicked".to_string()
+    }
+}
+
+fn send_response(
+    stream: &mut impl Write,
+    response: &query::Response,
+    started: Option<Instant>,
+) -> io::Result<()> {
+    stream.write_all(&encode_response(response, started)?)
+}
+
+/// A response along with the time the server took to produce it.
+#[derive(Serialize)]
+struct TimedResponse<'a> {
+    #[serde(flatten)]
+    response: &'a query::Response,
+    server_time_us: u64,
+}
+
+/// Serializes a response into a line, timed from `started` if given.
+fn encode_response(response: &query::Response, started: Option<Instant>) -> io::Result<Vec<u8>> {
+    // Compact JSON escapes every control character in strings, so the only
+    // newline is the one that ends the response.
+    let mut body = match started {
+        Some(started) => serde_json::to_vec(&TimedResponse {
+            response,
+            server_time_us: started.elapsed().as_nanos().div_ceil(1000) as u64,
+        })?,
+        None => serde_json::to_vec(response)?,
+    };
+    debug_assert!(!body.contains(&b'\n'));
+    body.push(b'\n');
+    Ok(body)
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    // Depending on the platform, a read timeout is reported as either kind.
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use tempfile::tempfile;
+
+    use crate::{
+        btree, buffer::BufferPool, catalog::Catalog, disk::DiskManager, encoding::KeyType,
+    };
+
+    use super::*;
+
+    fn bufmgr() -> Arc<BufferPoolManager> {
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let pool = BufferPool::new(10);
+        Arc::new(BufferPoolManager::new(disk, pool))
+    }
+
+    fn executor() -> Executor {
+        Executor::new(bufmgr())
+    }
+
+    fn request(stream: &TcpStream, request: &str) -> String {
+        (&*stream).write_all(request.as_bytes()).unwrap();
+        (&*stream).write_all(b"\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        line
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Handler::new(executor(), Some(Duration::from_millis(100))).handle(stream)
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let start = Instant::now();
+        let mut buf = vec![];
+        assert_eq!(0, client.read_to_end(&mut buf).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            max_connections: 2,
+            ..Config::default()
+        };
+        thread::spawn(move || serve(listener, bufmgr(), &config));
+
+        let flush = r#"{"type":"Flush"}"#;
+        let flushed = "{\"type\":\"Flush\"}\n";
+        let client1 = TcpStream::connect(addr).unwrap();
+        assert_eq!(flushed, request(&client1, flush));
+        let client2 = TcpStream::connect(addr).unwrap();
+        assert_eq!(flushed, request(&client2, flush));
+
+        let mut client3 = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        client3.read_to_string(&mut response).unwrap();
+        assert_eq!(
+            "{\"type\":\"Error\",\"error\":\"TooManyConnections\"}\n",
+            response
+        );
+
+        assert_eq!(flushed, request(&client1, flush));
+        drop(client2);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let client4 = TcpStream::connect(addr).unwrap();
+            if request(&client4, flush) == flushed {
+                break;
+            }
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Fails to accept the first few connections, like a server out of file
+    /// descriptors.
+    struct FlakyListener {
+        listener: TcpListener,
+        failures: AtomicUsize,
+    }
+
+    impl Listener for FlakyListener {
+        type Connection = TcpStream;
+
+        fn accept(&self) -> io::Result<TcpStream> {
+            let stream = self.listener.accept()?.0;
+            if self.failures.load(Ordering::Relaxed) > 0 {
+                self.failures.fetch_sub(1, Ordering::Relaxed);
+                return Err(io::Error::other("too many open files"));
+            }
+            Ok(stream)
+        }
+    }
+
+    #[test]
+    fn test_accept_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = FlakyListener {
+            listener,
+            failures: AtomicUsize::new(2),
+        };
+        thread::spawn(move || serve(listener, bufmgr(), &Config::default()));
+
+        for _ in 0..2 {
+            let mut client = TcpStream::connect(addr).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert_eq!("", response);
+        }
+        let client = TcpStream::connect(addr).unwrap();
+        assert_eq!(
+            "{\"type\":\"Flush\"}\n",
+            request(&client, r#"{"type":"Flush"}"#)
+        );
+    }
+
+    #[test]
+    fn test_server_timing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            server_timing: true,
+            ..Config::default()
+        };
+        thread::spawn(move || serve(listener, bufmgr(), &config));
+
+        let client = TcpStream::connect(addr).unwrap();
+        for (line, response_type) in [(r#"{"type":"Flush"}"#, "Flush"), ("{", "Error")] {
+            let response: serde_json::Value =
+                serde_json::from_str(&request(&client, line)).unwrap();
+            assert_eq!(response_type, response["type"]);
+            assert!(response["server_time_us"].as_u64().unwrap() > 0);
+        }
+    }
+
+    #[test]
+    fn test_slow_request_log() {
+        #[derive(Default)]
+        struct Collect(parking_lot::Mutex<Vec<SlowRequest>>);
+        impl SlowRequestLog for Collect {
+            fn log(&self, request: &SlowRequest) {
+                self.0.lock().push(request.clone());
+            }
+        }
+
+        let bufmgr = bufmgr();
+        let log = Arc::new(Collect::default());
+        let mut handler = Handler::new(Executor::new(bufmgr.clone()), None)
+            .with_slow_request_log(Duration::from_millis(20), log.clone());
+        let mut respond = |line: &str| {
+            let mut responses = vec![];
+            handler
+                .respond(line, &mut |response| {
+                    responses.push(response);
+                    Ok(())
+                })
+                .unwrap();
+            responses
+        };
+        let (_, buffer) = bufmgr.create_page().unwrap();
+        // A sync flush waits for the latched dirty page.
+        let mut rw_buffer = buffer.write();
+        rw_buffer.mark_dirty();
+        thread::scope(|s| {
+            let flush = s.spawn(|| respond(r#"{"type":"Flush","mode":"Sync"}"#));
+            thread::sleep(Duration::from_millis(50));
+            drop(rw_buffer);
+            flush.join().unwrap();
+        });
+        respond(r#"{"type":"Ping"}"#);
+
+        let logged = log.0.lock();
+        assert_eq!(1, logged.len());
+        assert_eq!(Some("Flush"), logged[0].request_type.as_deref());
+        assert_eq!(None, logged[0].table_id);
+        assert!(logged[0].elapsed_us >= 20_000);
+    }
+
+    #[test]
+    fn test_ping() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Any page access would fail with an empty pool.
+        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+        let bufmgr = Arc::new(BufferPoolManager::new(disk, BufferPool::new(0)));
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Handler::new(Executor::new(bufmgr), None).handle(stream)
+        });
+
+        let client = TcpStream::connect(addr).unwrap();
+        let response: serde_json::Value =
+            serde_json::from_str(&request(&client, r#"{"type":"Ping"}"#)).unwrap();
+        assert_eq!(
+            serde_json::json!({ "type": "Ping", "version": env!("CARGO_PKG_VERSION") }),
+            response
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qp.sock");
+        // Left behind by a server that is gone.
+        drop(UnixListener::bind(&path).unwrap());
+        let listener = bind_unix(&path).unwrap();
+        assert_eq!(
+            io::ErrorKind::AddrInUse,
+            bind_unix(&path).unwrap_err().kind()
+        );
+        let not_socket = dir.path().join("qp.data");
+        std::fs::write(&not_socket, b"data").unwrap();
+        assert!(bind_unix(&not_socket).is_err());
+        assert_eq!(b"data", &std::fs::read(&not_socket).unwrap()[..]);
+        let bufmgr = bufmgr();
+        Catalog::create(&bufmgr).unwrap();
+        thread::spawn(move || serve(listener, bufmgr, &Config::default()));
+
+        let client = UnixStream::connect(&path).unwrap();
+        let mut buf_read = BufReader::new(client.try_clone().unwrap());
+        let mut request = |request: &str| {
+            (&client).write_all(request.as_bytes()).unwrap();
+            (&client).write_all(b"\n").unwrap();
+            let mut line = String::new();
+            buf_read.read_line(&mut line).unwrap();
+            serde_json::from_str::<serde_json::Value>(&line).unwrap()
+        };
+        assert_eq!(
+            serde_json::json!("Ping"),
+            request(r#"{"type":"Ping"}"#)["type"]
+        );
+        assert_eq!(
+            serde_json::json!({ "type": "CreateTable" }),
+            request(r#"{"type":"CreateTable","table_id":"0000000000000001"}"#)
+        );
+        assert_eq!(
+            serde_json::json!({ "type": "TableExists", "exists": true }),
+            request(r#"{"type":"TableExists","table_id":"0000000000000001"}"#)
+        );
+    }
+
+    #[test]
+    fn test_control_characters() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bufmgr = bufmgr();
+        Catalog::create(&bufmgr).unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Handler::new(Executor::new(bufmgr), None).handle(stream)
+        });
+
+        let client = TcpStream::connect(addr).unwrap();
+        let value = "line 1\nline 2\r\n\r\u{0}\u{1f}\t\"end\"\\";
+        let table_id = "0000000000000001";
+        request(
+            &client,
+            &serde_json::json!({ "type": "CreateTable", "table_id": table_id }).to_string(),
+        );
+        let item = serde_json::json!({ "key": "0000000000000001", "value": value });
+        let put = serde_json::json!({ "type": "PutItem", "table_id": table_id, "item": item });
+        assert_eq!(
+            "{\"type\":\"PutItem\",\"created\":true}\n",
+            request(&client, &put.to_string())
+        );
+
+        let scan = serde_json::json!({ "type": "ScanItem", "table_id": table_id });
+        let line = request(&client, &scan.to_string());
+        assert_eq!(Some(line.len() - 1), line.find('\n'));
+        assert!(!line.contains('\r'));
+        let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(serde_json::json!(value), response["items"][0]["value"]);
+    }
+
+    #[test]
+    fn test_scan_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bufmgr = bufmgr();
+        Catalog::create(&bufmgr).unwrap();
+        let table = Catalog::open(&bufmgr)
+            .create_table(*b"streamed", KeyType::U64, false, false)
+            .unwrap();
+        let table_access = btree::Access::open(&bufmgr, table.btree_page_id);
+        for key in 0..5000u64 {
+            table_access.put(key.to_be_bytes(), &[b'x'; 100]).unwrap();
+        }
+        // Far fewer frames than the table has pages, so the rows can't have
+        // been gathered in the pool before being sent.
+        assert!(table_access.leaf_count().unwrap() > 10);
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Handler::new(Executor::new(bufmgr), None).handle(stream)
+        });
+
+        let client = TcpStream::connect(addr).unwrap();
+        let mut buf_read = BufReader::new(client.try_clone().unwrap());
+        let mut scan = |request: serde_json::Value| {
+            (&client)
+                .write_all(format!("{}\n", request).as_bytes())
+                .unwrap();
+            let mut keys = vec![];
+            loop {
+                let mut line = String::new();
+                buf_read.read_line(&mut line).unwrap();
+                let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+                match response["type"].as_str().unwrap() {
+                    "ScanRow" => keys.push(response["key"].as_u64().unwrap()),
+                    "ScanEnd" => {
+                        assert_eq!(keys.len() as u64, response["count"].as_u64().unwrap());
+                        return keys;
+                    }
+                    _ => panic!("unexpected response: {}", response),
+                }
+            }
+        };
+        let table_id = u64::from_be_bytes(*b"streamed");
+        let keys = scan(serde_json::json!({ "type": "ScanStream", "table_id": table_id }));
+        assert_eq!((0..5000).collect::<Vec<_>>(), keys);
+        let keys = scan(serde_json::json!({
+            "type": "ScanStream",
+            "table_id": table_id,
+            "start": 4000,
+            "backward": true,
+            "limit": 3,
+        }));
+        assert_eq!(vec![4000, 3999, 3998], keys);
+
+        let missing = serde_json::json!({ "type": "ScanStream", "table_id": 1 });
+        assert_eq!(
+            "{\"type\":\"Error\",\"error\":\"NoSuchTable\"}\n",
+            request(&client, &missing.to_string())
+        );
+    }
+
+    #[test]
+    fn test_subscribe() {
+        use serde_json::json;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bufmgr = bufmgr();
+        Catalog::create(&bufmgr).unwrap();
+        let executor = Executor::new(bufmgr);
+        thread::spawn(move || loop {
+            let (stream, _) = listener.accept().unwrap();
+            let executor = executor.clone();
+            thread::spawn(move || Handler::new(executor, None).handle(stream));
+        });
+        let subscriber = TcpStream::connect(addr).unwrap();
+        let writer = TcpStream::connect(addr).unwrap();
+        let send = |stream: &TcpStream, body: serde_json::Value| {
+            let response = request(stream, &body.to_string());
+            serde_json::from_str::<serde_json::Value>(&response).unwrap()
+        };
+        let table_id = "0000000000000001";
+        assert_eq!(
+            json!({ "type": "Error", "error": "NoSuchTable" }),
+            send(
+                &subscriber,
+                json!({ "type": "Subscribe", "table_id": table_id })
+            )
+        );
+        send(
+            &writer,
+            json!({ "type": "CreateTable", "table_id": table_id }),
+        );
+        assert_eq!(
+            json!({ "type": "Subscribe" }),
+            send(
+                &subscriber,
+                json!({ "type": "Subscribe", "table_id": table_id })
+            )
+        );
+
+        let key = "0000000000000002";
+        let item = json!({ "key": key, "value": "hello" });
+        send(
+            &writer,
+            json!({ "type": "PutItem", "table_id": table_id, "item": item }),
+        );
+        send(
+            &writer,
+            json!({ "type": "DeleteItem", "table_id": table_id, "key": key }),
+        );
+        let mut buf_read = BufReader::new(&subscriber);
+        let mut changes = vec![];
+        for _ in 0..2 {
+            let mut line = String::new();
+            buf_read.read_line(&mut line).unwrap();
+            changes.push(serde_json::from_str::<serde_json::Value>(&line).unwrap());
+        }
+        assert_eq!(
+            vec![
+                json!({ "type": "Change", "op": "Put", "key": key }),
+                json!({ "type": "Change", "op": "Delete", "key": key }),
+            ],
+            changes
+        );
+    }
+
+    #[test]
+    fn test_use_table() {
+        use serde_json::json;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bufmgr = bufmgr();
+        Catalog::create(&bufmgr).unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Handler::new(Executor::new(bufmgr), None).handle(stream)
+        });
+
+        let client = TcpStream::connect(addr).unwrap();
+        let execute = |body: serde_json::Value| {
+            let line = request(&client, &body.to_string());
+            serde_json::from_str::<serde_json::Value>(&line).unwrap()
+        };
+        for table_id in [1, 2] {
+            execute(json!({ "type": "CreateTable", "table_id": table_id }));
+        }
+        let item = json!({ "key": "0000000000000001", "value": "in table 2" });
+        let get = json!({ "type": "GetItem", "key": "0000000000000001" });
+        assert_eq!(json!("BadRequest"), execute(get.clone())["error"]);
+
+        assert_eq!(
+            json!({ "type": "UseTable" }),
+            execute(json!({ "type": "UseTable", "table_id": 2 }))
+        );
+        execute(json!({ "type": "PutItem", "item": item }));
+        assert_eq!(item, execute(get)["item"]);
+        let get_1 = json!({ "type": "GetItem", "table_id": 1, "key": "0000000000000001" });
+        assert_eq!(json!(null), execute(get_1)["item"]);
+        assert_eq!(json!(1), execute(json!({ "type": "CountItem" }))["count"]);
+    }
+
+    #[test]
+    fn test_key_encoding() {
+        use serde_json::json;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bufmgr = bufmgr();
+        Catalog::create(&bufmgr).unwrap();
+        thread::spawn(move || loop {
+            let (stream, _) = listener.accept().unwrap();
+            let executor = Executor::new(bufmgr.clone());
+            thread::spawn(move || Handler::new(executor, None).handle(stream));
+        });
+
+        let compact = TcpStream::connect(addr).unwrap();
+        let hex = TcpStream::connect(addr).unwrap();
+        let execute = |client: &TcpStream, body: serde_json::Value| {
+            let line = request(client, &body.to_string());
+            serde_json::from_str::<serde_json::Value>(&line).unwrap()
+        };
+        let keys: [btree::Key; 3] = [
+            [0, 1, 2, 3, 4, 5, 6, 7],
+            [0xFB, 0xFF, 0xBF, 0x00, 0x10, 0x83, 0x10, 0x51],
+            [0xFF; 8],
+        ];
+        execute(&hex, json!({ "type": "CreateTable", "table_id": 1 }));
+        for key in keys {
+            let item = json!({ "key": hex::encode_upper(key), "value": "v" });
+            let put = json!({ "type": "PutItem", "table_id": 1, "item": item });
+            assert_eq!("PutItem", execute(&hex, put)["type"]);
+        }
+
+        assert_eq!(
+            json!({ "type": "UseKeyEncoding" }),
+            execute(
+                &compact,
+                json!({ "type": "UseKeyEncoding", "encoding": "Base64" })
+            )
+        );
+        let scan = json!({ "type": "ScanItem", "table_id": 1, "limit": 2 });
+        let page = execute(&compact, scan);
+        let base64 = json!(["AAECAwQFBgc=", "+/+/ABCDEFE="]);
+        let scanned = page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["key"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(base64, json!(scanned));
+        for (key, text) in keys.iter().zip(scanned) {
+            let text = serde_json::from_value(text).unwrap();
+            assert_eq!(*key, KeyType::Raw.encode(&text).unwrap());
+        }
+        // Keys are accepted back as they were written.
+        let rest = json!({ "type": "ScanItem", "table_id": 1, "start": "+/+/ABCDEFE=" });
+        assert_eq!(
+            json!("//////////8="),
+            execute(&compact, rest)["items"][1]["key"]
+        );
+
+        // Other connections keep hex.
+        let scan = json!({ "type": "ScanItem", "table_id": 1, "limit": 1 });
+        assert_eq!(
+            json!("0001020304050607"),
+            execute(&hex, scan)["items"][0]["key"]
+        );
+    }
+
+    #[test]
+    fn test_import() {
+        use serde_json::json;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bufmgr = bufmgr();
+        Catalog::create(&bufmgr).unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Handler::new(Executor::new(bufmgr), None).handle(stream)
+        });
+
+        let client = TcpStream::connect(addr).unwrap();
+        let execute = |body: serde_json::Value| {
+            let line = request(&client, &body.to_string());
+            serde_json::from_str::<serde_json::Value>(&line).unwrap()
+        };
+        let import = |abort_on_malformed: bool, lines: &[String]| {
+            let input = json!({
+                "type": "Import",
+                "table_id": 1,
+                "abort_on_malformed": abort_on_malformed,
+            });
+            (&client)
+                .write_all(format!("{}\n", input).as_bytes())
+                .unwrap();
+            for line in lines {
+                (&client)
+                    .write_all(format!("{}\n", line).as_bytes())
+                    .unwrap();
+            }
+            let line = request(&client, "");
+            serde_json::from_str::<serde_json::Value>(&line).unwrap()
+        };
+        let item = |i: u64| json!({ "key": format!("{:016X}", i), "value": i.to_string() });
+        execute(json!({ "type": "CreateTable", "table_id": 1 }));
+        // Sorted, then going back over some of the same keys.
+        let mut lines = (0..300).map(|i| item(i).to_string()).collect::<Vec<_>>();
+        lines.push("not an item".to_string());
+        lines.extend((0..100).rev().map(|i| item(i * 3).to_string()));
+        assert_eq!(
+            json!({ "type": "Import", "imported": 400, "appended": 300, "skipped": 1 }),
+            import(false, &lines)
+        );
+        assert_eq!(
+            json!(300),
+            execute(json!({ "type": "CountItem", "table_id": 1 }))["count"]
+        );
+        let get = json!({ "type": "GetItem", "table_id": 1, "key": format!("{:016X}", 150) });
+        let response = execute(get);
+        assert_eq!(item(150), response["item"]);
+        assert_eq!(json!(2), response["version"]);
+
+        let lines = vec![
+            item(300).to_string(),
+            "{}".to_string(),
+            item(301).to_string(),
+        ];
+        let response = import(true, &lines);
+        assert_eq!(json!("BadRequest"), response["error"]);
+        assert_eq!(
+            json!(301),
+            execute(json!({ "type": "CountItem", "table_id": 1 }))["count"]
+        );
+    }
+
+    #[test]
+    fn test_panic_in_request() {
+        use serde_json::json;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bufmgr = bufmgr();
+        Catalog::create(&bufmgr).unwrap();
+        let server_bufmgr = bufmgr.clone();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Handler::new(Executor::new(server_bufmgr), None).handle(stream)
+        });
+
+        let client = TcpStream::connect(addr).unwrap();
+        let execute = |body: serde_json::Value| {
+            let line = request(&client, &body.to_string());
+            serde_json::from_str::<serde_json::Value>(&line).unwrap()
+        };
+        for table_id in [1, 2] {
+            execute(json!({ "type": "CreateTable", "table_id": table_id }));
+            let item = json!({ "key": "0000000000000001", "value": "value" });
+            execute(json!({ "type": "PutItem", "table_id": table_id, "item": item }));
+        }
+        // Gives the leaf of table 1 an unknown node type.
+        let table: btree::Access = Catalog::open(&bufmgr)
+            .open_table(1u64.to_be_bytes())
+            .unwrap()
+            .unwrap();
+        let leaf_page_id = table.leaf_stats(1u64.to_be_bytes()).unwrap().page_id;
+        bufmgr.fetch_page(leaf_page_id).unwrap().write().page[0] = 0xEE;
+
+        let get = |table_id: u64| {
+            execute(json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" }))
+        };
+        assert_eq!(json!("Internal"), get(1)["error"]);
+        assert_eq!(json!("value"), get(2)["item"]["value"]);
+    }
+
+    #[test]
+    fn test_handle_lines() {
+        let bufmgr = bufmgr();
+        Catalog::create(&bufmgr).unwrap();
+        let mut handler = Handler::new(Executor::new(bufmgr), None);
+        let exchanges = [
+            (
+                r#"{"type":"CreateTable","table_id":"0000000000000001","key_type":"U64"}"#,
+                r#"{"type":"CreateTable"}"#,
+            ),
+            (
+                r#"{"type":"UseTable","table_id":"0000000000000001"}"#,
+                r#"{"type":"UseTable"}"#,
+            ),
+            (
+                r#"{"type":"PutItem","item":{"key":1,"value":"one"}}"#,
+                r#"{"type":"PutItem","created":true}"#,
+            ),
+            (
+                r#"{"type":"GetItem","key":1}"#,
+                r#"{"type":"GetItem","item":{"key":1,"value":"one"},"version":1}"#,
+            ),
+            (
+                r#"{"type":"GetItem","key":2}"#,
+                r#"{"type":"GetItem","item":null}"#,
+            ),
+            (
+                r#"{"type":"GetItem","table_id":"0000000000000002","key":1}"#,
+                r#"{"type":"Error","error":"NoSuchTable"}"#,
+            ),
+            (
+                "nonsense",
+                r#"{"type":"Error","error":"BadRequest","detail":"expected ident at line 1 column 2"}"#,
+            ),
+        ];
+        let requests = exchanges.map(|(request, _)| request).join("\n");
+        let mut output = vec![];
+        handler
+            .handle_lines(io::Cursor::new(requests), &mut output)
+            .unwrap();
+        let responses = exchanges.map(|(_, response)| format!("{}\n", response));
+        assert_eq!(responses.concat(), String::from_utf8(output).unwrap());
+    }
+}
@@ -0,0 +1,147 @@
+use std::{io, sync::Arc};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task, time,
+};
+
+use super::{encode_response, Config, ConnectionLimit, Handler, ACCEPT_RETRY_DELAY};
+use crate::{buffer::BufferPoolManager, query};
+
+/// Responses a request may queue up before its thread waits for them to be
+/// written.
+const RESPONSE_QUEUE: usize = 64;
+
+/// Like `server::serve`, but each connection is a task of the current tokio
+/// runtime rather than a thread. Requests still run on the runtime's
+/// blocking threads, as the storage engine blocks.
+pub async fn serve(
+    listener: TcpListener,
+    bufmgr: Arc<BufferPoolManager>,
+    config: &Config,
+) -> Result<(), anyhow::Error> {
+    let limit = Arc::new(ConnectionLimit::new(config.max_connections));
+    let executor = config.executor(bufmgr);
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                eprintln!("failed to accept a connection: {}", err);
+                time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let guard = match limit.try_acquire() {
+            Some(guard) => guard,
+            None => {
+                let response = query::Response::Error(query::Error::TooManyConnections);
+                // The client is turned away either way, so write failures are
+                // ignored.
+                let _ = stream.write_all(&encode_response(&response, None)?).await;
+                continue;
+            }
+        };
+        let handler = config.handler(executor.clone());
+        tokio::spawn(async move {
+            let _guard = guard;
+            handle(handler, stream).await
+        });
+    }
+}
+
+/// Serves requests until the client disconnects or stays idle for longer
+/// than the handler's `idle_timeout`. The handler is moved to a blocking
+/// thread for each request and handed back once it is answered.
+async fn handle(mut handler: Handler, stream: TcpStream) -> Result<(), anyhow::Error> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let next_line = lines.next_line();
+        let line = match handler.idle_timeout {
+            Some(idle_timeout) => match time::timeout(idle_timeout, next_line).await {
+                Ok(line) => line?,
+                Err(_) => break,
+            },
+            None => next_line.await?,
+        };
+        let line = match line {
+            Some(line) => line,
+            None => break,
+        };
+        let started = handler.timing();
+        let (tx, mut rx) = mpsc::channel(RESPONSE_QUEUE);
+        let responding = task::spawn_blocking(move || {
+            let result = handler.respond(&line, &mut |response| {
+                tx.blocking_send(response)
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+            });
+            (handler, result)
+        });
+        while let Some(response) = rx.recv().await {
+            writer
+                .write_all(&encode_response(&response, started)?)
+                .await?;
+        }
+        let (returned, result) = responding.await?;
+        handler = returned;
+        result?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tempfile::tempfile;
+    use tokio::{
+        io::Lines,
+        net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+    };
+
+    use crate::{buffer::BufferPool, catalog::Catalog, disk::DiskManager};
+
+    use super::*;
+
+    async fn request(
+        writer: &mut OwnedWriteHalf,
+        lines: &mut Lines<BufReader<OwnedReadHalf>>,
+        body: serde_json::Value,
+    ) -> serde_json::Value {
+        writer
+            .write_all(format!("{}\n", body).as_bytes())
+            .await
+            .unwrap();
+        let line = lines.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn test() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let disk = DiskManager::new(tempfile().unwrap()).unwrap();
+            let bufmgr = Arc::new(BufferPoolManager::new(disk, BufferPool::new(10)));
+            Catalog::create(&bufmgr).unwrap();
+            tokio::spawn(async move { serve(listener, bufmgr, &Config::default()).await });
+
+            let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let table_id = "0000000000000001";
+            let create = json!({ "type": "CreateTable", "table_id": table_id });
+            assert_eq!(
+                json!({ "type": "CreateTable" }),
+                request(&mut writer, &mut lines, create).await
+            );
+            let item = json!({ "key": "0000000000000001", "value": "hello" });
+            let put = json!({ "type": "PutItem", "table_id": table_id, "item": item });
+            request(&mut writer, &mut lines, put).await;
+            let get = json!({ "type": "GetItem", "table_id": table_id, "key": "0000000000000001" });
+            let response = request(&mut writer, &mut lines, get).await;
+            assert_eq!(json!("hello"), response["item"]["value"]);
+        });
+    }
+}
@@ -5,6 +5,18 @@ use std::{
 
 use zerocopy::{AsBytes, ByteSlice, ByteSliceMut, FromBytes, LayoutVerified};
 
+use crate::disk::PAGE_SIZE;
+
+/// Largest body whose offsets and lengths fit in the `u16`s of `Header` and
+/// `Pointer`.
+pub const MAX_CAPACITY: usize = u16::MAX as usize;
+
+// A page with a larger body would have its offsets silently wrap around.
+const _: () = assert!(
+    PAGE_SIZE <= MAX_CAPACITY,
+    "pages are too large for u16 offsets"
+);
+
 #[derive(Debug, FromBytes, AsBytes)]
 #[repr(C)]
 pub struct Header {
@@ -35,8 +47,13 @@ pub struct Slotted<B> {
 }
 
 impl<B: ByteSlice> Slotted<B> {
+    /// Returns `None` if `bytes` can't hold the header, or if the body would
+    /// be larger than `MAX_CAPACITY`.
     pub fn new(bytes: B) -> Option<Self> {
         let (header, body) = LayoutVerified::new_from_prefix(bytes)?;
+        if body.len() > MAX_CAPACITY {
+            return None;
+        }
         Some(Self { header, body })
     }
 
@@ -63,12 +80,60 @@ impl<B: ByteSlice> Slotted<B> {
     fn data(&self, pointer: Pointer) -> &[u8] {
         &self.body[pointer.range()]
     }
+
+    /// Panics unless the pointers end before the payload and the records
+    /// fill the payload exactly, catching a miscalculated offset before it
+    /// lets one region overwrite the other.
+    pub fn check_layout(&self) {
+        if let Some(problem) = self.layout_problem() {
+            panic!("{}", problem);
+        }
+    }
+
+    /// What `check_layout` would panic with, for pages read from disk that
+    /// may be damaged.
+    pub fn layout_problem(&self) -> Option<String> {
+        let free_space_offset = self.header.free_space_offset as usize;
+        if self.pointers_size() > free_space_offset || free_space_offset > self.body.len() {
+            return Some(format!(
+                "pointers end at {} past the payload at {}",
+                self.pointers_size(),
+                free_space_offset
+            ));
+        }
+        let mut payload_len = 0;
+        for (index, pointer) in self.pointers().iter().enumerate() {
+            let range = pointer.range();
+            if free_space_offset > range.start || range.end > self.body.len() {
+                return Some(format!(
+                    "slot {} at {:?} is outside the payload at {}..{}",
+                    index,
+                    range,
+                    free_space_offset,
+                    self.body.len()
+                ));
+            }
+            payload_len += range.len();
+        }
+        if self.body.len() - free_space_offset != payload_len {
+            return Some("records don't fill the payload".to_string());
+        }
+        None
+    }
+
+    /// `check_layout`, in debug builds only, as it walks every pointer.
+    fn debug_check_layout(&self) {
+        if cfg!(debug_assertions) {
+            self.check_layout();
+        }
+    }
 }
 
 impl<B: ByteSliceMut> Slotted<B> {
     pub fn initialize(&mut self) {
         self.header.num_slots = 0;
         self.header.free_space_offset = self.body.len() as u16;
+        self.debug_check_layout();
     }
 
     fn pointers_mut(&mut self) -> Pointers<&mut [u8]> {
@@ -81,7 +146,8 @@ impl<B: ByteSliceMut> Slotted<B> {
     }
 
     pub fn reverse(&mut self) {
-        self.pointers_mut().reverse()
+        self.pointers_mut().reverse();
+        self.debug_check_layout();
     }
 
     pub fn insert(&mut self, index: usize, len: usize) -> Option<()> {
@@ -97,14 +163,15 @@ impl<B: ByteSliceMut> Slotted<B> {
         let pointer = &mut pointers_mut[index];
         pointer.offset = free_space_offset;
         pointer.len = len as u16;
+        self.debug_check_layout();
         Some(())
     }
 
     pub fn remove(&mut self, index: usize) {
         self.resize(index, 0);
-        self.pointers_mut()
-            .copy_within(index + 1.., index);
+        self.pointers_mut().copy_within(index + 1.., index);
         self.header.num_slots -= 1;
+        self.debug_check_layout();
     }
 
     pub fn resize(&mut self, index: usize, len_new: usize) -> Option<()> {
@@ -122,10 +189,16 @@ impl<B: ByteSliceMut> Slotted<B> {
         let shift_range = free_space_offset..offset_orig as usize;
         let free_space_offset_new = (free_space_offset as isize - len_incr) as usize;
         self.header.free_space_offset = free_space_offset_new as u16;
-        self.body.as_bytes_mut().copy_within(shift_range, free_space_offset_new);
+        self.body
+            .as_bytes_mut()
+            .copy_within(shift_range, free_space_offset_new);
         let mut pointers_mut = self.pointers_mut();
-        for pointer in pointers_mut.iter_mut() {
-            if pointer.offset <= offset_orig {
+        for (i, pointer) in pointers_mut.iter_mut().enumerate() {
+            // An empty record may share its offset with the next record,
+            // which stays where it is.
+            let moved = pointer.offset < offset_orig
+                || pointer.offset == offset_orig && (i == index || pointer.len == 0);
+            if moved {
                 pointer.offset = (pointer.offset as isize - len_incr) as u16;
             }
         }
@@ -134,6 +207,7 @@ impl<B: ByteSliceMut> Slotted<B> {
         if len_new == 0 {
             pointer.offset = free_space_offset_new as u16;
         }
+        self.debug_check_layout();
         Some(())
     }
 }
@@ -154,6 +228,8 @@ impl<B: ByteSliceMut> IndexMut<usize> for Slotted<B> {
 
 #[cfg(test)]
 mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
     use super::*;
 
     #[test]
@@ -165,7 +241,7 @@ mod tests {
             slotted[index].copy_from_slice(buf);
         };
         let push = |slotted: &mut Slotted<&mut [u8]>, buf: &[u8]| {
-            let index = slotted.num_slots() as usize;
+            let index = slotted.num_slots();
             insert(slotted, index, buf);
         };
         slotted.initialize();
@@ -180,4 +256,128 @@ mod tests {
         assert_eq!(&slotted[2], b"world");
         assert_eq!(&slotted[3], b"!");
     }
+
+    #[test]
+    fn test_max_capacity() {
+        let mut page_data = vec![0u8; size_of::<Header>() + MAX_CAPACITY];
+        let mut slotted = Slotted::new(page_data.as_mut_slice()).unwrap();
+        slotted.initialize();
+        assert_eq!(MAX_CAPACITY, slotted.capacity());
+        let len = MAX_CAPACITY - size_of::<Pointer>();
+        assert_eq!(len + size_of::<Pointer>(), slotted.free_space());
+        slotted.insert(0, len).unwrap();
+        slotted[0].fill(0xAB);
+        assert_eq!(len, slotted[0].len());
+        assert_eq!(0, slotted.free_space());
+
+        let mut page_data = vec![0u8; size_of::<Header>() + MAX_CAPACITY + 1];
+        assert!(Slotted::new(page_data.as_mut_slice()).is_none());
+    }
+
+    #[test]
+    fn test_layout_near_full() {
+        let mut page_data = vec![0u8; 128];
+        let mut slotted = Slotted::new(page_data.as_mut_slice()).unwrap();
+        slotted.initialize();
+        slotted.check_layout();
+        let mut len = 1;
+        while slotted.insert(slotted.num_slots() / 2, len).is_some() {
+            slotted.check_layout();
+            len = len % 7 + 1;
+        }
+        assert!(slotted.free_space() < size_of::<Pointer>() + len);
+        // Grows the middle record into the last bytes, then shrinks it back.
+        let index = slotted.num_slots() / 2;
+        let len_orig = slotted[index].len();
+        let len_full = len_orig + slotted.free_space();
+        assert!(slotted.resize(index, len_full + 1).is_none());
+        slotted.check_layout();
+        slotted.resize(index, len_full).unwrap();
+        slotted.check_layout();
+        assert_eq!(0, slotted.free_space());
+        slotted.resize(index, 0).unwrap();
+        slotted.check_layout();
+        slotted.reverse();
+        slotted.check_layout();
+        while slotted.num_slots() > 0 {
+            slotted.remove(slotted.num_slots() / 2);
+            slotted.check_layout();
+        }
+        assert_eq!(slotted.capacity(), slotted.free_space());
+    }
+
+    #[test]
+    #[should_panic(expected = "past the payload")]
+    fn test_layout_overlap() {
+        let mut page_data = vec![0u8; 128];
+        let mut slotted = Slotted::new(page_data.as_mut_slice()).unwrap();
+        slotted.initialize();
+        for _ in 0..4 {
+            slotted.insert(0, 8).unwrap();
+        }
+        // As if the payload had been miscalculated to start among the
+        // pointers.
+        slotted.header.free_space_offset = 8;
+        slotted.check_layout();
+    }
+
+    #[test]
+    fn test_random_ops() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut page_data = vec![0u8; 512];
+        let mut slotted = Slotted::new(page_data.as_mut_slice()).unwrap();
+        slotted.initialize();
+        // What each slot should read back as.
+        let mut model: Vec<Vec<u8>> = vec![];
+        let random_bytes = |rng: &mut StdRng, len| (0..len).map(|_| rng.gen()).collect();
+        for _ in 0..20_000 {
+            match rng.gen_range(0, 10) {
+                0..=3 => {
+                    let index = rng.gen_range(0, model.len() + 1);
+                    let len = rng.gen_range(0, 64);
+                    let free_space = slotted.free_space();
+                    if slotted.insert(index, len).is_none() {
+                        assert!(free_space < size_of::<Pointer>() + len);
+                        continue;
+                    }
+                    let data: Vec<u8> = random_bytes(&mut rng, len);
+                    slotted[index].copy_from_slice(&data);
+                    model.insert(index, data);
+                }
+                4..=5 if !model.is_empty() => {
+                    let index = rng.gen_range(0, model.len());
+                    slotted.remove(index);
+                    model.remove(index);
+                }
+                6..=8 if !model.is_empty() => {
+                    let index = rng.gen_range(0, model.len());
+                    let len_orig = model[index].len();
+                    let len_new = rng.gen_range(0, 96);
+                    let free_space = slotted.free_space();
+                    if slotted.resize(index, len_new).is_none() {
+                        assert!(len_new > len_orig + free_space);
+                    } else if len_new < len_orig {
+                        // A shrunk record keeps the end of its bytes.
+                        model[index].drain(..len_orig - len_new);
+                    } else {
+                        // A grown one keeps them at its end, after bytes
+                        // that are filled in here.
+                        let head: Vec<u8> = random_bytes(&mut rng, len_new - len_orig);
+                        slotted[index][..head.len()].copy_from_slice(&head);
+                        model[index].splice(..0, head);
+                    }
+                }
+                9 => {
+                    slotted.reverse();
+                    model.reverse();
+                }
+                _ => continue,
+            }
+            slotted.check_layout();
+            assert_eq!(model.len(), slotted.num_slots());
+            for (index, data) in model.iter().enumerate() {
+                assert_eq!(&data[..], &slotted[index]);
+            }
+        }
+    }
 }
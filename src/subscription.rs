@@ -0,0 +1,135 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+    },
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    btree::{self, Mutation, MutationObserver},
+    encoding::{KeyEncoding, KeyType},
+    query::{self, Change},
+};
+
+/// Changes a subscriber may fall behind by before it is dropped, so that a
+/// slow one never holds up writers.
+pub const SUBSCRIBER_QUEUE: usize = 1024;
+
+/// A table, by namespace and id.
+type TableRef = (Option<query::Key>, btree::Key);
+
+struct Subscriber {
+    sender: SyncSender<Change>,
+    key_type: KeyType,
+    key_encoding: KeyEncoding,
+}
+
+/// The connections subscribed to each table, shared by every clone of an
+/// executor.
+#[derive(Default)]
+pub struct Subscribers {
+    tables: Mutex<HashMap<TableRef, Vec<Subscriber>>>,
+    /// Lets writes skip the lock while no one is subscribed.
+    count: AtomicUsize,
+}
+
+impl Subscribers {
+    /// Returns the changes made to `table` from now on, with keys decoded
+    /// as `key_type` and written in `key_encoding`. The receiver is
+    /// disconnected once it falls `SUBSCRIBER_QUEUE` changes behind.
+    pub fn subscribe(
+        &self,
+        table: TableRef,
+        key_type: KeyType,
+        key_encoding: KeyEncoding,
+    ) -> Receiver<Change> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+        let mut tables = self.tables.lock();
+        tables.entry(table).or_default().push(Subscriber {
+            sender,
+            key_type,
+            key_encoding,
+        });
+        self.count.fetch_add(1, Ordering::Relaxed);
+        receiver
+    }
+
+    /// Reports the mutations made to `table` to its subscribers.
+    pub fn observer(&self, table: TableRef) -> TableObserver<'_> {
+        TableObserver {
+            subscribers: self,
+            table,
+        }
+    }
+}
+
+pub struct TableObserver<'a> {
+    subscribers: &'a Subscribers,
+    table: TableRef,
+}
+
+impl MutationObserver for TableObserver<'_> {
+    fn on_mutation(&self, mutation: Mutation<'_>) {
+        if self.subscribers.count.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut tables = self.subscribers.tables.lock();
+        let subscribers = match tables.get_mut(&self.table) {
+            Some(subscribers) => subscribers,
+            None => return,
+        };
+        let before = subscribers.len();
+        // Called under page latches, so a full queue drops the subscriber
+        // rather than waiting for it.
+        subscribers.retain(|subscriber| {
+            let decode = |key| {
+                subscriber
+                    .key_type
+                    .decode_with(key, subscriber.key_encoding)
+            };
+            let change = match mutation {
+                Mutation::Put { key, .. } => Change::Put { key: decode(key) },
+                Mutation::Delete { key } => Change::Delete { key: decode(key) },
+                Mutation::DeleteRange { start, end } => Change::DeleteRange {
+                    start: decode(start),
+                    end: decode(end),
+                },
+                Mutation::Split { .. } | Mutation::NewRoot { .. } => return true,
+            };
+            subscriber.sender.try_send(change).is_ok()
+        });
+        let dropped = before - subscribers.len();
+        if subscribers.is_empty() {
+            tables.remove(&self.table);
+        }
+        self.subscribers.count.fetch_sub(dropped, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slow_subscriber() {
+        let subscribers = Subscribers::default();
+        let table = (None, *b"watched\0");
+        let changes = subscribers.subscribe(table, KeyType::U64, KeyEncoding::Hex);
+        let other = subscribers.subscribe((None, *b"ignored\0"), KeyType::U64, KeyEncoding::Hex);
+        let observer = subscribers.observer(table);
+        for key in 0..SUBSCRIBER_QUEUE as u64 + 1 {
+            observer.on_mutation(Mutation::Put {
+                key: key.to_be_bytes(),
+                value: b"",
+            });
+        }
+        // The queue filled up and the subscriber was dropped, instead of the
+        // last put waiting for it.
+        assert_eq!(SUBSCRIBER_QUEUE, changes.iter().count());
+        assert!(other.try_recv().is_err());
+        assert_eq!(1, subscribers.count.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,44 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+use qp::{
+    buffer::{BufferPool, BufferPoolManager},
+    disk::DiskManager,
+    server, Db,
+};
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_embedded_then_served() {
+    let data_file = NamedTempFile::new().unwrap();
+    let table_id = *b"\0\0\0\0\0\0\0\x01";
+    {
+        let db = Db::open(data_file.path()).unwrap();
+        db.create_table(table_id).unwrap();
+        db.put(table_id, 1u64.to_be_bytes(), b"hello").unwrap();
+        db.flush().unwrap();
+    }
+
+    let disk = DiskManager::open(data_file.path()).unwrap();
+    let bufmgr = Arc::new(BufferPoolManager::new(disk, BufferPool::new(10)));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || server::serve(listener, bufmgr, &server::Config::default()));
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    (&stream)
+        .write_all(b"{\"type\":\"GetItem\",\"table_id\":\"0000000000000001\",\"key\":\"0000000000000001\"}\n")
+        .unwrap();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(
+        // Versioned as if put by a request.
+        "{\"type\":\"GetItem\",\"item\":{\"key\":\"0000000000000001\",\"value\":\"hello\"},\"version\":1}\n",
+        line
+    );
+}